@@ -16,6 +16,13 @@ const NONCE_SIZE: usize = 24;
 #[derive(Clone, PartialEq)]
 pub struct PublicKey(box_::PublicKey);
 
+impl PublicKey {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &(self.0).0
+    }
+}
+
 impl AsRef<box_::PublicKey> for PublicKey {
     fn as_ref(&self) -> &box_::PublicKey {
         &self.0
@@ -46,6 +53,13 @@ impl FromHex for PublicKey {
 /// Convenience wrapper around `sodiumoxide::crypto::box_::SecretKey`
 pub struct SecretKey(box_::SecretKey);
 
+impl SecretKey {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &(self.0).0
+    }
+}
+
 impl AsRef<box_::SecretKey> for SecretKey {
     fn as_ref(&self) -> &box_::SecretKey {
         &self.0
@@ -111,6 +125,12 @@ pub enum CryptoError {
     FailedToDecrypt,
 }
 
+/// Generate new random key pair
+pub fn random_keypair() -> (PublicKey, SecretKey) {
+    let (pk, sk) = box_::gen_keypair();
+    (PublicKey(pk), SecretKey(sk))
+}
+
 /// Create `PrecomputedKey` from public key and secret key
 ///
 /// # Arguments
@@ -173,6 +193,17 @@ mod tests {
         assert_eq!(NONCE_SIZE, nonce.0.len())
     }
 
+    #[test]
+    fn generate_random_keypair() -> Result<(), Error> {
+        let (pk, sk) = random_keypair();
+        assert_eq!(CRYPTO_KEY_SIZE, pk.as_bytes().len());
+        assert_eq!(CRYPTO_KEY_SIZE, sk.as_bytes().len());
+
+        // generated keys can be used to create precomputed key
+        precompute(&hex::encode(pk.as_bytes()), &hex::encode(sk.as_bytes()))?;
+        Ok(())
+    }
+
     #[test]
     fn generate_precomputed_key() -> Result<(), Error> {
         let pk = "96678b88756dd6cfd6c129980247b70a6e44da77823c3672a2ec0eae870d8646";
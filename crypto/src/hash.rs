@@ -111,8 +111,13 @@ impl HashType {
     /// Convert hash byte representation into string.
     pub fn bytes_to_string(&self, data: &[u8]) -> String {
         let hash_fn = self.hash_fn();
-        let data = hash_fn(data);
+        self.hash_to_string(&hash_fn(data))
+    }
 
+    /// Convert bytes, which are already the hash (e.g. peer id received in a message), into string.
+    ///
+    /// Unlike [bytes_to_string](HashType::bytes_to_string) the hash function is not applied.
+    pub fn hash_to_string(&self, data: &[u8]) -> String {
         assert_eq!(self.size(), data.len(), "Expected data length is {} but instead found {}", self.size(), data.len());
         let mut hash = Vec::with_capacity(self.prefix().len() + data.len());
         hash.extend(self.prefix());
@@ -231,6 +236,16 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_encode_public_key_hash_without_hashing() -> Result<(), failure::Error> {
+        let peer_id = HashType::CryptoboxPublicKeyHash.string_to_bytes("idsg2wkkDDv2cbEMK4zH49fjgyn7XT")?;
+        let encoded = HashType::CryptoboxPublicKeyHash.hash_to_string(&peer_id);
+        let expected = "idsg2wkkDDv2cbEMK4zH49fjgyn7XT";
+        assert_eq!(expected, encoded);
+
+        Ok(())
+    }
+
     #[test]
     fn test_encode_contract_tz1() -> Result<(), failure::Error> {
         let decoded = HashType::ContractTz1Hash.bytes_to_string(&hex::decode("83846eddd5d3c5ed96e962506253958649c84a74")?);
@@ -0,0 +1,218 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Proof of work of the peer identity, follows tezos `Crypto_box.check_proof_of_work`.
+//!
+//! Blake2b hash of the public key and the proof of work stamp is read as a little-endian number,
+//! which must not exceed the target derived from the expected difficulty.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use failure::Fail;
+use num_bigint::BigUint;
+use rand::Rng;
+
+use super::blake2b;
+
+/// Size of the proof of work stamp
+pub const POW_STAMP_SIZE: usize = 24;
+/// Size of the cryptobox public key
+const PUBLIC_KEY_SIZE: usize = 32;
+/// How often is the progress of the proof of work generation reported
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+/// Stamps tried by the generator thread before the shared attempt counter is updated
+const ATTEMPTS_BATCH: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Fail)]
+pub enum ProofOfWorkError {
+    #[fail(display = "Invalid public key length: {}", length)]
+    InvalidPublicKey {
+        length: usize
+    },
+    #[fail(display = "Invalid proof of work stamp length: {}", length)]
+    InvalidStamp {
+        length: usize
+    },
+    #[fail(display = "Proof of work does not reach expected difficulty: {}", expected_pow)]
+    NotEnoughProofOfWork {
+        expected_pow: f64
+    },
+    #[fail(display = "All proof of work generator threads failed")]
+    GeneratorFailed,
+}
+
+/// Verify, that the proof of work `stamp` of the `public_key` reaches the `expected_pow` difficulty.
+pub fn check_proof_of_work(public_key: &[u8], stamp: &[u8], expected_pow: f64) -> Result<(), ProofOfWorkError> {
+    if public_key.len() != PUBLIC_KEY_SIZE {
+        return Err(ProofOfWorkError::InvalidPublicKey { length: public_key.len() });
+    }
+    if stamp.len() != POW_STAMP_SIZE {
+        return Err(ProofOfWorkError::InvalidStamp { length: stamp.len() });
+    }
+
+    if reaches_target(public_key, stamp, &make_target(expected_pow)) {
+        Ok(())
+    } else {
+        Err(ProofOfWorkError::NotEnoughProofOfWork { expected_pow })
+    }
+}
+
+/// Search for the proof of work stamp of the `public_key`, which reaches the `expected_pow` difficulty.
+///
+/// Search runs in `threads` parallel threads, every thread starts from a random stamp and increments it.
+/// Number of already tried stamps is periodically reported to the `progress` callback.
+pub fn generate_proof_of_work<F>(public_key: &[u8], expected_pow: f64, threads: usize, progress: F) -> Result<Vec<u8>, ProofOfWorkError>
+    where
+        F: Fn(u64)
+{
+    if public_key.len() != PUBLIC_KEY_SIZE {
+        return Err(ProofOfWorkError::InvalidPublicKey { length: public_key.len() });
+    }
+
+    let target = Arc::new(make_target(expected_pow));
+    let found = Arc::new(AtomicBool::new(false));
+    let attempts = Arc::new(AtomicU64::new(0));
+    let (result_tx, result_rx) = mpsc::channel();
+
+    for _ in 0..threads.max(1) {
+        let public_key = public_key.to_vec();
+        let target = target.clone();
+        let found = found.clone();
+        let attempts = attempts.clone();
+        let result_tx = result_tx.clone();
+        thread::spawn(move || {
+            let mut stamp = [0u8; POW_STAMP_SIZE];
+            rand::thread_rng().fill(&mut stamp);
+            while !found.load(Ordering::Acquire) {
+                for _ in 0..ATTEMPTS_BATCH {
+                    if reaches_target(&public_key, &stamp, &target) {
+                        found.store(true, Ordering::Release);
+                        let _ = result_tx.send(stamp.to_vec());
+                        return;
+                    }
+                    increment(&mut stamp);
+                }
+                attempts.fetch_add(ATTEMPTS_BATCH, Ordering::Relaxed);
+            }
+        });
+    }
+    // only generator threads hold the sender now, so the receiver is disconnected if all of them fail
+    drop(result_tx);
+
+    loop {
+        match result_rx.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(stamp) => return Ok(stamp),
+            Err(mpsc::RecvTimeoutError::Timeout) => progress(attempts.load(Ordering::Relaxed)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(ProofOfWorkError::GeneratorFailed),
+        }
+    }
+}
+
+fn reaches_target(public_key: &[u8], stamp: &[u8], target: &BigUint) -> bool {
+    let mut data = Vec::with_capacity(PUBLIC_KEY_SIZE + POW_STAMP_SIZE);
+    data.extend_from_slice(public_key);
+    data.extend_from_slice(stamp);
+    BigUint::from_bytes_le(&blake2b::digest_256(&data)) <= *target
+}
+
+/// Increment stamp as a big-endian number, overflow wraps around to zero
+fn increment(stamp: &mut [u8]) {
+    for byte in stamp.iter_mut().rev() {
+        let (value, overflow) = byte.overflowing_add(1);
+        *byte = value;
+        if !overflow {
+            break;
+        }
+    }
+}
+
+/// Target for the difficulty `expected_pow`, which is approximately number of leading zero bits of the hash,
+/// follows tezos `Crypto_box.make_target`.
+fn make_target(expected_pow: f64) -> BigUint {
+    let expected_pow = expected_pow.max(0.0).min(256.0);
+    let shift = expected_pow.trunc();
+    let fraction = expected_pow - shift;
+    let shift = shift as usize;
+
+    // 48 most significant bits of the target
+    let mantissa = if fraction == 0.0 {
+        (1u64 << 48) - 1
+    } else {
+        2f64.powf(48.0 - fraction) as u64
+    };
+
+    let mantissa = BigUint::from(mantissa);
+    if shift < 208 {
+        // bits below the mantissa are filled with ones
+        (mantissa << (208 - shift)) | ((BigUint::from(1u32) << (208 - shift)) - 1u32)
+    } else {
+        mantissa >> (shift - 208)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_proof_of_work() {
+        let public_key = hex::decode("fa576459d75f386d6434ae94984ccf35aebf1a2d353b9d672d9a49959dad6760").unwrap();
+        let stamp = hex::decode("f64a4d0fbd6956f8aa1286057608ee7c1f45069041c0b645").unwrap();
+
+        assert!(check_proof_of_work(&public_key, &stamp, 0.0).is_ok());
+        assert!(check_proof_of_work(&public_key, &stamp, 26.0).is_ok());
+        assert!(check_proof_of_work(&public_key, &stamp, 26.5).is_ok());
+        assert_eq!(
+            Err(ProofOfWorkError::NotEnoughProofOfWork { expected_pow: 40.0 }),
+            check_proof_of_work(&public_key, &stamp, 40.0)
+        );
+        assert!(check_proof_of_work(&public_key, &[0; POW_STAMP_SIZE], 26.0).is_err());
+        assert_eq!(
+            Err(ProofOfWorkError::InvalidStamp { length: 23 }),
+            check_proof_of_work(&public_key, &stamp[1..], 0.0)
+        );
+    }
+
+    #[test]
+    fn test_generate_proof_of_work() {
+        let public_key = hex::decode("fa576459d75f386d6434ae94984ccf35aebf1a2d353b9d672d9a49959dad6760").unwrap();
+
+        let stamp = generate_proof_of_work(&public_key, 12.0, 2, |_| ()).unwrap();
+        assert_eq!(POW_STAMP_SIZE, stamp.len());
+        assert!(check_proof_of_work(&public_key, &stamp, 12.0).is_ok());
+
+        assert_eq!(
+            Err(ProofOfWorkError::InvalidPublicKey { length: 31 }),
+            generate_proof_of_work(&public_key[1..], 12.0, 2, |_| ())
+        );
+    }
+
+    #[test]
+    fn test_increment() {
+        let mut stamp = [0u8, 0xFE, 0xFF];
+        increment(&mut stamp);
+        assert_eq!([0u8, 0xFF, 0x00], stamp);
+        increment(&mut stamp);
+        assert_eq!([1u8, 0x00, 0x00], stamp);
+    }
+
+    #[test]
+    fn test_make_target() {
+        assert_eq!((BigUint::from(1u32) << 256) - 1u32, make_target(0.0));
+        assert_eq!((BigUint::from(1u32) << 230) - 1u32, make_target(26.0));
+        assert_eq!(
+            BigUint::parse_bytes(b"2d413cccfe77bfffffffffffffffffffffffffffffffffffffffffffff", 16).unwrap(),
+            make_target(26.5)
+        );
+        assert_eq!(
+            BigUint::parse_bytes(b"1ae89f995ad3bfffffffffffffffffffffffffffffffffffffffffffffffffff", 16).unwrap(),
+            make_target(3.25)
+        );
+        assert!(make_target(26.5) < make_target(26.0));
+        assert!(make_target(26.5) > make_target(27.0));
+    }
+}
@@ -2,25 +2,42 @@
 // SPDX-License-Identifier: MIT
 
 use std::fs;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::env;
+use std::time::Duration;
 
 use std::io::{self, BufRead};
 use std::ffi::OsString;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
+use networking::p2p::bandwidth::BandwidthLimits;
+use shell::history_pruner::HistoryPrunerConfiguration;
 use shell::peer_manager::Threshold;
+use shell::peer_reputation::BanPolicy;
 use tezos_api::environment;
+use storage::pruning::HistoryMode;
 use tezos_api::environment::TezosEnvironment;
 
+/// Default proof of work difficulty, same as in the tezos node
+const DEFAULT_EXPECTED_POW: f64 = 26.0;
+/// Default count of cycles retained in full and rolling history modes, rights RPCs require context up to 2 * preserved_cycles + 2 cycles back
+const DEFAULT_HISTORY_RETAINED_CYCLES: i32 = 8;
+/// Default count of blocks in a cycle, same as on the mainnet
+const DEFAULT_HISTORY_BLOCKS_PER_CYCLE: i32 = 4096;
+
 #[derive(Debug, Clone)]
 pub struct P2p {
     pub listener_port: u16,
+    pub listener_addresses: Vec<IpAddr>,
     pub bootstrap_lookup_addresses: Vec<String>,
     pub initial_peers: Vec<SocketAddr>,
     pub peer_threshold: Threshold,
+    pub ban_policy: BanPolicy,
+    pub expected_pow: f64,
+    pub private_node: bool,
+    pub bandwidth_limits: BandwidthLimits,
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +58,40 @@ pub struct Logging {
 pub struct Storage {
     pub bootstrap_db_path: PathBuf,
     pub tezos_data_dir: PathBuf,
+    /// Only report pending database migrations and exit
+    pub migration_dry_run: bool,
+    pub history: HistoryPrunerConfiguration,
+    /// Verify context hashes returned by the protocol with the native merkle tree
+    pub verify_context_hash: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Database directory of the node, which recorded the p2p messages
+    pub recorded_db_path: PathBuf,
+    /// Recorded delays between messages are divided by this factor
+    pub speedup: f64,
+    /// Only messages of these peers are replayed, all peers are replayed if empty
+    pub peers: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyStorage {
+    /// Repair problems, which can be recomputed from the other stored data
+    pub repair: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Snapshot {
+    /// Export snapshot of the block, current head is exported if no block is provided
+    Export {
+        block_hash: Option<String>,
+        file: PathBuf,
+    },
+    /// Import snapshot into the empty storage
+    Import {
+        file: PathBuf,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +120,11 @@ pub struct Environment {
     pub storage: Storage,
 
     pub record: bool,
+    pub replay: Option<Replay>,
+    /// Verify the storage and exit instead of running the node
+    pub verify_storage: Option<VerifyStorage>,
+    /// Export or import the snapshot and exit instead of running the node
+    pub snapshot: Option<Snapshot>,
     pub identity_json_file_path: PathBuf,
     pub tezos_network: TezosEnvironment,
     pub enable_testchain: bool,
@@ -139,7 +195,7 @@ pub fn tezos_app() -> App<'static, 'static> {
             .long("bootstrap-lookup-address")
             .takes_value(true)
             .conflicts_with("peers")
-            .help("A peers for dns lookup to get the peers to bootstrap the network from. Peers are delimited by a colon. Default: used according to --network parameter see TezosEnvironment"))
+            .help("A peers for dns lookup to get the peers to bootstrap the network from. Peers are separated by a comma. Default: used according to --network parameter see TezosEnvironment"))
         .arg(Arg::with_name("log-file")
             .long("log-file")
             .takes_value(true)
@@ -173,6 +229,22 @@ pub fn tezos_app() -> App<'static, 'static> {
                 .value_name("PORT")
                 .help("Socket listening port for p2p for communication with tezos world")
                 .validator(parse_validator_fn!(u16, "Value must be a valid port number")))
+            .arg(Arg::with_name("p2p-listen-addresses")
+                .long("p2p-listen-addresses")
+                .takes_value(true)
+                .value_name("IP")
+                .help("Addresses on which the node listens for p2p connections at the p2p port. Addresses are separated by a comma. Format: IP1,IP2, e.g. 0.0.0.0,:: Default: 0.0.0.0")
+                .validator(|v| {
+                    let err_count = v.split(',')
+                        .map(|ip| ip.parse::<IpAddr>())
+                        .filter(|v| v.is_err())
+                        .count();
+                    if err_count == 0 {
+                        Ok(())
+                    } else {
+                        Err(format!("Value '{}' is not valid. Expected format is: IP1,IP2", v))
+                    }
+                }))
             .arg(Arg::with_name("rpc-port")
                 .long("rpc-port")
                 .takes_value(true)
@@ -183,7 +255,7 @@ pub fn tezos_app() -> App<'static, 'static> {
             .long("enable-testchain")
             .takes_value(true)
             .value_name("BOOL")
-            .help("Flag for enable/disable test chain switching for block applying. Default: false"))
+            .help("Flag for enable/disable test chain switching for block applying and following of the forked test chains. Default: false"))
         .arg(Arg::with_name("websocket-address")
             .long("websocket-address")
             .takes_value(true)
@@ -200,7 +272,7 @@ pub fn tezos_app() -> App<'static, 'static> {
                 .long("peers")
                 .takes_value(true)
                 .value_name("IP:PORT")
-                .help("A peer to bootstrap the network from. Peers are delimited by a colon. Format: IP1:PORT1,IP2:PORT2,IP3:PORT3")
+                .help("A peer to bootstrap the network from. Peers are separated by a comma. Format: IP1:PORT1,IP2:PORT2,[IPV6]:PORT3")
                 .validator(|v| {
                     let err_count = v.split(',')
                         .map(|ip_port| ip_port.parse::<SocketAddr>())
@@ -209,7 +281,7 @@ pub fn tezos_app() -> App<'static, 'static> {
                     if err_count == 0 {
                         Ok(())
                     } else {
-                        Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,IP3:PORT3", v))
+                        Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,[IPV6]:PORT3", v))
                     }
                 }))
             .arg(Arg::with_name("peer-thresh-low")
@@ -224,6 +296,47 @@ pub fn tezos_app() -> App<'static, 'static> {
                 .value_name("NUM")
                 .help("Maximal number of peers to connect to")
                 .validator(parse_validator_fn!(usize, "Value must be a valid number")))
+            .arg(Arg::with_name("peer-ban-duration")
+                .long("peer-ban-duration")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Duration of the first ban of the misbehaving peer, every repeated ban doubles the duration")
+                .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+            .arg(Arg::with_name("expected-pow")
+                .long("expected-pow")
+                .takes_value(true)
+                .value_name("NUM")
+                .help("Proof of work difficulty required from remote peers, it is also used when generating new identity")
+                .validator(parse_validator_fn!(f64, "Value must be a valid number")))
+            .arg(Arg::with_name("private-node")
+                .long("private-node")
+                .takes_value(true)
+                .value_name("BOOL")
+                .help("Private node connects to and accepts connections only from the peers specified by --peers and its address is not advertised to other peers. Default: false"))
+            .arg(Arg::with_name("download-limit")
+                .long("download-limit")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Download bandwidth limit shared by all peers in bytes per second. Default: unlimited")
+                .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+            .arg(Arg::with_name("upload-limit")
+                .long("upload-limit")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Upload bandwidth limit shared by all peers in bytes per second. Default: unlimited")
+                .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+            .arg(Arg::with_name("peer-download-limit")
+                .long("peer-download-limit")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Download bandwidth limit of a single peer in bytes per second. Default: unlimited")
+                .validator(parse_validator_fn!(u64, "Value must be a valid number")))
+            .arg(Arg::with_name("peer-upload-limit")
+                .long("peer-upload-limit")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("Upload bandwidth limit of a single peer in bytes per second. Default: unlimited")
+                .validator(parse_validator_fn!(u64, "Value must be a valid number")))
             .arg(Arg::with_name("protocol-runner")
                 .long("protocol-runner")
                 .takes_value(true)
@@ -246,7 +359,99 @@ pub fn tezos_app() -> App<'static, 'static> {
             .long("record")
             .takes_value(true)
             .value_name("BOOL")
-            .help("Flag for turn on/off record mode"));
+            .help("Flag for turn on/off record mode"))
+        .arg(Arg::with_name("db-migration-dry-run")
+            .long("db-migration-dry-run")
+            .takes_value(true)
+            .value_name("BOOL")
+            .help("Only report migrations required to upgrade the bootstrap database and exit. Database is opened read only, so it is not modified. Default: false"))
+        .arg(Arg::with_name("verify-context-hash")
+            .long("verify-context-hash")
+            .takes_value(true)
+            .value_name("BOOL")
+            .help("Compute context hashes by the native merkle tree and log blocks, where the hash differs from the hash returned by the protocol. Default: false"))
+        .arg(Arg::with_name("history-mode")
+            .long("history-mode")
+            .takes_value(true)
+            .value_name("MODE")
+            .possible_values(&["archive", "full", "rolling"])
+            .help("Archive keeps all data, full removes context of the old blocks, rolling removes also operations of the old blocks. Block headers and context actions are kept in the commit logs, which are never compacted, so their disk space is not reclaimed. Default: archive"))
+        .arg(Arg::with_name("history-retained-cycles")
+            .long("history-retained-cycles")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Count of cycles preceding the current cycle, which are not pruned in full and rolling history modes. Default: 8")
+            .validator(parse_validator_fn!(i32, "Value must be a valid number")))
+        .arg(Arg::with_name("history-blocks-per-cycle")
+            .long("history-blocks-per-cycle")
+            .takes_value(true)
+            .value_name("NUM")
+            .help("Count of blocks in a cycle of the chosen network, used to compute pruned levels. Default: 4096")
+            .validator(|v| match v.parse::<i32>() {
+                Ok(blocks) if blocks > 0 => Ok(()),
+                _ => Err("Value must be a positive number".to_string())
+            }))
+        .arg(Arg::with_name("replay")
+            .long("replay")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Replay p2p messages recorded in the bootstrap database at the PATH instead of connecting to the network")
+            .validator(|v| if Path::new(&v).exists() { Ok(()) } else { Err(format!("Recorded database not found at '{}'", v)) }))
+        .arg(Arg::with_name("replay-speedup")
+            .long("replay-speedup")
+            .takes_value(true)
+            .value_name("FACTOR")
+            .requires("replay")
+            .help("Recorded delays between replayed messages are divided by this factor. Default: 1")
+            .validator(|v| match v.parse::<f64>() {
+                Ok(speedup) if speedup > 0.0 => Ok(()),
+                _ => Err("Value must be a positive number".to_string())
+            }))
+        .arg(Arg::with_name("replay-peers")
+            .long("replay-peers")
+            .takes_value(true)
+            .value_name("IP:PORT")
+            .requires("replay")
+            .help("Replay only messages of these peers, all recorded peers are replayed by default. Format: IP1:PORT1,IP2:PORT2,IP3:PORT3")
+            .validator(|v| {
+                let err_count = v.split(',')
+                    .map(|ip_port| ip_port.parse::<SocketAddr>())
+                    .filter(|v| v.is_err())
+                    .count();
+                if err_count == 0 {
+                    Ok(())
+                } else {
+                    Err(format!("Value '{}' is not valid. Expected format is: IP1:PORT1,IP2:PORT2,IP3:PORT3", v))
+                }
+            }))
+        .subcommand(SubCommand::with_name("verify-storage")
+            .about("Verify consistency of the bootstrap database and exit, the node must not be running")
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .takes_value(true)
+                .value_name("BOOL")
+                .help("Repair problems, which can be recomputed from the other stored data. Default: false")))
+        .subcommand(SubCommand::with_name("export-snapshot")
+            .about("Export snapshot of the applied block and exit, the node must not be running. Irmin context is copied whole, so it contains every context stored by the node")
+            .arg(Arg::with_name("block")
+                .long("block")
+                .takes_value(true)
+                .value_name("HASH")
+                .help("Hash of the exported block. Default: current head"))
+            .arg(Arg::with_name("file")
+                .long("file")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(true)
+                .help("Path to the new snapshot file")))
+        .subcommand(SubCommand::with_name("import-snapshot")
+            .about("Import snapshot into the empty bootstrap database and Irmin context and exit, the node must not be running")
+            .arg(Arg::with_name("file")
+                .long("file")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(true)
+                .help("Path to the snapshot file")));
     app
 }
 
@@ -386,6 +591,12 @@ impl Environment {
                     .unwrap_or("")
                     .parse::<u16>()
                     .expect("Was expecting value of p2p-port"),
+                listener_addresses: args.value_of("p2p-listen-addresses")
+                    .map(|addresses_str| addresses_str
+                        .split(',')
+                        .map(|ip| ip.parse().expect("Was expecting IP"))
+                        .collect()
+                    ).unwrap_or_else(|| vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]),
                 bootstrap_lookup_addresses: args.
                     value_of("bootstrap-lookup-address")
                     .map(|addresses_str| addresses_str
@@ -419,6 +630,26 @@ impl Environment {
                         .parse::<usize>()
                         .expect("Provided value cannot be converted to number"),
                 ),
+                ban_policy: args.value_of("peer-ban-duration")
+                    .map(|seconds| BanPolicy::new(Duration::from_secs(seconds.parse::<u64>().expect("Provided value cannot be converted to number"))))
+                    .unwrap_or_default(),
+                expected_pow: args.value_of("expected-pow")
+                    .map(|pow| pow.parse::<f64>().expect("Provided value cannot be converted to number"))
+                    .unwrap_or(DEFAULT_EXPECTED_POW),
+                private_node: args.value_of("private-node")
+                    .unwrap_or("false")
+                    .parse::<bool>()
+                    .expect("Provided value cannot be converted to bool"),
+                bandwidth_limits: BandwidthLimits {
+                    global_download: args.value_of("download-limit")
+                        .map(|limit| limit.parse::<u64>().expect("Provided value cannot be converted to number")),
+                    global_upload: args.value_of("upload-limit")
+                        .map(|limit| limit.parse::<u64>().expect("Provided value cannot be converted to number")),
+                    peer_download: args.value_of("peer-download-limit")
+                        .map(|limit| limit.parse::<u64>().expect("Provided value cannot be converted to number")),
+                    peer_upload: args.value_of("peer-upload-limit")
+                        .map(|limit| limit.parse::<u64>().expect("Provided value cannot be converted to number")),
+                },
             },
             rpc: crate::configuration::Rpc {
                 listener_port: args
@@ -465,6 +696,26 @@ impl Environment {
                         .expect("Provided value cannot be converted to path");
                     get_final_path(&data_dir, db_path)
                 },
+                migration_dry_run: args.value_of("db-migration-dry-run")
+                    .unwrap_or("false")
+                    .parse::<bool>()
+                    .expect("Provided value cannot be converted to bool"),
+                history: HistoryPrunerConfiguration {
+                    mode: args.value_of("history-mode")
+                        .unwrap_or("archive")
+                        .parse::<HistoryMode>()
+                        .expect("Was expecting one value from HistoryMode"),
+                    retained_cycles: args.value_of("history-retained-cycles")
+                        .map(|cycles| cycles.parse::<i32>().expect("Provided value cannot be converted to number"))
+                        .unwrap_or(DEFAULT_HISTORY_RETAINED_CYCLES),
+                    blocks_per_cycle: args.value_of("history-blocks-per-cycle")
+                        .map(|blocks| blocks.parse::<i32>().expect("Provided value cannot be converted to number"))
+                        .unwrap_or(DEFAULT_HISTORY_BLOCKS_PER_CYCLE),
+                },
+                verify_context_hash: args.value_of("verify-context-hash")
+                    .unwrap_or("false")
+                    .parse::<bool>()
+                    .expect("Provided value cannot be converted to bool"),
             },
             identity_json_file_path: {
                 let identity_path = args.value_of("identity-file")
@@ -477,6 +728,43 @@ impl Environment {
                 .unwrap_or("")
                 .parse::<bool>()
                 .expect("Provided value cannot be converted to bool"),
+            replay: args.value_of("replay")
+                .map(|db_path| Replay {
+                    recorded_db_path: db_path.parse::<PathBuf>().expect("Provided value cannot be converted to path"),
+                    speedup: args.value_of("replay-speedup")
+                        .unwrap_or("1")
+                        .parse::<f64>()
+                        .expect("Provided value cannot be converted to number"),
+                    peers: args.value_of("replay-peers")
+                        .map(|peers_str| peers_str
+                            .split(',')
+                            .map(|ip_port| ip_port.parse().expect("Was expecting IP:PORT"))
+                            .collect()
+                        ).unwrap_or_default(),
+                }),
+            verify_storage: args.subcommand_matches("verify-storage")
+                .map(|verify_args| VerifyStorage {
+                    repair: verify_args.value_of("repair")
+                        .unwrap_or("false")
+                        .parse::<bool>()
+                        .expect("Provided value cannot be converted to bool"),
+                }),
+            snapshot: match args.subcommand() {
+                ("export-snapshot", Some(snapshot_args)) => Some(Snapshot::Export {
+                    block_hash: snapshot_args.value_of("block").map(|block_hash| block_hash.to_string()),
+                    file: snapshot_args.value_of("file")
+                        .unwrap_or("")
+                        .parse::<PathBuf>()
+                        .expect("Provided value cannot be converted to path"),
+                }),
+                ("import-snapshot", Some(snapshot_args)) => Some(Snapshot::Import {
+                    file: snapshot_args.value_of("file")
+                        .unwrap_or("")
+                        .parse::<PathBuf>()
+                        .expect("Provided value cannot be converted to path"),
+                }),
+                _ => None,
+            },
             protocol_runner: args
                 .value_of("protocol-runner")
                 .unwrap_or("")
@@ -1,13 +1,17 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::process::Child;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use std::time::Duration;
 
 use riker::actors::*;
-use slog::{crit, debug, Drain, error, info, Logger};
+use rocksdb::{ColumnFamilyDescriptor, DB};
+use slog::{crit, debug, Drain, error, info, Logger, warn};
 
+use crypto::hash::HashType;
 use logging::detailed_json;
 use logging::file::FileAppenderBuilder;
 use monitoring::{listener::{
@@ -17,14 +21,22 @@ use monitoring::{listener::{
 use networking::p2p::network_channel::NetworkChannel;
 use rpc::rpc_actor::RpcServer;
 use shell::chain_feeder::ChainFeeder;
-use shell::chain_manager::ChainManager;
+use shell::chain_manager::{ChainManager, PeerTimeouts};
 use shell::context_listener::ContextListener;
-use shell::peer_manager::PeerManager;
+use shell::context_verifier::ContextHashVerifier;
+use shell::history_pruner::HistoryPruner;
+use shell::mempool_manager::MempoolManager;
+use shell::p2p_replay::P2PReplay;
+use shell::peer_manager::{bind_listeners, PeerManager};
 use shell::shell_channel::{ShellChannel, ShellChannelTopic, ShuttingDown};
-use storage::{block_storage, BlockMetaStorage, BlockStorage, context_action_storage, ContextActionStorage, OperationsMetaStorage, OperationsStorage, resolve_storage_init_chain_data, StorageError, StorageInitInfo, SystemStorage};
+use storage::{block_storage, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, context_action_storage, ContextActionStorage, OperationsMetaStorage, OperationsStorage, PeerBanStorage, PointStorage, ProtocolStorage, resolve_storage_init_chain_data, StorageError, StorageInitInfo, SystemStorage};
+use storage::integrity::IntegrityChecker;
+use storage::migration::Migrator;
 use storage::p2p_message_storage::{P2PMessageSecondaryIndex, P2PMessageStorage};
-use storage::persistent::{CommitLogSchema, KeyValueSchema, open_cl, open_kv, PersistentStorage};
+use storage::persistent::{CommitLogDescriptor, CommitLogSchema, KeyValueSchema, open_cl, open_kv, open_kv_read_only, PersistentStorage};
 use storage::persistent::sequence::Sequences;
+use storage::pruning::{check_history_mode, HistoryMode};
+use storage::snapshot::SnapshotManager;
 use storage::skip_list::{DatabaseBackedSkipList, Lane, ListValue};
 use tezos_api::environment;
 use tezos_api::environment::TezosEnvironmentConfiguration;
@@ -32,14 +44,11 @@ use tezos_api::ffi::TezosRuntimeConfiguration;
 use tezos_api::identity::Identity;
 use tezos_wrapper::service::{IpcCmdServer, IpcEvtServer, ProtocolEndpointConfiguration, ProtocolRunner, ProtocolRunnerEndpoint};
 
-use crate::configuration::LogFormat;
+use crate::configuration::{LogFormat, Snapshot};
 
 mod configuration;
 mod identity;
 
-const EXPECTED_POW: f64 = 26.0;
-const DATABASE_VERSION: i64 = 11;
-
 macro_rules! shutdown_and_exit {
     ($err:expr, $sys:ident) => {{
         $err;
@@ -93,6 +102,39 @@ fn create_tokio_runtime(env: &crate::configuration::Environment) -> tokio::runti
     builder.build().expect("Failed to create tokio runtime")
 }
 
+/// Spawns a thread, which (re)starts protocol runner process whenever it is not running.
+fn keep_protocol_runner_running(protocol_runner: ProtocolRunner, run: Arc<AtomicBool>, log: Logger) {
+    let _ = thread::spawn(move || {
+        let mut protocol_runner_process: Option<Child> = None;
+        while run.load(Ordering::Acquire) {
+            let is_running = match protocol_runner_process.as_mut() {
+                Some(process) => ProtocolRunner::is_running(process),
+                None => false,
+            };
+            if !is_running {
+                info!(log, "Starting protocol runner process");
+                protocol_runner_process = match protocol_runner.spawn() {
+                    Ok(process) => {
+                        info!(log, "Protocol runner started successfully");
+                        Some(process)
+                    }
+                    Err(e) => {
+                        crit!(log, "Failed to spawn protocol runner process"; "reason" => e);
+                        break;
+                    }
+                };
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        if let Some(mut process) = protocol_runner_process {
+            if ProtocolRunner::is_running(&mut process) {
+                ProtocolRunner::terminate(process);
+            }
+        }
+    });
+}
+
 fn block_on_actors(
     env: &crate::configuration::Environment,
     tezos_env: &TezosEnvironmentConfiguration,
@@ -100,11 +142,22 @@ fn block_on_actors(
     identity: Identity,
     actor_system: ActorSystem,
     persistent_storage: PersistentStorage,
+    recorded_db: Option<Arc<DB>>,
     protocol_commands: IpcCmdServer,
     protocol_events: IpcEvtServer,
     protocol_runner_run: Arc<AtomicBool>,
     log: Logger) {
 
+    // node, which can not accept incoming p2p connections, is not started at all
+    let listeners = if env.replay.is_none() {
+        match bind_listeners(env.p2p.listener_port, &env.p2p.listener_addresses, &log) {
+            Ok(listeners) => listeners,
+            Err(e) => shutdown_and_exit!(crit!(log, "Failed to listen for incoming p2p connections on any address"; "reason" => format!("{:?}", e)), actor_system),
+        }
+    } else {
+        vec![]
+    };
+
     let mut tokio_runtime = create_tokio_runtime(env);
 
     let network_channel = NetworkChannel::actor(&actor_system)
@@ -113,33 +166,71 @@ fn block_on_actors(
         .expect("Failed to create shell channel");
 
     // it's important to start ContextListener before ChainFeeder, because chain_feeder can trigger init_genesis which send ContextAction, and we need thouse action to process first
-    let _ = ContextListener::actor(&actor_system, &persistent_storage, protocol_events, log.clone())
+    let context_hash_verifier = if env.storage.verify_context_hash {
+        info!(log, "Context hash verification is enabled");
+        Some(ContextHashVerifier::shared(log.clone()))
+    } else {
+        None
+    };
+    let _ = ContextListener::actor(&actor_system, &persistent_storage, protocol_events, context_hash_verifier.clone(), log.clone())
         .expect("Failed to create context event listener");
-    let _ = ChainFeeder::actor(&actor_system, shell_channel.clone(), &persistent_storage, &init_storage_data, &tezos_env, protocol_commands, log.clone())
+    let _ = ChainFeeder::actor(&actor_system, shell_channel.clone(), &persistent_storage, &init_storage_data, &tezos_env, protocol_commands, context_hash_verifier, log.clone())
         .expect("Failed to create chain feeder");
     // if feeding is started, than run chain manager
-    let _ = ChainManager::actor(&actor_system, network_channel.clone(), shell_channel.clone(), &persistent_storage, &init_storage_data.chain_id)
+    let local_peer_id = HashType::CryptoboxPublicKeyHash.string_to_bytes(&identity.peer_id).expect("Invalid peer id in the identity");
+    let _ = ChainManager::actor(&actor_system, network_channel.clone(), shell_channel.clone(), &persistent_storage, &init_storage_data.chain_id, &local_peer_id, env.enable_testchain, PeerTimeouts::default())
         .expect("Failed to create chain manager");
+    let _ = MempoolManager::actor(&actor_system, network_channel.clone(), shell_channel.clone(), &persistent_storage, &init_storage_data.chain_id)
+        .expect("Failed to create mempool manager");
+    if env.storage.history.mode != HistoryMode::Archive {
+        info!(log, "History pruning is enabled"; "history_mode" => env.storage.history.mode.to_string(), "retained_cycles" => env.storage.history.retained_cycles);
+        let _ = HistoryPruner::actor(&actor_system, shell_channel.clone(), &persistent_storage, &env.storage.history)
+            .expect("Failed to create history pruner");
+    }
 
-    // and than open p2p and others
-    let _ = PeerManager::actor(
-        &actor_system,
-        network_channel.clone(),
-        shell_channel.clone(),
-        tokio_runtime.handle().clone(),
-        &env.p2p.bootstrap_lookup_addresses,
-        &env.p2p.initial_peers,
-        env.p2p.peer_threshold,
-        env.p2p.listener_port,
-        identity,
-        tezos_env.version.clone(),
-        persistent_storage.clone())
-        .expect("Failed to create peer manager");
+    // and than open p2p and others, or replay recorded p2p messages without any network connection
+    match (&env.replay, recorded_db) {
+        (Some(replay), Some(recorded_db)) => {
+            info!(log, "Running in replay mode"; "recorded_db_path" => replay.recorded_db_path.to_str().unwrap_or(""));
+            let _ = P2PReplay::actor(
+                &actor_system,
+                network_channel.clone(),
+                shell_channel.clone(),
+                tokio_runtime.handle().clone(),
+                identity,
+                tezos_env.version.clone(),
+                &persistent_storage,
+                recorded_db,
+                replay.speedup,
+                &replay.peers)
+                .expect("Failed to create p2p replay");
+        }
+        _ => {
+            let _ = PeerManager::actor(
+                &actor_system,
+                network_channel.clone(),
+                shell_channel.clone(),
+                tokio_runtime.handle().clone(),
+                &env.p2p.bootstrap_lookup_addresses,
+                &env.p2p.initial_peers,
+                env.p2p.peer_threshold,
+                env.p2p.listener_port,
+                &listeners,
+                identity,
+                tezos_env.version.clone(),
+                env.p2p.expected_pow,
+                env.p2p.private_node,
+                env.p2p.bandwidth_limits,
+                persistent_storage.clone(),
+                env.p2p.ban_policy)
+                .expect("Failed to create peer manager");
+        }
+    }
     let websocket_handler = WebsocketHandler::actor(&actor_system, env.rpc.websocket_address, log.clone())
         .expect("Failed to start websocket actor");
     let _ = Monitor::actor(&actor_system, network_channel.clone(), websocket_handler, shell_channel.clone(), &persistent_storage)
         .expect("Failed to create monitor actor");
-    let _ = RpcServer::actor(&actor_system, shell_channel.clone(), ([0, 0, 0, 0], env.rpc.listener_port).into(), &tokio_runtime.handle(), &persistent_storage, &init_storage_data)
+    let _ = RpcServer::actor(&actor_system, shell_channel.clone(), ([0, 0, 0, 0], env.rpc.listener_port).into(), &tokio_runtime.handle(), &persistent_storage, &init_storage_data, env.p2p.ban_policy)
         .expect("Failed to create RPC server");
     if env.record {
         info!(log, "Running in record mode");
@@ -147,7 +238,6 @@ fn block_on_actors(
     }
 
     tokio_runtime.block_on(async move {
-        use std::thread;
         use tokio::signal;
 
         signal::ctrl_c().await.expect("Failed to listen for ctrl-c event");
@@ -176,18 +266,47 @@ fn block_on_actors(
     tokio_runtime.shutdown_timeout(Duration::from_millis(100));
 }
 
-fn check_database_compatibility(db: Arc<rocksdb::DB>, tezos_env: &TezosEnvironmentConfiguration, log: Logger) -> Result<bool, StorageError> {
-    let mut system_info = SystemStorage::new(db.clone());
-    let db_version_ok = match system_info.get_db_version()? {
-        Some(db_version) => db_version == DATABASE_VERSION,
-        None => {
-            system_info.set_db_version(DATABASE_VERSION)?;
-            true
+fn kv_schemas() -> Vec<ColumnFamilyDescriptor> {
+    vec![
+        block_storage::BlockPrimaryIndex::descriptor(),
+        block_storage::BlockByLevelIndex::descriptor(),
+        block_storage::BlockByContextHashIndex::descriptor(),
+        BlockMetaStorage::descriptor(),
+        OperationsStorage::descriptor(),
+        OperationsMetaStorage::descriptor(),
+        EventPayloadStorage::descriptor(),
+        EventStorage::descriptor(),
+        context_action_storage::ContextActionPrimaryIndex::descriptor(),
+        context_action_storage::ContextActionByContractIndex::descriptor(),
+        SystemStorage::descriptor(),
+        PeerBanStorage::descriptor(),
+        PointStorage::descriptor(),
+        ProtocolStorage::descriptor(),
+        DatabaseBackedSkipList::descriptor(),
+        P2PMessageStorage::descriptor(),
+        P2PMessageSecondaryIndex::descriptor(),
+        Lane::descriptor(),
+        ListValue::descriptor(),
+        Sequences::descriptor(),
+    ]
+}
+
+fn cl_schemas() -> Vec<CommitLogDescriptor> {
+    vec![
+        BlockStorage::descriptor(),
+        ContextActionStorage::descriptor()
+    ]
+}
+
+fn check_database_compatibility(persistent_storage: &PersistentStorage, tezos_env: &TezosEnvironmentConfiguration, log: Logger) -> Result<bool, StorageError> {
+    let mut system_info = SystemStorage::new(persistent_storage.kv());
+    let db_version_ok = match Migrator::current().run(persistent_storage, &log) {
+        Ok(_) => true,
+        Err(e) => {
+            error!(log, "Failed to migrate database. Please re-sync your node to empty storage - see configuration!"; "reason" => e);
+            false
         }
     };
-    if !db_version_ok {
-        error!(log, "Incompatible database version found. Please re-sync your node to empty storage - see configuration!");
-    }
 
     let tezos_env_main_chain = tezos_env.main_chain_id().map_err(|e| StorageError::TezosEnvironmentError { error: e })?;
 
@@ -237,7 +356,7 @@ fn main() {
     let actor_system = SystemBuilder::new().name("light-node").log(log.clone()).create().expect("Failed to create actor system");
 
     // tezos protocol runner endpoint
-    let mut protocol_runner_endpoint = ProtocolRunnerEndpoint::new(ProtocolEndpointConfiguration::new(
+    let protocol_endpoint_configuration = ProtocolEndpointConfiguration::new(
         TezosRuntimeConfiguration {
             log_enabled: env.logging.ocaml_log_enabled,
             no_of_ffi_calls_treshold_for_gc: env.no_of_ffi_calls_threshold_for_gc,
@@ -246,13 +365,83 @@ fn main() {
         env.enable_testchain,
         &env.storage.tezos_data_dir,
         &env.protocol_runner,
-    ));
+    );
+    let protocol_runner_endpoint = ProtocolRunnerEndpoint::new(protocol_endpoint_configuration);
+
+    if env.storage.migration_dry_run {
+        // database is opened read only, so the dry run can not modify it
+        let system_storage = match open_kv_read_only(&env.storage.bootstrap_db_path) {
+            Ok(db) => SystemStorage::new(Arc::new(db)),
+            Err(e) => shutdown_and_exit!(error!(log, "Failed to open RocksDB database for the migration dry run at '{:?}'", &env.storage.bootstrap_db_path; "reason" => format!("{:?}", e)), actor_system)
+        };
+        match Migrator::current().dry_run(&system_storage, &log) {
+            Ok(pending) => info!(log, "Database migration dry run finished"; "pending_migrations" => pending),
+            Err(e) => error!(log, "Database can not be migrated. Please re-sync your node to empty storage - see configuration!"; "reason" => e),
+        }
+        shutdown_and_exit!(info!(log, "Database was not modified by the migration dry run"), actor_system);
+    }
 
-    // TODO: refactor and move to restarting protocol_runner thread, when generate_identity will be in rust
-    let mut protocol_runner_process = match protocol_runner_endpoint.runner.spawn() {
-        Ok(process) => process,
-        Err(e) => shutdown_and_exit!(error!(log, "Failed to spawn protocol runner process"; "reason" => e), actor_system),
+    let rocks_db = match open_kv(&env.storage.bootstrap_db_path, kv_schemas()) {
+        Ok(db) => Arc::new(db),
+        Err(_) => shutdown_and_exit!(error!(log, "Failed to create RocksDB database at '{:?}'", &env.storage.bootstrap_db_path), actor_system)
+    };
+    debug!(log, "Loaded RocksDB database");
+
+    let commit_logs = match open_cl(&env.storage.bootstrap_db_path, cl_schemas()) {
+        Ok(commit_logs) => Arc::new(commit_logs),
+        Err(e) => shutdown_and_exit!(error!(log, "Failed to open commit logs"; "reason" => e), actor_system)
     };
+    let persistent_storage = PersistentStorage::new(rocks_db, commit_logs);
+
+    match check_database_compatibility(&persistent_storage, &tezos_env, log.clone()) {
+        Ok(false) => shutdown_and_exit!(crit!(log, "Database incompatibility detected"), actor_system),
+        Err(e) => shutdown_and_exit!(error!(log, "Failed to verify database compatibility"; "reason" => e), actor_system),
+        _ => ()
+    }
+    if let Err(e) = check_history_mode(&mut SystemStorage::new(persistent_storage.kv()), env.storage.history.mode) {
+        shutdown_and_exit!(crit!(log, "Database can not be used in the configured history mode. Please re-sync your node to empty storage - see configuration!"; "reason" => e), actor_system);
+    }
+    if let Some(verify_storage) = &env.verify_storage {
+        info!(log, "Verifying storage"; "repair" => verify_storage.repair);
+        match IntegrityChecker::new(&persistent_storage, verify_storage.repair, log.clone()).check() {
+            Ok(report) if report.is_consistent() => shutdown_and_exit!(info!(log, "Storage is consistent"; "checked_blocks" => report.checked_blocks), actor_system),
+            Ok(report) => shutdown_and_exit!(warn!(log, "Storage inconsistencies found"; "checked_blocks" => report.checked_blocks, "problems" => report.problems.len(), "repaired" => report.repaired), actor_system),
+            Err(e) => shutdown_and_exit!(error!(log, "Failed to verify storage"; "reason" => e), actor_system),
+        }
+    }
+    if let Some(snapshot) = &env.snapshot {
+        let mut snapshot_manager = SnapshotManager::new(&persistent_storage, &env.storage.tezos_data_dir, log.clone());
+        info!(log, "Irmin context is copied whole, so the snapshot contains every context stored by the exporting node, not only the context of the snapshot block");
+        let result = match snapshot {
+            Snapshot::Export { block_hash, file } => {
+                let block_hash = match block_hash {
+                    Some(block_hash) => HashType::BlockHash.string_to_bytes(block_hash).ok(),
+                    None => BlockMetaStorage::new(&persistent_storage).load_current_head().unwrap_or(None),
+                };
+                match block_hash {
+                    Some(block_hash) => {
+                        info!(log, "Exporting snapshot"; "block_hash" => HashType::BlockHash.bytes_to_string(&block_hash), "file" => file.display().to_string());
+                        snapshot_manager.export(&block_hash, file)
+                    }
+                    None => shutdown_and_exit!(error!(log, "Snapshot block is invalid or there is no current head"), actor_system),
+                }
+            }
+            Snapshot::Import { file } => {
+                info!(log, "Importing snapshot"; "file" => file.display().to_string());
+                snapshot_manager.import(file)
+            }
+        };
+        match result {
+            Ok(snapshot) => shutdown_and_exit!(info!(log, "Snapshot finished";
+                "block_hash" => HashType::BlockHash.bytes_to_string(&snapshot.block_hash),
+                "level" => snapshot.level,
+                "blocks" => snapshot.blocks,
+                "operations" => snapshot.operations,
+                "context_values" => snapshot.context_values,
+                "irmin_context_files" => snapshot.irmin_context_files), actor_system),
+            Err(e) => shutdown_and_exit!(error!(log, "Snapshot failed"; "reason" => e), actor_system),
+        }
+    }
 
     // Loads tezos identity based on provided identity-file argument. In case it does not exist, it will try to automatically generate it
     let tezos_identity =
@@ -265,62 +454,27 @@ fn main() {
                 Err(e) => shutdown_and_exit!(error!(log, "Failed to load identity"; "reason" => e, "file" => env.identity_json_file_path.into_os_string().into_string().unwrap()), actor_system),
             }
         } else {
-            info!(log, "Generating new tezos identity. This will take a while"; "expected_pow" => EXPECTED_POW);
+            info!(log, "Generating new tezos identity. This will take a while"; "expected_pow" => env.p2p.expected_pow);
 
-            // TODO: TE-74 will be replace with rust version
-            // setup tezos ocaml runtime just for generate identity
-            let protocol_controller = match protocol_runner_endpoint.commands.accept() {
-                Ok(controller) => controller,
-                Err(e) => shutdown_and_exit!(error!(log, "Failed to create protocol controller. Reason: {:?}", e), actor_system),
-            };
-
-            match protocol_controller.generate_identity(EXPECTED_POW) {
+            let progress_log = log.clone();
+            let identity = Identity::generate(env.p2p.expected_pow, num_cpus::get(), |attempts| {
+                info!(progress_log, "Generating new tezos identity"; "attempts" => attempts);
+            });
+            match identity {
                 Ok(identity) => {
-                    info!(log, "Identity successfully generated");
+                    info!(log, "Identity successfully generated"; "peer_id" => &identity.peer_id);
                     match identity::store_identity(&env.identity_json_file_path, &identity) {
                         Ok(()) => {
-                            drop(protocol_controller);
                             info!(log, "Generated identity stored to file"; "file" => env.identity_json_file_path.clone().into_os_string().into_string().unwrap());
                             identity
                         }
                         Err(e) => shutdown_and_exit!(error!(log, "Failed to store generated identity"; "reason" => e), actor_system),
                     }
                 }
-                Err(e) => shutdown_and_exit!(error!(log, "Failed to generate identity"; "reason" => e), actor_system),
+                Err(e) => shutdown_and_exit!(error!(log, "Failed to generate identity"; "reason" => format!("{}", e)), actor_system),
             }
         };
 
-    let schemas = vec![
-        block_storage::BlockPrimaryIndex::descriptor(),
-        block_storage::BlockByLevelIndex::descriptor(),
-        block_storage::BlockByContextHashIndex::descriptor(),
-        BlockMetaStorage::descriptor(),
-        OperationsStorage::descriptor(),
-        OperationsMetaStorage::descriptor(),
-        EventPayloadStorage::descriptor(),
-        EventStorage::descriptor(),
-        context_action_storage::ContextActionPrimaryIndex::descriptor(),
-        context_action_storage::ContextActionByContractIndex::descriptor(),
-        SystemStorage::descriptor(),
-        DatabaseBackedSkipList::descriptor(),
-        P2PMessageStorage::descriptor(),
-        P2PMessageSecondaryIndex::descriptor(),
-        Lane::descriptor(),
-        ListValue::descriptor(),
-        Sequences::descriptor(),
-    ];
-    let rocks_db = match open_kv(&env.storage.bootstrap_db_path, schemas) {
-        Ok(db) => Arc::new(db),
-        Err(_) => shutdown_and_exit!(error!(log, "Failed to create RocksDB database at '{:?}'", &env.storage.bootstrap_db_path), actor_system)
-    };
-    debug!(log, "Loaded RocksDB database");
-
-    match check_database_compatibility(rocks_db.clone(), &tezos_env, log.clone()) {
-        Ok(false) => shutdown_and_exit!(crit!(log, "Database incompatibility detected"), actor_system),
-        Err(e) => shutdown_and_exit!(error!(log, "Failed to verify database compatibility"; "reason" => e), actor_system),
-        _ => ()
-    }
-
 
     let ProtocolRunnerEndpoint {
         runner: protocol_runner,
@@ -329,50 +483,20 @@ fn main() {
     } = protocol_runner_endpoint;
 
     let protocol_runner_run = Arc::new(AtomicBool::new(true));
-    {
-        use std::thread;
-
-        let log = log.clone();
-        let run = protocol_runner_run.clone();
-
-        let _ = thread::spawn(move || {
-            while run.load(Ordering::Acquire) {
-                if !ProtocolRunner::is_running(&mut protocol_runner_process) {
-                    info!(log, "Starting protocol runner process");
-                    protocol_runner_process = match protocol_runner.spawn() {
-                        Ok(process) => {
-                            info!(log, "Protocol runner started successfully");
-                            process
-                        }
-                        Err(e) => {
-                            crit!(log, "Failed to spawn protocol runner process"; "reason" => e);
-                            break;
-                        }
-                    };
-                }
-                thread::sleep(Duration::from_secs(1));
-            }
-
-            if ProtocolRunner::is_running(&mut protocol_runner_process) {
-                ProtocolRunner::terminate(protocol_runner_process);
-            }
-        });
-    }
-
-    let schemas = vec![
-        BlockStorage::descriptor(),
-        ContextActionStorage::descriptor()
-    ];
+    keep_protocol_runner_running(protocol_runner, protocol_runner_run.clone(), log.clone());
 
     {
-        let commit_logs = match open_cl(&env.storage.bootstrap_db_path, schemas) {
-            Ok(commit_logs) => Arc::new(commit_logs),
-            Err(e) => shutdown_and_exit!(error!(log, "Failed to open commit logs"; "reason" => e), actor_system)
+        // database of the node, which recorded the replayed p2p messages, replay only reads from it
+        let recorded_db = match &env.replay {
+            Some(replay) => match open_kv_read_only(&replay.recorded_db_path) {
+                Ok(db) => Some(Arc::new(db)),
+                Err(_) => shutdown_and_exit!(error!(log, "Failed to open recorded RocksDB database at '{:?}'", &replay.recorded_db_path), actor_system)
+            },
+            None => None
         };
 
-        let persistent_storage = PersistentStorage::new(rocks_db, commit_logs);
         match resolve_storage_init_chain_data(&tezos_env,log.clone()) {
-            Ok(init_data) => block_on_actors(&env, tezos_env, init_data, tezos_identity, actor_system, persistent_storage, protocol_commands, protocol_events, protocol_runner_run, log),
+            Ok(init_data) => block_on_actors(&env, tezos_env, init_data, tezos_identity, actor_system, persistent_storage, recorded_db, protocol_commands, protocol_events, protocol_runner_run, log),
             Err(e) => shutdown_and_exit!(error!(log, "Failed to resolve init storage chain data. Reason: {}", e), actor_system),
         }
     }
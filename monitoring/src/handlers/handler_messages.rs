@@ -6,6 +6,8 @@ use std::iter::FromIterator;
 use serde::Serialize;
 use slog_derive::SerdeValue;
 
+use networking::p2p::bandwidth::PeerBandwidth;
+
 use crate::monitors::PeerMonitor;
 use crate::monitors::ChainMonitor;
 
@@ -79,16 +81,27 @@ pub struct PeerMetrics {
     transferred_bytes: usize,
     average_transfer_speed: f32,
     current_transfer_speed: f32,
+    uploaded_bytes: u64,
+    download_limit: Option<u64>,
+    upload_limit: Option<u64>,
+    // time (milliseconds) the transfers waited because of the bandwidth limits
+    download_throttled_ms: u64,
+    upload_throttled_ms: u64,
 }
 
 impl PeerMetrics {
-    pub fn new(public_key: Option<String>, ip_address: String, transferred_bytes: usize, average_transfer_speed: f32, current_transfer_speed: f32) -> Self {
+    pub fn new(public_key: Option<String>, ip_address: String, transferred_bytes: usize, average_transfer_speed: f32, current_transfer_speed: f32, bandwidth: &PeerBandwidth) -> Self {
         Self {
             public_key,
             ip_address,
             transferred_bytes,
             average_transfer_speed,
             current_transfer_speed,
+            uploaded_bytes: bandwidth.upload().stats().transferred(),
+            download_limit: bandwidth.download().stats().limit(),
+            upload_limit: bandwidth.upload().stats().limit(),
+            download_throttled_ms: bandwidth.download().stats().throttled_ms(),
+            upload_throttled_ms: bandwidth.upload().stats().throttled_ms(),
         }
     }
 }
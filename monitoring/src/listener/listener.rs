@@ -67,7 +67,7 @@ impl Actor for NetworkChannelListener {
             }
             NetworkChannelMsg::PeerBootstrapped(msg) => {
                 match msg {
-                    PeerBootstrapped::Success { peer, peer_id } => (EventType::PeerBootstrapped, peer.name().to_string(), peer_id.into_bytes()),
+                    PeerBootstrapped::Success { peer, peer_id, .. } => (EventType::PeerBootstrapped, peer.name().to_string(), peer_id.into_bytes()),
                     PeerBootstrapped::Failure { .. } => return,   // ignore message
                 }
 
@@ -212,6 +212,7 @@ impl Receive<NetworkChannelMsg> for Monitor {
                 let identifier = msg.peer.uri();
                 let mut monitor = PeerMonitor::new(identifier.clone());
                 monitor.addr = Some(msg.address);
+                monitor.bandwidth = msg.bandwidth.clone();
                 if let Some(monitor) = self.peer_monitors.insert(msg.peer.uri().clone(), monitor) {
                     warn!(ctx.system.log(), "Duplicate monitor found for peer"; "peer" => monitor.identifier.to_string());
                 }
@@ -222,7 +223,7 @@ impl Receive<NetworkChannelMsg> for Monitor {
             }
             NetworkChannelMsg::PeerBootstrapped(msg) => {
                 match msg {
-                    PeerBootstrapped::Success { peer, peer_id } => if let Some(monitor) = self.peer_monitors.get_mut(peer.uri()) {
+                    PeerBootstrapped::Success { peer, peer_id, .. } => if let Some(monitor) = self.peer_monitors.get_mut(peer.uri()) {
                         monitor.public_key = Some(peer_id);
                     }
                     PeerBootstrapped::Failure { .. } => ()
@@ -266,6 +267,10 @@ impl Receive<ShellChannelMsg> for Monitor {
                 self.chain_monitor.process_block_operations(msg.level as usize);
 
             },
+            ShellChannelMsg::NewCurrentHead(_) => (),
+            ShellChannelMsg::ChainReorganized(_) => (),
+            ShellChannelMsg::PeerMisbehaved(_) => (),
+            ShellChannelMsg::TestChainForked(_) => (),
             ShellChannelMsg::ShuttingDown(_) => ()
         }
     }
@@ -5,6 +5,8 @@ use std::{net::SocketAddr, time::Instant};
 
 use riker::actor::ActorUri;
 
+use networking::p2p::bandwidth::PeerBandwidth;
+
 use crate::handlers::handler_messages::PeerMetrics;
 
 /// Peer specific details about transfer *FROM* peer.
@@ -13,6 +15,7 @@ pub(crate) struct PeerMonitor {
     pub total_transferred: usize,
     pub addr: Option<SocketAddr>,
     pub public_key: Option<String>,
+    pub bandwidth: PeerBandwidth,
     current_transferred: usize,
     last_update: Instant,
     first_update: Instant,
@@ -26,6 +29,7 @@ impl PeerMonitor {
             total_transferred: 0,
             addr: None,
             public_key: None,
+            bandwidth: PeerBandwidth::default(),
             current_transferred: 0,
             last_update: now,
             first_update: now,
@@ -56,6 +60,7 @@ impl PeerMonitor {
             self.total_transferred,
             self.avg_speed(),
             self.current_speed(),
+            &self.bandwidth,
         );
 
         self.current_transferred = 0;
@@ -0,0 +1,196 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Bandwidth limiting of the p2p connections.
+//!
+//! Transferred data are throttled by token buckets. Every peer can have its own bucket for download and upload,
+//! global buckets are shared by all peers. Data are transferred only when there are enough tokens in all buckets.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::time::delay_for;
+
+/// Bandwidth limits in bytes per second, `None` means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BandwidthLimits {
+    /// Download limit shared by all peers
+    pub global_download: Option<u64>,
+    /// Upload limit shared by all peers
+    pub global_upload: Option<u64>,
+    /// Download limit of a single peer
+    pub peer_download: Option<u64>,
+    /// Upload limit of a single peer
+    pub peer_upload: Option<u64>,
+}
+
+/// Token bucket, which is refilled at the `rate` of bytes per second and holds at most one second of tokens.
+///
+/// Bucket can go into debt, so a message larger than the bucket capacity can still be transferred,
+/// the debt is then paid by waiting for the refill.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take tokens for `bytes` of data and return how long to wait before the data can be transferred
+    pub fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 || self.rate <= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+    }
+}
+
+/// Statistics of the transfers in one direction
+#[derive(Debug, Default)]
+pub struct ThrottleStats {
+    /// Limit of the peer in bytes per second
+    limit: Option<u64>,
+    /// Total transferred bytes
+    transferred: AtomicU64,
+    /// Total time (milliseconds) spent waiting for the tokens
+    throttled_ms: AtomicU64,
+}
+
+impl ThrottleStats {
+    #[inline]
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    #[inline]
+    pub fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn throttled_ms(&self) -> u64 {
+        self.throttled_ms.load(Ordering::Relaxed)
+    }
+}
+
+/// Throttles transfers of the peer in one direction
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    global: Option<Arc<Mutex<TokenBucket>>>,
+    peer: Option<Arc<Mutex<TokenBucket>>>,
+    stats: Arc<ThrottleStats>,
+}
+
+impl Throttle {
+    fn new(global: Option<Arc<Mutex<TokenBucket>>>, peer_limit: Option<u64>) -> Self {
+        Throttle {
+            global,
+            peer: peer_limit.map(|limit| Arc::new(Mutex::new(TokenBucket::new(limit)))),
+            stats: Arc::new(ThrottleStats { limit: peer_limit, ..Default::default() }),
+        }
+    }
+
+    /// Wait until `bytes` of data can be transferred
+    pub async fn transfer(&self, bytes: usize) {
+        let now = Instant::now();
+        let wait = [&self.global, &self.peer].iter()
+            .filter_map(|bucket| bucket.as_ref())
+            .map(|bucket| bucket.lock().map(|mut bucket| bucket.take(bytes, now)).unwrap_or_default())
+            .max()
+            .unwrap_or_default();
+
+        self.stats.transferred.fetch_add(bytes as u64, Ordering::Relaxed);
+        if wait > Duration::from_secs(0) {
+            self.stats.throttled_ms.fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+            delay_for(wait).await;
+        }
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &ThrottleStats {
+        &self.stats
+    }
+}
+
+/// Download and upload throttles of a single peer
+#[derive(Clone, Debug, Default)]
+pub struct PeerBandwidth {
+    download: Throttle,
+    upload: Throttle,
+}
+
+impl PeerBandwidth {
+    #[inline]
+    pub fn download(&self) -> &Throttle {
+        &self.download
+    }
+
+    #[inline]
+    pub fn upload(&self) -> &Throttle {
+        &self.upload
+    }
+}
+
+/// Bandwidth limits of the node, it holds global buckets shared by all peers
+#[derive(Clone, Debug, Default)]
+pub struct Bandwidth {
+    limits: BandwidthLimits,
+    global_download: Option<Arc<Mutex<TokenBucket>>>,
+    global_upload: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl Bandwidth {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Bandwidth {
+            limits,
+            global_download: limits.global_download.map(|limit| Arc::new(Mutex::new(TokenBucket::new(limit)))),
+            global_upload: limits.global_upload.map(|limit| Arc::new(Mutex::new(TokenBucket::new(limit)))),
+        }
+    }
+
+    /// Create throttles for a new peer
+    pub fn peer(&self) -> PeerBandwidth {
+        PeerBandwidth {
+            download: Throttle::new(self.global_download.clone(), self.limits.peer_download),
+            upload: Throttle::new(self.global_upload.clone(), self.limits.peer_upload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000);
+
+        assert_eq!(Duration::from_secs(0), bucket.take(600, start));
+        assert_eq!(Duration::from_secs(0), bucket.take(400, start));
+        // bucket is empty, data have to wait for the refill
+        assert_eq!(Duration::from_millis(500), bucket.take(500, start));
+        // debt is paid after the refill
+        assert_eq!(Duration::from_secs(0), bucket.take(100, start + Duration::from_millis(700)));
+        // bucket holds at most one second of tokens
+        assert_eq!(Duration::from_secs(1), bucket.take(2000, start + Duration::from_secs(10)));
+    }
+}
@@ -4,5 +4,8 @@
 //! This module handles low level p2p communication.
 
 mod stream;
+pub mod bandwidth;
 pub mod peer;
 pub mod network_channel;
+#[cfg(feature = "testing")]
+pub mod simulated_peer;
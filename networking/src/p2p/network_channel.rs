@@ -8,8 +8,11 @@ use std::sync::Arc;
 
 use riker::actors::*;
 
+use tezos_messages::p2p::encoding::metadata::MetadataMessage;
 use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+use tezos_messages::p2p::encoding::version::Version;
 
+use super::bandwidth::PeerBandwidth;
 use super::peer::PeerRef;
 
 pub const DEFAULT_TOPIC: &str = "network";
@@ -21,6 +24,8 @@ pub const DEFAULT_TOPIC: &str = "network";
 pub struct PeerCreated {
     pub peer: PeerRef,
     pub address: SocketAddr,
+    /// Bandwidth limits and transfer statistics of the peer
+    pub bandwidth: PeerBandwidth,
 }
 
 /// Peer has been bootstrapped.
@@ -29,6 +34,12 @@ pub enum PeerBootstrapped {
     Success {
         peer: PeerRef,
         peer_id: String,
+        /// Metadata received from the peer during the handshake
+        peer_metadata: MetadataMessage,
+        /// Version negotiated with the peer, messages exchanged with the peer have to follow this version
+        version: Version,
+        /// Peer only replays messages recorded by another node, it answers requests of the recording node, not ours
+        replayed: bool,
     },
     Failure {
         address: SocketAddr,
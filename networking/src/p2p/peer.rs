@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 use std::convert::TryFrom;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
@@ -18,16 +18,20 @@ use tokio::time::timeout;
 use crypto::crypto_box::precompute;
 use crypto::hash::HashType;
 use crypto::nonce::{self, Nonce, NoncePair};
+use crypto::proof_of_work::{check_proof_of_work, ProofOfWorkError};
 use storage::p2p_message_storage::P2PMessageStorage;
 use tezos_encoding::binary_reader::BinaryReaderError;
 use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryMessage};
-use tezos_messages::p2p::encoding::ack::NackInfo;
+use tezos_messages::p2p::encoding::ack::{NackInfo, NackMotive};
 use tezos_messages::p2p::encoding::prelude::*;
 
+use super::bandwidth::PeerBandwidth;
 use super::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerMessageReceived};
 use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStream, StreamError};
 
-const SUPPORTED_DISTRIBUTED_DB_VERSION: u16 = 0;
+/// Supported distributed db versions, the highest version supported also by the remote peer is used
+const SUPPORTED_DISTRIBUTED_DB_VERSIONS: &[u16] = &[0];
+/// Highest supported p2p version, lower version is used with peers, which do not support it yet
 const SUPPORTED_P2P_VERSION: u16 = 1;
 
 const IO_TIMEOUT: Duration = Duration::from_secs(6);
@@ -40,9 +44,9 @@ pub type PublicKey = Vec<u8>;
 
 #[derive(Debug, Fail)]
 enum PeerError {
-    #[fail(display = "Unsupported protocol - supported_version: {} vs. {}", supported_version, incompatible_versions)]
+    #[fail(display = "Unsupported protocol - supported_versions: {} vs. {}", supported_versions, incompatible_versions)]
     UnsupportedProtocol {
-        supported_version: String,
+        supported_versions: String,
         incompatible_versions: String,
     },
     #[fail(display = "Received NACK from remote peer")]
@@ -51,6 +55,12 @@ enum PeerError {
     NackWithMotiveReceived {
         nack_info: NackInfo
     },
+    #[fail(display = "Connection was refused with NACK")]
+    ConnectionRefused,
+    #[fail(display = "Remote peer failed proof of work check: {}", error)]
+    InvalidProofOfWork {
+        error: ProofOfWorkError
+    },
     #[fail(display = "Failed to create precomputed key")]
     FailedToPrecomputeKey,
     #[fail(display = "Network error: {}", message)]
@@ -119,15 +129,22 @@ pub struct Bootstrap {
     stream: Arc<Mutex<Option<TcpStream>>>,
     address: SocketAddr,
     incoming: bool,
+    /// Connection is refused after the handshake, remote peer receives these potential peers in the NACK
+    refuse_with: Option<Vec<String>>,
 }
 
 impl Bootstrap {
     pub fn incoming(stream: Arc<Mutex<Option<TcpStream>>>, address: SocketAddr) -> Self {
-        Bootstrap { stream, address, incoming: true }
+        Bootstrap { stream, address, incoming: true, refuse_with: None }
     }
 
     pub fn outgoing(stream: TcpStream, address: SocketAddr) -> Self {
-        Bootstrap { stream: Arc::new(Mutex::new(Some(stream))), address, incoming: false }
+        Bootstrap { stream: Arc::new(Mutex::new(Some(stream))), address, incoming: false, refuse_with: None }
+    }
+
+    /// Incoming connection, which is refused because we have too many connections.
+    pub fn refused(stream: Arc<Mutex<Option<TcpStream>>>, address: SocketAddr, potential_peers_to_connect: Vec<String>) -> Self {
+        Bootstrap { stream, address, incoming: true, refuse_with: Some(potential_peers_to_connect) }
     }
 }
 
@@ -165,8 +182,12 @@ pub struct Local {
     secret_key: String,
     /// proof of work
     proof_of_work_stamp: String,
-    /// version of network protocol
-    version: String,
+    /// supported versions of network protocol
+    supported_versions: Vec<Version>,
+    /// proof of work required from remote peers
+    expected_pow: f64,
+    /// private node does not want to be advertised to other peers
+    private_node: bool,
 }
 
 pub type PeerRef = ActorRef<PeerMsg>;
@@ -185,6 +206,8 @@ pub struct Peer {
     /// Msg storage
     msg_store: P2PMessageStorage,
     remote_addr: SocketAddr,
+    /// Bandwidth limits of the connection
+    bandwidth: PeerBandwidth,
 }
 
 impl Peer {
@@ -196,23 +219,28 @@ impl Peer {
                  secret_key: &str,
                  proof_of_work_stamp: &str,
                  version: &str,
+                 expected_pow: f64,
+                 private_node: bool,
                  tokio_executor: Handle,
                  socket_address: &SocketAddr,
-                 p2p_msg_store: P2PMessageStorage) -> Result<PeerRef, CreateError>
+                 p2p_msg_store: P2PMessageStorage,
+                 bandwidth: PeerBandwidth) -> Result<PeerRef, CreateError>
     {
         let info = Local {
             listener_port,
             proof_of_work_stamp: proof_of_work_stamp.into(),
             public_key: public_key.into(),
             secret_key: secret_key.into(),
-            version: version.into(),
+            supported_versions: supported_versions(version),
+            expected_pow,
+            private_node,
         };
-        let props = Props::new_args(Peer::new, (network_channel, Arc::new(info), tokio_executor, *socket_address, p2p_msg_store));
+        let props = Props::new_args(Peer::new, (network_channel, Arc::new(info), tokio_executor, *socket_address, p2p_msg_store, bandwidth));
         let actor_id = ACTOR_ID_GENERATOR.fetch_add(1, Ordering::SeqCst);
         sys.actor_of(props, &format!("peer-{}", actor_id))
     }
 
-    fn new((event_channel, info, tokio_executor, socket_address, msg_store): (NetworkChannelRef, Arc<Local>, Handle, SocketAddr, P2PMessageStorage)) -> Self {
+    fn new((event_channel, info, tokio_executor, socket_address, msg_store, bandwidth): (NetworkChannelRef, Arc<Local>, Handle, SocketAddr, P2PMessageStorage, PeerBandwidth)) -> Self {
         Peer {
             network_channel: event_channel,
             local: info,
@@ -223,7 +251,8 @@ impl Peer {
             },
             tokio_executor,
             msg_store,
-            remote_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
+            remote_addr: socket_address,
+            bandwidth,
         }
     }
 }
@@ -253,6 +282,7 @@ impl Receive<Bootstrap> for Peer {
         self.remote_addr = msg.address;
 
         let store = self.msg_store.clone();
+        let bandwidth = self.bandwidth.clone();
         self.tokio_executor.spawn(async move {
             async fn setup_net(net: &Network, tx: EncryptedMessageWriter) {
                 net.rx_run.store(true, Ordering::Release);
@@ -261,9 +291,11 @@ impl Receive<Bootstrap> for Peer {
 
             let peer_address = msg.address;
             debug!(system.log(), "Bootstrapping"; "ip" => &peer_address, "peer" => myself.name());
-            match bootstrap(msg, info, system.log(), store.clone()).await {
-                Ok(BootstrapOutput(rx, tx, public_key)) => {
-                    debug!(system.log(), "Bootstrap successful"; "ip" => &peer_address, "peer" => myself.name());
+            match bootstrap(msg, info, bandwidth, system.log(), store.clone()).await {
+                Ok(BootstrapOutput(rx, tx, public_key, peer_metadata)) => {
+                    let version = rx.version().clone();
+                    debug!(system.log(), "Bootstrap successful"; "ip" => &peer_address, "peer" => myself.name(),
+                                         "distributed_db_version" => version.distributed_db_version(), "p2p_version" => version.p2p_version());
                     setup_net(&net, tx).await;
 
                     let peer_id = HashType::CryptoboxPublicKeyHash.bytes_to_string(&public_key);
@@ -272,6 +304,9 @@ impl Receive<Bootstrap> for Peer {
                         msg: PeerBootstrapped::Success {
                             peer: myself.clone(),
                             peer_id: peer_id.clone(),
+                            peer_metadata,
+                            version,
+                            replayed: false,
                         }.into(),
                         topic: NetworkChannelTopic::NetworkEvents.into(),
                     }, Some(myself.clone().into()));
@@ -282,6 +317,10 @@ impl Receive<Bootstrap> for Peer {
                     // connection to peer was closed, stop this actor
                     system.stop(myself);
                 }
+                Err(PeerError::ConnectionRefused) => {
+                    debug!(system.log(), "Connection refused, too many connections"; "ip" => &peer_address, "peer" => myself.name());
+                    system.stop(myself);
+                }
                 Err(err) => {
                     info!(system.log(), "Connection to peer failed"; "reason" => &err, "ip" => &peer_address, "peer" => myself.name());
 
@@ -337,25 +376,30 @@ impl Receive<SendMessage> for Peer {
 }
 
 /// Output values of the successful bootstrap process
-struct BootstrapOutput(EncryptedMessageReader, EncryptedMessageWriter, PublicKey);
+struct BootstrapOutput(EncryptedMessageReader, EncryptedMessageWriter, PublicKey, MetadataMessage);
+
+/// Versions advertised to the remote peers for the chain
+pub fn supported_versions(chain_name: &str) -> Vec<Version> {
+    SUPPORTED_DISTRIBUTED_DB_VERSIONS.iter()
+        .map(|distributed_db_version| Version::new(chain_name.to_string(), *distributed_db_version, SUPPORTED_P2P_VERSION))
+        .collect()
+}
 
-async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P2PMessageStorage) -> Result<BootstrapOutput, PeerError> {
+async fn bootstrap(msg: Bootstrap, info: Arc<Local>, bandwidth: PeerBandwidth, log: Logger, mut storage: P2PMessageStorage) -> Result<BootstrapOutput, PeerError> {
     let addr = msg.address;
     let (mut msg_rx, mut msg_tx) = {
         let stream = msg.stream.lock().await.take().expect("Someone took ownership of the socket before the Peer");
-        let msg_reader: MessageStream = stream.into();
+        let msg_reader = MessageStream::new(stream, bandwidth);
         msg_reader.split()
     };
 
-    let supported_protocol_version = Version::new(info.version.clone(), SUPPORTED_DISTRIBUTED_DB_VERSION, SUPPORTED_P2P_VERSION);
-
     // send connection message
     let connection_message = ConnectionMessage::new(
         info.listener_port,
         &info.public_key,
         &info.proof_of_work_stamp,
         &Nonce::random().get_bytes(),
-        vec![supported_protocol_version.clone()]);
+        info.supported_versions.clone());
     let _ = storage.store_connection_message(&connection_message, false, addr);
     let connection_message_sent = {
         let connection_message_bytes = BinaryChunk::from_content(&connection_message.as_bytes()?)?;
@@ -372,15 +416,6 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
     };
     if let Ok(connection_message) = ConnectionMessage::from_bytes(received_connection_msg.content().to_vec()) {
         let _ = storage.store_connection_message(&connection_message, true, addr);
-
-        if !connection_message.versions.iter().any(|version| supported_protocol_version.supports(version)) {
-            return Err(
-                PeerError::UnsupportedProtocol {
-                    supported_version: format!("{:?}", &supported_protocol_version),
-                    incompatible_versions: format!("{:?}", &connection_message.versions()),
-                }
-            );
-        }
     }
 
     // generate local and remote nonce
@@ -392,6 +427,16 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
     let peer_id = HashType::CryptoboxPublicKeyHash.bytes_to_string(&peer_public_key);
     debug!(log, "Received peer public key"; "public_key" => &peer_id);
 
+    // like the proof of work, version is negotiated now, but incompatible peer is refused only with encrypted NACK
+    let negotiated_version = Version::negotiate(&info.supported_versions, received_connection_msg.versions());
+    // incompatible peer still has to understand the NACK, so it is encoded for the p2p version of the peer
+    let connection_version = negotiated_version.clone()
+        .unwrap_or_else(|| refusal_version(&info.supported_versions, received_connection_msg.versions()));
+
+    // identity of the remote peer is checked now, but the peer is refused only after the encrypted channel is established,
+    // because the NACK has to be encrypted
+    let proof_of_work = check_proof_of_work(peer_public_key, &received_connection_msg.proof_of_work_stamp, info.expected_pow);
+
     // pre-compute encryption key
     let precomputed_key = match precompute(&hex::encode(peer_public_key), &info.secret_key) {
         Ok(key) => key,
@@ -399,11 +444,11 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
     };
 
     // from now on all messages will be encrypted
-    let mut msg_tx = EncryptedMessageWriter::new(msg_tx, precomputed_key.clone(), nonce_local, peer_id.clone(), log.clone());
-    let mut msg_rx = EncryptedMessageReader::new(msg_rx, precomputed_key, nonce_remote, peer_id, log.clone());
+    let mut msg_tx = EncryptedMessageWriter::new(msg_tx, precomputed_key.clone(), nonce_local, peer_id.clone(), connection_version.clone(), log.clone());
+    let mut msg_rx = EncryptedMessageReader::new(msg_rx, precomputed_key, nonce_remote, peer_id, connection_version, log.clone());
 
     // send metadata
-    let metadata = MetadataMessage::new(false, false);
+    let metadata = MetadataMessage::new(false, info.private_node);
     let _ = storage.store_metadata_message(&metadata, false, addr);
     timeout(IO_TIMEOUT, msg_tx.write_message(&metadata)).await??;
 
@@ -412,6 +457,35 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
     let _ = storage.store_metadata_message(&metadata_received, true, addr);
     debug!(log, "Received remote peer metadata"; "disable_mempool" => metadata_received.disable_mempool(), "private_node" => metadata_received.private_node());
 
+    // refuse peer without sufficient proof of work
+    if let Err(error) = proof_of_work {
+        debug!(log, "Refusing peer with insufficient proof of work"; "reason" => format!("{}", error));
+        timeout(IO_TIMEOUT, msg_tx.write_nack(NackMotive::NoMotive, vec![])).await??;
+        return Err(PeerError::InvalidProofOfWork { error });
+    }
+
+    // refuse peer without any compatible version
+    if negotiated_version.is_none() {
+        let motive = if received_connection_msg.versions().iter().any(|version| info.supported_versions.iter().any(|supported| supported.chain_name() == version.chain_name())) {
+            NackMotive::DeprecatedDistributedDbVersion
+        } else {
+            NackMotive::UnknownChainName
+        };
+        debug!(log, "Refusing peer without compatible version"; "motive" => format!("{:?}", motive));
+        timeout(IO_TIMEOUT, msg_tx.write_nack(motive, vec![])).await??;
+        return Err(PeerError::UnsupportedProtocol {
+            supported_versions: format!("{:?}", &info.supported_versions),
+            incompatible_versions: format!("{:?}", received_connection_msg.versions()),
+        });
+    }
+
+    // refuse peer, when we have too many connections, but advertise other peers to it
+    if let Some(potential_peers_to_connect) = msg.refuse_with {
+        debug!(log, "Refusing peer, too many connections");
+        timeout(IO_TIMEOUT, msg_tx.write_nack(NackMotive::TooManyConnections, potential_peers_to_connect)).await??;
+        return Err(PeerError::ConnectionRefused);
+    }
+
     // send ack
     timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::Ack)).await??;
 
@@ -421,7 +495,7 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
     match ack_received {
         AckMessage::Ack => {
             debug!(log, "Received ACK");
-            Ok(BootstrapOutput(msg_rx, msg_tx, peer_public_key.clone()))
+            Ok(BootstrapOutput(msg_rx, msg_tx, peer_public_key.clone(), metadata_received))
         }
         AckMessage::NackV0 => {
             debug!(log, "Received NACK");
@@ -435,6 +509,15 @@ async fn bootstrap(msg: Bootstrap, info: Arc<Local>, log: Logger, mut storage: P
 }
 
 
+/// Version used to refuse the peer, which does not support any of our versions.
+///
+/// The p2p version is the lower one of the highest versions announced by both sides, like when the version is [negotiated](Version::negotiate).
+fn refusal_version(local_versions: &[Version], remote_versions: &[Version]) -> Version {
+    let local = local_versions.first().expect("No supported version");
+    let remote_p2p_version = remote_versions.iter().map(|version| version.p2p_version()).max().unwrap_or(0);
+    Version::new(local.chain_name().clone(), local.distributed_db_version(), std::cmp::min(local.p2p_version(), remote_p2p_version))
+}
+
 /// Generate nonces (sent and recv encoding must be with length bytes also)
 ///
 /// local_nonce is used for writing crypto messages to other peers
@@ -481,3 +564,24 @@ async fn begin_process_incoming(mut rx: EncryptedMessageReader, rx_run: Arc<Atom
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refusal_version_respects_peer_p2p_version() {
+        let local_versions = supported_versions("TEZOS_ALPHANET_2018-11-30T15:30:56Z");
+
+        // peer with p2p version 0 expects NackV0
+        let remote_versions = vec![Version::new("TEZOS_ZERONET".to_string(), 0, 0)];
+        assert_eq!(0, refusal_version(&local_versions, &remote_versions).p2p_version());
+
+        let remote_versions = vec![Version::new("TEZOS_ZERONET".to_string(), 0, 0), Version::new("TEZOS_ZERONET".to_string(), 1, 1)];
+        assert_eq!(1, refusal_version(&local_versions, &remote_versions).p2p_version());
+
+        // our p2p version is never exceeded
+        let remote_versions = vec![Version::new("TEZOS_ZERONET".to_string(), 0, SUPPORTED_P2P_VERSION + 1)];
+        assert_eq!(SUPPORTED_P2P_VERSION, refusal_version(&local_versions, &remote_versions).p2p_version());
+    }
+}
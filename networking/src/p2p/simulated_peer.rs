@@ -0,0 +1,506 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Simulated tezos peer for the integration tests of the shell.
+//!
+//! Simulated peer runs in its own thread with its own tokio runtime. It performs the real encrypted handshake
+//! over a local TCP socket, serves a [synthetic chain](SyntheticChain) and can [misbehave](Behaviour) on demand.
+//! Messages received from the node are recorded, so tests can assert what the node asked for.
+
+use std::convert::TryFrom;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use failure::{bail, Error};
+use futures::future;
+use futures::lock::Mutex;
+use slog::{debug, Discard, Logger, o, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{delay_for, timeout};
+
+use crypto::crypto_box::{precompute, random_keypair};
+use crypto::hash::{BlockHash, ChainId, HashType};
+use crypto::nonce::{self, Nonce, NoncePair};
+use crypto::proof_of_work::generate_proof_of_work;
+use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryMessage, MessageHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+use super::bandwidth::PeerBandwidth;
+use super::stream::{EncryptedMessageReader, EncryptedMessageWriter, MessageStream};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(6);
+/// How often is the simulated peer checking for the commands from the test
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Time between two synthetic blocks (seconds)
+const BLOCK_INTERVAL: i64 = 60;
+
+/// Behaviour of the simulated peer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Behaviour {
+    /// Answers all requests for the synthetic chain
+    Honest,
+    /// Receives requests, but never answers them
+    Silent,
+    /// Answers requests after the delay
+    Delayed(Duration),
+    /// Answers requests for operations with validation pass, which was not requested
+    WrongValidationPass,
+    /// Sends block header and operations of a block, which were not requested, right after the handshake
+    UnrequestedData,
+}
+
+/// Chain of synthetic blocks without operations, which follows the genesis block
+#[derive(Clone, Debug)]
+pub struct SyntheticChain {
+    chain_id: ChainId,
+    /// Blocks ordered by level
+    blocks: Vec<(BlockHash, BlockHeader)>,
+    validation_passes: u8,
+}
+
+impl SyntheticChain {
+    /// Create chain of `length` blocks on top of the genesis block
+    pub fn new(chain_id: ChainId, genesis_hash: &BlockHash, genesis: &BlockHeader, length: usize, validation_passes: u8) -> Self {
+        let mut blocks: Vec<(BlockHash, BlockHeader)> = Vec::with_capacity(length);
+        for _ in 0..length {
+            let block = match blocks.last() {
+                Some((hash, header)) => next_block(hash, header, validation_passes),
+                None => next_block(genesis_hash, genesis, validation_passes),
+            };
+            blocks.push(block);
+        }
+
+        SyntheticChain { chain_id, blocks, validation_passes }
+    }
+
+    #[inline]
+    pub fn chain_id(&self) -> &ChainId {
+        &self.chain_id
+    }
+
+    #[inline]
+    pub fn blocks(&self) -> &[(BlockHash, BlockHeader)] {
+        &self.blocks
+    }
+
+    #[inline]
+    pub fn head(&self) -> Option<&(BlockHash, BlockHeader)> {
+        self.blocks.last()
+    }
+
+    pub fn block_header(&self, block_hash: &BlockHash) -> Option<&BlockHeader> {
+        self.blocks.iter()
+            .find(|(hash, _)| hash == block_hash)
+            .map(|(_, header)| header)
+    }
+
+    /// Current branch with the head of the chain and without history
+    pub fn current_branch(&self) -> Option<CurrentBranchMessage> {
+        self.head()
+            .map(|(_, header)| CurrentBranchMessage::new(self.chain_id.clone(), CurrentBranch::new(header.clone(), vec![])))
+    }
+
+    /// Create responses for the request of the node
+    fn respond(&self, message: &PeerMessage, behaviour: &Behaviour) -> Vec<PeerMessage> {
+        match message {
+            PeerMessage::GetCurrentBranch(message) if message.chain_id == self.chain_id => {
+                self.current_branch()
+                    .map(PeerMessage::from)
+                    .into_iter()
+                    .collect()
+            }
+            PeerMessage::GetCurrentHead(message) if message.chain_id() == &self.chain_id => {
+                self.head()
+                    .map(|(_, header)| CurrentHeadMessage::new(self.chain_id.clone(), header.clone()).into())
+                    .into_iter()
+                    .collect()
+            }
+            PeerMessage::GetBlockHeaders(message) => {
+                message.get_block_headers().iter()
+                    .filter_map(|hash| self.block_header(hash))
+                    .map(|header| BlockHeaderMessage::from(header.clone()).into())
+                    .collect()
+            }
+            PeerMessage::GetOperationsForBlocks(message) => {
+                message.get_operations_for_blocks().iter()
+                    .filter(|operations| self.block_header(operations.hash()).is_some())
+                    .map(|operations| {
+                        let validation_pass = match behaviour {
+                            // validation pass out of the range of the block is never requested
+                            Behaviour::WrongValidationPass => self.validation_passes as i8,
+                            _ => operations.validation_pass(),
+                        };
+                        self.operations(operations.hash(), validation_pass).into()
+                    })
+                    .collect()
+            }
+            _ => vec![]
+        }
+    }
+
+    fn operations(&self, block_hash: &BlockHash, validation_pass: i8) -> OperationsForBlocksMessage {
+        OperationsForBlocksMessage::new(OperationsForBlock::new(block_hash.clone(), validation_pass), Path::Op, vec![])
+    }
+
+    /// Block header and all operations of the block following the head, which is never offered to the node
+    fn unrequested_data(&self) -> Vec<PeerMessage> {
+        match self.head() {
+            Some((head_hash, head)) => {
+                let (hash, header) = next_block(head_hash, head, self.validation_passes);
+                let mut messages: Vec<PeerMessage> = vec![BlockHeaderMessage::from(header).into()];
+                messages.extend((0..self.validation_passes).map(|validation_pass| PeerMessage::from(self.operations(&hash, validation_pass as i8))));
+                messages
+            }
+            None => vec![]
+        }
+    }
+}
+
+/// Create synthetic block on top of the `predecessor`
+fn next_block(predecessor_hash: &BlockHash, predecessor: &BlockHeader, validation_passes: u8) -> (BlockHash, BlockHeader) {
+    let level = predecessor.level() + 1;
+    let header = BlockHeaderBuilder::default()
+        .level(level)
+        .proto(predecessor.proto())
+        .predecessor(predecessor_hash.clone())
+        .timestamp(predecessor.timestamp() + BLOCK_INTERVAL)
+        .validation_pass(validation_passes)
+        .operations_hash(vec![0; 32])
+        .fitness(vec![vec![0], (level as u64).to_be_bytes().to_vec()])
+        .context(vec![0; 32])
+        .protocol_data(vec![])
+        .build()
+        .expect("Failed to build synthetic block header");
+    let hash = header.message_hash().expect("Failed to hash synthetic block header");
+    (hash, header)
+}
+
+/// Configuration of the simulated peer
+#[derive(Clone, Debug)]
+pub struct SimulatedPeerConfig {
+    /// Chain name sent in the connection message, it has to be supported by the node
+    pub chain_name: String,
+    pub chain: SyntheticChain,
+    pub behaviour: Behaviour,
+    /// Proof of work of the generated identity
+    pub expected_pow: f64,
+}
+
+impl SimulatedPeerConfig {
+    pub fn new(chain_name: &str, chain: SyntheticChain) -> Self {
+        SimulatedPeerConfig {
+            chain_name: chain_name.to_string(),
+            chain,
+            behaviour: Behaviour::Honest,
+            expected_pow: 0.0,
+        }
+    }
+
+    pub fn behaviour(mut self, behaviour: Behaviour) -> Self {
+        self.behaviour = behaviour;
+        self
+    }
+}
+
+/// Hex encoded identity of the simulated peer
+struct SimulatedIdentity {
+    public_key: String,
+    secret_key: String,
+    proof_of_work_stamp: String,
+}
+
+impl SimulatedIdentity {
+    fn generate(expected_pow: f64) -> Result<Self, Error> {
+        let (public_key, secret_key) = random_keypair();
+        let proof_of_work_stamp = generate_proof_of_work(public_key.as_bytes(), expected_pow, 1, |_| ())?;
+        Ok(SimulatedIdentity {
+            public_key: hex::encode(public_key.as_bytes()),
+            secret_key: hex::encode(secret_key.as_bytes()),
+            proof_of_work_stamp: hex::encode(proof_of_work_stamp),
+        })
+    }
+}
+
+/// State shared between the test and the thread of the simulated peer
+struct SharedState {
+    chain_name: String,
+    chain: SyntheticChain,
+    behaviour: StdMutex<Behaviour>,
+    received: StdMutex<Vec<PeerMessage>>,
+    outgoing: StdMutex<Vec<PeerMessage>>,
+    connected: AtomicBool,
+    connections: AtomicUsize,
+    disconnections: AtomicUsize,
+    disconnect: AtomicBool,
+    shutdown: AtomicBool,
+}
+
+impl SharedState {
+    fn behaviour(&self) -> Behaviour {
+        self.behaviour.lock().unwrap().clone()
+    }
+
+    fn is_running(&self) -> bool {
+        !self.shutdown.load(Ordering::Acquire)
+    }
+}
+
+/// Handle of the simulated peer, peer is shut down when the handle is dropped
+pub struct SimulatedPeer {
+    peer_id: String,
+    listener_address: Option<SocketAddr>,
+    state: Arc<SharedState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SimulatedPeer {
+    /// Start simulated peer, which accepts connections from the node on a random local port
+    pub fn listen(config: SimulatedPeerConfig, log: Option<Logger>) -> Result<Self, Error> {
+        let listener = StdTcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let listener_address = listener.local_addr()?;
+
+        Self::start(config, Some(listener_address), log, move |identity, state, log| async move {
+            let mut listener = match TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => return warn!(log, "Simulated peer failed to listen"; "reason" => format!("{}", e)),
+            };
+            while state.is_running() {
+                if let Ok(Ok((stream, _))) = timeout(POLL_INTERVAL, listener.accept()).await {
+                    run_connection(stream, true, &identity, &state, &log).await;
+                }
+            }
+        })
+    }
+
+    /// Start simulated peer, which connects to the node at `node_address`
+    pub fn connect(config: SimulatedPeerConfig, node_address: SocketAddr, log: Option<Logger>) -> Result<Self, Error> {
+        Self::start(config, None, log, move |identity, state, log| async move {
+            match timeout(IO_TIMEOUT, TcpStream::connect(node_address)).await {
+                Ok(Ok(stream)) => run_connection(stream, false, &identity, &state, &log).await,
+                _ => warn!(log, "Simulated peer failed to connect to the node"; "address" => node_address.to_string()),
+            }
+        })
+    }
+
+    fn start<F, R>(config: SimulatedPeerConfig, listener_address: Option<SocketAddr>, log: Option<Logger>, run: F) -> Result<Self, Error>
+        where
+            F: FnOnce(SimulatedIdentity, Arc<SharedState>, Logger) -> R + Send + 'static,
+            R: future::Future<Output=()>
+    {
+        let identity = SimulatedIdentity::generate(config.expected_pow)?;
+        let peer_id = HashType::CryptoboxPublicKeyHash.bytes_to_string(&hex::decode(&identity.public_key)?);
+        let log = log.unwrap_or_else(|| Logger::root(Discard, o!()))
+            .new(o!("simulated_peer" => peer_id.clone()));
+        let state = Arc::new(SharedState {
+            chain_name: config.chain_name,
+            chain: config.chain,
+            behaviour: StdMutex::new(config.behaviour),
+            received: StdMutex::new(vec![]),
+            outgoing: StdMutex::new(vec![]),
+            connected: AtomicBool::new(false),
+            connections: AtomicUsize::new(0),
+            disconnections: AtomicUsize::new(0),
+            disconnect: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let thread = {
+            let state = state.clone();
+            thread::spawn(move || {
+                let mut runtime = tokio::runtime::Builder::new()
+                    .basic_scheduler()
+                    .enable_all()
+                    .build()
+                    .expect("Failed to create tokio runtime of the simulated peer");
+                runtime.block_on(run(identity, state, log));
+            })
+        };
+
+        Ok(SimulatedPeer { peer_id, listener_address, state, thread: Some(thread) })
+    }
+
+    /// Peer id of the simulated peer as seen by the node
+    #[inline]
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Address, where the simulated peer accepts connections, `None` if the peer connects to the node
+    #[inline]
+    pub fn listener_address(&self) -> Option<SocketAddr> {
+        self.listener_address
+    }
+
+    /// Change behaviour of the simulated peer, it is used for all following messages
+    pub fn set_behaviour(&self, behaviour: Behaviour) {
+        *self.state.behaviour.lock().unwrap() = behaviour;
+    }
+
+    /// Send any message to the node, message is sent when the peer is connected
+    pub fn send(&self, message: PeerMessage) {
+        self.state.outgoing.lock().unwrap().push(message);
+    }
+
+    /// All messages received from the node
+    pub fn received_messages(&self) -> Vec<PeerMessage> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    /// Close the current connection to the node
+    pub fn disconnect(&self) {
+        self.state.disconnect.store(true, Ordering::Release);
+    }
+
+    /// Returns true if the handshake with the node was finished and the connection is still open
+    pub fn is_connected(&self) -> bool {
+        self.state.connected.load(Ordering::Acquire)
+    }
+
+    /// Number of the successfully established connections
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::Acquire)
+    }
+
+    /// Wait until the `condition` is met or the `timeout` elapses, returns true if the condition was met
+    pub fn wait_for<F: Fn(&SimulatedPeer) -> bool>(&self, timeout: Duration, condition: F) -> bool {
+        let started = Instant::now();
+        while !condition(self) {
+            if started.elapsed() > timeout {
+                return false;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        true
+    }
+
+    pub fn wait_for_connection(&self, timeout: Duration) -> bool {
+        self.wait_for(timeout, |peer| peer.is_connected())
+    }
+
+    /// Number of the established connections, which were closed by any side
+    pub fn disconnections(&self) -> usize {
+        self.state.disconnections.load(Ordering::Acquire)
+    }
+
+    /// Wait until the first established connection is closed
+    pub fn wait_for_disconnection(&self, timeout: Duration) -> bool {
+        self.wait_for(timeout, |peer| peer.disconnections() > 0)
+    }
+}
+
+impl Drop for SimulatedPeer {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Handshake with the node and serve it until the connection is closed
+async fn run_connection(stream: TcpStream, incoming: bool, identity: &SimulatedIdentity, state: &Arc<SharedState>, log: &Logger) {
+    let (rx, tx) = match handshake(stream, incoming, identity, &state.chain_name, log).await {
+        Ok(channel) => channel,
+        Err(e) => return warn!(log, "Simulated peer handshake failed"; "reason" => format!("{}", e)),
+    };
+    debug!(log, "Simulated peer connected"; "incoming" => incoming);
+    state.disconnect.store(false, Ordering::Release);
+    state.connected.store(true, Ordering::Release);
+    state.connections.fetch_add(1, Ordering::AcqRel);
+
+    let tx = Arc::new(Mutex::new(tx));
+    if state.behaviour() == Behaviour::UnrequestedData {
+        state.outgoing.lock().unwrap().extend(state.chain.unrequested_data());
+    }
+
+    // connection is closed by whichever side finishes first
+    let serve = Box::pin(serve_requests(rx, tx.clone(), state.clone()));
+    let control = Box::pin(control_connection(tx, state.clone()));
+    future::select(serve, control).await;
+
+    state.connected.store(false, Ordering::Release);
+    state.disconnections.fetch_add(1, Ordering::AcqRel);
+    debug!(log, "Simulated peer disconnected");
+}
+
+async fn handshake(stream: TcpStream, incoming: bool, identity: &SimulatedIdentity, chain_name: &str, log: &Logger) -> Result<(EncryptedMessageReader, EncryptedMessageWriter), Error> {
+    let (mut msg_rx, mut msg_tx) = MessageStream::new(stream, PeerBandwidth::default()).split();
+
+    // exchange connection messages
+    let version = Version::new(chain_name.to_string(), 0, 1);
+    let connection_message = ConnectionMessage::new(
+        0,
+        &identity.public_key,
+        &identity.proof_of_work_stamp,
+        &Nonce::random().get_bytes(),
+        vec![version.clone()]);
+    let connection_message_sent = BinaryChunk::from_content(&connection_message.as_bytes()?)?;
+    timeout(IO_TIMEOUT, msg_tx.write_message(&connection_message_sent)).await??;
+    let connection_message_received = timeout(IO_TIMEOUT, msg_rx.read_message()).await??;
+
+    let NoncePair { local: nonce_local, remote: nonce_remote } = nonce::generate_nonces(connection_message_sent.raw(), connection_message_received.raw(), incoming);
+    let connection_message_received = ConnectionMessage::try_from(connection_message_received)?;
+    let node_public_key = connection_message_received.public_key();
+    let node_peer_id = HashType::CryptoboxPublicKeyHash.bytes_to_string(node_public_key);
+    let precomputed_key = precompute(&hex::encode(node_public_key), &identity.secret_key)?;
+
+    // from now on all messages are encrypted
+    let mut msg_tx = EncryptedMessageWriter::new(msg_tx, precomputed_key.clone(), nonce_local, node_peer_id.clone(), version.clone(), log.clone());
+    let mut msg_rx = EncryptedMessageReader::new(msg_rx, precomputed_key, nonce_remote, node_peer_id, version, log.clone());
+
+    timeout(IO_TIMEOUT, msg_tx.write_message(&MetadataMessage::new(false, false))).await??;
+    timeout(IO_TIMEOUT, msg_rx.read_message::<MetadataMessage>()).await??;
+
+    timeout(IO_TIMEOUT, msg_tx.write_message(&AckMessage::Ack)).await??;
+    match timeout(IO_TIMEOUT, msg_rx.read_message::<AckMessage>()).await?? {
+        AckMessage::Ack => Ok((msg_rx, msg_tx)),
+        AckMessage::NackV0 => bail!("Node refused the connection"),
+        AckMessage::Nack(nack_info) => bail!("Node refused the connection: {:?}", nack_info),
+    }
+}
+
+/// Record and answer messages of the node until the connection is closed
+async fn serve_requests(mut rx: EncryptedMessageReader, tx: Arc<Mutex<EncryptedMessageWriter>>, state: Arc<SharedState>) {
+    while let Ok(received) = rx.read_message::<PeerMessageResponse>().await {
+        for message in received.messages() {
+            state.received.lock().unwrap().push(message.clone());
+
+            let behaviour = state.behaviour();
+            let responses = match behaviour {
+                Behaviour::Silent => continue,
+                Behaviour::Delayed(delay) => {
+                    delay_for(delay).await;
+                    state.chain.respond(message, &behaviour)
+                }
+                _ => state.chain.respond(message, &behaviour),
+            };
+
+            let mut tx = tx.lock().await;
+            for response in responses {
+                if tx.write_message(&PeerMessageResponse::from(response)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Send messages queued by the test and close the connection on request
+async fn control_connection(tx: Arc<Mutex<EncryptedMessageWriter>>, state: Arc<SharedState>) {
+    while state.is_running() && !state.disconnect.load(Ordering::Acquire) {
+        let outgoing = std::mem::take(&mut *state.outgoing.lock().unwrap());
+        if !outgoing.is_empty() {
+            let mut tx = tx.lock().await;
+            for message in outgoing {
+                if tx.write_message(&PeerMessageResponse::from(message)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        delay_for(POLL_INTERVAL).await;
+    }
+}
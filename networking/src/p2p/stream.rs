@@ -19,7 +19,10 @@ use crypto::crypto_box::{CryptoError, decrypt, encrypt, PrecomputedKey};
 use crypto::nonce::Nonce;
 use tezos_encoding::binary_reader::BinaryReaderError;
 use tezos_messages::p2p::binary_message::{BinaryChunk, BinaryChunkError, BinaryMessage, CONTENT_LENGTH_FIELD_BYTES};
+use tezos_messages::p2p::encoding::ack::{AckMessage, NackInfo, NackMotive};
+use tezos_messages::p2p::encoding::version::Version;
 
+use crate::p2p::bandwidth::{PeerBandwidth, Throttle};
 use crate::p2p::peer::PeerId;
 
 /// Max allowed content length in bytes when taking into account extra data added by encryption
@@ -89,11 +92,12 @@ pub struct MessageStream {
 }
 
 impl MessageStream {
-    fn new(stream: TcpStream) -> MessageStream {
+    /// Create message stream, which is throttled by the bandwidth limits of the peer
+    pub fn new(stream: TcpStream, bandwidth: PeerBandwidth) -> MessageStream {
         let (rx, tx) = tokio::io::split(stream);
         MessageStream {
-            reader: MessageReader { stream: rx },
-            writer: MessageWriter { stream: tx },
+            reader: MessageReader { stream: rx, throttle: bandwidth.download().clone() },
+            writer: MessageWriter { stream: tx, throttle: bandwidth.upload().clone() },
         }
     }
 
@@ -105,14 +109,16 @@ impl MessageStream {
 
 impl From<TcpStream> for MessageStream {
     fn from(stream: TcpStream) -> Self {
-        MessageStream::new(stream)
+        MessageStream::new(stream, PeerBandwidth::default())
     }
 }
 
 /// Reader of the TCP/IP connection.
 pub struct MessageReader {
     /// reader part or the TCP/IP network stream
-    stream: ReadHalf<TcpStream>
+    stream: ReadHalf<TcpStream>,
+    /// Download bandwidth limiting
+    throttle: Throttle,
 }
 
 impl MessageReader {
@@ -131,6 +137,9 @@ impl MessageReader {
         self.stream.read_exact(&mut msg_content_bytes).await?;
         all_recv_bytes.extend(&msg_content_bytes);
 
+        // next message is not read until the bandwidth limit allows it, TCP flow control slows down the remote peer meanwhile
+        self.throttle.transfer(all_recv_bytes.len()).await;
+
         Ok(all_recv_bytes.try_into()?)
     }
 
@@ -144,7 +153,9 @@ impl MessageReader {
 }
 
 pub struct MessageWriter {
-    stream: WriteHalf<TcpStream>
+    stream: WriteHalf<TcpStream>,
+    /// Upload bandwidth limiting
+    throttle: Throttle,
 }
 
 impl MessageWriter {
@@ -157,6 +168,7 @@ impl MessageWriter {
     /// message is returned as a result.
     #[inline]
     pub async fn write_message(&mut self, bytes: &BinaryChunk) -> Result<(), StreamError> {
+        self.throttle.transfer(bytes.raw().len()).await;
         Ok(self.stream.write_all(bytes.raw()).await?)
     }
 }
@@ -171,14 +183,26 @@ pub struct EncryptedMessageWriter {
     nonce_local: Nonce,
     /// Outgoing message writer
     tx: MessageWriter,
+    /// Version negotiated with the peer, it determines how are messages encoded
+    version: Version,
     /// Logger
     log: Logger,
 }
 
 impl EncryptedMessageWriter {
-    pub fn new(tx: MessageWriter, precomputed_key: PrecomputedKey, nonce_local: Nonce, peer_id: PeerId, log: Logger) -> Self {
+    pub fn new(tx: MessageWriter, precomputed_key: PrecomputedKey, nonce_local: Nonce, peer_id: PeerId, version: Version, log: Logger) -> Self {
         let log = log.new(o!("peer" => peer_id));
-        EncryptedMessageWriter { tx, precomputed_key, nonce_local, log }
+        EncryptedMessageWriter { tx, precomputed_key, nonce_local, version, log }
+    }
+
+    /// Refuse the connection. Peer with p2p version 0 does not understand NACK with motive, so it receives `NackV0`.
+    pub async fn write_nack(&mut self, motive: NackMotive, potential_peers_to_connect: Vec<String>) -> Result<(), StreamError> {
+        let nack = if self.version.p2p_version() == 0 {
+            AckMessage::NackV0
+        } else {
+            AckMessage::Nack(NackInfo::new(motive, potential_peers_to_connect))
+        };
+        self.write_message(&nack).await
     }
 
     pub async fn write_message<'a>(&'a mut self, message: &'a impl BinaryMessage) -> Result<(), StreamError> {
@@ -217,15 +241,22 @@ pub struct EncryptedMessageReader {
     nonce_remote: Nonce,
     /// Incoming message reader
     rx: MessageReader,
+    /// Version negotiated with the peer, it determines how are messages encoded
+    version: Version,
     /// Logger
     log: Logger,
 }
 
 impl EncryptedMessageReader {
     /// Create new encrypted message from async reader and peer data
-    pub fn new(rx: MessageReader, precomputed_key: PrecomputedKey, nonce_remote: Nonce, peer_id: PeerId, log: Logger) -> Self {
+    pub fn new(rx: MessageReader, precomputed_key: PrecomputedKey, nonce_remote: Nonce, peer_id: PeerId, version: Version, log: Logger) -> Self {
         let log = log.new(o!("peer" => peer_id));
-        EncryptedMessageReader { rx, precomputed_key, nonce_remote, log }
+        EncryptedMessageReader { rx, precomputed_key, nonce_remote, version, log }
+    }
+
+    #[inline]
+    pub fn version(&self) -> &Version {
+        &self.version
     }
 
     /// Consume content of inner message reader into specific message
@@ -10,6 +10,7 @@ use slog::{Logger, warn};
 use tokio::runtime::Handle;
 
 use crypto::hash::ChainId;
+use shell::peer_reputation::BanPolicy;
 use shell::shell_channel::{BlockApplied, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
 use storage::persistent::PersistentStorage;
 use storage::StorageInitInfo;
@@ -52,7 +53,8 @@ impl RpcServer {
         rpc_listen_address: SocketAddr,
         tokio_executor: &Handle,
         persistent_storage: &PersistentStorage,
-        init_storage_data: &StorageInitInfo) -> Result<RpcServerRef, CreateError> {
+        init_storage_data: &StorageInitInfo,
+        ban_policy: BanPolicy) -> Result<RpcServerRef, CreateError> {
 
         // TODO: refactor - call load_current_head in pre_start
         let shared_state = Arc::new(RwLock::new(RpcCollectedState {
@@ -66,7 +68,7 @@ impl RpcServer {
 
         // spawn RPC JSON server
         {
-            let env = RpcServiceEnvironment::new(sys.clone(), actor_ref.clone(), persistent_storage, &init_storage_data.genesis_block_header_hash, shared_state, sys.log());
+            let env = RpcServiceEnvironment::new(sys.clone(), actor_ref.clone(), persistent_storage, &init_storage_data.genesis_block_header_hash, shared_state, ban_policy, sys.log());
             let inner_log = sys.log();
 
             tokio_executor.spawn(async move {
@@ -100,15 +102,11 @@ impl Receive<ShellChannelMsg> for RpcServer {
 
     fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
         match msg {
-            ShellChannelMsg::BlockApplied(block) => {
+            ShellChannelMsg::NewCurrentHead(head) => {
                 let current_head_ref = &mut *self.state.write().unwrap();
-                match &mut current_head_ref.current_head {
-                    Some(current_head) => {
-                        if current_head.header().header.level() < block.header().header.level() {
-                            *current_head = block;
-                        }
-                    }
-                    None => current_head_ref.current_head = Some(block)
+                // head of the test chain is not served by RPC
+                if head.chain_id() == &current_head_ref.chain_id {
+                    current_head_ref.current_head = Some(BlockApplied::new(head.header().clone(), head.json_data().clone()));
                 }
             }
             _ => (/* Not yet implemented, do nothing */),
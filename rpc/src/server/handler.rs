@@ -55,8 +55,8 @@ pub async fn active_chains(_: Request<Body>, _: Params, _: Query, _: RpcServiceE
     empty()
 }
 
-pub async fn protocols(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnvironment) -> HResult {
-    empty()
+pub async fn protocols(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    result_to_json_response(service::get_stored_protocols(env.persistent_storage()), env.log())
 }
 
 pub async fn valid_blocks(_: Request<Body>, _: Params, _: Query, _: RpcServiceEnvironment) -> HResult {
@@ -186,3 +186,29 @@ pub async fn votes_listings(_: Request<Body>, params: Params, _: Query, env: Rpc
 
     result_to_json_response(services::protocol::get_votes_listings(chain_id, block_id, env.persistent_storage(), env.persistent_storage().context_storage(), env.state()), env.log())
 }
+
+pub async fn network_bans(_: Request<Body>, _: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    result_to_json_response(service::get_peer_bans(env.persistent_storage()), env.log())
+}
+
+pub async fn network_peer_ban(_: Request<Body>, params: Params, query: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let peer_id = params.get_str("peer_id").unwrap();
+    let duration = query.get_u64("duration");
+    result_to_json_response(service::peer_id_ban_key(peer_id).and_then(|key| service::ban_peer(key, duration, env.ban_policy(), env.persistent_storage())), env.log())
+}
+
+pub async fn network_peer_unban(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let peer_id = params.get_str("peer_id").unwrap();
+    result_option_to_json_response(service::peer_id_ban_key(peer_id).and_then(|key| service::unban_peer(key, env.persistent_storage())), env.log())
+}
+
+pub async fn network_point_ban(_: Request<Body>, params: Params, query: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let point = params.get_str("point").unwrap();
+    let duration = query.get_u64("duration");
+    result_to_json_response(service::point_ban_key(point).and_then(|key| service::ban_peer(key, duration, env.ban_policy(), env.persistent_storage())), env.log())
+}
+
+pub async fn network_point_unban(_: Request<Body>, params: Params, _: Query, env: RpcServiceEnvironment) -> ServiceResult {
+    let point = params.get_str("point").unwrap();
+    result_option_to_json_response(service::point_ban_key(point).and_then(|key| service::unban_peer(key, env.persistent_storage())), env.log())
+}
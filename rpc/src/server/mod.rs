@@ -14,6 +14,7 @@ use riker::actors::ActorSystem;
 use slog::Logger;
 
 use crypto::hash::{BlockHash, HashType};
+use shell::peer_reputation::BanPolicy;
 use storage::persistent::PersistentStorage;
 
 use crate::empty;
@@ -39,12 +40,14 @@ pub struct RpcServiceEnvironment {
     #[get = "pub(crate)"]
     state: RpcCollectedStateRef,
     #[get = "pub(crate)"]
+    ban_policy: BanPolicy,
+    #[get = "pub(crate)"]
     log: Logger,
 }
 
 impl RpcServiceEnvironment {
-    pub fn new(sys: ActorSystem, actor: RpcServerRef, persistent_storage: &PersistentStorage, genesis_hash: &BlockHash, state: RpcCollectedStateRef, log: Logger) -> Self {
-        Self { sys, actor, persistent_storage: persistent_storage.clone(), genesis_hash: HashType::BlockHash.bytes_to_string(genesis_hash), state, log }
+    pub fn new(sys: ActorSystem, actor: RpcServerRef, persistent_storage: &PersistentStorage, genesis_hash: &BlockHash, state: RpcCollectedStateRef, ban_policy: BanPolicy, log: Logger) -> Self {
+        Self { sys, actor, persistent_storage: persistent_storage.clone(), genesis_hash: HashType::BlockHash.bytes_to_string(genesis_hash), state, ban_policy, log }
     }
 }
 
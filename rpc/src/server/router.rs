@@ -29,6 +29,11 @@ pub(crate) fn create_routes() -> PathTree<Handler> {
     routes.handle("/chains/:chain_id/blocks/:block_id/helpers/baking_rights", handler::baking_rights);
     routes.handle("/chains/:chain_id/blocks/:block_id/helpers/endorsing_rights", handler::endorsing_rights);
     routes.handle("/chains/:chain_id/blocks/:block_id/votes/listings", handler::votes_listings);
+    routes.handle("/network/bans", handler::network_bans);
+    routes.handle("/network/peers/:peer_id/ban", handler::network_peer_ban);
+    routes.handle("/network/peers/:peer_id/unban", handler::network_peer_unban);
+    routes.handle("/network/points/:point/ban", handler::network_point_ban);
+    routes.handle("/network/points/:point/unban", handler::network_point_unban);
 
     // Tezedge dev and support rpc
     routes.handle("/dev/chains/main/blocks", dev_handler::dev_blocks);
@@ -2,14 +2,18 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::{HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
+use chrono::Utc;
 use failure::bail;
 use serde::{Deserialize, Serialize};
 
-use crypto::hash::{chain_id_to_b58_string};
+use crypto::hash::{chain_id_to_b58_string, HashType};
+use shell::peer_reputation::BanPolicy;
 use shell::shell_channel::BlockApplied;
 use shell::stats::memory::{Memory, MemoryData, MemoryStatsResult};
-use storage::{BlockHeaderWithHash, BlockStorage, BlockStorageReader, ContextActionRecordValue, ContextActionStorage};
+use storage::{BanKey, BlockHeaderWithHash, BlockStorage, BlockStorageReader, ContextActionRecordValue, ContextActionStorage, PeerBan, PeerBanStorage, ProtocolStorage, ProtocolStorageReader};
 use storage::block_storage::BlockJsonData;
 use storage::p2p_message_storage::P2PMessageStorage;
 use storage::p2p_message_storage::rpc_message::P2PRpcMessage;
@@ -17,6 +21,7 @@ use storage::persistent::PersistentStorage;
 use storage::skip_list::Bucket;
 use tezos_context::channel::ContextAction;
 use tezos_messages::protocol::RpcJsonMap;
+use tezos_messages::ts_to_rfc3339;
 
 use crate::ContextList;
 use crate::helpers::{BlockHeaderInfo, FullBlockInfo, get_block_hash_by_block_id, get_context_protocol_params, PagedResult};
@@ -43,6 +48,36 @@ pub struct CycleJson {
     random_seed: Option<String>,
 }
 
+/// Ban of the IP address or peer id
+#[derive(Serialize, Debug)]
+pub struct PeerBanInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_id: Option<String>,
+    banned_until: String,
+    active: bool,
+    ban_count: u32,
+    reason: String,
+}
+
+impl PeerBanInfo {
+    fn new(key: BanKey, ban: PeerBan, now: i64) -> Self {
+        let (ip, peer_id) = match key {
+            BanKey::Ip(ip) => (Some(ip.to_string()), None),
+            BanKey::PeerId(peer_id) => (None, Some(peer_id)),
+        };
+        PeerBanInfo {
+            ip,
+            peer_id,
+            banned_until: ts_to_rfc3339(ban.banned_until),
+            active: ban.is_active(now),
+            ban_count: ban.ban_count,
+            reason: ban.reason,
+        }
+    }
+}
+
 /// Retrieve blocks from database.
 pub(crate) fn get_blocks(every_nth_level: Option<i32>, block_id: &str, limit: usize, persistent_storage: &PersistentStorage, state: &RpcCollectedStateRef) -> Result<Vec<FullBlockInfo>, failure::Error> {
     let block_storage = BlockStorage::new(persistent_storage);
@@ -376,4 +411,59 @@ fn map_header_and_json_to_block_header_info(header: BlockHeaderWithHash, json_da
     BlockHeaderInfo::new(&BlockApplied::new(header, json_data), &chain_id)
 }
 
+/// Retrieve hashes of all protocols stored by the node
+pub(crate) fn get_stored_protocols(persistent_storage: &PersistentStorage) -> Result<Vec<String>, failure::Error> {
+    Ok(ProtocolStorage::new(persistent_storage).get_protocol_hashes()?
+        .iter()
+        .map(|protocol_hash| HashType::ProtocolHash.bytes_to_string(protocol_hash))
+        .collect())
+}
+
+/// Retrieve bans of all IP addresses and peer ids, including already expired bans
+pub(crate) fn get_peer_bans(persistent_storage: &PersistentStorage) -> Result<Vec<PeerBanInfo>, failure::Error> {
+    let now = Utc::now().timestamp();
+    Ok(PeerBanStorage::new(persistent_storage).get_all()?
+        .into_iter()
+        .map(|(key, ban)| PeerBanInfo::new(key, ban, now))
+        .collect())
+}
+
+/// Ban IP address or peer id. If `duration` (in seconds) is not provided, it is resolved from the count of previous bans.
+pub(crate) fn ban_peer(key: BanKey, duration: Option<u64>, ban_policy: &BanPolicy, persistent_storage: &PersistentStorage) -> Result<PeerBanInfo, failure::Error> {
+    let mut peer_ban_storage = PeerBanStorage::new(persistent_storage);
+    let now = Utc::now().timestamp();
+    let ban_count = peer_ban_storage.get(&key)?.map(|ban| ban.ban_count).unwrap_or(0) + 1;
+    let duration = duration.map(Duration::from_secs).unwrap_or_else(|| ban_policy.ban_duration(ban_count));
+    let ban = PeerBan {
+        banned_until: now + duration.as_secs() as i64,
+        ban_count,
+        reason: "Banned via RPC".to_string(),
+    };
+    peer_ban_storage.put(&key, &ban)?;
+    Ok(PeerBanInfo::new(key, ban, now))
+}
+
+/// Lift the ban of IP address or peer id
+pub(crate) fn unban_peer(key: BanKey, persistent_storage: &PersistentStorage) -> Result<Option<PeerBanInfo>, failure::Error> {
+    let mut peer_ban_storage = PeerBanStorage::new(persistent_storage);
+    let now = Utc::now().timestamp();
+    peer_ban_storage.unban(&key, now)?;
+    Ok(peer_ban_storage.get(&key)?.map(|ban| PeerBanInfo::new(key, ban, now)))
+}
 
+/// Create ban key from the peer id (crypto box public key hash)
+pub(crate) fn peer_id_ban_key(peer_id: &str) -> Result<BanKey, failure::Error> {
+    HashType::CryptoboxPublicKeyHash.string_to_bytes(peer_id)?;
+    Ok(BanKey::PeerId(peer_id.to_string()))
+}
+
+/// Create ban key from the point, which is IP address with optional port
+pub(crate) fn point_ban_key(point: &str) -> Result<BanKey, failure::Error> {
+    match point.parse::<IpAddr>() {
+        Ok(ip) => Ok(BanKey::Ip(ip)),
+        Err(_) => match point.parse::<SocketAddr>() {
+            Ok(address) => Ok(BanKey::Ip(address.ip())),
+            Err(_) => bail!("Invalid point: {}", point),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Block locator is a compact representation of the chain history, which is sent to the peers in the `CurrentBranch` message.
+//!
+//! Follows semantics of the tezos `Block_locator`: history contains predecessors of the head, ordered from the newest to the oldest.
+//! Steps between history blocks are drawn in sequences of 10. Steps of the first sequence have length 1 (consecutive blocks),
+//! base step length is doubled with every next sequence and a random gap is applied to every step.
+//! Random generator is seeded by peer ids of the sender and receiver and by the head hash, so the receiver
+//! can compute levels of all history blocks.
+
+use std::cmp;
+
+use sha2::{Digest, Sha256};
+
+use crypto::hash::{BlockHash, CryptoboxPublicKeyHash};
+
+/// Seed of the random generator of the history steps
+#[derive(Clone, Debug)]
+pub struct Seed {
+    /// Peer id of the node, which sends the locator
+    sender_id: CryptoboxPublicKeyHash,
+    /// Peer id of the node, which receives the locator
+    receiver_id: CryptoboxPublicKeyHash,
+}
+
+impl Seed {
+    pub fn new(sender_id: &CryptoboxPublicKeyHash, receiver_id: &CryptoboxPublicKeyHash) -> Self {
+        Seed {
+            sender_id: sender_id.clone(),
+            receiver_id: receiver_id.clone(),
+        }
+    }
+}
+
+/// Deterministic generator of the steps between history blocks
+struct Step {
+    step: i32,
+    counter: u8,
+    random_state: Vec<u8>,
+}
+
+impl Step {
+    fn new(seed: &Seed, head: &BlockHash) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.input(&seed.sender_id);
+        hasher.input(&seed.receiver_id);
+        hasher.input(head);
+        Step {
+            step: 1,
+            counter: 9,
+            random_state: hasher.result().to_vec(),
+        }
+    }
+
+    fn next(&mut self) -> i32 {
+        let random_gap = if self.step <= 1 {
+            0
+        } else {
+            self.draw(self.step / 2 + 1)
+        };
+        let step = self.step;
+        if self.counter == 0 {
+            self.step = self.step.saturating_mul(2);
+            self.counter = 9;
+        } else {
+            self.counter -= 1;
+        }
+        step - random_gap
+    }
+
+    fn draw(&mut self, n: i32) -> i32 {
+        let value = i32::from_be_bytes([self.random_state[0], self.random_state[1], self.random_state[2], self.random_state[3]]) % n;
+        self.random_state = Sha256::digest(&self.random_state).to_vec();
+        value
+    }
+}
+
+/// Compute history of the block locator for the `head`.
+///
+/// `find_predecessor` returns predecessor of the block in the requested distance together with the distance.
+/// If the chain is not long enough, it returns the oldest known ancestor (caboose) and the actual distance to it.
+pub fn compute_history<F, E>(head: &BlockHash, size: usize, seed: &Seed, mut find_predecessor: F) -> Result<Vec<BlockHash>, E>
+    where
+        F: FnMut(&BlockHash, i32) -> Result<(BlockHash, i32), E>
+{
+    let mut step = Step::new(seed, head);
+    let mut history = Vec::with_capacity(size);
+    let mut current = head.clone();
+
+    while history.len() < size {
+        let distance = step.next();
+        let (predecessor, actual_distance) = find_predecessor(&current, distance)?;
+        if actual_distance <= 0 {
+            // current block is the caboose
+            break;
+        }
+        history.push(predecessor.clone());
+        if actual_distance < distance {
+            // caboose was reached
+            break;
+        }
+        current = predecessor;
+    }
+
+    Ok(history)
+}
+
+/// Compute levels of the history blocks received in the block locator of the `head`.
+pub fn history_levels(head: &BlockHash, head_level: i32, history_len: usize, seed: &Seed) -> Vec<i32> {
+    let mut step = Step::new(seed, head);
+    let mut level = head_level;
+    (0..history_len)
+        .map(|_| {
+            // history can end with the genesis block, which is closer than the step
+            level = cmp::max(0, level - step.next());
+            level
+        })
+        .collect()
+}
+
+/// Find index of the newest history block, which is already known.
+///
+/// History is ordered from the newest to the oldest block, so all blocks older than a known block are
+/// expected to be known (or at least scheduled for download) too, which allows us to bisect the history.
+pub fn find_common_ancestor<F, E>(history: &[BlockHash], mut is_known: F) -> Result<Option<usize>, E>
+    where
+        F: FnMut(&BlockHash) -> Result<bool, E>
+{
+    // all blocks before `low` are unknown, all blocks from `high` are known
+    let (mut low, mut high) = (0, history.len());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if is_known(&history[mid])? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    Ok(if low < history.len() { Some(low) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Hash of the block in the linear test chain is its level
+    fn block_hash(level: i32) -> BlockHash {
+        level.to_be_bytes().to_vec()
+    }
+
+    fn block_level(block_hash: &BlockHash) -> i32 {
+        i32::from_be_bytes([block_hash[0], block_hash[1], block_hash[2], block_hash[3]])
+    }
+
+    fn seed() -> Seed {
+        Seed::new(&vec![1; 16], &vec![2; 16])
+    }
+
+    fn find_predecessor(block_hash: &BlockHash, distance: i32) -> Result<(BlockHash, i32), Infallible> {
+        let level = block_level(block_hash);
+        let actual_distance = cmp::min(level, distance);
+        Ok((self::block_hash(level - actual_distance), actual_distance))
+    }
+
+    #[test]
+    fn test_steps_grow_exponentially() {
+        let mut step = Step::new(&seed(), &block_hash(1000));
+        let steps = (0..40).map(|_| step.next()).collect::<Vec<_>>();
+        assert!(steps[0..10].iter().all(|&step| step == 1));
+        // random gap is signed, so the step can be also longer
+        assert!(steps[10..20].iter().all(|&step| step >= 1 && step <= 3));
+        assert!(steps[20..30].iter().all(|&step| step >= 2 && step <= 6));
+        assert!(steps[30..40].iter().all(|&step| step >= 4 && step <= 12));
+    }
+
+    #[test]
+    fn test_history_levels_match_computed_history() {
+        let head = block_hash(100_000);
+        let history = compute_history(&head, 200, &seed(), find_predecessor).unwrap();
+        let levels = history_levels(&head, 100_000, history.len(), &seed());
+
+        assert_eq!(history.iter().map(block_level).collect::<Vec<_>>(), levels);
+        // history ends with genesis
+        assert_eq!(Some(&0), levels.last());
+        // different seed gives different history
+        let other_history = compute_history(&head, 200, &Seed::new(&vec![2; 16], &vec![1; 16]), find_predecessor).unwrap();
+        assert_ne!(history, other_history);
+    }
+
+    #[test]
+    fn test_history_of_genesis_is_empty() {
+        assert!(compute_history(&block_hash(0), 200, &seed(), find_predecessor).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_common_ancestor() {
+        let history = (0..50).rev().map(block_hash).collect::<Vec<_>>();
+
+        let ancestor = find_common_ancestor(&history, |block_hash| Ok::<_, Infallible>(block_level(block_hash) <= 20)).unwrap();
+        assert_eq!(Some(29), ancestor);
+        assert_eq!(20, block_level(&history[ancestor.unwrap()]));
+
+        assert_eq!(Some(0), find_common_ancestor(&history, |_| Ok::<_, Infallible>(true)).unwrap());
+        assert_eq!(None, find_common_ancestor(&history, |_| Ok::<_, Infallible>(false)).unwrap());
+    }
+}
@@ -4,7 +4,15 @@
 //! Sends blocks to the `protocol_runner`.
 //! This actor is responsible for correct applying of blocks with Tezos protocol in context
 //! This actor is aslo responsible for correct initialization of genesis in storage.
-
+//!
+//! Every block, which predecessor is already applied, is applied (so blocks from forks are applied too).
+//! Current head is then selected by comparing fitness of the applied block with the current head.
+//!
+//! Blocks of the test chain are applied on top of the test chain genesis, which is stored when the test chain is forked.
+//! Test chain has its own current head, so it never competes with the main chain.
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
@@ -13,16 +21,22 @@ use std::time::Duration;
 
 use failure::{Error, Fail};
 use riker::actors::*;
-use slog::{debug, Logger, warn, trace};
+use slog::{debug, info, Logger, trace, warn};
 
-use crypto::hash::{BlockHash, HashType};
-use storage::{BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, initialize_storage_with_genesis_block, OperationsMetaStorage, OperationsStorage, OperationsStorageReader, StorageError, StorageInitInfo, store_applied_block_result, store_commit_genesis_result};
+use crypto::hash::{BlockHash, ChainId, HashType};
+use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, initialize_storage_with_genesis_block, OperationsMetaStorage, OperationsStorage, OperationsStorageReader, StorageError, StorageInitInfo, store_applied_block_result, store_commit_genesis_result, store_test_chain_genesis};
+use storage::block_meta_storage::Meta;
+use storage::block_storage::BlockJsonData;
 use storage::persistent::PersistentStorage;
 use tezos_api::environment::TezosEnvironmentConfiguration;
+use tezos_messages::base::fitness::fitness_compare;
 use tezos_wrapper::service::{IpcCmdServer, ProtocolController, ProtocolServiceError};
 
-use crate::shell_channel::{BlockApplied, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use crate::context_verifier::SharedContextHashVerifier;
+use crate::shell_channel::{BlockApplied, ChainReorganized, NewCurrentHead, ShellChannelMsg, ShellChannelRef, ShellChannelTopic, TestChainForked};
+use crate::test_chain::{TestChain, TestChainStatus};
 use crate::subscription::subscribe_to_shell_events;
+use crate::validation::BlockHeaderValidator;
 
 /// This command triggers feeding of completed blocks to the tezos protocol
 #[derive(Clone, Debug)]
@@ -30,6 +44,10 @@ pub struct FeedChainToProtocol;
 
 type SharedJoinHandle = Arc<Mutex<Option<JoinHandle<Result<(), Error>>>>>;
 
+/// Blocks which could become applicable (e.g. all operations were just received).
+/// Actor is pushing blocks to the queue and block applier thread is consuming them.
+type SharedBlockQueue = Arc<Mutex<VecDeque<BlockHash>>>;
+
 /// Feeds blocks and operations to the tezos protocol (ocaml code).
 #[actor(FeedChainToProtocol, ShellChannelMsg)]
 pub struct ChainFeeder {
@@ -39,6 +57,8 @@ pub struct ChainFeeder {
     block_applier_run: Arc<AtomicBool>,
     /// Block applier thread
     block_applier_thread: SharedJoinHandle,
+    /// Blocks which should be checked by the block applier thread
+    block_queue: SharedBlockQueue,
 }
 
 /// Reference to [chain feeder](ChainFeeder) actor
@@ -53,6 +73,7 @@ impl ChainFeeder {
     /// This actor spawns a new thread in which it will periodically monitor [`persistent_storage`](PersistentStorage).
     /// Purpose of the monitoring thread is to detect whether it is possible to apply blocks received by the p2p layer.
     /// If the block can be applied, it is sent via IPC to the `protocol_runner`, where it is then applied by calling a tezos ffi.
+    /// Commit info of the applied blocks is passed to the [`context_hash_verifier`](SharedContextHashVerifier), if provided.
     pub fn actor(
         sys: &impl ActorRefFactory,
         shell_channel: ShellChannelRef,
@@ -60,10 +81,13 @@ impl ChainFeeder {
         init_storage_data: &StorageInitInfo,
         tezos_env: &TezosEnvironmentConfiguration,
         ipc_server: IpcCmdServer,
+        context_hash_verifier: Option<SharedContextHashVerifier>,
         log: Logger) -> Result<ChainFeederRef, CreateError> {
         let apply_block_run = Arc::new(AtomicBool::new(true));
+        let block_queue = Arc::new(Mutex::new(VecDeque::new()));
         let block_applier_thread = {
             let apply_block_run = apply_block_run.clone();
+            let block_queue = block_queue.clone();
             let shell_channel = shell_channel.clone();
             let persistent_storage = persistent_storage.clone();
             let init_storage_data = init_storage_data.clone();
@@ -79,7 +103,7 @@ impl ChainFeeder {
                 while apply_block_run.load(Ordering::Acquire) {
                     match ipc_server.accept() {
                         Ok(protocol_controller) =>
-                            match feed_chain_to_protocol(&tezos_env, &init_storage_data, &apply_block_run, &shell_channel, &mut block_storage, &mut block_meta_storage, &operations_storage, &mut operations_meta_storage, protocol_controller, &log) {
+                            match feed_chain_to_protocol(&tezos_env, &init_storage_data, &apply_block_run, &block_queue, &shell_channel, &mut block_storage, &mut block_meta_storage, &operations_storage, &mut operations_meta_storage, protocol_controller, &context_hash_verifier, &log) {
                                 Ok(()) => debug!(log, "Feed chain to protocol finished"),
                                 Err(err) => {
                                     if apply_block_run.load(Ordering::Acquire) {
@@ -96,7 +120,7 @@ impl ChainFeeder {
         };
 
         let myself = sys.actor_of(
-            Props::new_args(ChainFeeder::new, (shell_channel, apply_block_run, Arc::new(Mutex::new(Some(block_applier_thread))))),
+            Props::new_args(ChainFeeder::new, (shell_channel, apply_block_run, Arc::new(Mutex::new(Some(block_applier_thread))), block_queue)),
             ChainFeeder::name())?;
 
         Ok(myself)
@@ -108,16 +132,24 @@ impl ChainFeeder {
         "chain-feeder"
     }
 
-    fn new((shell_channel, block_applier_run, block_applier_thread): (ShellChannelRef, Arc<AtomicBool>, SharedJoinHandle)) -> Self {
+    fn new((shell_channel, block_applier_run, block_applier_thread, block_queue): (ShellChannelRef, Arc<AtomicBool>, SharedJoinHandle, SharedBlockQueue)) -> Self {
         ChainFeeder {
             shell_channel,
             block_applier_run,
             block_applier_thread,
+            block_queue,
         }
     }
 
     fn process_shell_channel_message(&mut self, _ctx: &Context<ChainFeederMsg>, msg: ShellChannelMsg) -> Result<(), Error> {
         match msg {
+            ShellChannelMsg::AllBlockOperationsReceived(msg) => {
+                self.schedule_block(msg.hash);
+            }
+            ShellChannelMsg::BlockReceived(msg) => {
+                // block without operations can be applied right after the header was received
+                self.schedule_block(msg.hash);
+            }
             ShellChannelMsg::ShuttingDown(_) => {
                 self.block_applier_run.store(false, Ordering::Release);
             }
@@ -126,6 +158,14 @@ impl ChainFeeder {
 
         Ok(())
     }
+
+    /// Push block to the queue and wake up block applier thread
+    fn schedule_block(&mut self, block_hash: BlockHash) {
+        self.block_queue.lock().unwrap().push_back(block_hash);
+        if let Some(join_handle) = self.block_applier_thread.lock().unwrap().as_ref() {
+            join_handle.thread().unpark();
+        }
+    }
 }
 
 impl Actor for ChainFeeder {
@@ -210,15 +250,16 @@ fn feed_chain_to_protocol(
     tezos_env: &TezosEnvironmentConfiguration,
     init_storage_data: &StorageInitInfo,
     apply_block_run: &AtomicBool,
+    block_queue: &SharedBlockQueue,
     shell_channel: &ShellChannelRef,
     block_storage: &mut BlockStorage,
     block_meta_storage: &mut BlockMetaStorage,
     operations_storage: &OperationsStorage,
     operations_meta_storage: &mut OperationsMetaStorage,
     protocol_controller: ProtocolController,
+    context_hash_verifier: &Option<SharedContextHashVerifier>,
     log: &Logger,
 ) -> Result<(), FeedChainError> {
-    let chain_id = init_storage_data.chain_id.clone();
     let block_hash_encoding = HashType::BlockHash;
 
     // at first we initialize protocol runtime and ffi context
@@ -235,103 +276,89 @@ fn feed_chain_to_protocol(
     )?;
 
     // now resolve where to start apply next blocks (at least genesis should be there)
-    let mut current_head_hash: BlockHash = match &block_meta_storage.load_current_head()? {
-        Some(block) => block.clone(),
+    let mut current_head = match block_meta_storage.load_current_head()? {
+        Some(block_hash) => block_storage.get(&block_hash)?.ok_or(FeedChainError::UnknownCurrentHeadError)?,
         None => {
             // this should not happen here, we applied at least genesis before
             return Err(FeedChainError::UnknownCurrentHeadError);
         }
     };
 
+    // head of the test chain is known after the test chain is forked or after the first test chain block is applied
+    let mut test_chain_head: Option<ChainHead> = None;
+
+    // headers stored before their predecessor was known are validated against the predecessor before apply
+    let mut block_header_validator = BlockHeaderValidator::new();
+
     // now we can start applying block
     while apply_block_run.load(Ordering::Acquire) {
-        match block_meta_storage.get(&current_head_hash)? {
-            Some(mut current_head_meta) => {
-                if current_head_meta.is_applied() {
-                    // Current head is already applied, so we should move to successor
-                    // or in case no successor is available do nothing.
-                    match current_head_meta.successor() {
-                        Some(successor_hash) => {
-                            current_head_hash = successor_hash.clone();
-                            continue;
-                        }
-                        None => ( /* successor is not yet available, we do nothing for now */ )
+        let mut blocks_to_check: VecDeque<BlockHash> = block_queue.lock().unwrap().drain(..).collect();
+        if blocks_to_check.is_empty() {
+            // nothing was scheduled, so at least try to move forward from the current heads
+            let heads = std::iter::once(&current_head).chain(test_chain_head.as_ref().map(|(_, head)| head));
+            for head in heads {
+                if let Some(head_meta) = block_meta_storage.get(&head.hash)? {
+                    blocks_to_check.extend(head_meta.successors().iter().cloned());
+                }
+            }
+        }
+
+        let mut checked_blocks = HashSet::new();
+        while let Some(block_hash) = blocks_to_check.pop_front() {
+            if !apply_block_run.load(Ordering::Acquire) {
+                break;
+            }
+            if !checked_blocks.insert(block_hash.clone()) {
+                continue;
+            }
+
+            let block_meta = match block_meta_storage.get(&block_hash)? {
+                Some(block_meta) => block_meta,
+                None => {
+                    trace!(log, "No meta info record was found in database for the block"; "block_header_hash" => block_hash_encoding.bytes_to_string(&block_hash));
+                    continue;
+                }
+            };
+
+            let chain_id = block_meta.chain_id().clone();
+            let is_main_chain = chain_id == init_storage_data.chain_id;
+            let applied_block = if block_meta.is_applied() {
+                // block is already applied (e.g. before restart), so we just continue with successors
+                block_storage.get_with_json_data(&block_hash)?
+                    .map(|(block, block_json_data)| (block, block_json_data, block_meta.successors().clone()))
+            } else {
+                let chain_head = if is_main_chain {
+                    Some(current_head.clone())
+                } else {
+                    test_chain_head.as_ref().filter(|(test_chain_id, _)| test_chain_id == &chain_id).map(|(_, head)| head.clone())
+                };
+                apply_block(chain_head.as_ref(), block_hash, block_meta, &shell_channel, apply_block_run, block_storage, block_meta_storage, operations_storage, operations_meta_storage, &protocol_controller, context_hash_verifier, &mut block_header_validator, &mut test_chain_head, &log)?
+            };
+
+            if let Some((block, block_json_data, successors)) = applied_block {
+                blocks_to_check.extend(successors);
+
+                // fork choice - block with better fitness becomes new current head of its chain
+                if is_main_chain {
+                    if fitness_compare(block.header.fitness(), current_head.header.fitness()) == CmpOrdering::Greater {
+                        switch_current_head(&current_head, &block, block_json_data, &init_storage_data.chain_id, &shell_channel, apply_block_run, block_meta_storage, &log)?;
+                        current_head = block;
                     }
                 } else {
-                    // Current head is not applied, so we should apply it now.
-                    // But first let's fetch current head data from block storage..
-                    match block_storage.get(&current_head_hash)? {
-                        Some(current_head) => {
-                            // Good, we have block data available, let's' look is we have all operations
-                            // available. If yes we will apply them. If not, we will do nothing.
-                            if operations_meta_storage.is_complete(&current_head.hash)? {
-                                debug!(log, "Applying block"; "block_header_hash" => block_hash_encoding.bytes_to_string(&current_head.hash));
-                                let operations = operations_storage.get_operations(&current_head_hash)?
-                                    .drain(..)
-                                    .map(Some)
-                                    .collect();
-
-                                // apply block and it's operations
-                                let (predecessor, predecessor_additional_data) = match block_storage.get_with_additional_data(&current_head.header.predecessor())? {
-                                    None => {
-                                        warn!(log, "No data was found in database for the predecessor, so we try to apply it first"; "predecessor_block_header_hash" => block_hash_encoding.bytes_to_string(&current_head.header.predecessor()));
-                                        current_head_hash = current_head.header.predecessor().clone();
-                                        continue;
-                                    }
-                                    Some(predecesor_data) => predecesor_data
-                                };
-
-                                let apply_block_result = protocol_controller.apply_block(
-                                    &chain_id,
-                                    &current_head.header,
-                                    &predecessor.header,
-                                    &operations,
-                                    predecessor_additional_data.max_operations_ttl().clone(),
-                                )?;
-                                debug!(log, "Block was applied";"block_header_hash" => block_hash_encoding.bytes_to_string(&current_head.hash), "validation_result_message" => &apply_block_result.validation_result_message);
-
-
-
-                                // store result
-                                let (block_json_data, _) = store_applied_block_result(
-                                    block_storage,
-                                    block_meta_storage,
-                                    &current_head.hash,
-                                    apply_block_result,
-                                    &mut current_head_meta,
-                                )?;
-
-                                // notify listeners
-                                if apply_block_run.load(Ordering::Acquire) {
-                                    // notify others that the block successfully applied
-                                    shell_channel.tell(
-                                        Publish {
-                                            msg: BlockApplied::new(current_head, block_json_data).into(),
-                                            topic: ShellChannelTopic::ShellEvents.into(),
-                                        }, None);
-                                }
-
-                                // Current head is already applied, so we should move to successor
-                                // or in case no successor is available do nothing.
-                                match current_head_meta.successor() {
-                                    Some(successor_hash) => {
-                                        current_head_hash = successor_hash.clone();
-                                        continue;
-                                    }
-                                    None => ( /* successor is not yet available, we do nothing for now */ )
-                                }
-                            } else {
-                                // we don't have all operations available, do nothing
-                            }
-                        }
-                        None => ( /* it's possible that data was not yet written do the storage, so don't panic! */ )
+                    let is_new_head = match &test_chain_head {
+                        Some((test_chain_id, head)) if test_chain_id == &chain_id => fitness_compare(block.header.fitness(), head.header.fitness()) == CmpOrdering::Greater,
+                        // e.g. test chain was forked before restart
+                        _ => true,
+                    };
+                    if is_new_head {
+                        switch_test_chain_head(&chain_id, &block, block_json_data, &shell_channel, apply_block_run);
+                        test_chain_head = Some((chain_id, block));
                     }
                 }
             }
-            None => warn!(log, "No meta info record was found in database for the current head"; "block_header_hash" => block_hash_encoding.bytes_to_string(&current_head_hash))
         }
 
-        // This should be hit only in case that the current branch is applied
+        // This should be hit only in case that all applicable blocks were applied
         // and no successor was available to continue the apply cycle. In that case
         // this thread will be stopped and will wait until it's waked again.
         thread::park();
@@ -340,6 +367,290 @@ fn feed_chain_to_protocol(
     Ok(())
 }
 
+/// Applied block with its json data and successors known at the time of application
+type AppliedBlock = (BlockHeaderWithHash, BlockJsonData, Vec<BlockHash>);
+
+/// Current head of the test chain
+type ChainHead = (ChainId, BlockHeaderWithHash);
+
+/// Try to apply block, block can be applied only if its predecessor is applied and all operations are available.
+/// If the block forks the test chain, genesis of the test chain is stored and it becomes the `test_chain_head`.
+///
+/// Block header is validated against the predecessor first, invalid block is never applied.
+///
+/// Returns `None` if block cannot be applied (yet).
+fn apply_block(
+    chain_head: Option<&BlockHeaderWithHash>,
+    block_hash: BlockHash,
+    mut block_meta: Meta,
+    shell_channel: &ShellChannelRef,
+    apply_block_run: &AtomicBool,
+    block_storage: &mut BlockStorage,
+    block_meta_storage: &mut BlockMetaStorage,
+    operations_storage: &OperationsStorage,
+    operations_meta_storage: &mut OperationsMetaStorage,
+    protocol_controller: &ProtocolController,
+    context_hash_verifier: &Option<SharedContextHashVerifier>,
+    block_header_validator: &mut BlockHeaderValidator,
+    test_chain_head: &mut Option<ChainHead>,
+    log: &Logger,
+) -> Result<Option<AppliedBlock>, FeedChainError> {
+    let block_hash_encoding = HashType::BlockHash;
+
+    // we need to have block data available..
+    let block = match block_storage.get(&block_hash)? {
+        Some(block) => block,
+        None => return Ok(None) /* it's possible that data was not yet written do the storage, so don't panic! */
+    };
+    // ..and all operations, if not, we will do nothing
+    if !operations_meta_storage.is_complete(&block.hash)? {
+        return Ok(None);
+    }
+    // predecessor has to be applied
+    match block_meta_storage.get(block.header.predecessor())? {
+        Some(predecessor_meta) if predecessor_meta.is_applied() => (),
+        _ => return Ok(None)
+    }
+
+    // blocks below last allowed fork level of the current head cannot change current head, so we dont apply them
+    let chain_head_additional_data = match chain_head {
+        Some(chain_head) => block_storage.get_with_additional_data(&chain_head.hash)?,
+        None => None,
+    };
+    if let Some((_, current_head_additional_data)) = chain_head_additional_data {
+        if block.header.level() <= current_head_additional_data.last_allowed_fork_level() {
+            debug!(log, "Block is below last allowed fork level, so we ignore it";
+                        "block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash),
+                        "last_allowed_fork_level" => current_head_additional_data.last_allowed_fork_level());
+            return Ok(None);
+        }
+    }
+
+    let (predecessor, predecessor_additional_data) = match block_storage.get_with_additional_data(&block.header.predecessor())? {
+        Some(predecesor_data) => predecesor_data,
+        None => {
+            warn!(log, "No data was found in database for the applied predecessor"; "predecessor_block_header_hash" => block_hash_encoding.bytes_to_string(&block.header.predecessor()));
+            return Ok(None);
+        }
+    };
+
+    // header could be stored before its predecessor was known, so it was not validated against the predecessor yet
+    if let Err(e) = block_header_validator.validate_successor(&predecessor.header, &block.header) {
+        warn!(log, "Block header is not valid, so we do not apply it"; "reason" => format!("{}", e), "block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash));
+        return Ok(None);
+    }
+
+    debug!(log, "Applying block"; "block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash));
+    let operations = operations_storage.get_operations(&block.hash)?
+        .drain(..)
+        .map(Some)
+        .collect();
+
+    // apply block and it's operations, blocks of the test chain are applied with the chain id of the test chain
+    let apply_block_result = protocol_controller.apply_block(
+        block_meta.chain_id(),
+        &block.header,
+        &predecessor.header,
+        &operations,
+        predecessor_additional_data.max_operations_ttl(),
+    )?;
+    debug!(log, "Block was applied";"block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash), "validation_result_message" => &apply_block_result.validation_result_message);
+    block_header_validator.learn_protocol(&block.header, &predecessor.header);
+    if let Some(context_hash_verifier) = context_hash_verifier {
+        context_hash_verifier.lock().unwrap().block_applied(&block.hash, apply_block_result.validation_result_message.clone(), block.header.timestamp());
+    }
+    let forking_testchain_data = apply_block_result.forking_testchain_data.clone();
+
+    // store result
+    let (block_json_data, block_additional_data) = store_applied_block_result(
+        block_storage,
+        block_meta_storage,
+        &block.hash,
+        apply_block_result,
+        &mut block_meta,
+    )?;
+
+    // genesis of the forked test chain is stored right away, so the test chain manager can start from it
+    let forked_test_chain = match forking_testchain_data {
+        Some(forking_testchain_data) => {
+            // protocol and expiration of the forked test chain are available only in the block metadata
+            match TestChainStatus::from_block_metadata(block_json_data.block_header_proto_metadata_json()) {
+                Ok(Some(TestChainStatus::Forking { protocol, expiration })) => {
+                    let genesis = store_test_chain_genesis(
+                        block_storage,
+                        block_meta_storage,
+                        operations_meta_storage,
+                        &block,
+                        &block_json_data,
+                        &block_additional_data,
+                        &forking_testchain_data.genesis,
+                        &forking_testchain_data.chain_id,
+                    )?;
+                    *test_chain_head = Some((forking_testchain_data.chain_id.clone(), genesis.clone()));
+                    let test_chain = TestChain {
+                        chain_id: forking_testchain_data.chain_id,
+                        genesis: forking_testchain_data.genesis,
+                        protocol,
+                        expiration,
+                    };
+                    Some((genesis, test_chain))
+                }
+                status => {
+                    warn!(log, "Block forked test chain, but test chain status is not available";
+                                "block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash),
+                                "status" => format!("{:?}", status));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // notify listeners
+    if apply_block_run.load(Ordering::Acquire) {
+        // notify others that the block successfully applied
+        shell_channel.tell(
+            Publish {
+                msg: BlockApplied::new(block.clone(), block_json_data.clone()).into(),
+                topic: ShellChannelTopic::ShellEvents.into(),
+            }, None);
+
+        if let Some((genesis, test_chain)) = forked_test_chain {
+            info!(log, "Block forked test chain";
+                        "block_header_hash" => block_hash_encoding.bytes_to_string(&block.hash),
+                        "test_chain_id" => HashType::ChainId.bytes_to_string(&test_chain.chain_id));
+            shell_channel.tell(
+                Publish {
+                    msg: BlockApplied::new(genesis, block_json_data.clone()).into(),
+                    topic: ShellChannelTopic::ShellEvents.into(),
+                }, None);
+            shell_channel.tell(
+                Publish {
+                    msg: TestChainForked {
+                        forking_block: block.hash.clone(),
+                        test_chain,
+                    }.into(),
+                    topic: ShellChannelTopic::ShellEvents.into(),
+                }, None);
+        }
+    }
+
+    // reload successors, they could be received while the block was applied
+    let successors = block_meta_storage.get(&block.hash)?
+        .map(|meta| meta.successors().clone())
+        .unwrap_or_default();
+
+    Ok(Some((block, block_json_data, successors)))
+}
+
+/// Store new current head and notify listeners, if new head is not a successor of the old head, then chain reorganization is announced too.
+fn switch_current_head(
+    old_head: &BlockHeaderWithHash,
+    new_head: &BlockHeaderWithHash,
+    new_head_json_data: BlockJsonData,
+    chain_id: &ChainId,
+    shell_channel: &ShellChannelRef,
+    apply_block_run: &AtomicBool,
+    block_meta_storage: &mut BlockMetaStorage,
+    log: &Logger,
+) -> Result<(), FeedChainError> {
+    let block_hash_encoding = HashType::BlockHash;
+    block_meta_storage.store_current_head(&new_head.hash)?;
+
+    if !apply_block_run.load(Ordering::Acquire) {
+        return Ok(());
+    }
+
+    if new_head.header.predecessor() != &old_head.hash {
+        match find_common_ancestor(block_meta_storage, &old_head.hash, &new_head.hash)? {
+            Some((common_ancestor, common_ancestor_level)) => {
+                info!(log, "Chain reorganization";
+                            "old_head" => block_hash_encoding.bytes_to_string(&old_head.hash),
+                            "new_head" => block_hash_encoding.bytes_to_string(&new_head.hash),
+                            "common_ancestor" => block_hash_encoding.bytes_to_string(&common_ancestor),
+                            "common_ancestor_level" => common_ancestor_level);
+                shell_channel.tell(
+                    Publish {
+                        msg: ChainReorganized {
+                            old_head: old_head.hash.clone(),
+                            new_head: new_head.hash.clone(),
+                            common_ancestor,
+                            common_ancestor_level,
+                        }.into(),
+                        topic: ShellChannelTopic::ShellEvents.into(),
+                    }, None);
+            }
+            None => warn!(log, "Failed to find common ancestor for the new current head";
+                            "old_head" => block_hash_encoding.bytes_to_string(&old_head.hash),
+                            "new_head" => block_hash_encoding.bytes_to_string(&new_head.hash))
+        }
+    }
+
+    shell_channel.tell(
+        Publish {
+            msg: NewCurrentHead::new(chain_id.clone(), new_head.clone(), new_head_json_data).into(),
+            topic: ShellChannelTopic::ShellEvents.into(),
+        }, None);
+
+    Ok(())
+}
+
+/// Notify listeners about new current head of the test chain, head of the test chain is not stored
+fn switch_test_chain_head(
+    test_chain_id: &ChainId,
+    new_head: &BlockHeaderWithHash,
+    new_head_json_data: BlockJsonData,
+    shell_channel: &ShellChannelRef,
+    apply_block_run: &AtomicBool,
+) {
+    if apply_block_run.load(Ordering::Acquire) {
+        shell_channel.tell(
+            Publish {
+                msg: NewCurrentHead::new(test_chain_id.clone(), new_head.clone(), new_head_json_data).into(),
+                topic: ShellChannelTopic::ShellEvents.into(),
+            }, None);
+    }
+}
+
+/// Walk back from both blocks (by predecessors) until the same block is found
+fn find_common_ancestor(block_meta_storage: &BlockMetaStorage, block_a: &BlockHash, block_b: &BlockHash) -> Result<Option<(BlockHash, i32)>, StorageError> {
+    let load = |block_hash: &BlockHash| -> Result<Option<(BlockHash, Meta)>, StorageError> {
+        Ok(block_meta_storage.get(block_hash)?.map(|meta| (block_hash.clone(), meta)))
+    };
+    let predecessor = |(block_hash, meta): &(BlockHash, Meta)| -> Result<Option<(BlockHash, Meta)>, StorageError> {
+        match meta.predecessor() {
+            // genesis is its own predecessor
+            Some(predecessor) if predecessor != block_hash => load(predecessor),
+            _ => Ok(None)
+        }
+    };
+
+    let (mut a, mut b) = match (load(block_a)?, load(block_b)?) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Ok(None)
+    };
+
+    while a.0 != b.0 {
+        let level_a = a.1.level();
+        let level_b = b.1.level();
+        if level_a >= level_b {
+            a = match predecessor(&a)? {
+                Some(a) => a,
+                None => return Ok(None)
+            };
+        }
+        if level_b >= level_a {
+            b = match predecessor(&b)? {
+                Some(b) => b,
+                None => return Ok(None)
+            };
+        }
+    }
+
+    let level = a.1.level();
+    Ok(Some((a.0, level)))
+}
+
 /// This initializes ocaml runtime and protocol context,
 /// if we start with new databazes without genesis,
 /// it ensures correct initialization of storage with genesis and his data.
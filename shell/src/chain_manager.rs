@@ -4,8 +4,13 @@
 //! Manages chain synchronisation process.
 //! - tries to download most recent header from the other peers
 //! - also supplies downloaded data to other peers
+//!
+//! Chain manager of the main chain receives all network events. When the test chain is running,
+//! chain manager of the main chain spawns a child chain manager for the test chain and forwards
+//! to it all peer messages, which belong to the test chain.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use failure::Error;
@@ -13,24 +18,26 @@ use itertools::Itertools;
 use riker::actors::*;
 use slog::{debug, FnValue, info, trace, warn};
 
-use crypto::hash::{BlockHash, ChainId, HashType};
-use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, PeerBootstrapped};
+use crypto::base58::FromBase58CheckError;
+use crypto::hash::{BlockHash, ChainId, CryptoboxPublicKeyHash, HashType, ProtocolHash};
+use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, PeerBootstrapped, PeerMessageReceived};
 use networking::p2p::peer::{PeerRef, SendMessage};
-use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader, OperationsStorage, OperationsStorageReader, StorageError};
+use storage::{BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockStorage, BlockStorageReader, OperationsStorage, OperationsStorageReader, ProtocolStorage, ProtocolStorageReader, StorageError};
 use storage::block_meta_storage::BlockMetaStorageReader;
 use storage::persistent::PersistentStorage;
 use tezos_messages::p2p::binary_message::MessageHash;
 use tezos_messages::p2p::encoding::prelude::*;
 
-use crate::shell_channel::{AllBlockOperationsReceived, BlockReceived, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
+use crate::block_locator::{self, Seed};
+use crate::peer_reputation::Misbehaviour;
+use crate::shell_channel::{AllBlockOperationsReceived, BlockReceived, PeerMisbehaved, ShellChannelMsg, ShellChannelRef, ShellChannelTopic};
 use crate::state::block_state::{BlockState, MissingBlock};
+use crate::state::download_queue::DownloadQueue;
 use crate::state::operations_state::{MissingOperations, OperationsState};
 use crate::subscription::*;
+use crate::test_chain::{TestChain, TestChainStatus};
+use crate::validation::BlockHeaderValidator;
 
-/// Limit to how many blocks to request in a batch
-const BLOCK_HEADERS_BATCH_SIZE: usize = 10;
-/// Limit to how many operations to request in a batch
-const OPERATIONS_BATCH_SIZE: usize = 10;
 /// How often to check chain completeness
 const CHECK_CHAIN_COMPLETENESS_INTERVAL: Duration = Duration::from_secs(30);
 /// How often to ask all connected peers for current branch
@@ -43,6 +50,34 @@ const CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT: Duration = Duration::from_secs(120);
 const SILENT_PEER_TIMEOUT: Duration = Duration::from_secs(30);
 /// After this interval we will rehydrate state if no new blocks are applied
 const STALLED_CHAIN_COMPLETENESS_TIMEOUT: Duration = Duration::from_secs(240);
+/// How often to request missing protocols again
+const RETRY_MISSING_PROTOCOLS_INTERVAL: Duration = Duration::from_secs(60);
+/// Missing protocol is not requested anymore after this time, if no peer provided it
+const MISSING_PROTOCOL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Intervals of the periodic peer requests and timeouts after which the peer is considered stalled
+#[derive(Clone, Debug)]
+pub struct PeerTimeouts {
+    /// How often to check chain completeness and request missing data from peers
+    pub check_chain_completeness_interval: Duration,
+    /// How often to ask all connected peers for current branch
+    pub ask_current_branch_interval: Duration,
+    /// After this time we will disconnect peer if his current head level stays the same
+    pub current_head_level_update_timeout: Duration,
+    /// After this time peer will be disconnected if it fails to respond to our request
+    pub silent_peer_timeout: Duration,
+}
+
+impl Default for PeerTimeouts {
+    fn default() -> Self {
+        PeerTimeouts {
+            check_chain_completeness_interval: CHECK_CHAIN_COMPLETENESS_INTERVAL,
+            ask_current_branch_interval: ASK_CURRENT_BRANCH_INTERVAL,
+            current_head_level_update_timeout: CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT,
+            silent_peer_timeout: SILENT_PEER_TIMEOUT,
+        }
+    }
+}
 
 /// Message commands [`ChainManager`] to disconnect stalled peers.
 #[derive(Clone, Debug)]
@@ -56,6 +91,10 @@ pub struct CheckChainCompleteness;
 #[derive(Clone, Debug)]
 pub struct AskPeersAboutCurrentBranch;
 
+/// Message commands [`ChainManager`] to request missing protocols from all connected peers again.
+#[derive(Clone, Debug)]
+pub struct RetryMissingProtocols;
+
 /// Message commands [`ChainManager`] to log its internal stats.
 #[derive(Clone, Debug)]
 pub struct LogStats;
@@ -89,6 +128,21 @@ struct Head {
     level: i32,
 }
 
+/// Chain followed by the chain manager
+#[derive(Clone, Debug, PartialEq)]
+enum ChainRole {
+    /// Main chain, test chain is followed only if it is enabled
+    Main { enable_testchain: bool },
+    /// Test chain forked from the main chain, peer messages are forwarded by the main chain manager
+    Test { genesis: BlockHash },
+}
+
+/// Running test chain and its chain manager
+struct TestChainManager {
+    test_chain: TestChain,
+    chain_manager: ChainManagerRef,
+}
+
 /// Holds various stats with info about internal synchronization.
 struct Stats {
     /// Count of received blocks
@@ -106,12 +160,22 @@ struct Stats {
 }
 
 /// Purpose of this actor is to perform chain synchronization.
-#[actor(DisconnectStalledPeers, CheckChainCompleteness, AskPeersAboutCurrentBranch, LogStats, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
+#[actor(DisconnectStalledPeers, CheckChainCompleteness, AskPeersAboutCurrentBranch, RetryMissingProtocols, LogStats, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
 pub struct ChainManager {
     /// All events generated by the network layer will end up in this channel
     network_channel: NetworkChannelRef,
     /// All events from shell will be published to this channel
     shell_channel: ShellChannelRef,
+    /// Peer id of this node
+    local_peer_id: CryptoboxPublicKeyHash,
+    /// Chain followed by this chain manager
+    role: ChainRole,
+    /// Intervals of the periodic peer requests and peer timeouts
+    timeouts: PeerTimeouts,
+    /// Chain manager of the running test chain (only for the main chain)
+    test_chain: Option<TestChainManager>,
+    /// Persistent storage is required to spawn chain manager of the test chain
+    persistent_storage: PersistentStorage,
     /// Holds the state of all peers
     peers: HashMap<ActorUri, PeerState>,
     /// Block storage
@@ -120,10 +184,16 @@ pub struct ChainManager {
     block_meta_storage: Box<dyn BlockMetaStorageReader>,
     /// Operations storage
     operations_storage: Box<dyn OperationsStorageReader>,
+    /// Protocol storage
+    protocol_storage: ProtocolStorage,
+    /// Protocols activated by applied blocks, which are not stored yet and were requested from peers, with the time of the first request
+    missing_protocols: HashMap<ProtocolHash, Instant>,
     /// Holds state of the block chain
     block_state: BlockState,
     /// Holds state of the operations
     operations_state: OperationsState,
+    /// Validates received block headers before they are stored
+    block_header_validator: BlockHeaderValidator,
     /// Current head information
     current_head: CurrentHead,
     /// Internal stats
@@ -137,8 +207,11 @@ pub type ChainManagerRef = ActorRef<ChainManagerMsg>;
 
 impl ChainManager {
 
-    /// Create new actor instance.
-    pub fn actor(sys: &impl ActorRefFactory, network_channel: NetworkChannelRef, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, chain_id: &ChainId) -> Result<ChainManagerRef, CreateError> {
+    /// Create new actor instance for the main chain.
+    ///
+    /// Peer id of this node (`local_peer_id`) is required to compute block locators.
+    /// If `enable_testchain` is set, test chains forked from the main chain are followed too.
+    pub fn actor(sys: &impl ActorRefFactory, network_channel: NetworkChannelRef, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, chain_id: &ChainId, local_peer_id: &CryptoboxPublicKeyHash, enable_testchain: bool, timeouts: PeerTimeouts) -> Result<ChainManagerRef, CreateError> {
         sys.actor_of(
             Props::new_args(
                 ChainManager::new,
@@ -146,27 +219,55 @@ impl ChainManager {
                         network_channel,
                         shell_channel,
                         persistent_storage.clone(),
-                        chain_id.clone()
+                        chain_id.clone(),
+                        local_peer_id.clone(),
+                        ChainRole::Main { enable_testchain },
+                        timeouts,
                 )
             ),
             ChainManager::name())
     }
 
+    /// Create chain manager of the test chain as a child of the main chain manager
+    fn test_chain_actor(&self, ctx: &Context<ChainManagerMsg>, test_chain: &TestChain) -> Result<ChainManagerRef, CreateError> {
+        ctx.actor_of(
+            Props::new_args(
+                ChainManager::new,
+                (
+                        self.network_channel.clone(),
+                        self.shell_channel.clone(),
+                        self.persistent_storage.clone(),
+                        test_chain.chain_id.clone(),
+                        self.local_peer_id.clone(),
+                        ChainRole::Test { genesis: test_chain.genesis.clone() },
+                        self.timeouts.clone(),
+                )
+            ),
+            &format!("test-{}-{}", ChainManager::name(), HashType::ChainId.bytes_to_string(&test_chain.chain_id)))
+    }
+
     /// The `ChainManager` is intended to serve as a singleton actor so that's why
     /// we won't support multiple names per instance.
     fn name() -> &'static str {
         "chain-manager"
     }
 
-    fn new((network_channel, shell_channel, persistent_storage, chain_id): (NetworkChannelRef, ShellChannelRef, PersistentStorage, ChainId)) -> Self {
+    fn new((network_channel, shell_channel, persistent_storage, chain_id, local_peer_id, role, timeouts): (NetworkChannelRef, ShellChannelRef, PersistentStorage, ChainId, CryptoboxPublicKeyHash, ChainRole, PeerTimeouts)) -> Self {
         ChainManager {
             network_channel,
             shell_channel,
+            local_peer_id,
+            role,
+            timeouts,
+            test_chain: None,
             block_storage: Box::new(BlockStorage::new(&persistent_storage)),
             block_meta_storage: Box::new(BlockMetaStorage::new(&persistent_storage)),
             operations_storage: Box::new(OperationsStorage::new(&persistent_storage)),
+            protocol_storage: ProtocolStorage::new(&persistent_storage),
+            missing_protocols: HashMap::new(),
             block_state: BlockState::new(&persistent_storage, &chain_id),
             operations_state: OperationsState::new(&persistent_storage, &chain_id),
+            block_header_validator: BlockHeaderValidator::new(),
             peers: HashMap::new(),
             current_head: CurrentHead {
                 local: None,
@@ -181,6 +282,15 @@ impl ChainManager {
                 applied_block_level: None,
                 hydrated_state_last: None,
             },
+            persistent_storage,
+        }
+    }
+
+    #[inline]
+    fn is_test_chain(&self) -> bool {
+        match self.role {
+            ChainRole::Test { .. } => true,
+            ChainRole::Main { .. } => false,
         }
     }
 
@@ -188,19 +298,37 @@ impl ChainManager {
     fn check_chain_completeness(&mut self, ctx: &Context<ChainManagerMsg>) -> Result<(), Error> {
         let ChainManager { peers, block_state, operations_state, stats, .. } = self;
 
+        // requests which were not answered on time are returned back, so they can be assigned to faster peers
+        for peer in peers.values_mut() {
+            for missing_block in peer.queued_block_headers.drain_timed_out() {
+                debug!(ctx.system.log(), "Block header request timed out"; "peer" => peer.peer_ref.name(), "block_header_hash" => HashType::BlockHash.bytes_to_string(&missing_block.block_hash));
+                block_state.push_missing_block(missing_block)?;
+            }
+            let missing_operations = peer.queued_operations.drain_timed_out();
+            if !missing_operations.is_empty() {
+                debug!(ctx.system.log(), "Operations request timed out"; "peer" => peer.peer_ref.name(), "count" => missing_operations.len());
+                operations_state.push_missing_operations(missing_operations.into_iter())?;
+            }
+        }
+
         if block_state.has_missing_blocks() {
+            let mut timed_out_blocks = Vec::new();
             peers.values_mut()
                 .filter(|peer| peer.current_head_level.is_some())
                 .sorted_by_key(|peer| peer.available_block_queue_capacity()).rev()
                 .for_each(|peer| {
                     let available_capacity = peer.available_block_queue_capacity();
                     if available_capacity > 0 {
-                        let mut missing_blocks = block_state.drain_missing_blocks(available_capacity, peer.current_head_level.unwrap());
+                        // request, which timed out, is not assigned to the same peer again
+                        let (mut missing_blocks, peer_timed_out_blocks): (Vec<_>, Vec<_>) = block_state.drain_missing_blocks(available_capacity, peer.current_head_level.unwrap())
+                            .into_iter()
+                            .partition(|missing_block| !peer.queued_block_headers.has_timed_out(&missing_block.block_hash));
+                        timed_out_blocks.extend(peer_timed_out_blocks);
                         if !missing_blocks.is_empty() {
                             let queued_blocks = missing_blocks.drain(..)
                                 .map(|missing_block| {
                                     let missing_block_hash = missing_block.block_hash.clone();
-                                    if let None = peer.queued_block_headers.insert(missing_block_hash.clone(), missing_block) {
+                                    if peer.queued_block_headers.insert(missing_block_hash.clone(), missing_block) {
                                         // block was not already present in queue
                                         Some(missing_block_hash)
                                     } else {
@@ -212,26 +340,33 @@ impl ChainManager {
                                 .collect::<Vec<_>>();
 
                             if !queued_blocks.is_empty() {
-                                peer.block_request_last = Instant::now();
                                 tell_peer(GetBlockHeadersMessage::new(queued_blocks).into(), peer);
                             }
                         }
                     }
                 });
+            for missing_block in timed_out_blocks {
+                block_state.push_missing_block(missing_block)?;
+            }
         }
 
         if operations_state.has_missing_operations() {
+            let mut timed_out_operations = Vec::new();
             peers.values_mut()
                 .filter(|peer| peer.current_head_level.is_some())
                 .sorted_by_key(|peer| peer.available_operations_queue_capacity()).rev()
                 .for_each(|peer| {
                     let available_capacity = peer.available_operations_queue_capacity();
                     if available_capacity > 0 {
-                        let missing_operations = operations_state.drain_missing_operations(available_capacity, peer.current_head_level.unwrap());
+                        // request, which timed out, is not assigned to the same peer again
+                        let (missing_operations, peer_timed_out_operations): (Vec<_>, Vec<_>) = operations_state.drain_missing_operations(available_capacity, peer.current_head_level.unwrap())
+                            .into_iter()
+                            .partition(|missing_operation| !peer.queued_operations.has_timed_out(&missing_operation.block_hash));
+                        timed_out_operations.extend(peer_timed_out_operations);
                         if !missing_operations.is_empty() {
                             let queued_operations = missing_operations.iter()
                                 .map(|missing_operation| {
-                                    if let None = peer.queued_operations.insert(missing_operation.block_hash.clone(), missing_operation.clone()) {
+                                    if peer.queued_operations.insert(missing_operation.block_hash.clone(), missing_operation.clone()) {
                                         // operations were not already present in queue
                                         Some(missing_operation)
                                     } else {
@@ -243,13 +378,13 @@ impl ChainManager {
                                 .collect::<Vec<_>>();
 
                             if !queued_operations.is_empty() {
-                                peer.operations_request_last = Instant::now();
                                 queued_operations.iter()
                                     .for_each(|&missing_operation| tell_peer(GetOperationsForBlocksMessage::new(missing_operation.into()).into(), peer));
                             }
                         }
                     }
                 });
+            operations_state.push_missing_operations(timed_out_operations.into_iter())?;
         }
 
         if let (Some(applied_block_last), Some(hydrated_state_last)) = (stats.applied_block_last, stats.hydrated_state_last) {
@@ -266,25 +401,43 @@ impl ChainManager {
             peers,
             block_state,
             operations_state,
+            block_header_validator,
             shell_channel,
             block_storage,
+            block_meta_storage,
             operations_storage,
+            protocol_storage,
+            missing_protocols,
+            test_chain,
             stats,
             ..
         } = self;
 
         match msg {
-            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer, .. }) => {
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer, peer_id, peer_metadata, version, replayed }) => {
                 let log = ctx.system.log().new(slog::o!("peer" => peer.name().to_string()));
 
+                if let Some(test_chain) = test_chain {
+                    test_chain.chain_manager.tell(NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success {
+                        peer: peer.clone(),
+                        peer_id: peer_id.clone(),
+                        peer_metadata: peer_metadata.clone(),
+                        version: version.clone(),
+                        replayed,
+                    }), None);
+                }
+
                 debug!(log, "Requesting current branch");
-                let peer = PeerState::new(peer);
+                let peer = PeerState::new(peer, peer_id, peer_metadata, version, replayed)?;
                 // store peer
                 let actor_uri = peer.peer_ref.uri().clone();
                 self.peers.insert(actor_uri.clone(), peer);
                 // retrieve mutable reference and use it as `tell_peer()` parameter
                 let peer = self.peers.get_mut(&actor_uri).unwrap();
                 tell_peer(GetCurrentBranchMessage::new(block_state.get_chain_id().clone()).into(), peer);
+                if !missing_protocols.is_empty() {
+                    tell_peer(GetProtocolsMessage::new(missing_protocols.keys().cloned().collect()).into(), peer);
+                }
             }
             NetworkChannelMsg::PeerMessageReceived(received) => {
                 let log = ctx.system.log().new(slog::o!("peer" => received.peer.name().to_string()));
@@ -292,7 +445,29 @@ impl ChainManager {
                 match peers.get_mut(received.peer.uri()) {
                     Some(peer) => {
                         for message in received.message.messages() {
+                            // chain specific messages are routed by the chain id
+                            if let Some(message_chain_id) = message_chain_id(message) {
+                                if message_chain_id != block_state.get_chain_id() {
+                                    route_to_other_chain(test_chain, message_chain_id, peer, shell_channel, message);
+                                    continue;
+                                }
+                                match message {
+                                    PeerMessage::Deactivate(_) => (),
+                                    _ => peer.chain_activation = ChainActivation::Active,
+                                }
+                            }
+
                             match message {
+                                PeerMessage::Deactivate(_) => {
+                                    debug!(log, "Peer deactivated chain");
+                                    peer.chain_activation = ChainActivation::Deactivated;
+                                    peer.current_head_level = None;
+                                    // queued requests are assigned to other peers
+                                    for missing_block in peer.queued_block_headers.drain() {
+                                        block_state.push_missing_block(missing_block)?;
+                                    }
+                                    operations_state.push_missing_operations(peer.queued_operations.drain().into_iter())?;
+                                }
                                 PeerMessage::CurrentBranch(message) => {
                                     debug!(log, "Received current branch");
                                     if message.current_branch().current_head().level() > 0 {
@@ -301,13 +476,19 @@ impl ChainManager {
                                                 level: message.current_branch().current_head().level() - 1
                                             })?;
                                     }
-                                    //TODO: refactor to support non zero level in MissingBlock 
-                                    message.current_branch().history().iter().cloned().rev()
-                                        .map(|history_block_hash| block_state.push_missing_block(history_block_hash.into()))
-                                        .collect::<Result<Vec<_>, _>>()?;
 
                                     let current_head = message.current_branch().current_head();
 
+                                    // blocks older than the common ancestor are already known, so only newer history blocks are scheduled for download
+                                    let history = message.current_branch().history();
+                                    let history_levels = block_locator::history_levels(&current_head.message_hash()?, current_head.level(), history.len(), &Seed::new(&peer.peer_id, &self.local_peer_id));
+                                    let common_ancestor = block_locator::find_common_ancestor(history, |block_hash| block_storage.contains(block_hash))?;
+                                    history.iter().cloned()
+                                        .zip(history_levels)
+                                        .take(common_ancestor.unwrap_or_else(|| history.len()))
+                                        .map(|(block_hash, level)| block_state.push_missing_block(MissingBlock { block_hash, level }))
+                                        .collect::<Result<Vec<_>, _>>()?;
+
                                     // if needed, update remote current head
                                     if self.current_head.need_update_remote_level(message.current_branch().current_head().level()) {
                                         self.current_head.remote = Some(Head {
@@ -340,7 +521,7 @@ impl ChainManager {
                                     if block_state.get_chain_id() == &message.chain_id {
                                         if let Some(current_head_local) = &self.current_head.local {
                                             if let Some(current_head) = block_storage.get(&current_head_local.hash)? {
-                                                let history = block_state.get_history()?;
+                                                let history = block_state.get_history(&current_head.hash, &Seed::new(&self.local_peer_id, &peer.peer_id))?;
                                                 let msg = CurrentBranchMessage::new(block_state.get_chain_id().clone(), CurrentBranch::new((*current_head.header).clone(), history));
                                                 tell_peer(msg.into(), peer);
                                             }
@@ -349,36 +530,59 @@ impl ChainManager {
                                 }
                                 PeerMessage::BlockHeader(message) => {
                                     let block_header_with_hash = BlockHeaderWithHash::new(message.block_header().clone()).unwrap();
-                                    match peer.queued_block_headers.remove(&block_header_with_hash.hash) {
-                                        Some(_) => {
-                                            trace!(log, "Received block header");
-                                            peer.block_response_last = Instant::now();
-
-                                            let is_new_block =
-                                                block_state.process_block_header(&block_header_with_hash)
-                                                    .and(operations_state.process_block_header(&block_header_with_hash))?;
-
-                                            if is_new_block {
-                                                // update stats
-                                                stats.unseen_block_last = Instant::now();
-                                                stats.unseen_block_count += 1;
-
-                                                // trigger CheckChainCompleteness
-                                                ctx.myself().tell(CheckChainCompleteness, None);
-
-                                                // notify others that new block was received
-                                                shell_channel.tell(
-                                                    Publish {
-                                                        msg: BlockReceived {
-                                                            hash: block_header_with_hash.hash,
-                                                            level: block_header_with_hash.header.level(),
-                                                        }.into(),
-                                                        topic: ShellChannelTopic::ShellEvents.into(),
-                                                    }, Some(ctx.myself().into()));
+                                    // late response to the timed out request is accepted too, block could be still missing
+                                    let requested = peer.queued_block_headers.complete(&block_header_with_hash.hash).is_some()
+                                        // replayed peer answers requests of the recording node, so missing block is accepted without our request
+                                        || (peer.replayed && !block_storage.contains(&block_header_with_hash.hash)?);
+                                    if requested {
+                                        trace!(log, "Received block header");
+
+                                        // validate header before it is stored
+                                        let predecessor = block_storage.get(block_header_with_hash.header.predecessor())?;
+                                        let mut successors = Vec::new();
+                                        if let Some(meta) = block_meta_storage.get(&block_header_with_hash.hash)? {
+                                            for successor in meta.successors() {
+                                                if let Some(successor) = block_storage.get(successor)? {
+                                                    successors.push(successor.header);
+                                                }
                                             }
                                         }
-                                        None => {
+                                        let successors: Vec<&BlockHeader> = successors.iter().map(|successor| successor.as_ref()).collect();
+                                        if let Err(e) = block_header_validator.validate(&block_header_with_hash.header, predecessor.as_ref().map(|predecessor| predecessor.header.as_ref()), &successors) {
+                                            warn!(log, "Received invalid block header"; "reason" => format!("{}", e), "block_header_hash" => HashType::BlockHash.bytes_to_string(&block_header_with_hash.hash));
+                                            report_misbehaviour(shell_channel, &received.peer, Misbehaviour::InvalidBlockHeader);
+                                            continue;
+                                        }
+
+                                        let is_new_block =
+                                            block_state.process_block_header(&block_header_with_hash)
+                                                .and(operations_state.process_block_header(&block_header_with_hash))?;
+
+                                        if is_new_block {
+                                            // update stats
+                                            stats.unseen_block_last = Instant::now();
+                                            stats.unseen_block_count += 1;
+
+                                            // trigger CheckChainCompleteness
+                                            ctx.myself().tell(CheckChainCompleteness, None);
+
+                                            // notify others that new block was received
+                                            shell_channel.tell(
+                                                Publish {
+                                                    msg: BlockReceived {
+                                                        hash: block_header_with_hash.hash,
+                                                        level: block_header_with_hash.header.level(),
+                                                    }.into(),
+                                                    topic: ShellChannelTopic::ShellEvents.into(),
+                                                }, Some(ctx.myself().into()));
+                                        }
+                                    } else {
+                                        // block header could be requested by the test chain manager
+                                        if let Some(test_chain) = test_chain {
+                                            forward_peer_message(&test_chain.chain_manager, &received.peer, message);
+                                        } else {
                                             warn!(log, "Received unexpected block header"; "block_header_hash" => HashType::BlockHash.bytes_to_string(&block_header_with_hash.hash));
+                                            report_misbehaviour(shell_channel, &received.peer, Misbehaviour::UnexpectedBlockHeader);
                                         }
                                     }
                                 }
@@ -403,11 +607,17 @@ impl ChainManager {
                                 }
                                 PeerMessage::OperationsForBlocks(operations) => {
                                     let block_hash = operations.operations_for_block().hash().clone();
-                                    match peer.queued_operations.get_mut(&block_hash) {
-                                        Some(missing_operations) => {
-                                            let operation_was_expected = missing_operations.validation_passes.remove(&operations.operations_for_block().validation_pass());
+                                    // late response to the timed out request is accepted too, operations could be still missing
+                                    let operation_was_expected = match peer.queued_operations.get_mut(&block_hash) {
+                                        Some(missing_operations) => Some(missing_operations.validation_passes.remove(&operations.operations_for_block().validation_pass())),
+                                        // replayed peer answers requests of the recording node, so operations of the known block are accepted without our request
+                                        None if peer.replayed && block_storage.contains(&block_hash)? => Some(true),
+                                        None => None,
+                                    };
+                                    match operation_was_expected {
+                                        Some(operation_was_expected) => {
                                             if operation_was_expected {
-                                                peer.operations_response_last = Instant::now();
+                                                peer.queued_operations.response_received(&block_hash);
                                                 trace!(log, "Received operations validation pass"; "validation_pass" => operations.operations_for_block().validation_pass(), "block_header_hash" => HashType::BlockHash.bytes_to_string(&block_hash));
 
                                                 if operations_state.process_block_operations(&operations)? {
@@ -433,12 +643,19 @@ impl ChainManager {
                                                 }
                                             } else {
                                                 warn!(log, "Received unexpected validation pass"; "validation_pass" => operations.operations_for_block().validation_pass(), "block_header_hash" => HashType::BlockHash.bytes_to_string(&block_hash));
+                                                report_misbehaviour(shell_channel, &received.peer, Misbehaviour::UnexpectedValidationPass);
                                                 ctx.system.stop(received.peer.clone());
                                             }
                                         }
                                         None => {
-                                            warn!(log, "Received unexpected operations");
-                                            ctx.system.stop(received.peer.clone());
+                                            // operations could be requested by the test chain manager
+                                            if let Some(test_chain) = test_chain {
+                                                forward_peer_message(&test_chain.chain_manager, &received.peer, operations);
+                                            } else {
+                                                warn!(log, "Received unexpected operations");
+                                                report_misbehaviour(shell_channel, &received.peer, Misbehaviour::UnexpectedOperations);
+                                                ctx.system.stop(received.peer.clone());
+                                            }
                                         }
                                     }
                                 }
@@ -454,6 +671,25 @@ impl ChainManager {
                                         }
                                     }
                                 }
+                                PeerMessage::GetProtocols(message) => {
+                                    for protocol_hash in message.get_protocols() {
+                                        if let Some(protocol) = protocol_storage.get(protocol_hash)? {
+                                            let msg: ProtocolMessage = protocol.into();
+                                            tell_peer(msg.into(), peer);
+                                        }
+                                    }
+                                }
+                                PeerMessage::Protocol(message) => {
+                                    let protocol_hash = message.protocol().message_hash()?;
+                                    if missing_protocols.remove(&protocol_hash).is_some() {
+                                        info!(log, "Received protocol"; "protocol_hash" => HashType::ProtocolHash.bytes_to_string(&protocol_hash));
+                                        protocol_storage.put(&protocol_hash, message.protocol())?;
+                                    } else if !protocol_storage.contains(&protocol_hash)? {
+                                        // protocol could have been already received from other peer
+                                        warn!(log, "Received unexpected protocol"; "protocol_hash" => HashType::ProtocolHash.bytes_to_string(&protocol_hash));
+                                        report_misbehaviour(shell_channel, &received.peer, Misbehaviour::UnexpectedProtocol);
+                                    }
+                                }
                                 _ => trace!(log, "Ignored message"; "message" => FnValue(|_| format!("{:?}", message)))
                             }
                         }
@@ -470,12 +706,27 @@ impl ChainManager {
     fn process_shell_channel_message(&mut self, ctx: &Context<ChainManagerMsg>, msg: ShellChannelMsg) -> Result<(), Error> {
         match msg {
             ShellChannelMsg::BlockApplied(message) => {
+                // main chain manager and test chain manager receive the same events
+                if !self.block_state.belongs_to_chain(&message.header().hash)? {
+                    return Ok(());
+                }
+                self.stats.applied_block_level = Some(message.header().header.level());
+                self.stats.applied_block_last = Some(Instant::now());
+                self.learn_protocol(message.header())?;
+                self.request_missing_protocols(ctx, message.json_data())?;
+                self.update_test_chain(ctx, message.header(), message.json_data())?;
+            }
+            ShellChannelMsg::NewCurrentHead(message) if message.chain_id() == self.block_state.get_chain_id() => {
                 self.current_head.local = Some(Head {
                     hash: message.header().hash.clone(),
                     level: message.header().header.level(),
                 });
-                self.stats.applied_block_level = Some(message.header().header.level());
-                self.stats.applied_block_last = Some(Instant::now());
+            }
+            ShellChannelMsg::TestChainForked(message) => {
+                if let ChainRole::Main { enable_testchain: true } = self.role {
+                    info!(ctx.system.log(), "Test chain was forked"; "forking_block" => HashType::BlockHash.bytes_to_string(&message.forking_block));
+                    self.start_test_chain(ctx, message.test_chain);
+                }
             }
             ShellChannelMsg::ShuttingDown(_) => {
                 self.shutting_down = true;
@@ -487,6 +738,109 @@ impl ChainManager {
         Ok(())
     }
 
+    /// Applied block and its predecessor tell us, how many validation passes are required by the protocol
+    fn learn_protocol(&mut self, block: &BlockHeaderWithHash) -> Result<(), StorageError> {
+        // genesis (e.g. test chain genesis) is its own predecessor, so it tells nothing about the protocol
+        if block.header.predecessor() == &block.hash {
+            return Ok(());
+        }
+        if let Some(predecessor) = self.block_storage.get(block.header.predecessor())? {
+            self.block_header_validator.learn_protocol(&block.header, &predecessor.header);
+        }
+        Ok(())
+    }
+
+    /// Test chain status is part of every applied block, so the test chain is started (e.g. after restart) and stopped accordingly
+    fn update_test_chain(&mut self, ctx: &Context<ChainManagerMsg>, block: &BlockHeaderWithHash, json_data: &BlockJsonData) -> Result<(), Error> {
+        if self.role != (ChainRole::Main { enable_testchain: true }) {
+            return Ok(());
+        }
+
+        match TestChainStatus::from_block_metadata(json_data.block_header_proto_metadata_json())? {
+            Some(TestChainStatus::Running(test_chain)) => {
+                if test_chain.is_expired(block.header.timestamp()) {
+                    self.stop_test_chain(ctx);
+                } else {
+                    self.start_test_chain(ctx, test_chain);
+                }
+            }
+            Some(TestChainStatus::NotRunning) => self.stop_test_chain(ctx),
+            Some(TestChainStatus::Forking { .. }) | None => (),
+        }
+
+        Ok(())
+    }
+
+    /// Spawn chain manager for the test chain, if it is not already running. Previous test chain is stopped.
+    fn start_test_chain(&mut self, ctx: &Context<ChainManagerMsg>, test_chain: TestChain) {
+        if self.test_chain.as_ref().filter(|running| running.test_chain.chain_id == test_chain.chain_id).is_some() {
+            return;
+        }
+        self.stop_test_chain(ctx);
+
+        info!(ctx.system.log(), "Starting test chain";
+                    "test_chain_id" => HashType::ChainId.bytes_to_string(&test_chain.chain_id),
+                    "genesis" => HashType::BlockHash.bytes_to_string(&test_chain.genesis),
+                    "protocol" => HashType::ProtocolHash.bytes_to_string(&test_chain.protocol),
+                    "expiration" => test_chain.expiration);
+        let chain_manager = match self.test_chain_actor(ctx, &test_chain) {
+            Ok(chain_manager) => chain_manager,
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to create test chain manager"; "reason" => format!("{:?}", e));
+                return;
+            }
+        };
+
+        // test chain manager has to know about already connected peers
+        for peer in self.peers.values_mut() {
+            peer.unfollowed_chains.remove(&test_chain.chain_id);
+            chain_manager.tell(NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success {
+                peer: peer.peer_ref.clone(),
+                peer_id: peer.peer_id_encoded.clone(),
+                peer_metadata: peer.peer_metadata.clone(),
+                version: peer.version.clone(),
+                replayed: peer.replayed,
+            }), None);
+        }
+
+        self.test_chain = Some(TestChainManager { test_chain, chain_manager });
+    }
+
+    /// Stop chain manager of the test chain and tell peers, that we do not follow the test chain anymore
+    fn stop_test_chain(&mut self, ctx: &Context<ChainManagerMsg>) {
+        if let Some(TestChainManager { test_chain, chain_manager }) = self.test_chain.take() {
+            info!(ctx.system.log(), "Stopping test chain"; "test_chain_id" => HashType::ChainId.bytes_to_string(&test_chain.chain_id));
+            ctx.stop(chain_manager);
+
+            let msg = DeactivateMessage::new(test_chain.chain_id.clone());
+            self.peers.values_mut()
+                .for_each(|peer| {
+                    peer.unfollowed_chains.insert(test_chain.chain_id.clone());
+                    tell_peer(msg.clone().into(), peer);
+                });
+        }
+    }
+
+    /// Protocols used or activated by the applied block, which we do not have yet, are requested from all peers
+    fn request_missing_protocols(&mut self, ctx: &Context<ChainManagerMsg>, json_data: &BlockJsonData) -> Result<(), Error> {
+        let mut requested_protocols = vec![];
+        for protocol_hash in block_protocols(json_data)? {
+            if !self.missing_protocols.contains_key(&protocol_hash) && !self.protocol_storage.contains(&protocol_hash)? {
+                info!(ctx.system.log(), "Requesting unknown protocol"; "protocol_hash" => HashType::ProtocolHash.bytes_to_string(&protocol_hash));
+                self.missing_protocols.insert(protocol_hash.clone(), Instant::now());
+                requested_protocols.push(protocol_hash);
+            }
+        }
+
+        if !requested_protocols.is_empty() {
+            let msg = GetProtocolsMessage::new(requested_protocols);
+            self.peers.values_mut()
+                .for_each(|peer| tell_peer(msg.clone().into(), peer));
+        }
+
+        Ok(())
+    }
+
     fn hydrate_state(&mut self, ctx: &Context<ChainManagerMsg>) {
         info!(ctx.system.log(), "Hydrating block state");
         self.block_state.hydrate().expect("Failed to hydrate block state");
@@ -495,18 +849,26 @@ impl ChainManager {
         self.operations_state.hydrate().expect("Failed to hydrate operations state");
 
         info!(ctx.system.log(), "Loading current head");
-        self.current_head.local = match self.block_meta_storage.load_current_head().expect("Failed to load current head") {
+        // stored current head belongs to the main chain, test chain starts at its genesis stored by the chain feeder
+        let stored_current_head = match &self.role {
+            ChainRole::Main { .. } => self.block_meta_storage.load_current_head().expect("Failed to load current head"),
+            ChainRole::Test { genesis } => self.block_meta_storage.load_chain_head(genesis).expect("Failed to load test chain head"),
+        };
+        let current_head = match stored_current_head {
             Some(hash) => {
                 self.block_storage
                     .get(&hash)
                     .expect(&format!("Failed to read head: {}", HashType::BlockHash.bytes_to_string(&hash)))
-                    .map(|block| Head {
-                        hash: block.hash.clone(),
-                        level: block.header.level(),
-                    })
             }
             None => None
         };
+        if let Some(block) = &current_head {
+            self.learn_protocol(block).expect("Failed to read predecessor of the current head");
+        }
+        self.current_head.local = current_head.map(|block| Head {
+            hash: block.hash.clone(),
+            level: block.header.level(),
+        });
 
         info!(ctx.system.log(), "Hydrating completed successfully");
         self.stats.hydrated_state_last = Some(Instant::now());
@@ -518,36 +880,48 @@ impl Actor for ChainManager {
 
     fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
         subscribe_to_actor_terminated(ctx.system.sys_events(), ctx.myself());
-        subscribe_to_network_events(&self.network_channel, ctx.myself());
+        if !self.is_test_chain() {
+            // test chain manager receives network events from the main chain manager
+            subscribe_to_network_events(&self.network_channel, ctx.myself());
+        }
         subscribe_to_shell_events(&self.shell_channel, ctx.myself());
         subscribe_to_dead_letters(ctx.system.dead_letters(), ctx.myself());
 
         self.hydrate_state(ctx);
 
         ctx.schedule::<Self::Msg, _>(
-            CHECK_CHAIN_COMPLETENESS_INTERVAL / 4,
-            CHECK_CHAIN_COMPLETENESS_INTERVAL,
+            self.timeouts.check_chain_completeness_interval / 4,
+            self.timeouts.check_chain_completeness_interval,
             ctx.myself(),
             None,
             CheckChainCompleteness.into());
         ctx.schedule::<Self::Msg, _>(
-            ASK_CURRENT_BRANCH_INTERVAL,
-            ASK_CURRENT_BRANCH_INTERVAL,
+            self.timeouts.ask_current_branch_interval,
+            self.timeouts.ask_current_branch_interval,
             ctx.myself(),
             None,
             AskPeersAboutCurrentBranch.into());
         ctx.schedule::<Self::Msg, _>(
-            LOG_INTERVAL / 2,
-            LOG_INTERVAL,
+            RETRY_MISSING_PROTOCOLS_INTERVAL,
+            RETRY_MISSING_PROTOCOLS_INTERVAL,
             ctx.myself(),
             None,
-            LogStats.into());
+            RetryMissingProtocols.into());
         ctx.schedule::<Self::Msg, _>(
-            SILENT_PEER_TIMEOUT / 2,
-            SILENT_PEER_TIMEOUT / 2,
+            LOG_INTERVAL / 2,
+            LOG_INTERVAL,
             ctx.myself(),
             None,
-            DisconnectStalledPeers.into());
+            LogStats.into());
+        if !self.is_test_chain() {
+            // peers are not required to follow the test chain, so only main chain manager disconnects stalled peers
+            ctx.schedule::<Self::Msg, _>(
+                self.timeouts.silent_peer_timeout / 2,
+                self.timeouts.silent_peer_timeout / 2,
+                ctx.myself(),
+                None,
+                DisconnectStalledPeers.into());
+        }
     }
 
     fn sys_recv(&mut self, ctx: &Context<Self::Msg>, msg: SystemMsg, sender: Option<BasicActorRef>) {
@@ -569,11 +943,12 @@ impl Receive<SystemEvent> for ChainManager {
             if let Some(mut peer) = self.peers.remove(evt.actor.uri()) {
                 peer.queued_block_headers
                     .drain()
-                    .for_each(|(_, missing_block)| {
+                    .into_iter()
+                    .for_each(|missing_block| {
                         self.block_state.push_missing_block(missing_block).expect("Failed to re-schedule block hash");
                     });
 
-                self.operations_state.push_missing_operations(peer.queued_operations.drain().map(|(_, op)| op))
+                self.operations_state.push_missing_operations(peer.queued_operations.drain().into_iter())
                     .expect("Failed to return to queue")
             }
         }
@@ -609,6 +984,7 @@ impl Receive<LogStats> for ChainManager {
             ),
         };
         info!(log, "Head info";
+            "chain_id" => HashType::ChainId.bytes_to_string(self.block_state.get_chain_id()),
             "local" => local,
             "local_level" => local_level,
             "remote" => remote,
@@ -624,10 +1000,12 @@ impl Receive<LogStats> for ChainManager {
                 "actor_ref" => format!("{}", peer.peer_ref),
                 "queued_block_headers" => peer.queued_block_headers.len(),
                 "queued_operations" => peer.queued_operations.len(),
-                "block_request_secs" => peer.block_request_last.elapsed().as_secs(),
-                "block_response_secs" => peer.block_response_last.elapsed().as_secs(),
-                "operations_request_secs" => peer.operations_request_last.elapsed().as_secs(),
-                "operations_response_secs" => peer.operations_response_last.elapsed().as_secs(),
+                "block_queue_capacity" => peer.queued_block_headers.capacity(),
+                "operations_queue_capacity" => peer.queued_operations.capacity(),
+                "block_request_secs" => peer.queued_block_headers.request_last().elapsed().as_secs(),
+                "block_response_secs" => peer.queued_block_headers.response_last().elapsed().as_secs(),
+                "operations_request_secs" => peer.queued_operations.request_last().elapsed().as_secs(),
+                "operations_response_secs" => peer.queued_operations.response_last().elapsed().as_secs(),
                 "current_head_level" => peer.current_head_level,
                 "current_head_update_secs" => peer.current_head_update_last.elapsed().as_secs());
         }
@@ -639,30 +1017,36 @@ impl Receive<DisconnectStalledPeers> for ChainManager {
     type Msg = ChainManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: DisconnectStalledPeers, _sender: Sender) {
+        let PeerTimeouts { current_head_level_update_timeout, silent_peer_timeout, .. } = self.timeouts;
         self.peers.iter()
+            // peer which deactivated the chain is not expected to update its current head
+            .filter(|(_, state)| !state.is_chain_deactivated())
+            // replayed peer never receives our requests
+            .filter(|(_, state)| !state.replayed)
             .for_each(|(uri, state)| {
-                let block_response_pending = state.block_request_last > state.block_response_last;
-                let operations_response_pending = state.operations_request_last > state.operations_response_last;
-                let should_disconnect = if state.current_head_update_last.elapsed() > CURRENT_HEAD_LEVEL_UPDATE_TIMEOUT {
+                let blocks = &state.queued_block_headers;
+                let operations = &state.queued_operations;
+                let misbehaviour = if state.current_head_update_last.elapsed() > current_head_level_update_timeout {
                     warn!(ctx.system.log(), "Peer failed to update its current head"; "peer" => format!("{}", uri));
-                    true
-                } else if block_response_pending && (state.block_request_last - state.block_response_last > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer did not respond to our request for block on time"; "peer" => format!("{}", uri), "request_secs" => state.block_request_last.elapsed().as_secs(), "response_secs" => state.block_response_last.elapsed().as_secs());
-                    true
-                } else if operations_response_pending && (state.operations_request_last - state.operations_response_last > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer did not respond to our request for operations on time"; "peer" => format!("{}", uri), "request_secs" => state.operations_request_last.elapsed().as_secs(), "response_secs" => state.operations_response_last.elapsed().as_secs());
-                    true
-                } else if block_response_pending && !state.queued_block_headers.is_empty() && (state.block_response_last.elapsed() > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer is not providing requested blocks"; "peer" => format!("{}", uri), "queued_blocks" => state.queued_block_headers.len(), "response_secs" => state.block_response_last.elapsed().as_secs());
-                    true
-                } else if operations_response_pending && !state.queued_operations.is_empty() && (state.operations_response_last.elapsed() > SILENT_PEER_TIMEOUT) {
-                    warn!(ctx.system.log(), "Peer is not providing requested operations"; "peer" => format!("{}", uri), "queued_operations" => state.queued_operations.len(), "response_secs" => state.operations_response_last.elapsed().as_secs());
-                    true
+                    Some(Misbehaviour::StalledHead)
+                } else if blocks.is_response_pending() && (blocks.request_last() - blocks.response_last() > silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer did not respond to our request for block on time"; "peer" => format!("{}", uri), "request_secs" => blocks.request_last().elapsed().as_secs(), "response_secs" => blocks.response_last().elapsed().as_secs());
+                    Some(Misbehaviour::SilentPeer)
+                } else if operations.is_response_pending() && (operations.request_last() - operations.response_last() > silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer did not respond to our request for operations on time"; "peer" => format!("{}", uri), "request_secs" => operations.request_last().elapsed().as_secs(), "response_secs" => operations.response_last().elapsed().as_secs());
+                    Some(Misbehaviour::SilentPeer)
+                } else if blocks.is_response_pending() && !blocks.is_empty() && (blocks.response_last().elapsed() > silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer is not providing requested blocks"; "peer" => format!("{}", uri), "queued_blocks" => blocks.len(), "response_secs" => blocks.response_last().elapsed().as_secs());
+                    Some(Misbehaviour::SilentPeer)
+                } else if operations.is_response_pending() && !operations.is_empty() && (operations.response_last().elapsed() > silent_peer_timeout) {
+                    warn!(ctx.system.log(), "Peer is not providing requested operations"; "peer" => format!("{}", uri), "queued_operations" => operations.len(), "response_secs" => operations.response_last().elapsed().as_secs());
+                    Some(Misbehaviour::SilentPeer)
                 } else {
-                    false
+                    None
                 };
 
-                if should_disconnect {
+                if let Some(misbehaviour) = misbehaviour {
+                    report_misbehaviour(&self.shell_channel, &state.peer_ref, misbehaviour);
                     ctx.system.stop(state.peer_ref.clone());
                 }
             });
@@ -712,67 +1096,178 @@ impl Receive<AskPeersAboutCurrentBranch> for ChainManager {
     fn receive(&mut self, _ctx: &Context<Self::Msg>, _msg: AskPeersAboutCurrentBranch, _sender: Sender) {
         let ChainManager { peers, block_state, .. } = self;
         peers.iter_mut()
+            .filter(|(_, peer)| !peer.is_chain_deactivated())
             .for_each(|(_, peer)| tell_peer(GetCurrentBranchMessage::new(block_state.get_chain_id().clone()).into(), peer))
     }
 }
 
+impl Receive<RetryMissingProtocols> for ChainManager {
+    type Msg = ChainManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: RetryMissingProtocols, _sender: Sender) {
+        let ChainManager { peers, missing_protocols, .. } = self;
+
+        // protocol, which nobody provided for a long time, is not requested anymore
+        missing_protocols.retain(|protocol_hash, requested_at| {
+            let expired = requested_at.elapsed() > MISSING_PROTOCOL_TIMEOUT;
+            if expired {
+                warn!(ctx.system.log(), "Missing protocol was not provided by any peer"; "protocol_hash" => HashType::ProtocolHash.bytes_to_string(protocol_hash));
+            }
+            !expired
+        });
+
+        if !missing_protocols.is_empty() {
+            let msg = GetProtocolsMessage::new(missing_protocols.keys().cloned().collect());
+            peers.values_mut()
+                .for_each(|peer| tell_peer(msg.clone().into(), peer));
+        }
+    }
+}
+
+/// Activation of the chain by the peer
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChainActivation {
+    /// Peer did not send any message for the chain yet
+    Unknown,
+    /// Peer sent message for the chain
+    Active,
+    /// Peer does not follow the chain anymore, so we do not send it any messages for the chain
+    Deactivated,
+}
+
 /// Holds information about a specific peer.
 struct PeerState {
     /// Reference to peer actor
     peer_ref: PeerRef,
-    /// Queued blocks
-    queued_block_headers: HashMap<BlockHash, MissingBlock>,
-    /// Queued operations
-    queued_operations: HashMap<BlockHash, MissingOperations>,
+    /// Peer id of the peer
+    peer_id: CryptoboxPublicKeyHash,
+    /// Peer id of the peer as received from the networking layer (base58check encoded)
+    peer_id_encoded: String,
+    /// Metadata received from the peer during the handshake
+    peer_metadata: MetadataMessage,
+    /// Version negotiated with the peer during the handshake
+    version: Version,
+    /// Peer replays recorded messages, so it sends responses to requests of the recording node
+    replayed: bool,
+    /// Activation of the chain followed by the chain manager
+    chain_activation: ChainActivation,
+    /// Chains, which we do not follow, peer was already told so by the `Deactivate` message
+    unfollowed_chains: HashSet<ChainId>,
+    /// Queued blocks, queue tracks also the last block request and response
+    queued_block_headers: DownloadQueue<MissingBlock>,
+    /// Queued operations, queue tracks also the last operations request and response
+    queued_operations: DownloadQueue<MissingOperations>,
     /// Level of the current head received from peer
     current_head_level: Option<i32>,
     /// Last time we received updated head from peer
     current_head_update_last: Instant,
-    /// Last time we requested block from the peer
-    block_request_last: Instant,
-    /// Last time we received block from the peer
-    block_response_last: Instant,
-    /// Last time we requested operations from the peer
-    operations_request_last: Instant,
-    /// Last time we received operations from the peer
-    operations_response_last: Instant,
-
 }
 
 impl PeerState {
-    fn new(peer_ref: PeerRef) -> Self {
-        PeerState {
+    fn new(peer_ref: PeerRef, peer_id_encoded: String, peer_metadata: MetadataMessage, version: Version, replayed: bool) -> Result<Self, FromBase58CheckError> {
+        Ok(PeerState {
             peer_ref,
-            queued_block_headers: HashMap::new(),
-            queued_operations: HashMap::new(),
+            peer_id: HashType::CryptoboxPublicKeyHash.string_to_bytes(&peer_id_encoded)?,
+            peer_id_encoded,
+            peer_metadata,
+            version,
+            replayed,
+            chain_activation: ChainActivation::Unknown,
+            unfollowed_chains: HashSet::new(),
+            queued_block_headers: DownloadQueue::new(),
+            queued_operations: DownloadQueue::new(),
             current_head_level: None,
             current_head_update_last: Instant::now(),
-            block_request_last: Instant::now(),
-            block_response_last: Instant::now(),
-            operations_request_last: Instant::now(),
-            operations_response_last: Instant::now(),
-        }
+        })
     }
 
+    #[inline]
+    fn is_chain_deactivated(&self) -> bool {
+        self.chain_activation == ChainActivation::Deactivated
+    }
+
+    #[inline]
     fn available_block_queue_capacity(&self) -> usize {
-        let queued_count = self.queued_block_headers.len();
-        if queued_count < BLOCK_HEADERS_BATCH_SIZE {
-            BLOCK_HEADERS_BATCH_SIZE - queued_count
-        } else {
-            0
-        }
+        self.queued_block_headers.available_capacity()
     }
 
+    #[inline]
     fn available_operations_queue_capacity(&self) -> usize {
-        let queued_count = self.queued_operations.len();
-        if queued_count < OPERATIONS_BATCH_SIZE {
-            OPERATIONS_BATCH_SIZE - queued_count
-        } else {
-            0
-        }
+        self.queued_operations.available_capacity()
     }
 }
 
 fn tell_peer(msg: PeerMessageResponse, peer: &mut PeerState) {
     peer.peer_ref.tell(SendMessage::new(msg), None);
-}
\ No newline at end of file
+}
+
+/// Forward peer message to the chain manager of the test chain
+fn forward_peer_message<M>(chain_manager: &ChainManagerRef, peer: &PeerRef, message: &M)
+    where
+        M: Clone + Into<PeerMessage>
+{
+    let message: PeerMessage = message.clone().into();
+    chain_manager.tell(
+        NetworkChannelMsg::PeerMessageReceived(PeerMessageReceived {
+            peer: peer.clone(),
+            message: Arc::new(message.into()),
+        }), None);
+}
+
+/// Chain id of the chain specific message
+fn message_chain_id(message: &PeerMessage) -> Option<&ChainId> {
+    match message {
+        PeerMessage::CurrentBranch(message) => Some(message.chain_id()),
+        PeerMessage::GetCurrentBranch(message) => Some(&message.chain_id),
+        PeerMessage::CurrentHead(message) => Some(message.chain_id()),
+        PeerMessage::GetCurrentHead(message) => Some(message.chain_id()),
+        PeerMessage::Deactivate(message) => Some(message.deactivate()),
+        _ => None,
+    }
+}
+
+/// Message for the running test chain is forwarded to the test chain manager.
+///
+/// Messages for chains, which we do not follow, are ignored. Peer is told that we do not follow the chain
+/// and it is penalized if it keeps sending us messages for the chain.
+fn route_to_other_chain(test_chain: &Option<TestChainManager>, chain_id: &ChainId, peer: &mut PeerState, shell_channel: &ShellChannelRef, message: &PeerMessage) {
+    match test_chain.as_ref().filter(|test_chain| &test_chain.test_chain.chain_id == chain_id) {
+        Some(test_chain) => forward_peer_message(&test_chain.chain_manager, &peer.peer_ref, message),
+        None => match message {
+            // peer does not follow the chain either
+            PeerMessage::Deactivate(_) => (),
+            _ => if peer.unfollowed_chains.insert(chain_id.clone()) {
+                tell_peer(DeactivateMessage::new(chain_id.clone()).into(), peer);
+            } else {
+                report_misbehaviour(shell_channel, &peer.peer_ref, Misbehaviour::InactiveChain);
+            }
+        }
+    }
+}
+
+/// Read hashes of the protocol used by the block and of the protocol activated by the block from the block metadata
+fn block_protocols(json_data: &BlockJsonData) -> Result<Vec<ProtocolHash>, Error> {
+    let metadata: HashMap<String, serde_json::Value> = serde_json::from_str(json_data.block_header_proto_metadata_json())?;
+    let mut protocols: Vec<ProtocolHash> = vec![];
+    for key in &["protocol", "next_protocol"] {
+        if let Some(protocol_hash) = metadata.get(*key).and_then(|value| value.as_str()) {
+            let protocol_hash = HashType::ProtocolHash.string_to_bytes(protocol_hash)?;
+            if !protocols.contains(&protocol_hash) {
+                protocols.push(protocol_hash);
+            }
+        }
+    }
+    Ok(protocols)
+}
+
+/// Let the peer manager know about peer misbehaviour, so the peer can be banned
+fn report_misbehaviour(shell_channel: &ShellChannelRef, peer: &PeerRef, misbehaviour: Misbehaviour) {
+    shell_channel.tell(
+        Publish {
+            msg: PeerMisbehaved {
+                peer: peer.clone(),
+                misbehaviour,
+            }.into(),
+            topic: ShellChannelTopic::ShellEvents.into(),
+        }, None);
+}
@@ -12,14 +12,21 @@ use failure::Error;
 use riker::actors::*;
 use slog::{crit, debug, Logger, warn};
 
+use crypto::hash::{ContextHash, HashType};
 use storage::{BlockStorage, ContextActionStorage};
 use storage::context::{ContextApi, ContextDiff, TezedgeContext};
-use storage::persistent::PersistentStorage;
+use storage::merkle_tree::MerkleTree;
+use storage::persistent::{ContextList, PersistentStorage};
 use tezos_context::channel::ContextAction;
 use tezos_wrapper::service::IpcEvtServer;
 
+use crate::context_verifier::SharedContextHashVerifier;
+
 type SharedJoinHandle = Arc<Mutex<Option<JoinHandle<Result<(), Error>>>>>;
 
+/// Max count of committed trees, which can be checked out without rebuilding the tree from the context storage
+const MERKLE_TREE_RETAINED_COMMITS: usize = 16;
+
 /// This actor listens for events generated by the `protocol_runner`.
 #[actor]
 pub struct ContextListener {
@@ -37,7 +44,10 @@ impl ContextListener {
     ///
     /// This actor spawns a new thread in which it listens for incoming events from the `protocol_runner`.
     /// Events are received from IPC channel provided by [`event_server`](IpcEvtServer).
-    pub fn actor(sys: &impl ActorRefFactory, persistent_storage: &PersistentStorage, mut event_server: IpcEvtServer, log: Logger) -> Result<ContextListenerRef, CreateError> {
+    ///
+    /// If the [`context_hash_verifier`](SharedContextHashVerifier) is provided, context actions are applied also
+    /// to the native [`MerkleTree`] and its commits are verified against the context hashes returned by the protocol.
+    pub fn actor(sys: &impl ActorRefFactory, persistent_storage: &PersistentStorage, mut event_server: IpcEvtServer, context_hash_verifier: Option<SharedContextHashVerifier>, log: Logger) -> Result<ContextListenerRef, CreateError> {
         let context_storage = persistent_storage.context_storage();
         let listener_run = Arc::new(AtomicBool::new(true));
         let block_applier_thread = {
@@ -45,14 +55,21 @@ impl ContextListener {
             let persistent_storage = persistent_storage.clone();
 
             thread::spawn(move || {
-                let mut context: Box<dyn ContextApi> = Box::new(TezedgeContext::new(BlockStorage::new(&persistent_storage), context_storage));
+                let mut context: Box<dyn ContextApi> = Box::new(TezedgeContext::new(BlockStorage::new(&persistent_storage), context_storage.clone()));
                 let mut context_action_storage = ContextActionStorage::new(&persistent_storage);
+                let mut merkle = context_hash_verifier.map(|verifier| MerkleVerification {
+                    tree: MerkleTree::new(MERKLE_TREE_RETAINED_COMMITS),
+                    is_checked_out: true,
+                    context_storage,
+                    verifier,
+                });
                 while listener_run.load(Ordering::Acquire) {
                     match listen_protocol_events(
                         &listener_run,
                         &mut event_server,
                         &mut context_action_storage,
                         &mut context,
+                        &mut merkle,
                         &log,
                     ) {
                         Ok(()) => debug!(log, "Context listener finished"),
@@ -105,11 +122,66 @@ impl Actor for ContextListener {
     }
 }
 
+/// Native merkle tree updated by the context actions, used only to verify context hashes
+struct MerkleVerification {
+    tree: MerkleTree,
+    /// Actions are not applied until the next successful checkout, if the checkouted context is not available
+    is_checked_out: bool,
+    /// Tree is rebuilt from the context storage, if the checkouted context was not committed by the tree
+    context_storage: ContextList,
+    verifier: SharedContextHashVerifier,
+}
+
+impl MerkleVerification {
+    fn apply(&mut self, action: &ContextAction, context_diff: &ContextDiff, log: &Logger) {
+        match action {
+            ContextAction::Checkout { context_hash, .. } => {
+                self.is_checked_out = self.tree.checkout(context_hash).is_ok() || self.rebuild(context_hash, context_diff, log);
+            }
+            ContextAction::Commit { parent_context_hash, block_hash: Some(block_hash), new_context_hash, .. } if self.is_checked_out => {
+                self.tree.commit(new_context_hash);
+                let root_hash = self.tree.tree_hash();
+                self.verifier.lock().unwrap().tree_committed(block_hash, root_hash, parent_context_hash.clone(), new_context_hash.clone());
+            }
+            _ if self.is_checked_out => {
+                if let Err(e) = self.tree.apply_action(action) {
+                    warn!(log, "Failed to apply context action to merkle tree"; "reason" => format!("{}", e));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Rebuild the tree from the context storage, returns `true` if the context is available
+    fn rebuild(&mut self, context_hash: &ContextHash, context_diff: &ContextDiff, log: &Logger) -> bool {
+        let context = match context_diff.predecessor_level() {
+            Some(level) => self.context_storage.read().expect("lock poisoning").get(level),
+            None => Ok(None),
+        };
+        match context {
+            Ok(Some(context)) => {
+                debug!(log, "Merkle tree rebuilt from context storage"; "context_hash" => HashType::ContextHash.bytes_to_string(context_hash));
+                self.tree.checkout_context(context_hash, &context);
+                true
+            }
+            Ok(None) => {
+                warn!(log, "Context is not available, context hashes are not verified until the next checkout"; "context_hash" => HashType::ContextHash.bytes_to_string(context_hash));
+                false
+            }
+            Err(e) => {
+                warn!(log, "Failed to read context, context hashes are not verified until the next checkout"; "context_hash" => HashType::ContextHash.bytes_to_string(context_hash), "reason" => format!("{}", e));
+                false
+            }
+        }
+    }
+}
+
 fn listen_protocol_events(
     apply_block_run: &AtomicBool,
     event_server: &mut IpcEvtServer,
     context_action_storage: &mut ContextActionStorage,
     context: &mut Box<dyn ContextApi>,
+    merkle: &mut Option<MerkleVerification>,
     log: &Logger,
 ) -> Result<(), Error> {
     debug!(log, "Waiting for connection from protocol runner");
@@ -160,6 +232,10 @@ fn listen_protocol_events(
                     }
                     _ => (),
                 };
+
+                if let Some(merkle) = merkle {
+                    merkle.apply(&msg, &context_diff, log);
+                }
             }
             Err(err) => {
                 warn!(log, "Failed to receive event from protocol runner"; "reason" => format!("{:?}", err));
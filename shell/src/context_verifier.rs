@@ -0,0 +1,104 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Cross-checks context hashes computed by the native [`MerkleTree`](storage::merkle_tree::MerkleTree)
+//! with the context hashes returned by the protocol.
+//!
+//! Tree is committed by the context listener, but the commit message is known only after the block is applied
+//! by the chain feeder, so the verification is finished by whichever comes later.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use slog::{debug, Logger, warn};
+
+use crypto::hash::{BlockHash, ContextHash, HashType};
+use storage::merkle_tree::{CommitInfo, EntryHash, hash_commit};
+
+/// Author of the context commits created by the Tezos node
+const COMMIT_AUTHOR: &str = "Tezos";
+
+/// Max count of the commits waiting for the other half of the verification
+const MAX_PENDING_COMMITS: usize = 64;
+
+/// Verifier shared by the context listener and the chain feeder
+pub type SharedContextHashVerifier = Arc<Mutex<ContextHashVerifier>>;
+
+struct CommittedTree {
+    root_hash: EntryHash,
+    parent_context_hash: Option<ContextHash>,
+    context_hash: ContextHash,
+}
+
+/// Pairs the committed trees with the commit info of the applied blocks and logs hash mismatches
+pub struct ContextHashVerifier {
+    committed_trees: VecDeque<(BlockHash, CommittedTree)>,
+    commit_infos: VecDeque<(BlockHash, CommitInfo)>,
+    /// Count of blocks with the matching context hash
+    verified: usize,
+    /// Count of blocks with the different context hash
+    mismatched: usize,
+    log: Logger,
+}
+
+impl ContextHashVerifier {
+    pub fn new(log: Logger) -> Self {
+        ContextHashVerifier {
+            committed_trees: VecDeque::new(),
+            commit_infos: VecDeque::new(),
+            verified: 0,
+            mismatched: 0,
+            log,
+        }
+    }
+
+    pub fn shared(log: Logger) -> SharedContextHashVerifier {
+        Arc::new(Mutex::new(Self::new(log)))
+    }
+
+    /// Context listener committed tree of the block, `context_hash` is the hash returned by the protocol
+    pub fn tree_committed(&mut self, block_hash: &BlockHash, root_hash: EntryHash, parent_context_hash: Option<ContextHash>, context_hash: ContextHash) {
+        let tree = CommittedTree { root_hash, parent_context_hash, context_hash };
+        match take(&mut self.commit_infos, block_hash) {
+            Some(info) => self.verify(block_hash, &tree, &info),
+            None => push(&mut self.committed_trees, block_hash, tree),
+        }
+    }
+
+    /// Chain feeder applied the block, commit was created with the `message` and block timestamp as the `date`
+    pub fn block_applied(&mut self, block_hash: &BlockHash, message: String, date: i64) {
+        let info = CommitInfo { author: COMMIT_AUTHOR.to_string(), message, date };
+        match take(&mut self.committed_trees, block_hash) {
+            Some(tree) => self.verify(block_hash, &tree, &info),
+            None => push(&mut self.commit_infos, block_hash, info),
+        }
+    }
+
+    fn verify(&mut self, block_hash: &BlockHash, tree: &CommittedTree, info: &CommitInfo) {
+        let computed_hash = hash_commit(&tree.root_hash, tree.parent_context_hash.as_ref(), info);
+        if computed_hash == tree.context_hash {
+            self.verified += 1;
+            debug!(self.log, "Context hash verified"; "block_header_hash" => HashType::BlockHash.bytes_to_string(block_hash), "verified" => self.verified);
+        } else {
+            self.mismatched += 1;
+            warn!(self.log, "Context hash mismatch";
+                "block_header_hash" => HashType::BlockHash.bytes_to_string(block_hash),
+                "context_hash" => HashType::ContextHash.bytes_to_string(&tree.context_hash),
+                "computed_context_hash" => HashType::ContextHash.bytes_to_string(&computed_hash),
+                "mismatched" => self.mismatched);
+        }
+    }
+}
+
+fn take<T>(pending: &mut VecDeque<(BlockHash, T)>, block_hash: &BlockHash) -> Option<T> {
+    let position = pending.iter().position(|(hash, _)| hash == block_hash)?;
+    pending.remove(position).map(|(_, value)| value)
+}
+
+/// Oldest commits are dropped, e.g. genesis commit is never applied by the chain feeder
+fn push<T>(pending: &mut VecDeque<(BlockHash, T)>, block_hash: &BlockHash, value: T) {
+    pending.push_back((block_hash.clone(), value));
+    while pending.len() > MAX_PENDING_COMMITS {
+        pending.pop_front();
+    }
+}
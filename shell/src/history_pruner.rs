@@ -0,0 +1,151 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Prunes history of the old blocks in the `full` and `rolling` history modes.
+//! - keeps the current cycle and the configured count of the preceding cycles
+//! - blocks are pruned in small batches, so the context is not locked for too long
+
+use riker::actors::*;
+use slog::{debug, info, warn};
+
+use storage::block_storage::BlockLevel;
+use storage::persistent::PersistentStorage;
+use storage::pruning::{self, HistoryMode};
+
+use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use crate::subscription::subscribe_to_shell_events;
+
+/// Max count of levels pruned by a single [`PruneHistory`] message
+const PRUNE_BATCH_SIZE: BlockLevel = 64;
+
+/// Message commands [`HistoryPruner`] to prune the next batch of blocks.
+#[derive(Clone, Debug)]
+pub struct PruneHistory;
+
+/// History pruning configuration
+#[derive(Clone, Debug)]
+pub struct HistoryPrunerConfiguration {
+    pub mode: HistoryMode,
+    /// Count of retained cycles preceding the current cycle
+    pub retained_cycles: i32,
+    pub blocks_per_cycle: i32,
+}
+
+/// Purpose of this actor is to remove history, which is not required by the configured history mode.
+#[actor(PruneHistory, ShellChannelMsg)]
+pub struct HistoryPruner {
+    /// All events from shell will be published to this channel
+    shell_channel: ShellChannelRef,
+    /// Removes the data from the storage
+    pruner: pruning::HistoryPruner,
+    /// Count of retained cycles preceding the current cycle
+    retained_cycles: i32,
+    blocks_per_cycle: i32,
+    /// Blocks below this level should be pruned
+    cutoff_level: BlockLevel,
+    /// Indicates that [`PruneHistory`] message was sent and was not processed yet
+    pruning_scheduled: bool,
+    /// Indicates that system is shutting down
+    shutting_down: bool,
+}
+
+/// Reference to [history pruner](HistoryPruner) actor.
+pub type HistoryPrunerRef = ActorRef<HistoryPrunerMsg>;
+
+impl HistoryPruner {
+    /// Create new actor instance.
+    pub fn actor(sys: &impl ActorRefFactory, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, config: &HistoryPrunerConfiguration) -> Result<HistoryPrunerRef, CreateError> {
+        sys.actor_of(
+            Props::new_args(HistoryPruner::new, (shell_channel, persistent_storage.clone(), config.clone())),
+            HistoryPruner::name())
+    }
+
+    /// The `HistoryPruner` is intended to serve as a singleton actor so that's why
+    /// we won't support multiple names per instance.
+    fn name() -> &'static str {
+        "history-pruner"
+    }
+
+    fn new((shell_channel, persistent_storage, config): (ShellChannelRef, PersistentStorage, HistoryPrunerConfiguration)) -> Self {
+        HistoryPruner {
+            shell_channel,
+            pruner: pruning::HistoryPruner::new(&persistent_storage, config.mode),
+            retained_cycles: config.retained_cycles,
+            blocks_per_cycle: config.blocks_per_cycle,
+            cutoff_level: 0,
+            pruning_scheduled: false,
+            shutting_down: false,
+        }
+    }
+
+    fn schedule_pruning(&mut self, ctx: &Context<HistoryPrunerMsg>) {
+        if !self.pruning_scheduled {
+            self.pruning_scheduled = true;
+            ctx.myself().tell(PruneHistory, None);
+        }
+    }
+}
+
+impl Actor for HistoryPruner {
+    type Msg = HistoryPrunerMsg;
+
+    fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+        subscribe_to_shell_events(&self.shell_channel, ctx.myself());
+    }
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.receive(ctx, msg, sender);
+    }
+}
+
+impl Receive<ShellChannelMsg> for HistoryPruner {
+    type Msg = HistoryPrunerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
+        match msg {
+            ShellChannelMsg::BlockApplied(message) => {
+                let cutoff_level = pruning::cutoff_level(message.header().header.level(), self.retained_cycles, self.blocks_per_cycle);
+                if cutoff_level > self.cutoff_level {
+                    self.cutoff_level = cutoff_level;
+                    self.schedule_pruning(ctx);
+                }
+            }
+            ShellChannelMsg::ShuttingDown(_) => {
+                self.shutting_down = true;
+            }
+            _ => ()
+        }
+    }
+}
+
+impl Receive<PruneHistory> for HistoryPruner {
+    type Msg = HistoryPrunerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: PruneHistory, _sender: Sender) {
+        self.pruning_scheduled = false;
+        if self.shutting_down {
+            return;
+        }
+
+        let previous_level = match self.pruner.pruned_level() {
+            Ok(level) => level,
+            Err(e) => {
+                warn!(ctx.system.log(), "Failed to read pruned level"; "reason" => e);
+                return;
+            }
+        };
+
+        match self.pruner.prune(self.cutoff_level, PRUNE_BATCH_SIZE) {
+            Ok(pruned_level) if pruned_level > previous_level => {
+                debug!(ctx.system.log(), "History pruned"; "pruned_level" => pruned_level, "cutoff_level" => self.cutoff_level);
+                if pruned_level < self.cutoff_level {
+                    self.schedule_pruning(ctx);
+                } else {
+                    info!(ctx.system.log(), "History pruning finished"; "pruned_level" => pruned_level);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => warn!(ctx.system.log(), "Failed to prune history"; "reason" => e),
+        }
+    }
+}
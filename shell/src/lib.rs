@@ -3,6 +3,7 @@
 
 //! This crate contains all shell actors plus few types used to handle the complexity of chain synchronisation process.
 
+mod block_locator;
 mod collections;
 mod state;
 
@@ -10,8 +11,22 @@ pub mod stats;
 pub mod shell_channel;
 pub mod chain_feeder;
 pub mod context_listener;
+pub mod context_verifier;
 pub mod chain_manager;
+pub mod history_pruner;
+pub mod mempool_manager;
 pub mod peer_manager;
+pub mod peer_reputation;
+pub mod p2p_replay;
+pub mod test_chain;
+pub mod validation;
+
+/// Current time as unix timestamp (seconds)
+pub fn unix_timestamp_now() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
 
 pub(crate) mod subscription {
     use riker::actors::*;
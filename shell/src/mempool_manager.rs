@@ -0,0 +1,411 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Manages mempool - pool of operations which are not yet included in any block.
+//! - collects operation hashes advertised by peers in their current head and downloads missing operations
+//! - supplies stored operations to other peers
+//! - advertises newly received operations to other peers
+//! - removes operations included in the current head and operations with expired branch
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use riker::actors::*;
+use slog::{debug, FnValue, info, trace, warn};
+
+use crypto::hash::{ChainId, HashType, OperationHash};
+use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, PeerBootstrapped};
+use networking::p2p::peer::{PeerRef, SendMessage};
+use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, OperationsStorage, OperationsStorageReader};
+use storage::persistent::PersistentStorage;
+use tezos_messages::p2p::binary_message::MessageHash;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use crate::state::mempool_state::MempoolState;
+use crate::subscription::*;
+
+/// Maximal count of operations stored in the mempool
+const MEMPOOL_MAX_OPERATIONS: usize = 10_000;
+/// Limit to how many operations to request from peer in a batch
+const OPERATIONS_REQUEST_BATCH_SIZE: usize = 100;
+/// After this time the operation can be requested again (probably from different peer)
+const OPERATION_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to advertise newly received operations to peers
+const ADVERTISE_MEMPOOL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often to print stats in logs
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Message commands [`MempoolManager`] to advertise newly received operations to peers.
+#[derive(Clone, Debug)]
+pub struct AdvertiseMempool;
+
+/// Message commands [`MempoolManager`] to log its internal stats.
+#[derive(Clone, Debug)]
+pub struct LogStats;
+
+/// Current head with the data required to check the validity of the operation branch
+struct Head {
+    /// Current head block
+    block: BlockHeaderWithHash,
+    /// Operations with branch older than `max_operations_ttl` levels are expired
+    max_operations_ttl: u16,
+}
+
+impl Head {
+    /// Lowest level of the block which can be used as an operation branch
+    fn min_branch_level(&self) -> i32 {
+        self.block.header.level() - i32::from(self.max_operations_ttl)
+    }
+}
+
+/// Purpose of this actor is to maintain pool of pending operations.
+#[actor(AdvertiseMempool, LogStats, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
+pub struct MempoolManager {
+    /// All events generated by the network layer will end up in this channel
+    network_channel: NetworkChannelRef,
+    /// All events from shell will be published to this channel
+    shell_channel: ShellChannelRef,
+    /// Holds the state of all peers
+    peers: HashMap<ActorUri, PeerState>,
+    /// Block storage
+    block_storage: Box<dyn BlockStorageReader>,
+    /// Block meta storage
+    block_meta_storage: BlockMetaStorage,
+    /// Operations storage
+    operations_storage: Box<dyn OperationsStorageReader>,
+    /// Chain which mempool is maintained
+    chain_id: ChainId,
+    /// Current head
+    current_head: Option<Head>,
+    /// Pool of operations
+    mempool: MempoolState,
+    /// Operations which were requested from peers, but were not yet received
+    requested_operations: HashMap<OperationHash, Instant>,
+    /// Operations received since the last advertisement
+    unadvertised_operations: Vec<OperationHash>,
+    /// Indicates that system is shutting down
+    shutting_down: bool,
+}
+
+/// Reference to [mempool manager](MempoolManager) actor.
+pub type MempoolManagerRef = ActorRef<MempoolManagerMsg>;
+
+impl MempoolManager {
+
+    /// Create new actor instance.
+    pub fn actor(sys: &impl ActorRefFactory, network_channel: NetworkChannelRef, shell_channel: ShellChannelRef, persistent_storage: &PersistentStorage, chain_id: &ChainId) -> Result<MempoolManagerRef, CreateError> {
+        sys.actor_of(
+            Props::new_args(
+                MempoolManager::new,
+                (
+                    network_channel,
+                    shell_channel,
+                    persistent_storage.clone(),
+                    chain_id.clone()
+                )
+            ),
+            MempoolManager::name())
+    }
+
+    /// The `MempoolManager` is intended to serve as a singleton actor so that's why
+    /// we won't support multiple names per instance.
+    fn name() -> &'static str {
+        "mempool-manager"
+    }
+
+    fn new((network_channel, shell_channel, persistent_storage, chain_id): (NetworkChannelRef, ShellChannelRef, PersistentStorage, ChainId)) -> Self {
+        MempoolManager {
+            network_channel,
+            shell_channel,
+            peers: HashMap::new(),
+            block_storage: Box::new(BlockStorage::new(&persistent_storage)),
+            block_meta_storage: BlockMetaStorage::new(&persistent_storage),
+            operations_storage: Box::new(OperationsStorage::new(&persistent_storage)),
+            chain_id,
+            current_head: None,
+            mempool: MempoolState::new(MEMPOOL_MAX_OPERATIONS),
+            requested_operations: HashMap::new(),
+            unadvertised_operations: Vec::new(),
+            shutting_down: false,
+        }
+    }
+
+    fn process_network_channel_message(&mut self, ctx: &Context<MempoolManagerMsg>, msg: NetworkChannelMsg) -> Result<(), Error> {
+        match msg {
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer, peer_metadata, .. }) => {
+                self.peers.insert(peer.uri().clone(), PeerState::new(peer, peer_metadata.disable_mempool()));
+            }
+            NetworkChannelMsg::PeerMessageReceived(received) => {
+                let log = ctx.system.log().new(slog::o!("peer" => received.peer.name().to_string()));
+                let MempoolManager { peers, mempool, requested_operations, unadvertised_operations, block_meta_storage, current_head, chain_id, .. } = self;
+
+                match peers.get_mut(received.peer.uri()) {
+                    Some(peer) => {
+                        for message in received.message.messages() {
+                            match message {
+                                PeerMessage::CurrentHead(message) => {
+                                    if chain_id != message.chain_id() {
+                                        continue;
+                                    }
+                                    peer.chain_deactivated = false;
+
+                                    let mempool_operations = message.current_mempool().known_valid().iter()
+                                        .chain(message.current_mempool().pending().iter())
+                                        .filter(|operation_hash| !mempool.contains(operation_hash))
+                                        .filter(|operation_hash| !requested_operations.contains_key(*operation_hash))
+                                        .take(OPERATIONS_REQUEST_BATCH_SIZE)
+                                        .cloned()
+                                        .collect::<HashSet<_>>();
+
+                                    if !mempool_operations.is_empty() {
+                                        trace!(log, "Requesting mempool operations"; "count" => mempool_operations.len());
+                                        let now = Instant::now();
+                                        mempool_operations.iter().for_each(|operation_hash| { requested_operations.insert(operation_hash.clone(), now); });
+                                        tell_peer(GetOperationsMessage::new(mempool_operations.into_iter().collect()).into(), peer);
+                                    }
+                                }
+                                PeerMessage::Operation(message) => {
+                                    let operation = message.operation();
+                                    let operation_hash = operation.message_hash()?;
+                                    if requested_operations.remove(&operation_hash).is_none() {
+                                        trace!(log, "Received unexpected operation"; "operation_hash" => HashType::OperationHash.bytes_to_string(&operation_hash));
+                                        continue;
+                                    }
+
+                                    let branch_level = match block_meta_storage.get(operation.branch())? {
+                                        Some(branch_meta) => branch_meta.level(),
+                                        None => {
+                                            debug!(log, "Operation branch is unknown"; "operation_hash" => HashType::OperationHash.bytes_to_string(&operation_hash), "branch" => HashType::BlockHash.bytes_to_string(operation.branch()));
+                                            continue;
+                                        }
+                                    };
+                                    if let Some(current_head) = current_head {
+                                        if branch_level < current_head.min_branch_level() {
+                                            debug!(log, "Operation branch is expired"; "operation_hash" => HashType::OperationHash.bytes_to_string(&operation_hash), "branch" => HashType::BlockHash.bytes_to_string(operation.branch()));
+                                            continue;
+                                        }
+                                    }
+
+                                    if mempool.insert(operation_hash.clone(), operation.clone(), branch_level) {
+                                        trace!(log, "Operation added to mempool"; "operation_hash" => HashType::OperationHash.bytes_to_string(&operation_hash));
+                                        unadvertised_operations.push(operation_hash);
+                                    }
+                                }
+                                PeerMessage::Deactivate(message) => {
+                                    if chain_id == message.deactivate() {
+                                        peer.chain_deactivated = true;
+                                    }
+                                }
+                                PeerMessage::GetOperations(message) => {
+                                    for operation_hash in message.get_operations() {
+                                        if let Some(operation) = mempool.get(operation_hash) {
+                                            tell_peer(OperationMessage::new(operation.clone()).into(), peer);
+                                        }
+                                    }
+                                }
+                                _ => trace!(log, "Ignored message"; "message" => FnValue(|_| format!("{:?}", message)))
+                            }
+                        }
+                    }
+                    None => debug!(log, "Received message from non-existing peer")
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn process_shell_channel_message(&mut self, ctx: &Context<MempoolManagerMsg>, msg: ShellChannelMsg) -> Result<(), Error> {
+        match msg {
+            ShellChannelMsg::NewCurrentHead(message) if message.chain_id() == &self.chain_id => {
+                self.set_current_head(message.header().clone())?;
+
+                // remove operations included in the new head
+                let mut included_operations = 0;
+                for operations in self.operations_storage.get_operations(&message.header().hash)? {
+                    for operation in operations.operations() {
+                        if self.mempool.remove(&operation.message_hash()?).is_some() {
+                            included_operations += 1;
+                        }
+                    }
+                }
+
+                // remove operations with expired branch
+                let expired_operations = match &self.current_head {
+                    Some(current_head) => self.mempool.expire(current_head.min_branch_level()),
+                    None => vec![],
+                };
+                if included_operations > 0 || !expired_operations.is_empty() {
+                    debug!(ctx.system.log(), "Mempool operations removed"; "included" => included_operations, "expired" => expired_operations.len());
+                }
+
+                let MempoolManager { mempool, unadvertised_operations, .. } = self;
+                unadvertised_operations.retain(|operation_hash| mempool.contains(operation_hash));
+            }
+            ShellChannelMsg::ShuttingDown(_) => {
+                self.shutting_down = true;
+                unsubscribe_from_dead_letters(ctx.system.dead_letters(), ctx.myself());
+            }
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn set_current_head(&mut self, block: BlockHeaderWithHash) -> Result<(), Error> {
+        if let Some((_, additional_data)) = self.block_storage.get_with_additional_data(&block.hash)? {
+            self.current_head = Some(Head {
+                block,
+                max_operations_ttl: additional_data.max_operations_ttl(),
+            });
+        }
+        Ok(())
+    }
+
+    fn load_current_head(&mut self) -> Result<(), Error> {
+        if let Some(block_hash) = self.block_meta_storage.load_current_head()? {
+            if let Some(block) = self.block_storage.get(&block_hash)? {
+                self.set_current_head(block)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Actor for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+        subscribe_to_actor_terminated(ctx.system.sys_events(), ctx.myself());
+        subscribe_to_network_events(&self.network_channel, ctx.myself());
+        subscribe_to_shell_events(&self.shell_channel, ctx.myself());
+        subscribe_to_dead_letters(ctx.system.dead_letters(), ctx.myself());
+
+        if let Err(e) = self.load_current_head() {
+            warn!(ctx.system.log(), "Failed to load current head"; "reason" => format!("{:?}", e));
+        }
+
+        ctx.schedule::<Self::Msg, _>(
+            ADVERTISE_MEMPOOL_INTERVAL,
+            ADVERTISE_MEMPOOL_INTERVAL,
+            ctx.myself(),
+            None,
+            AdvertiseMempool.into());
+        ctx.schedule::<Self::Msg, _>(
+            LOG_INTERVAL / 2,
+            LOG_INTERVAL,
+            ctx.myself(),
+            None,
+            LogStats.into());
+    }
+
+    fn sys_recv(&mut self, ctx: &Context<Self::Msg>, msg: SystemMsg, sender: Option<BasicActorRef>) {
+        if let SystemMsg::Event(evt) = msg {
+            self.receive(ctx, evt, sender);
+        }
+    }
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.receive(ctx, msg, sender);
+    }
+}
+
+impl Receive<SystemEvent> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: SystemEvent, _sender: Option<BasicActorRef>) {
+        if let SystemEvent::ActorTerminated(evt) = msg {
+            self.peers.remove(evt.actor.uri());
+        }
+    }
+}
+
+impl Receive<DeadLetter> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: DeadLetter, _sender: Option<BasicActorRef>) {
+        self.peers.remove(msg.recipient.uri());
+    }
+}
+
+impl Receive<NetworkChannelMsg> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: NetworkChannelMsg, _sender: Sender) {
+        match self.process_network_channel_message(ctx, msg) {
+            Ok(_) => (),
+            Err(e) => warn!(ctx.system.log(), "Failed to process network channel message"; "reason" => format!("{:?}", e)),
+        }
+    }
+}
+
+impl Receive<ShellChannelMsg> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
+        match self.process_shell_channel_message(ctx, msg) {
+            Ok(_) => (),
+            Err(e) => warn!(ctx.system.log(), "Failed to process shell channel message"; "reason" => format!("{:?}", e)),
+        }
+    }
+}
+
+impl Receive<AdvertiseMempool> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, _msg: AdvertiseMempool, _sender: Sender) {
+        if self.shutting_down {
+            return;
+        }
+
+        // forget requests which were not answered on time, so operations can be requested again
+        self.requested_operations.retain(|_, requested| requested.elapsed() < OPERATION_REQUEST_TIMEOUT);
+
+        if self.unadvertised_operations.is_empty() {
+            return;
+        }
+
+        let MempoolManager { peers, current_head, unadvertised_operations, chain_id, .. } = self;
+        if let Some(current_head) = current_head {
+            let mempool = Mempool::new(vec![], unadvertised_operations.drain(..).collect());
+            peers.values_mut()
+                .filter(|peer| !peer.chain_deactivated && !peer.disable_mempool)
+                .for_each(|peer| tell_peer(CurrentHeadMessage::with_mempool(chain_id.clone(), (*current_head.block.header).clone(), mempool.clone()).into(), peer));
+        }
+    }
+}
+
+impl Receive<LogStats> for MempoolManager {
+    type Msg = MempoolManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: LogStats, _sender: Sender) {
+        info!(ctx.system.log(), "Mempool info";
+            "operations" => self.mempool.len(),
+            "requested_operations" => self.requested_operations.len(),
+            "unadvertised_operations" => self.unadvertised_operations.len(),
+            "peer_count" => self.peers.len());
+    }
+}
+
+/// Holds information about a specific peer.
+struct PeerState {
+    /// Reference to peer actor
+    peer_ref: PeerRef,
+    /// Peer does not follow our chain, so mempool is not advertised to it
+    chain_deactivated: bool,
+    /// Peer told us in its metadata, that it does not want to receive mempool operations
+    disable_mempool: bool,
+}
+
+impl PeerState {
+    fn new(peer_ref: PeerRef, disable_mempool: bool) -> Self {
+        PeerState { peer_ref, chain_deactivated: false, disable_mempool }
+    }
+}
+
+fn tell_peer(msg: PeerMessageResponse, peer: &mut PeerState) {
+    peer.peer_ref.tell(SendMessage::new(msg), None);
+}
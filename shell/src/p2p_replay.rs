@@ -0,0 +1,326 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Offline replay of the p2p messages recorded in the [`P2PMessageStorage`].
+//!
+//! Messages received from the remote peers are published into the network channel as if they were received
+//! from the network, the recorded delays between messages are compressed by the speedup factor.
+//! Every recorded peer is represented by a peer actor, which is never connected, so messages sent to it are dropped.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use riker::actors::*;
+use rocksdb::DB;
+use slog::{info, warn};
+use tokio::runtime::Handle;
+use tokio::time::delay_for;
+
+use crypto::hash::HashType;
+use networking::p2p::bandwidth::PeerBandwidth;
+use networking::p2p::network_channel::{NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerCreated, PeerMessageReceived};
+use networking::p2p::peer::{Peer, PeerRef, supported_versions};
+use storage::p2p_message_storage::{P2PMessage, P2PMessageStorage};
+use storage::persistent::PersistentStorage;
+use tezos_api::identity::Identity;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
+use crate::subscription::*;
+
+/// Count of the recorded messages read from the storage at once
+const REPLAY_BATCH_SIZE: usize = 1_000;
+
+/// Recorded connection of the remote peer
+#[derive(Default)]
+struct RecordedSession {
+    /// Connection message received from the remote peer
+    connection_message: Option<ConnectionMessage>,
+    /// Metadata received from the remote peer
+    metadata: Option<MetadataMessage>,
+    /// Peer actor is created when the first peer message of the session is replayed
+    peer: Option<PeerRef>,
+}
+
+/// Replays recorded messages in a tokio task
+struct Replayer {
+    network_channel: NetworkChannelRef,
+    tokio_executor: Handle,
+    identity: Identity,
+    protocol_version: String,
+    /// Storage with the recorded messages
+    recorded_storage: P2PMessageStorage,
+    /// Message storage of the replaying node, it is used by the replayed peers
+    p2p_msg_storage: P2PMessageStorage,
+    /// Recorded delays between messages are divided by this factor
+    speedup: f64,
+    /// Only messages of these peers are replayed, all peers are replayed if empty
+    peers: HashSet<SocketAddr>,
+    /// Replay is stopped when set to false
+    run: Arc<AtomicBool>,
+    sessions: HashMap<SocketAddr, RecordedSession>,
+}
+
+impl Replayer {
+    async fn run(mut self, system: ActorSystem) {
+        let log = system.log();
+        info!(log, "Replaying recorded p2p messages"; "speedup" => self.speedup, "peers" => format!("{:?}", &self.peers));
+
+        let started = Instant::now();
+        let mut first_timestamp = None;
+        let mut next_id = 0;
+        let mut replayed: usize = 0;
+        'replay: while self.run.load(Ordering::Acquire) {
+            let messages = match self.recorded_storage.get_from(next_id, REPLAY_BATCH_SIZE) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!(log, "Failed to read recorded p2p messages"; "reason" => e);
+                    break;
+                }
+            };
+            next_id = match messages.last() {
+                Some(message) => message.id() + 1,
+                None => break,
+            };
+
+            for message in messages {
+                if !self.run.load(Ordering::Acquire) {
+                    break 'replay;
+                }
+                if !self.peers.is_empty() && !self.peers.contains(&message.remote_addr()) {
+                    continue;
+                }
+
+                // keep the recorded delay from the first replayed message
+                let first_timestamp = *first_timestamp.get_or_insert(message.timestamp());
+                let due = Duration::from_nanos((message.timestamp().saturating_sub(first_timestamp) as f64 / self.speedup) as u64);
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    delay_for(wait).await;
+                }
+
+                if self.replay_message(&system, message) {
+                    replayed += 1;
+                }
+            }
+        }
+
+        // recorded connections are closed after the replay
+        for peer in self.sessions.drain().filter_map(|(_, session)| session.peer) {
+            system.stop(peer);
+        }
+        info!(log, "Replay of recorded p2p messages finished"; "replayed_messages" => replayed, "elapsed_secs" => started.elapsed().as_secs());
+    }
+
+    /// Replay single recorded message, returns true if the message was published into the network channel
+    fn replay_message(&mut self, system: &ActorSystem, message: P2PMessage) -> bool {
+        let remote_addr = message.remote_addr();
+        let received = message.is_received();
+        match message {
+            P2PMessage::ConnectionMessage { message, .. } => {
+                if received {
+                    self.sessions.entry(remote_addr).or_default().connection_message = Some(message);
+                } else if let Some(RecordedSession { peer: Some(peer), .. }) = self.sessions.insert(remote_addr, RecordedSession::default()) {
+                    // node connected again to the same peer, so the previous connection was closed
+                    system.stop(peer);
+                }
+                false
+            }
+            P2PMessage::Metadata { message, .. } if received => {
+                self.sessions.entry(remote_addr).or_default().metadata = Some(message);
+                false
+            }
+            P2PMessage::P2PMessage { message, .. } if received => {
+                match self.bootstrap_peer(system, remote_addr) {
+                    Some(peer) => {
+                        for message in message {
+                            self.network_channel.tell(
+                                Publish {
+                                    msg: PeerMessageReceived {
+                                        peer: peer.clone(),
+                                        message: Arc::new(message.into()),
+                                    }.into(),
+                                    topic: NetworkChannelTopic::NetworkEvents.into(),
+                                }, None);
+                        }
+                        true
+                    }
+                    None => false
+                }
+            }
+            // messages sent by the recording node are not replayed, the replaying node sends its own messages
+            _ => false
+        }
+    }
+
+    /// Return peer actor of the recorded session, peer is created and bootstrapped on the first call
+    fn bootstrap_peer(&mut self, system: &ActorSystem, remote_addr: SocketAddr) -> Option<PeerRef> {
+        let session = self.sessions.get_mut(&remote_addr)?;
+        if let Some(peer) = &session.peer {
+            return Some(peer.clone());
+        }
+
+        let (connection_message, metadata) = match (&session.connection_message, &session.metadata) {
+            (Some(connection_message), Some(metadata)) => (connection_message, metadata),
+            _ => return None,
+        };
+        // recorded peer without compatible version was refused by the recording node
+        let version = Version::negotiate(&supported_versions(&self.protocol_version), connection_message.versions())?;
+        let peer_id = HashType::CryptoboxPublicKeyHash.bytes_to_string(connection_message.public_key());
+
+        let bandwidth = PeerBandwidth::default();
+        let peer = match Peer::actor(
+            system,
+            self.network_channel.clone(),
+            0,
+            &self.identity.public_key,
+            &self.identity.secret_key,
+            &self.identity.proof_of_work_stamp,
+            &self.protocol_version,
+            0.0,
+            false,
+            self.tokio_executor.clone(),
+            &remote_addr,
+            self.p2p_msg_storage.clone(),
+            bandwidth.clone()) {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!(system.log(), "Failed to create replayed peer"; "reason" => format!("{:?}", e), "ip" => remote_addr.to_string());
+                return None;
+            }
+        };
+
+        self.network_channel.tell(
+            Publish {
+                msg: PeerCreated {
+                    peer: peer.clone(),
+                    address: remote_addr,
+                    bandwidth,
+                }.into(),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            }, None);
+        self.network_channel.tell(
+            Publish {
+                msg: PeerBootstrapped::Success {
+                    peer: peer.clone(),
+                    peer_id,
+                    peer_metadata: metadata.clone(),
+                    version,
+                    replayed: true,
+                }.into(),
+                topic: NetworkChannelTopic::NetworkEvents.into(),
+            }, None);
+
+        session.peer = Some(peer.clone());
+        Some(peer)
+    }
+}
+
+/// This actor replays the p2p messages recorded by another node instead of the peer manager.
+///
+/// It does not need any network connection, so sync issues can be reproduced only with the database of the recording node.
+#[actor(ShellChannelMsg)]
+pub struct P2PReplay {
+    /// All events from shell will be published to this channel
+    shell_channel: ShellChannelRef,
+    /// Tokio runtime
+    tokio_executor: Handle,
+    /// Replay is stopped when set to false
+    run: Arc<AtomicBool>,
+    /// Replayer is moved into the tokio task when the actor is started
+    replayer: Option<Replayer>,
+}
+
+/// Reference to [p2p replay](P2PReplay) actor.
+pub type P2PReplayRef = ActorRef<P2PReplayMsg>;
+
+impl P2PReplay {
+    /// Create new actor instance.
+    ///
+    /// Messages are read from the `recorded_db`, delays between them are divided by the `speedup`.
+    /// If `peers` are not empty, only messages of these peers are replayed.
+    pub fn actor(sys: &impl ActorRefFactory,
+                 network_channel: NetworkChannelRef,
+                 shell_channel: ShellChannelRef,
+                 tokio_executor: Handle,
+                 identity: Identity,
+                 protocol_version: String,
+                 persistent_storage: &PersistentStorage,
+                 recorded_db: Arc<DB>,
+                 speedup: f64,
+                 peers: &[SocketAddr],
+    ) -> Result<P2PReplayRef, CreateError> {
+        sys.actor_of(
+            Props::new_args(P2PReplay::new, (
+                network_channel,
+                shell_channel,
+                tokio_executor,
+                identity,
+                protocol_version,
+                persistent_storage.clone(),
+                recorded_db,
+                speedup,
+                peers.iter().cloned().collect())),
+            P2PReplay::name())
+    }
+
+    /// The `P2PReplay` is intended to serve as a singleton actor so that's why
+    /// we won't support multiple names per instance.
+    fn name() -> &'static str {
+        "p2p-replay"
+    }
+
+    fn new((network_channel, shell_channel, tokio_executor, identity, protocol_version, persistent_storage, recorded_db, speedup, peers):
+           (NetworkChannelRef, ShellChannelRef, Handle, Identity, String, PersistentStorage, Arc<DB>, f64, HashSet<SocketAddr>)) -> Self {
+        let run = Arc::new(AtomicBool::new(true));
+        P2PReplay {
+            shell_channel,
+            tokio_executor: tokio_executor.clone(),
+            run: run.clone(),
+            replayer: Some(Replayer {
+                network_channel,
+                tokio_executor,
+                identity,
+                protocol_version,
+                recorded_storage: P2PMessageStorage::recorded(recorded_db),
+                p2p_msg_storage: P2PMessageStorage::new(&persistent_storage),
+                speedup,
+                peers,
+                run,
+                sessions: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl Actor for P2PReplay {
+    type Msg = P2PReplayMsg;
+
+    fn pre_start(&mut self, ctx: &Context<Self::Msg>) {
+        subscribe_to_shell_events(&self.shell_channel, ctx.myself());
+
+        if let Some(replayer) = self.replayer.take() {
+            self.tokio_executor.spawn(replayer.run(ctx.system.clone()));
+        }
+    }
+
+    fn post_stop(&mut self) {
+        self.run.store(false, Ordering::Release);
+    }
+
+    fn recv(&mut self, ctx: &Context<Self::Msg>, msg: Self::Msg, sender: Sender) {
+        self.receive(ctx, msg, sender);
+    }
+}
+
+impl Receive<ShellChannelMsg> for P2PReplay {
+    type Msg = P2PReplayMsg;
+
+    fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: ShellChannelMsg, _sender: Sender) {
+        if let ShellChannelMsg::ShuttingDown(_) = msg {
+            self.run.store(false, Ordering::Release);
+        }
+    }
+}
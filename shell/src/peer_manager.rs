@@ -6,6 +6,7 @@
 use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
+use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -13,6 +14,7 @@ use std::time::{Duration, Instant};
 
 use dns_lookup::LookupError;
 use futures::lock::Mutex;
+use net2::TcpBuilder;
 use rand::seq::SliceRandom;
 use riker::actors::*;
 use slog::{debug, info, Logger, warn};
@@ -20,15 +22,21 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Handle;
 use tokio::time::timeout;
 
+use crypto::hash::{CryptoboxPublicKeyHash, HashType};
+use networking::p2p::bandwidth::{Bandwidth, BandwidthLimits, PeerBandwidth};
 use networking::p2p::network_channel::{NetworkChannelMsg, NetworkChannelRef, NetworkChannelTopic, PeerBootstrapped, PeerCreated};
 use networking::p2p::peer::{Bootstrap, Peer, PeerRef, SendMessage};
+use storage::{BanKey, PeerBan, PeerBanStorage, PointInfo, PointStorage, PointTrust, StorageError};
 use storage::p2p_message_storage::P2PMessageStorage;
 use storage::persistent::PersistentStorage;
 use tezos_api::identity::Identity;
+use tezos_messages::p2p::encoding::advertise::parse_point;
 use tezos_messages::p2p::encoding::prelude::*;
 
+use crate::peer_reputation::{BanPolicy, Misbehaviour, PeerReputation};
 use crate::shell_channel::{ShellChannelMsg, ShellChannelRef};
 use crate::subscription::*;
+use crate::unix_timestamp_now;
 
 /// Timeout for outgoing connections
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
@@ -38,6 +46,24 @@ const WHITELIST_INTERVAL: Duration = Duration::from_secs(1_800);
 const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
 /// Limit how often we allow to trigger check of a peer count
 const CHECK_PEER_COUNT_LIMIT: Duration = Duration::from_secs(5);
+/// How often to propose connection swap to a random peer
+const SWAP_INTERVAL: Duration = Duration::from_secs(120);
+/// Swap request is not accepted sooner than this after the last swap
+const SWAP_LINGER: Duration = Duration::from_secs(30);
+/// Point is greylisted for this time (seconds) after the first failed connection, duration doubles with every next failure
+const GREYLIST_BASE_DURATION: i64 = 60;
+/// Limit of the point greylist duration (one day)
+const GREYLIST_MAX_DURATION: i64 = 86_400;
+/// Points, which were not seen for this time (30 days), are removed from the address book
+const POINT_EXPIRATION: i64 = 2_592_000;
+/// Limit of the count of known points, points advertised above the limit are ignored
+const MAX_KNOWN_POINTS: usize = 1_000;
+/// Max count of points sent in the advertise message
+const MAX_ADVERTISED_POINTS: usize = 50;
+/// Max count of points sent in the NACK to refused peer
+const MAX_NACK_POINTS: usize = 20;
+/// Max count of the pending incoming connections
+const LISTENER_BACKLOG: i32 = 1_024;
 
 /// Check peer threshold
 #[derive(Clone, Debug)]
@@ -47,6 +73,16 @@ pub struct CheckPeerCount;
 #[derive(Clone, Debug)]
 pub struct WhitelistAllIpAddresses;
 
+/// Propose connection swap to a random peer
+#[derive(Clone, Debug)]
+pub struct SwapPeers;
+
+/// Outgoing connection failed before the peer could be bootstrapped
+#[derive(Clone, Debug)]
+pub struct ConnectionFailed {
+    address: SocketAddr,
+}
+
 /// Accept incoming peer connection.
 #[derive(Clone, Debug)]
 pub struct AcceptPeer {
@@ -81,12 +117,55 @@ impl Threshold {
     }
 }
 
+/// Info about the connected peer, which is required to ban the peer
+#[derive(Clone, Debug)]
+struct PeerInfo {
+    /// Address of the peer
+    address: SocketAddr,
+    /// Peer id is known after the peer is successfully bootstrapped
+    peer_id: Option<String>,
+    /// Address of the outgoing connection is the point, where the peer accepts connections
+    outgoing: bool,
+    /// Peer told us in its metadata, that it is private, so its point must not be advertised
+    private_node: bool,
+}
+
+/// Local node info shared with all created peers
+#[derive(Clone)]
+struct LocalNode {
+    /// Tezos identity
+    identity: Identity,
+    /// Protocol version
+    protocol_version: String,
+    /// Proof of work required from remote peers
+    expected_pow: f64,
+    /// Private node connects only to the trusted (initial) peers and is not advertised by them
+    private_node: bool,
+    /// Bandwidth limits, global limits are shared by all peers
+    bandwidth: Bandwidth,
+}
+
+/// Swap request sent to the peer, which waits for the swap ack
+struct SwapRequested {
+    /// Peer asked to swap
+    peer: ActorUri,
+    /// Peer proposed to the asked peer, it will be disconnected after the swap
+    proposed_peer: ActorUri,
+}
+
+/// Connection swap, which waits for the connection to the new point
+struct PendingSwap {
+    new_point: SocketAddr,
+    /// Peer disconnected, when connection to the new point is established
+    replaced_peer: ActorUri,
+}
+
 /// This actor is responsible for peer management.
 ///
 /// It monitors number of connected peers. If the number of connected peers is too low it tries to
 /// connect to more peers. If the number of connected peers is too high, then randomly selected peers
 /// are disconnected.
-#[actor(CheckPeerCount, WhitelistAllIpAddresses, AcceptPeer, ConnectToPeer, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
+#[actor(CheckPeerCount, WhitelistAllIpAddresses, SwapPeers, AcceptPeer, ConnectToPeer, ConnectionFailed, NetworkChannelMsg, ShellChannelMsg, SystemEvent, DeadLetter)]
 pub struct PeerManager {
     /// All events generated by the network layer will end up in this channel
     network_channel: NetworkChannelRef,
@@ -96,9 +175,11 @@ pub struct PeerManager {
     threshold: Threshold,
     /// Map of all peers
     peers: HashMap<ActorUri, PeerRef>,
+    /// Address and peer id of all peers
+    peer_info: HashMap<ActorUri, PeerInfo>,
     /// DNS addresses used for bootstrapping
     bootstrap_addresses: Vec<String>,
-    /// List of initial peers to connect to
+    /// List of initial peers to connect to, they are trusted
     initial_peers: HashSet<SocketAddr>,
     /// List of potential peers to connect to
     potential_peers: HashSet<SocketAddr>,
@@ -106,10 +187,10 @@ pub struct PeerManager {
     tokio_executor: Handle,
     /// We will listen for incoming connection at this port
     listener_port: u16,
-    /// Tezos identity
-    identity: Identity,
-    /// Protocol version
-    protocol_version: String,
+    /// We will accept incoming connections from these listeners
+    listeners: Vec<P2pListener>,
+    /// Local node info
+    local: LocalNode,
     /// Message receiver boolean indicating whether
     /// more connections should be accepted from network
     rx_run: Arc<AtomicBool>,
@@ -123,11 +204,30 @@ pub struct PeerManager {
     shutting_down: bool,
     /// Storage
     p2p_msg_storage: P2PMessageStorage,
+    /// Banned IP addresses and peer ids
+    peer_ban_storage: PeerBanStorage,
+    /// Penalty scores of misbehaving peers
+    peer_reputation: PeerReputation,
+    /// Resolves how long misbehaving peers are banned
+    ban_policy: BanPolicy,
+    /// Address book of the known points
+    point_storage: PointStorage,
+    /// Count of points in the address book
+    known_points: usize,
+    /// Swap request, which waits for the swap ack
+    swap_requested: Option<SwapRequested>,
+    /// Swap, which waits for the connection to the new point
+    pending_swap: Option<PendingSwap>,
+    /// Last time we started connection swap
+    swap_last: Option<Instant>,
 }
 
 /// Reference to [peer manager](PeerManager) actor.
 pub type PeerManagerRef = ActorRef<PeerManagerMsg>;
 
+/// Listener of incoming p2p connections, which is bound by [`bind_listeners`] before the [`PeerManager`] is started
+pub type P2pListener = Arc<std::net::TcpListener>;
+
 impl PeerManager {
     pub fn actor(sys: &impl ActorRefFactory,
                  network_channel: NetworkChannelRef,
@@ -137,9 +237,14 @@ impl PeerManager {
                  initial_peers: &[SocketAddr],
                  threshold: Threshold,
                  listener_port: u16,
+                 listeners: &[P2pListener],
                  identity: Identity,
                  protocol_version: String,
+                 expected_pow: f64,
+                 private_node: bool,
+                 bandwidth_limits: BandwidthLimits,
                  ps: PersistentStorage,
+                 ban_policy: BanPolicy,
     ) -> Result<PeerManagerRef, CreateError> {
         sys.actor_of(
             Props::new_args(PeerManager::new, (
@@ -150,9 +255,10 @@ impl PeerManager {
                 HashSet::from_iter(initial_peers.to_vec()),
                 threshold,
                 listener_port,
-                identity,
-                protocol_version,
-                ps)),
+                listeners.to_vec(),
+                LocalNode { identity, protocol_version, expected_pow, private_node, bandwidth: Bandwidth::new(bandwidth_limits) },
+                ps,
+                ban_policy)),
             PeerManager::name())
     }
 
@@ -162,8 +268,8 @@ impl PeerManager {
         "peer-manager"
     }
 
-    fn new((network_channel, shell_channel, tokio_executor, bootstrap_addresses, initial_peers, threshold, listener_port, identity, protocol_version, ps):
-           (NetworkChannelRef, ShellChannelRef, Handle, Vec<String>, HashSet<SocketAddr>, Threshold, u16, Identity, String, PersistentStorage)) -> Self {
+    fn new((network_channel, shell_channel, tokio_executor, bootstrap_addresses, initial_peers, threshold, listener_port, listeners, local, ps, ban_policy):
+           (NetworkChannelRef, ShellChannelRef, Handle, Vec<String>, HashSet<SocketAddr>, Threshold, u16, Vec<P2pListener>, LocalNode, PersistentStorage, BanPolicy)) -> Self {
         PeerManager {
             network_channel,
             shell_channel,
@@ -172,16 +278,25 @@ impl PeerManager {
             initial_peers,
             threshold,
             listener_port,
-            identity,
-            protocol_version,
+            listeners,
+            local,
             rx_run: Arc::new(AtomicBool::new(true)),
             potential_peers: HashSet::new(),
             peers: HashMap::new(),
+            peer_info: HashMap::new(),
             ip_blacklist: HashSet::new(),
             discovery_last: None,
             check_peer_count_last: None,
             shutting_down: false,
             p2p_msg_storage: P2PMessageStorage::new(&ps),
+            peer_ban_storage: PeerBanStorage::new(&ps),
+            peer_reputation: PeerReputation::new(),
+            ban_policy,
+            point_storage: PointStorage::new(&ps),
+            known_points: 0,
+            swap_requested: None,
+            pending_swap: None,
+            swap_last: None,
         }
     }
 
@@ -190,14 +305,19 @@ impl PeerManager {
         if self.peers.is_empty() || self.discovery_last.filter(|discovery_last| discovery_last.elapsed() <= DISCOVERY_INTERVAL).is_none() {
             self.discovery_last = Some(Instant::now());
 
-            info!(log, "Doing peer DNS lookup"; "bootstrap_addresses" => format!("{:?}", &self.bootstrap_addresses));
-            dns_lookup_peers(&self.bootstrap_addresses, &log).iter()
-                .for_each(|address| {
-                    if !self.is_blacklisted(&address.ip()) {
+            // private node connects only to the trusted peers
+            if !self.local.private_node {
+                info!(log, "Doing peer DNS lookup"; "bootstrap_addresses" => format!("{:?}", &self.bootstrap_addresses));
+                for address in dns_lookup_peers(&self.bootstrap_addresses, &log) {
+                    if !self.is_blacklisted(&address.ip()) && !self.is_greylisted(&address) {
                         info!(log, "Found potential peer"; "address" => address);
-                        self.potential_peers.insert(*address);
+                        if let Err(e) = self.remember_point(&address) {
+                            warn!(log, "Failed to store potential peer"; "address" => address, "reason" => e);
+                        }
+                        self.potential_peers.insert(address);
                     }
-                });
+                }
+            }
 
             if self.potential_peers.is_empty() {
                 info!(log, "Using initial peers as a potential peers"; "initial_peers" => format!("{:?}", &self.initial_peers));
@@ -210,28 +330,20 @@ impl PeerManager {
         }
     }
 
-    /// Create new peer actor
-    fn create_peer(&mut self, sys: &impl ActorRefFactory, socket_address: &SocketAddr) -> PeerRef {
-        let peer = Peer::actor(
-            sys,
-            self.network_channel.clone(),
-            self.listener_port,
-            &self.identity.public_key,
-            &self.identity.secret_key,
-            &self.identity.proof_of_work_stamp,
-            &self.protocol_version,
-            self.tokio_executor.clone(),
-            socket_address,
-            self.p2p_msg_storage.clone(),
-        ).unwrap();
+    /// Create new peer actor, which is managed by this actor
+    fn create_peer(&mut self, sys: &impl ActorRefFactory, socket_address: &SocketAddr, outgoing: bool) -> PeerRef {
+        let bandwidth = self.local.bandwidth.peer();
+        let peer = self.peer_actor(sys, socket_address, bandwidth.clone());
 
         self.peers.insert(peer.uri().clone(), peer.clone());
+        self.peer_info.insert(peer.uri().clone(), PeerInfo { address: *socket_address, peer_id: None, outgoing, private_node: false });
 
         self.network_channel.tell(
             Publish {
                 msg: PeerCreated {
                     peer: peer.clone(),
                     address: *socket_address,
+                    bandwidth,
                 }.into(),
                 topic: NetworkChannelTopic::NetworkEvents.into(),
             }, None);
@@ -239,13 +351,337 @@ impl PeerManager {
         peer
     }
 
+    fn peer_actor(&self, sys: &impl ActorRefFactory, socket_address: &SocketAddr, bandwidth: PeerBandwidth) -> PeerRef {
+        Peer::actor(
+            sys,
+            self.network_channel.clone(),
+            self.listener_port,
+            &self.local.identity.public_key,
+            &self.local.identity.secret_key,
+            &self.local.identity.proof_of_work_stamp,
+            &self.local.protocol_version,
+            self.local.expected_pow,
+            self.local.private_node,
+            self.tokio_executor.clone(),
+            socket_address,
+            self.p2p_msg_storage.clone(),
+            bandwidth,
+        ).unwrap()
+    }
+
     /// Check if given ip address is blacklisted to connect to
     fn is_blacklisted(&self, ip_address: &IpAddr) -> bool {
-        self.ip_blacklist.contains(ip_address)
+        self.ip_blacklist.contains(ip_address) || self.is_banned(&BanKey::Ip(*ip_address))
+    }
+
+    /// Check if there is an active ban for the IP address or peer id
+    fn is_banned(&self, key: &BanKey) -> bool {
+        self.peer_ban_storage.is_banned(key, unix_timestamp_now()).unwrap_or(false)
+    }
+
+    /// Check if connected peer is banned by its IP address or peer id
+    fn is_peer_banned(&self, peer_info: &PeerInfo) -> bool {
+        self.is_banned(&BanKey::Ip(peer_info.address.ip()))
+            || peer_info.peer_id.as_ref().filter(|peer_id| self.is_banned(&BanKey::PeerId(peer_id.to_string()))).is_some()
+    }
+
+    /// Penalize peer for its misbehaviour. If the penalty score of the peer is too high, then peer is banned and disconnected.
+    fn penalize_peer(&mut self, ctx: &Context<PeerManagerMsg>, peer: &PeerRef, misbehaviour: Misbehaviour) -> Result<(), StorageError> {
+        let peer_info = match self.peer_info.get(peer.uri()) {
+            Some(peer_info) => peer_info.clone(),
+            None => return Ok(()),
+        };
+
+        // peer is identified by its peer id, which is not known before bootstrap, in that case IP address is used instead
+        let reputation_key = peer_info.peer_id.clone().unwrap_or_else(|| peer_info.address.ip().to_string());
+        if self.peer_reputation.penalize(&reputation_key, misbehaviour) {
+            let mut ban_keys = vec![BanKey::Ip(peer_info.address.ip())];
+            if let Some(peer_id) = peer_info.peer_id {
+                ban_keys.push(BanKey::PeerId(peer_id));
+            }
+            let ban = self.ban(&ban_keys, format!("{:?}", misbehaviour))?;
+            info!(ctx.system.log(), "Banning misbehaving peer"; "peer" => peer.name(), "ip" => format!("{}", peer_info.address.ip()), "reason" => &ban.reason, "ban_count" => ban.ban_count, "banned_until" => ban.banned_until);
+            ctx.system.stop(peer.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Store ban for all keys. Ban duration is resolved from the number of previous bans.
+    fn ban(&mut self, ban_keys: &[BanKey], reason: String) -> Result<PeerBan, StorageError> {
+        let mut ban_count = 0;
+        for ban_key in ban_keys {
+            if let Some(previous_ban) = self.peer_ban_storage.get(ban_key)? {
+                ban_count = cmp::max(ban_count, previous_ban.ban_count);
+            }
+        }
+        let ban_count = ban_count + 1;
+
+        let ban = PeerBan {
+            banned_until: unix_timestamp_now() + self.ban_policy.ban_duration(ban_count).as_secs() as i64,
+            ban_count,
+            reason,
+        };
+        for ban_key in ban_keys {
+            self.peer_ban_storage.put(ban_key, &ban)?;
+        }
+
+        Ok(ban)
+    }
+
+    /// Check if the point is greylisted after failed connection
+    fn is_greylisted(&self, point: &SocketAddr) -> bool {
+        match self.point_storage.get(point) {
+            Ok(Some(info)) => info.is_greylisted(unix_timestamp_now()),
+            _ => false,
+        }
+    }
+
+    /// Check if we are connected to the point or to the peer
+    fn is_connected(&self, point: &SocketAddr, peer_id: &str) -> bool {
+        is_connected(self.peer_info.values(), point, peer_id)
+    }
+
+    /// Initial peers configured by the node operator are trusted
+    fn is_trusted(&self, point: &SocketAddr) -> bool {
+        self.initial_peers.contains(point)
+    }
+
+    /// Incoming connections use random port, so only IP address of the trusted peers can be checked
+    fn is_trusted_ip(&self, ip_address: &IpAddr) -> bool {
+        self.initial_peers.iter().any(|point| point.ip() == *ip_address)
+    }
+
+    /// Store initial peers to the address book as trusted points, so they are never greylisted nor pruned
+    fn trust_initial_peers(&mut self) -> Result<(), StorageError> {
+        let now = unix_timestamp_now();
+        for point in self.initial_peers.clone() {
+            self.point_storage.update(&point, now, |info| info.trust = PointTrust::Trusted)?;
+        }
+        Ok(())
+    }
+
+    /// Store point learned from the DNS lookup or from other peers to the address book
+    fn remember_point(&mut self, point: &SocketAddr) -> Result<(), StorageError> {
+        let now = unix_timestamp_now();
+        match self.point_storage.get(point)? {
+            Some(mut info) => {
+                info.last_seen = now;
+                self.point_storage.put(point, &info)
+            }
+            None if self.known_points < MAX_KNOWN_POINTS => {
+                self.known_points += 1;
+                self.point_storage.put(point, &PointInfo::new(now))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Greylist the point after failed connection, greylist duration grows with every failure.
+    /// Unknown addresses (e.g. of incoming connections) are ignored.
+    fn point_connection_failed(&mut self, point: &SocketAddr) -> Result<(), StorageError> {
+        if let Some(mut info) = self.point_storage.get(point)? {
+            let greylist_duration = cmp::min(GREYLIST_BASE_DURATION << cmp::min(info.failed_connections, 20), GREYLIST_MAX_DURATION);
+            info.connection_failed(greylist_duration, unix_timestamp_now());
+            self.point_storage.put(point, &info)?;
+        }
+        Ok(())
+    }
+
+    /// Remove points, which were not seen for a long time, from the address book
+    fn prune_known_points(&mut self) -> Result<(), StorageError> {
+        let expired_before = unix_timestamp_now() - POINT_EXPIRATION;
+        for (point, info) in self.point_storage.get_all()? {
+            if !info.is_trusted() && info.last_seen < expired_before {
+                self.point_storage.delete(&point)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Use points from the address book, which are not connected nor greylisted, as potential peers
+    fn load_known_points(&mut self) -> Result<(), StorageError> {
+        let now = unix_timestamp_now();
+        let connected = self.peer_info.values().map(|peer_info| peer_info.address).collect::<HashSet<_>>();
+        let points = self.point_storage.get_all()?;
+        self.known_points = points.len();
+        for (point, info) in points {
+            if self.local.private_node && !self.is_trusted(&point) {
+                continue;
+            }
+            if !info.is_greylisted(now) && !connected.contains(&point) && !self.is_blacklisted(&point.ip()) {
+                self.potential_peers.insert(point);
+            }
+        }
+        Ok(())
+    }
+
+    /// Random sample of the points, which we were able to connect to, excluding the points at the `requester_ip`.
+    ///
+    /// Private nodes are never included and private node itself does not share any points.
+    fn good_points_sample(&self, requester_ip: &IpAddr, limit: usize) -> Result<Vec<SocketAddr>, StorageError> {
+        if self.local.private_node {
+            return Ok(vec![]);
+        }
+        let now = unix_timestamp_now();
+        let mut points = self.point_storage.get_all()?.into_iter()
+            .filter(|(point, info)| info.last_established.is_some() && !info.private_node && !info.is_greylisted(now) && point.ip() != *requester_ip)
+            .map(|(point, _)| point)
+            .collect::<Vec<_>>();
+        points.shuffle(&mut rand::thread_rng());
+        points.truncate(limit);
+        Ok(points)
+    }
+
+    /// Bootstrapped outgoing connections, their points and peer ids are known, so they can be proposed in a swap.
+    /// Private peers are never proposed.
+    fn swap_candidates(&self) -> Vec<(ActorUri, SocketAddr, CryptoboxPublicKeyHash)> {
+        self.peer_info.iter()
+            .filter(|(_, peer_info)| peer_info.outgoing && !peer_info.private_node)
+            .filter_map(|(uri, peer_info)| peer_info.peer_id.as_ref()
+                .and_then(|peer_id| HashType::CryptoboxPublicKeyHash.string_to_bytes(peer_id).ok())
+                .map(|peer_id| (uri.clone(), peer_info.address, peer_id)))
+            .collect()
+    }
+
+    /// Connect to the new point, `replaced_peer` is disconnected when the connection is established
+    fn start_swap(&mut self, ctx: &Context<PeerManagerMsg>, new_point: SocketAddr, replaced_peer: ActorUri) {
+        if let Some(peer) = self.peers.get(&replaced_peer) {
+            info!(ctx.system.log(), "Swapping peer connection"; "new_point" => new_point, "replaced_peer" => peer.name());
+        }
+        self.swap_last = Some(Instant::now());
+        self.pending_swap = Some(PendingSwap { new_point, replaced_peer });
+        ctx.myself().tell(ConnectToPeer { address: new_point }, None);
+    }
+
+    /// Check, that we can connect to the point proposed in a swap message
+    fn parse_swap_point(&self, msg: &SwapMessage) -> Option<SocketAddr> {
+        parse_point(msg.point())
+            .filter(|point| !self.is_blacklisted(&point.ip()) && !self.is_greylisted(point) && !self.is_connected(point, &swap_peer_id(msg)))
+    }
+
+    /// Other peer proposed us to connect to the point, we propose one of our connections in exchange
+    fn process_swap_request(&mut self, ctx: &Context<PeerManagerMsg>, source: &PeerRef, msg: &SwapMessage) {
+        if self.local.private_node {
+            debug!(ctx.system.log(), "Swap request ignored by private node"; "peer" => source.name());
+            return;
+        }
+        if self.swap_last.filter(|swap_last| swap_last.elapsed() < SWAP_LINGER).is_some() {
+            debug!(ctx.system.log(), "Swap request ignored, last swap is too recent"; "peer" => source.name());
+            return;
+        }
+        let new_point = match self.parse_swap_point(msg) {
+            Some(new_point) => new_point,
+            None => return,
+        };
+
+        let candidates = self.swap_candidates().into_iter()
+            .filter(|(uri, _, _)| uri != source.uri())
+            .collect::<Vec<_>>();
+        if let Some((replaced_peer, point, peer_id)) = candidates.choose(&mut rand::thread_rng()).cloned() {
+            source.tell(SendMessage::new(PeerMessage::SwapAck(SwapMessage::new(point.to_string(), peer_id)).into()), None);
+            self.start_swap(ctx, new_point, replaced_peer);
+        }
+    }
+
+    /// Peer accepted our swap request and proposed us a point in exchange
+    fn process_swap_ack(&mut self, ctx: &Context<PeerManagerMsg>, source: &PeerRef, msg: &SwapMessage) {
+        match self.swap_requested.take() {
+            Some(requested) if &requested.peer == source.uri() => {
+                if let Some(new_point) = self.parse_swap_point(msg) {
+                    self.start_swap(ctx, new_point, requested.proposed_peer);
+                }
+            }
+            requested => {
+                debug!(ctx.system.log(), "Unexpected swap ack"; "peer" => source.name());
+                self.swap_requested = requested;
+            }
+        }
+    }
+
+    fn process_network_channel_message(&mut self, ctx: &Context<PeerManagerMsg>, msg: NetworkChannelMsg) -> Result<(), failure::Error> {
+        match msg {
+            NetworkChannelMsg::PeerMessageReceived(received) => {
+                for message in received.message.messages() {
+                    match message {
+                        PeerMessage::Advertise(message) => {
+                            // received message containing additional peers to which we can connect in the future
+                            info!(ctx.system.log(), "Received advertise message from peer"; "peer" => received.peer.name());
+                            if !self.local.private_node {
+                                self.process_potential_peers(&message.points())?;
+                            }
+                        }
+                        PeerMessage::Bootstrap => {
+                            let points = match self.peer_info.get(received.peer.uri()) {
+                                Some(peer_info) => self.good_points_sample(&peer_info.address.ip(), MAX_ADVERTISED_POINTS)?,
+                                None => vec![],
+                            };
+                            if !points.is_empty() {
+                                received.peer.tell(SendMessage::new(AdvertiseMessage::new(&points).into()), None);
+                            }
+                        }
+                        PeerMessage::SwapRequest(message) => self.process_swap_request(ctx, &received.peer, message),
+                        PeerMessage::SwapAck(message) => self.process_swap_ack(ctx, &received.peer, message),
+                        _ => (),
+                    }
+                }
+                self.trigger_check_peer_count(ctx);
+            }
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Success { peer, peer_id, peer_metadata, .. }) => {
+                if self.is_banned(&BanKey::PeerId(peer_id.clone())) {
+                    info!(ctx.system.log(), "Disconnecting banned peer"; "peer" => peer.name(), "peer_id" => &peer_id);
+                    ctx.system.stop(peer.clone());
+                }
+                let address = match self.peer_info.get_mut(peer.uri()) {
+                    Some(peer_info) => {
+                        peer_info.peer_id = Some(peer_id.clone());
+                        peer_info.private_node = peer_metadata.private_node();
+                        Some(peer_info.address).filter(|_| peer_info.outgoing)
+                    }
+                    None => None,
+                };
+                if let Some(address) = address {
+                    let now = unix_timestamp_now();
+                    self.point_storage.update(&address, now, |info| info.connection_established(peer_id, peer_metadata.private_node(), now))?;
+
+                    // connection to the swapped point was established, so the replaced peer can be disconnected
+                    if let Some(pending_swap) = self.pending_swap.take() {
+                        if pending_swap.new_point == address {
+                            if let Some(replaced_peer) = self.peers.get(&pending_swap.replaced_peer) {
+                                info!(ctx.system.log(), "Disconnecting swapped peer"; "peer" => replaced_peer.name());
+                                ctx.system.stop(replaced_peer.clone());
+                            }
+                        } else {
+                            self.pending_swap = Some(pending_swap);
+                        }
+                    }
+                }
+            }
+            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Failure { address, potential_peers_to_connect }) => {
+                // received message that bootstrap process failed for the peer
+                info!(ctx.system.log(), "Blacklisting IP because peer failed at bootstrap process"; "ip" => format!("{}", address.ip()));
+                self.ip_blacklist.insert(address.ip());
+                self.point_connection_failed(&address)?;
+
+                if let Some(peers) = potential_peers_to_connect.filter(|_| !self.local.private_node) {
+                    let points = peers.iter()
+                        .filter_map(|point| parse_point(point))
+                        .collect::<Vec<_>>();
+                    self.process_potential_peers(&points)?;
+                    self.trigger_check_peer_count(ctx);
+                }
+            }
+            _ => ()
+        }
+
+        Ok(())
     }
 
     fn process_shell_channel_message(&mut self, ctx: &Context<PeerManagerMsg>, msg: ShellChannelMsg) -> Result<(), failure::Error> {
         match msg {
+            ShellChannelMsg::PeerMisbehaved(msg) => {
+                self.penalize_peer(ctx, &msg.peer, msg.misbehaviour)?;
+            }
             ShellChannelMsg::ShuttingDown(_) => {
                 self.shutting_down = true;
                 unsubscribe_from_dead_letters(ctx.system.dead_letters(), ctx.myself());
@@ -267,12 +703,18 @@ impl PeerManager {
         }
     }
 
-    fn process_potential_peers(&mut self, potential_peers: &[String]) {
+    fn process_potential_peers(&mut self, potential_peers: &[SocketAddr]) -> Result<(), StorageError> {
         let sock_addresses = potential_peers.iter()
-            .filter_map(|str_ip_port| str_ip_port.parse().ok())
+            .cloned()
             .filter(|address: &SocketAddr| !self.is_blacklisted(&address.ip()))
             .collect::<Vec<_>>();
-        self.potential_peers.extend(sock_addresses);
+        for address in sock_addresses {
+            self.remember_point(&address)?;
+            if !self.is_greylisted(&address) {
+                self.potential_peers.insert(address);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -297,16 +739,34 @@ impl Actor for PeerManager {
             ctx.myself(),
             None,
             WhitelistAllIpAddresses.into());
+        ctx.schedule::<Self::Msg, _>(
+            SWAP_INTERVAL,
+            SWAP_INTERVAL,
+            ctx.myself(),
+            None,
+            SwapPeers.into());
 
-
-        let listener_port = self.listener_port;
-        let myself = ctx.myself();
-        let rx_run = self.rx_run.clone();
+        // reconnect to the points known from the previous run
+        if let Err(e) = self.trust_initial_peers().and_then(|_| self.prune_known_points()).and_then(|_| self.load_known_points()) {
+            warn!(ctx.system.log(), "Failed to load known points"; "reason" => e);
+        }
 
         // start to listen for incoming p2p connections
-        self.tokio_executor.spawn(async move {
-            begin_listen_incoming(listener_port, myself, rx_run).await;
-        });
+        for listener in &self.listeners {
+            let listener = match listener.try_clone() {
+                Ok(listener) => listener,
+                Err(e) => {
+                    warn!(ctx.system.log(), "Failed to listen for incoming connections"; "reason" => format!("{:?}", e));
+                    continue;
+                }
+            };
+            let myself = ctx.myself();
+            let rx_run = self.rx_run.clone();
+            let log = ctx.system.log();
+            self.tokio_executor.spawn(async move {
+                begin_listen_incoming(listener, myself, rx_run, log).await;
+            });
+        }
     }
 
     fn post_stop(&mut self) {
@@ -329,6 +789,7 @@ impl Receive<DeadLetter> for PeerManager {
 
     fn receive(&mut self, _ctx: &Context<Self::Msg>, msg: DeadLetter, _sender: Option<BasicActorRef>) {
         self.peers.remove(msg.recipient.uri());
+        self.peer_info.remove(msg.recipient.uri());
     }
 }
 
@@ -349,6 +810,7 @@ impl Receive<SystemEvent> for PeerManager {
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: SystemEvent, _sender: Option<BasicActorRef>) {
         if let SystemEvent::ActorTerminated(evt) = msg {
+            self.peer_info.remove(evt.actor.uri());
             if let Some(_) = self.peers.remove(evt.actor.uri()) {
                 self.trigger_check_peer_count(ctx);
             }
@@ -365,9 +827,23 @@ impl Receive<CheckPeerCount> for PeerManager {
             return;
         }
 
+        // disconnect peers, which were banned meanwhile (e.g. via RPC)
+        self.peers.iter()
+            .filter(|(uri, _)| self.peer_info.get(uri).filter(|peer_info| self.is_peer_banned(peer_info)).is_some())
+            .for_each(|(_, peer)| {
+                info!(ctx.system.log(), "Disconnecting banned peer"; "peer" => peer.name());
+                ctx.system.stop(peer.clone());
+            });
+
         if self.peers.len() < self.threshold.low {
             // peer count is too low, try to connect to more peers
             warn!(ctx.system.log(), "Peer count is too low"; "actual" => self.peers.len(), "required" => self.threshold.low);
+            if self.potential_peers.len() < self.threshold.low {
+                // points from the address book are preferred, DNS lookup is used only when they are not enough
+                if let Err(e) = self.load_known_points() {
+                    warn!(ctx.system.log(), "Failed to load known points"; "reason" => e);
+                }
+            }
             if self.potential_peers.len() < self.threshold.low {
                 self.discover_peers(ctx.system.log());
             }
@@ -400,28 +876,9 @@ impl Receive<NetworkChannelMsg> for PeerManager {
     type Msg = PeerManagerMsg;
 
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: NetworkChannelMsg, _sender: Sender) {
-        match msg {
-            NetworkChannelMsg::PeerMessageReceived(received) => {
-                // received message containing additional peers to which we can connect in the future
-                let messages = received.message.messages();
-                messages.iter()
-                    .for_each(|message| if let PeerMessage::Advertise(message) = message {
-                        info!(ctx.system.log(), "Received advertise message from peer"; "peer" => received.peer.name());
-                        self.process_potential_peers(message.id());
-                    });
-                self.trigger_check_peer_count(ctx);
-            }
-            NetworkChannelMsg::PeerBootstrapped(PeerBootstrapped::Failure { address, potential_peers_to_connect }) => {
-                // received message that bootstrap process failed for the peer
-                info!(ctx.system.log(), "Blacklisting IP because peer failed at bootstrap process"; "ip" => format!("{}", address.ip()));
-                self.ip_blacklist.insert(address.ip());
-
-                if let Some(peers) = potential_peers_to_connect {
-                    self.process_potential_peers(&peers);
-                    self.trigger_check_peer_count(ctx);
-                }
-            }
-            _ => ()
+        match self.process_network_channel_message(ctx, msg) {
+            Ok(_) => (),
+            Err(e) => warn!(ctx.system.log(), "Failed to process network channel message"; "reason" => format!("{:?}", e)),
         }
     }
 }
@@ -432,6 +889,42 @@ impl Receive<WhitelistAllIpAddresses> for PeerManager {
     fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: WhitelistAllIpAddresses, _sender: Sender) {
         info!(ctx.system.log(), "Whitelisting all IP addresses");
         self.ip_blacklist.clear();
+        self.peer_reputation.forgive_all();
+    }
+}
+
+impl Receive<SwapPeers> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, _msg: SwapPeers, _sender: Sender) {
+        if self.shutting_down || self.local.private_node || self.peers.len() < self.threshold.low {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+        let (proposed_peer, point, peer_id) = match self.swap_candidates().choose(&mut rng).cloned() {
+            Some(candidate) => candidate,
+            None => return,
+        };
+        let peers = self.peer_info.iter()
+            .filter(|(uri, peer_info)| **uri != proposed_peer && peer_info.peer_id.is_some())
+            .filter_map(|(uri, _)| self.peers.get(uri).cloned())
+            .collect::<Vec<_>>();
+        if let Some(peer) = peers.choose(&mut rng) {
+            debug!(ctx.system.log(), "Sending swap request"; "peer" => peer.name(), "point" => point);
+            peer.tell(SendMessage::new(PeerMessage::SwapRequest(SwapMessage::new(point.to_string(), peer_id)).into()), None);
+            self.swap_requested = Some(SwapRequested { peer: peer.uri().clone(), proposed_peer });
+        }
+    }
+}
+
+impl Receive<ConnectionFailed> for PeerManager {
+    type Msg = PeerManagerMsg;
+
+    fn receive(&mut self, ctx: &Context<Self::Msg>, msg: ConnectionFailed, _sender: Sender) {
+        if let Err(e) = self.point_connection_failed(&msg.address) {
+            warn!(ctx.system.log(), "Failed to greylist point"; "point" => msg.address, "reason" => e);
+        }
     }
 }
 
@@ -443,8 +936,16 @@ impl Receive<ConnectToPeer> for PeerManager {
 
         if self.is_blacklisted(&msg.address.ip()) {
             debug!(ctx.system.log(), "Peer is blacklisted - will not connect"; "ip" => format!("{}", msg.address.ip()));
+        } else if self.local.private_node && !self.is_trusted(&msg.address) {
+            debug!(ctx.system.log(), "Point is not trusted - private node will not connect"; "point" => msg.address);
+        } else if self.is_greylisted(&msg.address) {
+            debug!(ctx.system.log(), "Point is greylisted - will not connect"; "point" => msg.address);
         } else {
-            let peer = self.create_peer(ctx, &msg.address);
+            if let Err(e) = self.remember_point(&msg.address) {
+                warn!(ctx.system.log(), "Failed to store point"; "point" => msg.address, "reason" => e);
+            }
+            let peer = self.create_peer(ctx, &msg.address, true);
+            let myself = ctx.myself();
             let system = ctx.system.clone();
 
             self.tokio_executor.spawn(async move {
@@ -456,10 +957,12 @@ impl Receive<ConnectToPeer> for PeerManager {
                     }
                     Ok(Err(e)) => {
                         info!(system.log(), "Connection failed"; "ip" => msg.address, "peer" => peer.name(), "reason" => format!("{:?}", e));
+                        myself.tell(ConnectionFailed { address: msg.address }, None);
                         system.stop(peer);
                     }
                     Err(_) => {
                         info!(system.log(), "Connection timed out"; "ip" => msg.address, "peer" => peer.name());
+                        myself.tell(ConnectionFailed { address: msg.address }, None);
                         system.stop(peer);
                     }
                 }
@@ -474,21 +977,46 @@ impl Receive<AcceptPeer> for PeerManager {
     fn receive(&mut self, ctx: &Context<Self::Msg>, msg: AcceptPeer, _sender: Sender) {
         if self.is_blacklisted(&msg.address.ip()) {
             debug!(ctx.system.log(), "Peer is blacklisted - will not accept connection"; "ip" => format!("{}", msg.address.ip()));
+        } else if self.local.private_node && !self.is_trusted_ip(&msg.address.ip()) {
+            debug!(ctx.system.log(), "Peer is not trusted - private node will not accept connection"; "ip" => msg.address);
         } else if self.peers.len() < self.threshold.high {
             info!(ctx.system.log(), "Connection from"; "ip" => msg.address);
-            let peer = self.create_peer(ctx, &msg.address);
+            let peer = self.create_peer(ctx, &msg.address, false);
             peer.tell(Bootstrap::incoming(msg.stream, msg.address), None);
         } else {
-            debug!(ctx.system.log(), "Cannot accept incoming peer connection because peer limit was reached");
-            drop(msg.stream); // not needed, just wanted to be explicit here
+            debug!(ctx.system.log(), "Cannot accept incoming peer connection because peer limit was reached"; "ip" => msg.address);
+            // peer is not managed, it only sends NACK with potential peers and terminates
+            let potential_peers = self.good_points_sample(&msg.address.ip(), MAX_NACK_POINTS)
+                .unwrap_or_default()
+                .iter()
+                .map(SocketAddr::to_string)
+                .collect();
+            let peer = self.peer_actor(ctx, &msg.address, self.local.bandwidth.peer());
+            peer.tell(Bootstrap::refused(msg.stream, msg.address, potential_peers), None);
         }
     }
 }
 
-/// Start to listen for incoming connections indefinitely.
-async fn begin_listen_incoming(listener_port: u16, peer_manager: PeerManagerRef, rx_run: Arc<AtomicBool>) {
-    let listener_address = format!("0.0.0.0:{}", listener_port).parse::<SocketAddr>().expect("Failed to parse listener address");
-    let mut listener = TcpListener::bind(&listener_address).await.expect("Failed to bind to address");
+/// Start to listen for incoming connections at the `listener_address` indefinitely.
+/// Check if any of the peers is connected to the point or has the peer id
+fn is_connected<'a>(peer_info: impl IntoIterator<Item = &'a PeerInfo>, point: &SocketAddr, peer_id: &str) -> bool {
+    peer_info.into_iter()
+        .any(|peer_info| peer_info.address == *point || peer_info.peer_id.as_deref() == Some(peer_id))
+}
+
+/// Swap message carries the hash of the public key, so it is encoded the same way as the peer id of the bootstrapped peer
+fn swap_peer_id(msg: &SwapMessage) -> String {
+    HashType::CryptoboxPublicKeyHash.hash_to_string(msg.peer_id())
+}
+
+async fn begin_listen_incoming(listener: std::net::TcpListener, peer_manager: PeerManagerRef, rx_run: Arc<AtomicBool>, log: Logger) {
+    let mut listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!(log, "Failed to listen for incoming connections"; "reason" => format!("{:?}", e));
+            return;
+        }
+    };
 
     while rx_run.load(Ordering::Acquire) {
         if let Ok((stream, address)) = listener.accept().await {
@@ -497,6 +1025,50 @@ async fn begin_listen_incoming(listener_port: u16, peer_manager: PeerManagerRef,
     }
 }
 
+/// Bind listeners of incoming p2p connections on all `listener_addresses` at the `listener_port`.
+///
+/// Address which can not be bound is skipped with a warning, error is returned only when no listener is bound,
+/// because node without any listener can not accept incoming connections.
+pub fn bind_listeners(listener_port: u16, listener_addresses: &[IpAddr], log: &Logger) -> io::Result<Vec<P2pListener>> {
+    let mut listeners = Vec::with_capacity(listener_addresses.len());
+    let mut last_error = None;
+    for listener_address in listener_addresses {
+        let listener_address = SocketAddr::new(*listener_address, listener_port);
+        match bind_listener(&listener_address) {
+            Ok(listener) => {
+                info!(log, "Listening for incoming connections"; "address" => listener_address.to_string());
+                listeners.push(Arc::new(listener));
+            }
+            Err(e) => {
+                warn!(log, "Failed to listen for incoming connections"; "address" => listener_address.to_string(), "reason" => format!("{:?}", e));
+                last_error = Some(e);
+            }
+        }
+    }
+
+    if listeners.is_empty() {
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No listener address")))
+    } else {
+        Ok(listeners)
+    }
+}
+
+/// Bind listener to the address, IPv6 listener does not accept IPv4 connections,
+/// so the node can listen on both `0.0.0.0` and `::` at the same port.
+fn bind_listener(listener_address: &SocketAddr) -> io::Result<std::net::TcpListener> {
+    let builder = match listener_address {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            builder
+        }
+    };
+    builder.reuse_address(true)?;
+    builder.bind(listener_address)?;
+    builder.listen(LISTENER_BACKLOG)
+}
+
 /// Do DNS lookup for collection of names and create collection of socket addresses
 fn dns_lookup_peers(bootstrap_addresses: &[String], log: &Logger) -> HashSet<SocketAddr> {
     let mut resolved_peers = HashSet::new();
@@ -526,3 +1098,31 @@ fn resolve_dns_name_to_peer_address(address: &str) -> Result<Vec<SocketAddr>, Lo
     Ok(addrs)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_with_connected_peer_id_is_rejected() -> Result<(), failure::Error> {
+        // peer id of the bootstrapped peer is computed from its public key
+        let public_key = hex::decode("2cc1b580f4b8b1f6dbd0aa1d9cde2655c2081c07d7e61249aad8b11d954fb01a")?;
+        let connected = PeerInfo {
+            address: "127.0.0.1:9732".parse()?,
+            peer_id: Some(HashType::CryptoboxPublicKeyHash.bytes_to_string(&public_key)),
+            outgoing: true,
+            private_node: false,
+        };
+
+        // swap proposes the same peer at a different point, peer id in the message is already hashed
+        let swap = SwapMessage::new("127.0.0.2:9732".to_string(), crypto::blake2b::digest_128(&public_key));
+        let point = parse_point(swap.point()).unwrap();
+        assert_eq!(connected.peer_id.as_deref(), Some(swap_peer_id(&swap).as_str()));
+        assert!(is_connected(vec![&connected], &point, &swap_peer_id(&swap)));
+
+        // swap proposing other peer at other point is accepted
+        let other = SwapMessage::new("127.0.0.2:9732".to_string(), crypto::blake2b::digest_128(&[1; 32]));
+        assert!(!is_connected(vec![&connected], &point, &swap_peer_id(&other)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Reputation of the peers.
+//!
+//! Every misbehaviour of the peer is penalized. When the penalty score of the peer reaches
+//! [`BAN_THRESHOLD`], the peer is banned. Duration of the ban grows with every repeated ban.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Peer is banned when its penalty score reaches this threshold
+pub const BAN_THRESHOLD: u32 = 100;
+/// Default duration of the first ban
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(3_600);
+/// Ban duration will never exceed this limit
+pub const MAX_BAN_DURATION: Duration = Duration::from_secs(30 * 24 * 3_600);
+
+/// Misbehaviour of the peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Peer sent operations for validation pass, which does not exist
+    UnexpectedValidationPass,
+    /// Peer sent operations, which were not requested
+    UnexpectedOperations,
+    /// Peer sent block header, which was not requested
+    UnexpectedBlockHeader,
+    /// Peer sent block header, which failed validation
+    InvalidBlockHeader,
+    /// Peer sent protocol, which was not requested
+    UnexpectedProtocol,
+    /// Peer keeps sending messages for the chain, which we do not follow
+    InactiveChain,
+    /// Peer did not respond to our requests on time
+    SilentPeer,
+    /// Peer did not update its current head for a long time
+    StalledHead,
+}
+
+impl Misbehaviour {
+    /// Penalty score assigned to the peer for the misbehaviour
+    pub fn penalty(&self) -> u32 {
+        match self {
+            Misbehaviour::UnexpectedValidationPass => BAN_THRESHOLD,
+            Misbehaviour::UnexpectedOperations => 50,
+            Misbehaviour::InvalidBlockHeader => 40,
+            Misbehaviour::SilentPeer => 25,
+            Misbehaviour::StalledHead => 25,
+            Misbehaviour::UnexpectedBlockHeader => 20,
+            Misbehaviour::UnexpectedProtocol => 20,
+            Misbehaviour::InactiveChain => 10,
+        }
+    }
+}
+
+/// Penalty scores of the peers, peers are identified by peer id
+pub struct PeerReputation {
+    scores: HashMap<String, u32>,
+}
+
+impl PeerReputation {
+    pub fn new() -> Self {
+        PeerReputation {
+            scores: HashMap::new(),
+        }
+    }
+
+    /// Add penalty for the peer misbehaviour.
+    ///
+    /// Returns `true` if peer should be banned. Score of the banned peer is reset.
+    pub fn penalize(&mut self, peer_id: &str, misbehaviour: Misbehaviour) -> bool {
+        let score = self.scores.entry(peer_id.to_string()).or_insert(0);
+        *score = score.saturating_add(misbehaviour.penalty());
+        if *score >= BAN_THRESHOLD {
+            self.scores.remove(peer_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    pub fn score(&self, peer_id: &str) -> u32 {
+        self.scores.get(peer_id).cloned().unwrap_or(0)
+    }
+
+    /// Forget penalties of all peers
+    #[inline]
+    pub fn forgive_all(&mut self) {
+        self.scores.clear();
+    }
+}
+
+/// Resolves how long the peer should be banned
+#[derive(Clone, Copy, Debug)]
+pub struct BanPolicy {
+    /// Duration of the first ban
+    ban_duration: Duration,
+}
+
+impl BanPolicy {
+    pub fn new(ban_duration: Duration) -> Self {
+        BanPolicy { ban_duration }
+    }
+
+    /// Duration of the ban is doubled with every repeated ban (`ban_count` starts at 1)
+    pub fn ban_duration(&self, ban_count: u32) -> Duration {
+        let multiplier = 1u32.checked_shl(ban_count.saturating_sub(1)).unwrap_or(u32::max_value());
+        self.ban_duration.checked_mul(multiplier)
+            .map(|duration| cmp::min(duration, MAX_BAN_DURATION))
+            .unwrap_or(MAX_BAN_DURATION)
+    }
+}
+
+impl Default for BanPolicy {
+    fn default() -> Self {
+        BanPolicy::new(DEFAULT_BAN_DURATION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_penalize() {
+        let mut reputation = PeerReputation::new();
+        assert!(!reputation.penalize("peer_a", Misbehaviour::InvalidBlockHeader));
+        assert!(!reputation.penalize("peer_a", Misbehaviour::InvalidBlockHeader));
+        assert_eq!(80, reputation.score("peer_a"));
+        assert!(reputation.penalize("peer_a", Misbehaviour::InvalidBlockHeader));
+        assert_eq!(0, reputation.score("peer_a"));
+
+        assert!(reputation.penalize("peer_b", Misbehaviour::UnexpectedValidationPass));
+
+        assert!(!reputation.penalize("peer_c", Misbehaviour::SilentPeer));
+        reputation.forgive_all();
+        assert_eq!(0, reputation.score("peer_c"));
+    }
+
+    #[test]
+    fn test_ban_duration_grows() {
+        let policy = BanPolicy::new(Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(60), policy.ban_duration(0));
+        assert_eq!(Duration::from_secs(60), policy.ban_duration(1));
+        assert_eq!(Duration::from_secs(120), policy.ban_duration(2));
+        assert_eq!(Duration::from_secs(240), policy.ban_duration(3));
+        assert_eq!(MAX_BAN_DURATION, policy.ban_duration(20));
+        assert_eq!(MAX_BAN_DURATION, policy.ban_duration(100));
+    }
+}
@@ -6,10 +6,14 @@
 use getset::Getters;
 use riker::actors::*;
 
-use crypto::hash::BlockHash;
+use crypto::hash::{BlockHash, ChainId};
+use networking::p2p::peer::PeerRef;
 use storage::block_storage::BlockJsonData;
 use storage::BlockHeaderWithHash;
 
+use crate::peer_reputation::Misbehaviour;
+use crate::test_chain::TestChain;
+
 /// Message informing actors about successful block application by protocol
 #[derive(Clone, Debug, Getters)]
 pub struct BlockApplied {
@@ -25,6 +29,36 @@ impl BlockApplied {
     }
 }
 
+/// Message informing actors that fork choice selected a new current head of the chain (main chain or test chain)
+#[derive(Clone, Debug, Getters)]
+pub struct NewCurrentHead {
+    #[get = "pub"]
+    chain_id: ChainId,
+    #[get = "pub"]
+    header: BlockHeaderWithHash,
+    #[get = "pub"]
+    json_data: BlockJsonData,
+}
+
+impl NewCurrentHead {
+    pub fn new(chain_id: ChainId, header: BlockHeaderWithHash, json_data: BlockJsonData) -> Self {
+        Self { chain_id, header, json_data }
+    }
+}
+
+/// Message informing actors that current head was switched to a block from different branch
+#[derive(Clone, Debug)]
+pub struct ChainReorganized {
+    /// Previous current head
+    pub old_head: BlockHash,
+    /// New current head, which is not a descendant of the `old_head`
+    pub new_head: BlockHash,
+    /// Last block which is common for both branches
+    pub common_ancestor: BlockHash,
+    /// Level of the `common_ancestor`
+    pub common_ancestor_level: i32,
+}
+
 /// Notify actors that system is about to shut down
 #[derive(Clone, Debug)]
 pub struct ShuttingDown;
@@ -43,12 +77,31 @@ pub struct AllBlockOperationsReceived {
     pub level: i32,
 }
 
+/// Message informing actors that peer misbehaved, so it can be penalized
+#[derive(Clone, Debug)]
+pub struct PeerMisbehaved {
+    pub peer: PeerRef,
+    pub misbehaviour: Misbehaviour,
+}
+
+/// Message informing actors that applied block forked a test chain
+#[derive(Clone, Debug)]
+pub struct TestChainForked {
+    /// Block which forked the test chain
+    pub forking_block: BlockHash,
+    pub test_chain: TestChain,
+}
+
 /// Shell channel event message.
 #[derive(Clone, Debug)]
 pub enum ShellChannelMsg {
     BlockApplied(BlockApplied),
+    NewCurrentHead(NewCurrentHead),
+    ChainReorganized(ChainReorganized),
     BlockReceived(BlockReceived),
     AllBlockOperationsReceived(AllBlockOperationsReceived),
+    PeerMisbehaved(PeerMisbehaved),
+    TestChainForked(TestChainForked),
     ShuttingDown(ShuttingDown),
 }
 
@@ -58,6 +111,18 @@ impl From<BlockApplied> for ShellChannelMsg {
     }
 }
 
+impl From<NewCurrentHead> for ShellChannelMsg {
+    fn from(msg: NewCurrentHead) -> Self {
+        ShellChannelMsg::NewCurrentHead(msg)
+    }
+}
+
+impl From<ChainReorganized> for ShellChannelMsg {
+    fn from(msg: ChainReorganized) -> Self {
+        ShellChannelMsg::ChainReorganized(msg)
+    }
+}
+
 impl From<BlockReceived> for ShellChannelMsg {
     fn from(msg: BlockReceived) -> Self {
         ShellChannelMsg::BlockReceived(msg)
@@ -70,6 +135,18 @@ impl From<AllBlockOperationsReceived> for ShellChannelMsg {
     }
 }
 
+impl From<PeerMisbehaved> for ShellChannelMsg {
+    fn from(msg: PeerMisbehaved) -> Self {
+        ShellChannelMsg::PeerMisbehaved(msg)
+    }
+}
+
+impl From<TestChainForked> for ShellChannelMsg {
+    fn from(msg: TestChainForked) -> Self {
+        ShellChannelMsg::TestChainForked(msg)
+    }
+}
+
 impl From<ShuttingDown> for ShellChannelMsg {
     fn from(msg: ShuttingDown) -> Self {
         ShellChannelMsg::ShuttingDown(msg)
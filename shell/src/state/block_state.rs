@@ -4,14 +4,16 @@
 use std::cmp;
 use std::cmp::Ordering;
 
-use rand::Rng;
-
 use crypto::hash::{BlockHash, ChainId};
 use storage::{BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader, IteratorMode, StorageError};
 use storage::persistent::PersistentStorage;
 
+use crate::block_locator::{self, Seed};
 use crate::collections::{BlockData, UniqueBlockData};
 
+/// Max number of blocks in the history of the block locator, it is enough to cover more than 10 million levels
+const HISTORY_SIZE: usize = 200;
+
 /// Holds state of all known blocks
 pub struct BlockState {
     /// persistent block storage
@@ -98,21 +100,29 @@ impl BlockState {
         &self.chain_id
     }
 
-    pub fn get_history(&self) -> Result<Vec<BlockHash>, StorageError> {
-        let history_max = 20;
-        let mut history = Vec::with_capacity(history_max);
-        let mut rng = rand::thread_rng();
-        for (key, value) in self.block_meta_storage.iter(IteratorMode::Start)? {
-            let pivot = (1 + rng.gen::<u8>() % 24) as i32;
-            let (block_hash, meta) = (key?, value?);
-            if meta.is_applied() && (meta.level() != 0) && (meta.level() % pivot == 0) && (meta.chain_id() == &self.chain_id) {
-                history.push(block_hash);
-                if history.len() >= history_max {
-                    break;
-                }
+    /// Check if the stored block belongs to the chain of this state
+    #[inline]
+    pub fn belongs_to_chain(&self, block_hash: &BlockHash) -> Result<bool, StorageError> {
+        Ok(self.block_meta_storage.get(block_hash)?.filter(|meta| meta.chain_id() == &self.chain_id).is_some())
+    }
+
+    /// Compute history of the block locator for the `head`, see [`block_locator`](crate::block_locator)
+    pub fn get_history(&self, head: &BlockHash, seed: &Seed) -> Result<Vec<BlockHash>, StorageError> {
+        block_locator::compute_history(head, HISTORY_SIZE, seed, |block_hash, distance| self.find_predecessor(block_hash, distance))
+    }
+
+    /// Walk back from the block by `distance` predecessors. If genesis or the oldest stored block is reached sooner,
+    /// then it is returned together with the distance walked.
+    fn find_predecessor(&self, block_hash: &BlockHash, distance: i32) -> Result<(BlockHash, i32), StorageError> {
+        let mut current = block_hash.clone();
+        for walked in 0..distance {
+            match self.block_meta_storage.get(&current)?.and_then(|meta| meta.predecessor().clone()) {
+                // predecessor of the genesis is the genesis itself
+                Some(predecessor) if predecessor != current => current = predecessor,
+                _ => return Ok((current, walked)),
             }
         }
-        Ok(history)
+        Ok((current, distance))
     }
 }
 
@@ -129,16 +139,6 @@ impl BlockData for MissingBlock {
     }
 }
 
-impl From<BlockHash> for MissingBlock {
-    fn from(block_hash: BlockHash) -> Self {
-        MissingBlock {
-            block_hash,
-            //TODO: refactor to support None
-            level: 0
-        }
-    }
-}
-
 impl PartialEq for MissingBlock {
     fn eq(&self, other: &Self) -> bool {
         self.level == other.level && self.block_hash == other.block_hash
@@ -0,0 +1,352 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Adaptive scheduling of block header and operations downloads.
+//!
+//! Every peer has its own download queue. Size of the queue follows measured throughput of the peer,
+//! so the fastest peers receive most of the requests. Requests, which are not answered on time,
+//! are taken back from the peer, so they can be assigned to other peers. Late responses to such requests
+//! are still accepted for a while.
+//!
+//! Queue is the only place, where the time of the last request and response is tracked, it is used to detect silent peers too.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crypto::hash::BlockHash;
+
+/// Queue size used until throughput of the peer is measured
+const INITIAL_QUEUE_SIZE: usize = 10;
+/// Even the slowest peer can have this many requests queued
+const MIN_QUEUE_SIZE: usize = 2;
+/// Limit of the queue size for the fastest peers
+const MAX_QUEUE_SIZE: usize = 100;
+/// Queue is sized so the peer can process it during this time
+const TARGET_QUEUE_DURATION: Duration = Duration::from_secs(2);
+/// Throughput is sampled after this time
+const THROUGHPUT_SAMPLE_DURATION: Duration = Duration::from_secs(1);
+/// Weight of the new sample in the moving averages
+const SMOOTHING_FACTOR: f64 = 0.2;
+/// Request times out after latency of the peer multiplied by this value
+const REQUEST_TIMEOUT_LATENCY_MULTIPLIER: u32 = 4;
+/// Minimal time to wait for the response
+const MIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Request will be assigned to other peer after this time, even if the peer is very slow
+const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+/// Late response to the timed out request is accepted during this time
+const LATE_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct QueuedRequest<T> {
+    data: T,
+    /// Time of the request or time of the last partial response
+    requested_at: Instant,
+}
+
+/// Queue of download requests sent to a single peer
+pub struct DownloadQueue<T> {
+    queued: HashMap<BlockHash, QueuedRequest<T>>,
+    /// Requests taken back from the peer, `requested_at` holds the time of the timeout
+    timed_out: HashMap<BlockHash, QueuedRequest<T>>,
+    /// Moving average of the response latency
+    latency: Option<Duration>,
+    /// Moving average of the throughput (responses per second)
+    throughput: Option<f64>,
+    /// Start of the current throughput sample, it is set only when there are queued requests
+    sample_start: Option<Instant>,
+    /// Responses received during current throughput sample
+    sample_responses: usize,
+    /// Last time a request was queued
+    request_last: Instant,
+    /// Last time a response to the queued request (including the late response) was received
+    response_last: Instant,
+}
+
+impl<T> DownloadQueue<T> {
+    pub fn new() -> Self {
+        DownloadQueue {
+            queued: HashMap::new(),
+            timed_out: HashMap::new(),
+            latency: None,
+            throughput: None,
+            sample_start: None,
+            sample_responses: 0,
+            request_last: Instant::now(),
+            response_last: Instant::now(),
+        }
+    }
+
+    /// Count of requests which can be queued for the peer
+    pub fn capacity(&self) -> usize {
+        match self.throughput {
+            Some(throughput) => {
+                let capacity = (throughput * TARGET_QUEUE_DURATION.as_secs_f64()).ceil() as usize;
+                cmp::min(cmp::max(capacity, MIN_QUEUE_SIZE), MAX_QUEUE_SIZE)
+            }
+            None => INITIAL_QUEUE_SIZE,
+        }
+    }
+
+    #[inline]
+    pub fn available_capacity(&self) -> usize {
+        self.capacity().saturating_sub(self.queued.len())
+    }
+
+    /// Time to wait for the response from the peer
+    pub fn request_timeout(&self) -> Duration {
+        match self.latency {
+            Some(latency) => cmp::min(cmp::max(latency * REQUEST_TIMEOUT_LATENCY_MULTIPLIER, MIN_REQUEST_TIMEOUT), MAX_REQUEST_TIMEOUT),
+            None => MAX_REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Queue new request. Returns `false` if request for the block was already queued.
+    pub fn insert(&mut self, block_hash: BlockHash, data: T) -> bool {
+        if self.queued.contains_key(&block_hash) {
+            return false;
+        }
+        if self.queued.is_empty() {
+            self.sample_start = Some(Instant::now());
+            self.sample_responses = 0;
+        }
+        self.request_last = Instant::now();
+        self.queued.insert(block_hash, QueuedRequest { data, requested_at: self.request_last });
+        true
+    }
+
+    /// Get queued request or request, which timed out recently, so its late response can be accepted
+    pub fn get_mut(&mut self, block_hash: &BlockHash) -> Option<&mut T> {
+        if self.queued.contains_key(block_hash) {
+            self.queued.get_mut(block_hash).map(|request| &mut request.data)
+        } else {
+            self.timed_out.get_mut(block_hash)
+                .filter(|request| request.requested_at.elapsed() <= LATE_RESPONSE_TIMEOUT)
+                .map(|request| &mut request.data)
+        }
+    }
+
+    /// Returns `true` if request for the block timed out recently, so it should be assigned to other peers
+    pub fn has_timed_out(&self, block_hash: &BlockHash) -> bool {
+        self.timed_out.get(block_hash)
+            .filter(|request| request.requested_at.elapsed() <= LATE_RESPONSE_TIMEOUT)
+            .is_some()
+    }
+
+    /// Record (partial) response to the queued request. Timeout of the request is restarted.
+    ///
+    /// Returns `false` if request was not queued.
+    pub fn response_received(&mut self, block_hash: &BlockHash) -> bool {
+        let latency = match self.queued.get_mut(block_hash) {
+            Some(request) => {
+                let latency = request.requested_at.elapsed();
+                request.requested_at = Instant::now();
+                latency
+            }
+            None => return false,
+        };
+        self.response_last = Instant::now();
+        self.update_stats(latency);
+        true
+    }
+
+    /// Record response to the queued request and remove the request from the queue.
+    /// Late response to the request, which timed out recently, is accepted too.
+    pub fn complete(&mut self, block_hash: &BlockHash) -> Option<T> {
+        if self.response_received(block_hash) {
+            self.remove(block_hash)
+        } else if self.has_timed_out(block_hash) {
+            self.response_last = Instant::now();
+            self.remove(block_hash)
+        } else {
+            None
+        }
+    }
+
+    pub fn remove(&mut self, block_hash: &BlockHash) -> Option<T> {
+        let late = self.timed_out.remove(block_hash).map(|request| request.data);
+        let removed = self.queued.remove(block_hash).map(|request| request.data).or(late);
+        if self.queued.is_empty() {
+            self.finish_sample();
+        }
+        removed
+    }
+
+    /// Take back all requests, which were not answered on time. Throughput of the peer is penalized.
+    pub fn drain_timed_out(&mut self) -> Vec<T>
+        where T: Clone
+    {
+        self.timed_out.retain(|_, request| request.requested_at.elapsed() <= LATE_RESPONSE_TIMEOUT);
+
+        let request_timeout = self.request_timeout();
+        let timed_out = self.queued.iter()
+            .filter(|(_, request)| request.requested_at.elapsed() > request_timeout)
+            .map(|(block_hash, _)| block_hash.clone())
+            .collect::<Vec<_>>();
+
+        if !timed_out.is_empty() {
+            let throughput = self.throughput.unwrap_or(INITIAL_QUEUE_SIZE as f64 / TARGET_QUEUE_DURATION.as_secs_f64());
+            self.throughput = Some(throughput / 2.0);
+        }
+
+        timed_out.into_iter()
+            .filter_map(|block_hash| {
+                let data = self.remove(&block_hash)?;
+                self.timed_out.insert(block_hash, QueuedRequest { data: data.clone(), requested_at: Instant::now() });
+                Some(data)
+            })
+            .collect()
+    }
+
+    /// Remove all requests from the queue
+    pub fn drain(&mut self) -> Vec<T> {
+        self.sample_start = None;
+        self.timed_out.clear();
+        self.queued.drain().map(|(_, request)| request.data).collect()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    #[inline]
+    pub fn request_last(&self) -> Instant {
+        self.request_last
+    }
+
+    #[inline]
+    pub fn response_last(&self) -> Instant {
+        self.response_last
+    }
+
+    /// Returns `true` if no response was received since the last request
+    #[inline]
+    pub fn is_response_pending(&self) -> bool {
+        self.request_last > self.response_last
+    }
+
+    fn update_stats(&mut self, latency: Duration) {
+        self.latency = Some(match self.latency {
+            Some(average) => average.mul_f64(1.0 - SMOOTHING_FACTOR) + latency.mul_f64(SMOOTHING_FACTOR),
+            None => latency,
+        });
+
+        self.sample_responses += 1;
+        if self.sample_start.filter(|sample_start| sample_start.elapsed() >= THROUGHPUT_SAMPLE_DURATION).is_some() {
+            self.finish_sample();
+            self.sample_start = Some(Instant::now());
+        }
+    }
+
+    /// Update throughput from the current sample. Only time when the peer had some requests queued is measured.
+    fn finish_sample(&mut self) {
+        if let Some(sample_start) = self.sample_start.take() {
+            let elapsed = sample_start.elapsed().as_secs_f64();
+            if self.sample_responses > 0 && elapsed > 0.0 {
+                let sample = self.sample_responses as f64 / elapsed;
+                self.throughput = Some(match self.throughput {
+                    Some(average) => average * (1.0 - SMOOTHING_FACTOR) + sample * SMOOTHING_FACTOR,
+                    None => sample,
+                });
+            }
+            self.sample_responses = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_complete() {
+        let mut queue = DownloadQueue::new();
+        assert_eq!(INITIAL_QUEUE_SIZE, queue.available_capacity());
+
+        assert!(queue.insert(vec![1; 32], 1));
+        assert!(!queue.insert(vec![1; 32], 1));
+        assert!(queue.insert(vec![2; 32], 2));
+        assert_eq!(2, queue.len());
+        assert_eq!(INITIAL_QUEUE_SIZE - 2, queue.available_capacity());
+
+        assert!(queue.is_response_pending());
+        assert!(queue.response_received(&vec![2; 32]));
+        assert!(!queue.is_response_pending());
+        assert_eq!(Some(&mut 2), queue.get_mut(&vec![2; 32]));
+        assert_eq!(Some(1), queue.complete(&vec![1; 32]));
+        assert_eq!(None, queue.complete(&vec![1; 32]));
+        assert!(!queue.response_received(&vec![3; 32]));
+        assert_eq!(vec![2], queue.drain());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_follows_throughput() {
+        let mut queue = DownloadQueue::<u8>::new();
+        queue.throughput = Some(100.0);
+        assert_eq!(MAX_QUEUE_SIZE, queue.capacity());
+        queue.throughput = Some(10.0);
+        assert_eq!(20, queue.capacity());
+        queue.throughput = Some(0.1);
+        assert_eq!(MIN_QUEUE_SIZE, queue.capacity());
+    }
+
+    #[test]
+    fn test_request_timeout_follows_latency() {
+        let mut queue = DownloadQueue::<u8>::new();
+        assert_eq!(MAX_REQUEST_TIMEOUT, queue.request_timeout());
+        queue.latency = Some(Duration::from_millis(100));
+        assert_eq!(MIN_REQUEST_TIMEOUT, queue.request_timeout());
+        queue.latency = Some(Duration::from_secs(3));
+        assert_eq!(Duration::from_secs(12), queue.request_timeout());
+        queue.latency = Some(Duration::from_secs(60));
+        assert_eq!(MAX_REQUEST_TIMEOUT, queue.request_timeout());
+    }
+
+    #[test]
+    fn test_drain_timed_out() {
+        let mut queue = DownloadQueue::new();
+        queue.latency = Some(Duration::from_millis(100));
+        queue.insert(vec![1; 32], 1);
+        queue.insert(vec![2; 32], 2);
+        queue.queued.get_mut(&vec![1; 32]).unwrap().requested_at -= MIN_REQUEST_TIMEOUT * 2;
+
+        assert_eq!(vec![1], queue.drain_timed_out());
+        assert_eq!(1, queue.len());
+        // peer is penalized for the timeout
+        assert_eq!(INITIAL_QUEUE_SIZE / 2, queue.capacity());
+        assert!(queue.has_timed_out(&vec![1; 32]));
+        assert!(!queue.has_timed_out(&vec![2; 32]));
+    }
+
+    #[test]
+    fn test_late_response() {
+        let mut queue = DownloadQueue::new();
+        queue.insert(vec![1; 32], 1);
+        queue.insert(vec![2; 32], 2);
+        queue.queued.get_mut(&vec![1; 32]).unwrap().requested_at -= MAX_REQUEST_TIMEOUT * 2;
+        queue.queued.get_mut(&vec![2; 32]).unwrap().requested_at -= MAX_REQUEST_TIMEOUT * 2;
+        assert_eq!(2, queue.drain_timed_out().len());
+        assert!(queue.is_empty());
+
+        // late response is accepted only once
+        assert!(queue.is_response_pending());
+        assert_eq!(Some(&mut 1), queue.get_mut(&vec![1; 32]));
+        assert_eq!(Some(1), queue.complete(&vec![1; 32]));
+        assert!(!queue.is_response_pending());
+        assert_eq!(None, queue.complete(&vec![1; 32]));
+        assert!(!queue.has_timed_out(&vec![1; 32]));
+
+        // too late response is not accepted
+        queue.timed_out.get_mut(&vec![2; 32]).unwrap().requested_at -= LATE_RESPONSE_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(None, queue.get_mut(&vec![2; 32]));
+        assert_eq!(None, queue.complete(&vec![2; 32]));
+        assert!(queue.drain_timed_out().is_empty());
+        assert!(queue.timed_out.is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crypto::hash::{BlockHash, OperationHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+/// Key of the operations group. Groups are ordered by the level of the branch,
+/// so operations with the oldest branch can be easily expired or evicted.
+type BranchKey = (i32, BlockHash);
+
+/// Bounded pool of pending operations, operations are grouped by their branch.
+pub struct MempoolState {
+    /// All operations stored in the pool
+    operations: HashMap<OperationHash, (Operation, BranchKey)>,
+    /// Operation hashes grouped by branch
+    operations_by_branch: BTreeMap<BranchKey, HashSet<OperationHash>>,
+    /// Maximal count of operations stored in the pool
+    max_operations: usize,
+}
+
+impl MempoolState {
+    pub fn new(max_operations: usize) -> Self {
+        MempoolState {
+            operations: HashMap::new(),
+            operations_by_branch: BTreeMap::new(),
+            max_operations,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, operation_hash: &OperationHash) -> bool {
+        self.operations.contains_key(operation_hash)
+    }
+
+    #[inline]
+    pub fn get(&self, operation_hash: &OperationHash) -> Option<&Operation> {
+        self.operations.get(operation_hash).map(|(operation, _)| operation)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Return hashes of all operations stored in the pool
+    pub fn operation_hashes(&self) -> Vec<OperationHash> {
+        self.operations.keys().cloned().collect()
+    }
+
+    /// Insert operation into the pool.
+    ///
+    /// If the pool is full, then operations with the oldest branch are evicted.
+    /// Returns `false` if operation was already present or if it's branch is older than all branches in the full pool.
+    pub fn insert(&mut self, operation_hash: OperationHash, operation: Operation, branch_level: i32) -> bool {
+        if self.contains(&operation_hash) {
+            return false;
+        }
+
+        let branch_key = (branch_level, operation.branch().clone());
+        while self.operations.len() >= self.max_operations {
+            let oldest_branch_key = match self.operations_by_branch.keys().next() {
+                Some(oldest_branch_key) if oldest_branch_key.0 < branch_level => oldest_branch_key.clone(),
+                _ => return false
+            };
+            self.remove_branch(&oldest_branch_key);
+        }
+
+        self.operations_by_branch.entry(branch_key.clone())
+            .or_insert_with(HashSet::new)
+            .insert(operation_hash.clone());
+        self.operations.insert(operation_hash, (operation, branch_key));
+        true
+    }
+
+    /// Remove operation from the pool (e.g. operation was included in the block)
+    pub fn remove(&mut self, operation_hash: &OperationHash) -> Option<Operation> {
+        self.operations.remove(operation_hash)
+            .map(|(operation, branch_key)| {
+                if let Some(branch_operations) = self.operations_by_branch.get_mut(&branch_key) {
+                    branch_operations.remove(operation_hash);
+                    if branch_operations.is_empty() {
+                        self.operations_by_branch.remove(&branch_key);
+                    }
+                }
+                operation
+            })
+    }
+
+    /// Remove all operations which branch level is lower than `min_branch_level`.
+    ///
+    /// Returns hashes of all removed operations.
+    pub fn expire(&mut self, min_branch_level: i32) -> Vec<OperationHash> {
+        let expired_branch_keys = self.operations_by_branch.keys()
+            .take_while(|(branch_level, _)| *branch_level < min_branch_level)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        expired_branch_keys.iter()
+            .flat_map(|branch_key| self.remove_branch(branch_key))
+            .collect()
+    }
+
+    fn remove_branch(&mut self, branch_key: &BranchKey) -> Vec<OperationHash> {
+        match self.operations_by_branch.remove(branch_key) {
+            Some(operation_hashes) => {
+                operation_hashes.iter().for_each(|operation_hash| { self.operations.remove(operation_hash); });
+                operation_hashes.into_iter().collect()
+            }
+            None => vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tezos_messages::p2p::binary_message::BinaryMessage;
+
+    use super::*;
+
+    fn operation(branch: u8, data: u8) -> Operation {
+        let mut bytes = vec![branch; 32];
+        bytes.push(data);
+        Operation::from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut mempool = MempoolState::new(10);
+        assert!(mempool.insert(vec![1; 32], operation(1, 1), 1));
+        assert!(!mempool.insert(vec![1; 32], operation(1, 1), 1));
+        assert!(mempool.insert(vec![2; 32], operation(1, 2), 1));
+        assert_eq!(2, mempool.len());
+        assert_eq!(Some(&operation(1, 2)), mempool.get(&vec![2; 32]));
+
+        assert_eq!(Some(operation(1, 1)), mempool.remove(&vec![1; 32]));
+        assert_eq!(None, mempool.remove(&vec![1; 32]));
+        assert_eq!(1, mempool.len());
+        assert_eq!(1, mempool.operations_by_branch.len());
+
+        mempool.remove(&vec![2; 32]);
+        assert!(mempool.is_empty());
+        assert!(mempool.operations_by_branch.is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut mempool = MempoolState::new(10);
+        mempool.insert(vec![1; 32], operation(1, 1), 1);
+        mempool.insert(vec![2; 32], operation(2, 2), 2);
+        mempool.insert(vec![3; 32], operation(2, 3), 2);
+        mempool.insert(vec![4; 32], operation(3, 4), 3);
+
+        let mut expired = mempool.expire(3);
+        expired.sort();
+        assert_eq!(vec![vec![1; 32], vec![2; 32], vec![3; 32]], expired);
+        assert_eq!(1, mempool.len());
+        assert!(mempool.contains(&vec![4; 32]));
+    }
+
+    #[test]
+    fn test_full_mempool_evicts_oldest_branch() {
+        let mut mempool = MempoolState::new(2);
+        assert!(mempool.insert(vec![1; 32], operation(1, 1), 1));
+        assert!(mempool.insert(vec![2; 32], operation(2, 2), 2));
+
+        // older or the same branch cannot replace anything
+        assert!(!mempool.insert(vec![3; 32], operation(1, 3), 1));
+        // newer branch evicts the oldest one
+        assert!(mempool.insert(vec![4; 32], operation(3, 4), 3));
+        assert_eq!(2, mempool.len());
+        assert!(!mempool.contains(&vec![1; 32]));
+        assert!(mempool.contains(&vec![2; 32]));
+        assert!(mempool.contains(&vec![4; 32]));
+    }
+}
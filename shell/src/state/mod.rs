@@ -2,4 +2,6 @@
 // SPDX-License-Identifier: MIT
 
 pub mod block_state;
+pub mod download_queue;
+pub mod mempool_state;
 pub mod operations_state;
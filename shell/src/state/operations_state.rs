@@ -57,13 +57,18 @@ impl OperationsState {
 
     /// Process block operations. This will mark operations in store for the block as seen.
     ///
-    /// If all block operations were processed return `true`.
+    /// If all block operations were processed by this message return `true`.
     ///
-    /// If there are still block operations to be processed return `false`.
+    /// If there are still block operations to be processed, or operations were already complete
+    /// (e.g. late response to the timed out request) return `false`.
     pub fn process_block_operations(&mut self, message: &OperationsForBlocksMessage) -> Result<bool, StorageError> {
+        let block_hash = message.operations_for_block().hash();
+        if self.operations_meta_storage.is_complete(block_hash)? {
+            return Ok(false);
+        }
         self.operations_storage.put_operations(message)?;
         self.operations_meta_storage.put_operations(message)?;
-        self.operations_meta_storage.is_complete(message.operations_for_block().hash())
+        self.operations_meta_storage.is_complete(block_hash)
     }
 
     pub fn drain_missing_operations(&mut self, n: usize, level_max: i32) -> Vec<MissingOperations> {
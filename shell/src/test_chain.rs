@@ -0,0 +1,132 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Test chain is forked from the main chain during the testing vote period, so the proposed protocol can be tried out.
+//!
+//! Status of the test chain is part of the metadata of every main chain block. Block which forks the test chain has status `forking`,
+//! all following blocks have status `running` until the test chain expires.
+
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use failure::{bail, Error};
+use serde_json::Value;
+
+use crypto::hash::{BlockHash, ChainId, HashType, ProtocolHash};
+
+/// Running test chain
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestChain {
+    /// Chain id of the test chain
+    pub chain_id: ChainId,
+    /// Hash of the genesis block of the test chain
+    pub genesis: BlockHash,
+    /// Protocol tested on the test chain
+    pub protocol: ProtocolHash,
+    /// Unix timestamp (seconds), test chain is shut down when main chain reaches this time
+    pub expiration: i64,
+}
+
+impl TestChain {
+    /// Test chain expires, when main chain block with timestamp after the expiration is applied
+    #[inline]
+    pub fn is_expired(&self, block_timestamp: i64) -> bool {
+        block_timestamp >= self.expiration
+    }
+}
+
+/// Status of the test chain as seen by the main chain block
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestChainStatus {
+    NotRunning,
+    Forking {
+        protocol: ProtocolHash,
+        expiration: i64,
+    },
+    Running(TestChain),
+}
+
+impl TestChainStatus {
+    /// Read test chain status from the block header metadata json.
+    ///
+    /// Returns `None` if metadata do not contain test chain status (e.g. genesis block).
+    pub fn from_block_metadata(block_header_proto_metadata_json: &str) -> Result<Option<Self>, Error> {
+        let metadata: HashMap<String, Value> = serde_json::from_str(block_header_proto_metadata_json)?;
+        let status = match metadata.get("test_chain_status") {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+
+        let status = match status.get("status").and_then(Value::as_str) {
+            Some("not_running") => TestChainStatus::NotRunning,
+            Some("forking") => TestChainStatus::Forking {
+                protocol: hash_field(status, "protocol", HashType::ProtocolHash)?,
+                expiration: timestamp_field(status, "expiration")?,
+            },
+            Some("running") => TestChainStatus::Running(TestChain {
+                chain_id: hash_field(status, "chain_id", HashType::ChainId)?,
+                genesis: hash_field(status, "genesis", HashType::BlockHash)?,
+                protocol: hash_field(status, "protocol", HashType::ProtocolHash)?,
+                expiration: timestamp_field(status, "expiration")?,
+            }),
+            other => bail!("Unknown test chain status: {:?}", other),
+        };
+        Ok(Some(status))
+    }
+}
+
+fn hash_field(status: &Value, field: &str, hash_type: HashType) -> Result<Vec<u8>, Error> {
+    match status.get(field).and_then(Value::as_str) {
+        Some(hash) => Ok(hash_type.string_to_bytes(hash)?),
+        None => bail!("Test chain status is missing field: {}", field),
+    }
+}
+
+/// Protocol time is encoded either as RFC 3339 string or as a number of seconds
+fn timestamp_field(status: &Value, field: &str) -> Result<i64, Error> {
+    match status.get(field) {
+        Some(Value::String(time)) => Ok(DateTime::parse_from_rfc3339(time)?.timestamp()),
+        Some(Value::Number(time)) => match time.as_i64() {
+            Some(time) => Ok(time),
+            None => bail!("Invalid test chain timestamp: {}", time),
+        },
+        _ => bail!("Test chain status is missing field: {}", field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_test_chain_status() -> Result<(), Error> {
+        assert_eq!(None, TestChainStatus::from_block_metadata(r#"{"protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb"}"#)?);
+        assert_eq!(
+            Some(TestChainStatus::NotRunning),
+            TestChainStatus::from_block_metadata(r#"{"test_chain_status":{"status":"not_running"}}"#)?
+        );
+
+        let forking = TestChainStatus::from_block_metadata(r#"{"test_chain_status":{"status":"forking","protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb","expiration":"2019-06-11T14:25:58Z"}}"#)?;
+        assert_eq!(
+            Some(TestChainStatus::Forking {
+                protocol: HashType::ProtocolHash.string_to_bytes("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?,
+                expiration: 1_560_263_158,
+            }),
+            forking
+        );
+
+        let running = TestChainStatus::from_block_metadata(r#"{"test_chain_status":{"status":"running","chain_id":"NetXgtSLGNJvNye","genesis":"BLockGenesisGenesisGenesisGenesisGenesis355e8bjkYPv","protocol":"PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb","expiration":1560263158}}"#)?;
+        match running {
+            Some(TestChainStatus::Running(test_chain)) => {
+                assert_eq!("NetXgtSLGNJvNye", HashType::ChainId.bytes_to_string(&test_chain.chain_id));
+                assert_eq!("BLockGenesisGenesisGenesisGenesisGenesis355e8bjkYPv", HashType::BlockHash.bytes_to_string(&test_chain.genesis));
+                assert!(!test_chain.is_expired(1_560_263_157));
+                assert!(test_chain.is_expired(1_560_263_158));
+            }
+            other => panic!("Unexpected test chain status: {:?}", other),
+        }
+
+        assert!(TestChainStatus::from_block_metadata(r#"{"test_chain_status":{"status":"running"}}"#).is_err());
+        Ok(())
+    }
+}
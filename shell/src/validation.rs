@@ -0,0 +1,251 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Validation of the block headers received from peers, which is done before the header is stored.
+//!
+//! Only checks which do not require the protocol are done here:
+//! - block timestamp is not too far in the future
+//! - level, fitness and protocol level are consistent with the predecessor (if predecessor is already known)
+//!   and with the successors (if some successors were already stored)
+//! - validation pass matches the protocol which will validate the block (if the protocol is already known)
+//!
+//! If the predecessor is not known yet, the validation pass is checked against the known protocol levels only,
+//! the full check against the predecessor is done later by the chain feeder before the block is applied.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use failure::Fail;
+
+use tezos_messages::base::fitness::fitness_compare;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::unix_timestamp_now;
+
+/// How far in the future the block timestamp can be
+pub const FUTURE_BLOCK_TOLERANCE: Duration = Duration::from_secs(15);
+
+/// Possible reasons why the block header is rejected
+#[derive(Debug, Fail, PartialEq)]
+pub enum BlockHeaderValidationError {
+    #[fail(display = "Block timestamp {} is in the future, local time: {}", timestamp, now)]
+    FutureBlock {
+        timestamp: i64,
+        now: i64,
+    },
+    #[fail(display = "Invalid block level, expected: {}, actual: {}", expected, actual)]
+    InvalidLevel {
+        expected: i32,
+        actual: i32,
+    },
+    #[fail(display = "Block fitness is not greater than the fitness of the predecessor")]
+    FitnessNotIncreased,
+    #[fail(display = "Invalid protocol level, predecessor: {}, actual: {}", predecessor, actual)]
+    InvalidProtocolLevel {
+        predecessor: u8,
+        actual: u8,
+    },
+    #[fail(display = "Invalid validation pass, expected: {}, actual: {}", expected, actual)]
+    InvalidValidationPass {
+        expected: u8,
+        actual: u8,
+    },
+}
+
+/// Validates block headers before they are stored.
+pub struct BlockHeaderValidator {
+    /// Number of validation passes required by the protocol. Key is the protocol level of the predecessor,
+    /// because block is validated by the protocol activated in its predecessor.
+    validation_passes: HashMap<u8, u8>,
+}
+
+impl BlockHeaderValidator {
+    pub fn new() -> Self {
+        BlockHeaderValidator {
+            validation_passes: HashMap::new(),
+        }
+    }
+
+    /// Remember number of validation passes of the protocol from the block, which was successfully applied.
+    pub fn learn_protocol(&mut self, block_header: &BlockHeader, predecessor: &BlockHeader) {
+        self.validation_passes.insert(predecessor.proto(), block_header.validation_pass());
+    }
+
+    /// Validate block header against the predecessor and against the successors, which were already stored.
+    ///
+    /// If predecessor is not known yet, then only checks independent of the predecessor are done.
+    pub fn validate(&self, block_header: &BlockHeader, predecessor: Option<&BlockHeader>, successors: &[&BlockHeader]) -> Result<(), BlockHeaderValidationError> {
+        self.validate_at(block_header, predecessor, successors, unix_timestamp_now())
+    }
+
+    fn validate_at(&self, block_header: &BlockHeader, predecessor: Option<&BlockHeader>, successors: &[&BlockHeader], now: i64) -> Result<(), BlockHeaderValidationError> {
+        if block_header.timestamp() > now + FUTURE_BLOCK_TOLERANCE.as_secs() as i64 {
+            return Err(BlockHeaderValidationError::FutureBlock { timestamp: block_header.timestamp(), now });
+        }
+
+        match predecessor {
+            Some(predecessor) => self.validate_successor(predecessor, block_header)?,
+            None => self.validate_protocol_level(block_header)?,
+        }
+
+        for successor in successors {
+            self.validate_successor(block_header, successor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Validate level, fitness, protocol level and validation pass of the block against its predecessor.
+    pub fn validate_successor(&self, predecessor: &BlockHeader, block_header: &BlockHeader) -> Result<(), BlockHeaderValidationError> {
+        if block_header.level() != predecessor.level() + 1 {
+            return Err(BlockHeaderValidationError::InvalidLevel { expected: predecessor.level() + 1, actual: block_header.level() });
+        }
+
+        if fitness_compare(predecessor.fitness(), block_header.fitness()) != std::cmp::Ordering::Less {
+            return Err(BlockHeaderValidationError::FitnessNotIncreased);
+        }
+
+        // protocol level stays the same or is increased by one on protocol activation
+        if block_header.proto() != predecessor.proto() && block_header.proto() != predecessor.proto().wrapping_add(1) {
+            return Err(BlockHeaderValidationError::InvalidProtocolLevel { predecessor: predecessor.proto(), actual: block_header.proto() });
+        }
+
+        if let Some(&expected) = self.validation_passes.get(&predecessor.proto()) {
+            if block_header.validation_pass() != expected {
+                return Err(BlockHeaderValidationError::InvalidValidationPass { expected, actual: block_header.validation_pass() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Without the predecessor the block is validated by the protocol of the same protocol level,
+    /// or by the protocol one level lower (block activating a new protocol). Block is rejected only if both
+    /// protocols are known and the validation pass matches neither of them.
+    fn validate_protocol_level(&self, block_header: &BlockHeader) -> Result<(), BlockHeaderValidationError> {
+        let same_level = self.validation_passes.get(&block_header.proto());
+        let previous_level = self.validation_passes.get(&block_header.proto().wrapping_sub(1));
+        if let (Some(&expected), Some(&previous)) = (same_level, previous_level) {
+            if block_header.validation_pass() != expected && block_header.validation_pass() != previous {
+                return Err(BlockHeaderValidationError::InvalidValidationPass { expected, actual: block_header.validation_pass() });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_header(level: i32, proto: u8, timestamp: i64, validation_pass: u8, fitness: Vec<Vec<u8>>) -> BlockHeader {
+        BlockHeaderBuilder::default()
+            .level(level)
+            .proto(proto)
+            .predecessor(vec![0; 32])
+            .timestamp(timestamp)
+            .validation_pass(validation_pass)
+            .operations_hash(vec![0; 32])
+            .fitness(fitness)
+            .context(vec![0; 32])
+            .protocol_data(vec![])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_validate_timestamp() {
+        let validator = BlockHeaderValidator::new();
+        let now = 1_000_000;
+
+        assert!(validator.validate_at(&block_header(10, 1, now + 10, 4, vec![vec![1]]), None, &[], now).is_ok());
+        assert_eq!(
+            Err(BlockHeaderValidationError::FutureBlock { timestamp: now + 60, now }),
+            validator.validate_at(&block_header(10, 1, now + 60, 4, vec![vec![1]]), None, &[], now)
+        );
+    }
+
+    #[test]
+    fn test_validate_against_predecessor() {
+        let validator = BlockHeaderValidator::new();
+        let now = 1_000_000;
+        let predecessor = block_header(10, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 5]]);
+
+        assert!(validator.validate_at(&block_header(11, 1, now, 4, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now).is_ok());
+        // protocol activation
+        assert!(validator.validate_at(&block_header(11, 2, now, 4, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now).is_ok());
+
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidLevel { expected: 11, actual: 12 }),
+            validator.validate_at(&block_header(12, 1, now, 4, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now)
+        );
+        assert_eq!(
+            Err(BlockHeaderValidationError::FitnessNotIncreased),
+            validator.validate_at(&block_header(11, 1, now, 4, vec![vec![0], vec![0, 0, 0, 5]]), Some(&predecessor), &[], now)
+        );
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidProtocolLevel { predecessor: 1, actual: 3 }),
+            validator.validate_at(&block_header(11, 3, now, 4, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now)
+        );
+    }
+
+    #[test]
+    fn test_validate_validation_pass_of_known_protocol() {
+        let mut validator = BlockHeaderValidator::new();
+        let now = 1_000_000;
+        let predecessor = block_header(10, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 5]]);
+
+        // protocol is not known yet
+        assert!(validator.validate_at(&block_header(11, 1, now, 3, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now).is_ok());
+
+        validator.learn_protocol(&block_header(10, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 5]]), &block_header(9, 1, now - 120, 4, vec![vec![0], vec![0, 0, 0, 4]]));
+        assert!(validator.validate_at(&block_header(11, 1, now, 4, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now).is_ok());
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidValidationPass { expected: 4, actual: 3 }),
+            validator.validate_at(&block_header(11, 1, now, 3, vec![vec![0], vec![0, 0, 0, 6]]), Some(&predecessor), &[], now)
+        );
+    }
+
+    #[test]
+    fn test_validate_against_successors() {
+        let validator = BlockHeaderValidator::new();
+        let now = 1_000_000;
+        let successor = block_header(12, 1, now, 4, vec![vec![0], vec![0, 0, 0, 7]]);
+
+        assert!(validator.validate_at(&block_header(11, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 6]]), None, &[&successor], now).is_ok());
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidLevel { expected: 11, actual: 12 }),
+            validator.validate_at(&block_header(10, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 6]]), None, &[&successor], now)
+        );
+        assert_eq!(
+            Err(BlockHeaderValidationError::FitnessNotIncreased),
+            validator.validate_at(&block_header(11, 1, now - 60, 4, vec![vec![0], vec![0, 0, 0, 7]]), None, &[&successor], now)
+        );
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidProtocolLevel { predecessor: 2, actual: 1 }),
+            validator.validate_at(&block_header(11, 2, now - 60, 4, vec![vec![0], vec![0, 0, 0, 6]]), None, &[&successor], now)
+        );
+    }
+
+    #[test]
+    fn test_validate_validation_pass_without_predecessor() {
+        let mut validator = BlockHeaderValidator::new();
+        let now = 1_000_000;
+
+        // protocol level 1 requires 4 validation passes
+        validator.learn_protocol(&block_header(10, 1, now - 60, 4, vec![vec![1]]), &block_header(9, 1, now - 120, 4, vec![vec![0]]));
+        // block could be the activation block validated by the protocol of level 1, which is known
+        assert!(validator.validate_at(&block_header(11, 2, now, 4, vec![vec![2]]), None, &[], now).is_ok());
+        // block could be validated by the unknown protocol of level 2
+        assert!(validator.validate_at(&block_header(11, 2, now, 3, vec![vec![2]]), None, &[], now).is_ok());
+
+        // protocol level 0 requires 0 validation passes
+        validator.learn_protocol(&block_header(1, 1, now - 600, 0, vec![]), &block_header(0, 0, now - 660, 0, vec![]));
+        assert!(validator.validate_at(&block_header(11, 1, now, 4, vec![vec![2]]), None, &[], now).is_ok());
+        assert_eq!(
+            Err(BlockHeaderValidationError::InvalidValidationPass { expected: 4, actual: 3 }),
+            validator.validate_at(&block_header(11, 1, now, 3, vec![vec![2]]), None, &[], now)
+        );
+    }
+}
@@ -25,6 +25,8 @@ use tezos_messages::p2p::binary_message::BinaryMessage;
 use tezos_messages::p2p::encoding::prelude::BlockHeader;
 use tezos_wrapper::service::IpcEvtServer;
 
+mod common;
+
 #[test]
 fn test_apply_first_three_block_and_check_context() -> Result<(), failure::Error> {
 
@@ -63,7 +65,7 @@ fn test_apply_first_three_block_and_check_context() -> Result<(), failure::Error
 
     // run context_listener actor
     let actor_system = SystemBuilder::new().name("test_apply_first_three_block_and_check_context").log(log.clone()).create().expect("Failed to create actor system");
-    let _ = ContextListener::actor(&actor_system, &persistent_storage, event_server, log.clone()).expect("Failed to create context event listener");
+    let _ = ContextListener::actor(&actor_system, &persistent_storage, event_server, None, log.clone()).expect("Failed to create context event listener");
 
     // run apply blocks
     let _ = apply_first_three_blocks_like_chain_feeder(
@@ -306,38 +308,3 @@ mod test_data {
             .collect()
     }
 }
-
-mod common {
-    use std::env;
-    use std::fs;
-    use std::path::{Path, PathBuf};
-
-    pub fn prepare_empty_dir(dir_name: &str) -> String {
-        let path = test_storage_dir_path(dir_name);
-        if path.exists() {
-            fs::remove_dir_all(&path).unwrap_or_else(|_| panic!("Failed to delete directory: {:?}", &path));
-        }
-        fs::create_dir_all(&path).unwrap_or_else(|_| panic!("Failed to create directory: {:?}", &path));
-        String::from(path.to_str().unwrap())
-    }
-
-    pub fn test_storage_dir_path(dir_name: &str) -> PathBuf {
-        let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not defined");
-        let path = Path::new(out_dir.as_str())
-            .join(Path::new(dir_name))
-            .to_path_buf();
-        path
-    }
-
-    pub fn is_ocaml_log_enabled() -> bool {
-        env::var("OCAML_LOG_ENABLED")
-            .unwrap_or("false".to_string())
-            .parse::<bool>().unwrap()
-    }
-
-    pub fn no_of_ffi_calls_treshold_for_gc() -> i32 {
-        env::var("OCAML_CALLS_GC")
-            .unwrap_or("2000".to_string())
-            .parse::<i32>().unwrap()
-    }
-}
\ No newline at end of file
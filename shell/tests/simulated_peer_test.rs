@@ -0,0 +1,458 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Tests of the chain synchronization with the simulated peer, which serves synthetic chain over the real p2p connection.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use riker::actors::*;
+use slog::{Drain, Level, Logger};
+use tokio::runtime::Runtime;
+
+use crypto::hash::{BlockHash, ChainId, HashType, OperationHash};
+use networking::p2p::bandwidth::BandwidthLimits;
+use networking::p2p::network_channel::NetworkChannel;
+use networking::p2p::simulated_peer::{Behaviour, SimulatedPeer, SimulatedPeerConfig, SyntheticChain};
+use shell::chain_manager::{ChainManager, PeerTimeouts};
+use shell::mempool_manager::MempoolManager;
+use shell::peer_manager::{bind_listeners, PeerManager, Threshold};
+use shell::peer_reputation::BanPolicy;
+use shell::shell_channel::{ShellChannel, ShellChannelRef, ShellChannelTopic, TestChainForked};
+use shell::test_chain::TestChain;
+use shell::unix_timestamp_now;
+use storage::{BanKey, BlockAdditionalDataBuilder, BlockHeaderWithHash, BlockJsonDataBuilder, BlockMetaStorage, BlockStorage, BlockStorageReader, initialize_storage_with_genesis_block, OperationsMetaStorage, PeerBanStorage, resolve_storage_init_chain_data, store_test_chain_genesis};
+use storage::block_meta_storage::Meta;
+use storage::tests_common::TmpStorage;
+use tezos_api::environment::{TEZOS_ENV, TezosEnvironment, TezosEnvironmentConfiguration};
+use tezos_api::identity::Identity;
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+mod common;
+
+const TEZOS_NETWORK: TezosEnvironment = TezosEnvironment::Alphanet;
+const CHAIN_LENGTH: usize = 10;
+const VALIDATION_PASSES: u8 = 4;
+/// How long to wait for the expected reaction of the node
+const WAIT_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long to wait to make sure, that the node does not react, it spans several periodic peer checks and the mempool advertisement
+const QUIET_PERIOD: Duration = Duration::from_secs(6);
+
+#[test]
+fn test_sync_chain_from_simulated_peer() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_sync"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+
+    let simulated_peer = SimulatedPeer::listen(SimulatedPeerConfig::new(&tezos_env().version, chain.clone()), Some(log.clone()))?;
+    let _node = TestNode::start("test_sync_chain_from_simulated_peer", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+
+    // all blocks and their operations are downloaded
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+    assert!(wait_until(WAIT_TIMEOUT, || {
+        chain.blocks().iter().all(|(hash, _)| {
+            block_storage.get(hash).map(|block| block.is_some()).unwrap_or(false) && operations_meta_storage.is_complete(hash).unwrap_or(false)
+        })
+    }));
+
+    let received = simulated_peer.received_messages();
+    assert!(received.iter().any(|message| if let PeerMessage::GetCurrentBranch(_) = message { true } else { false }));
+    assert!(received.iter().any(|message| if let PeerMessage::GetBlockHeaders(_) = message { true } else { false }));
+    assert!(received.iter().any(|message| if let PeerMessage::GetOperationsForBlocks(_) = message { true } else { false }));
+    assert_eq!(0, simulated_peer.disconnections());
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_with_wrong_validation_pass_is_banned() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_wrong_validation_pass"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::WrongValidationPass);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_peer_with_wrong_validation_pass_is_banned", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+
+    let ban_storage = PeerBanStorage::new(tmp_storage.storage());
+    let ban_key = BanKey::PeerId(simulated_peer.peer_id().to_string());
+    assert!(wait_until(WAIT_TIMEOUT, || ban_storage.is_banned(&ban_key, unix_timestamp_now()).unwrap_or(false)));
+    assert!(simulated_peer.wait_for_disconnection(WAIT_TIMEOUT));
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_with_unrequested_data_is_disconnected() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_unrequested_data"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::UnrequestedData);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_peer_with_unrequested_data_is_disconnected", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+    assert!(simulated_peer.wait_for_disconnection(WAIT_TIMEOUT));
+
+    Ok(())
+}
+
+#[test]
+fn test_silent_peer_is_disconnected() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_silent"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::Silent);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_silent_peer_is_disconnected", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+
+    // peer advertises its branch, but it never provides the blocks
+    simulated_peer.send(chain.current_branch().expect("Synthetic chain is empty").into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| {
+        peer.received_messages().iter().any(|message| if let PeerMessage::GetBlockHeaders(_) = message { true } else { false })
+    }));
+    assert!(simulated_peer.wait_for_disconnection(WAIT_TIMEOUT));
+
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    assert!(block_storage.get(&chain.head().unwrap().0)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_test_chain_is_started_and_stopped() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_test_chain"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+    let test_chain = prepare_test_chain(&tmp_storage, &chain, vec![1, 2, 3, 4], vec![5; 32])?;
+    let next_test_chain = prepare_test_chain(&tmp_storage, &chain, vec![2, 3, 4, 5], vec![6; 32])?;
+
+    let simulated_peer = SimulatedPeer::listen(SimulatedPeerConfig::new(&tezos_env().version, chain.clone()), Some(log.clone()))?;
+    let node = TestNode::start("test_test_chain_is_started_and_stopped", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), true, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch(peer, chain.chain_id())));
+
+    // test chain manager asks the already connected peer for the test chain branch
+    node.fork_test_chain(test_chain.clone());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch(peer, &test_chain.chain_id)));
+
+    // messages for the test chain are routed to the test chain manager, which starts at the stored test chain genesis
+    simulated_peer.send(GetCurrentHeadMessage::new(test_chain.chain_id.clone()).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| {
+        peer.received_messages().iter().any(|message| match message {
+            PeerMessage::CurrentHead(message) => message.chain_id() == &test_chain.chain_id && message.current_block_header().predecessor() == &test_chain.genesis,
+            _ => false,
+        })
+    }));
+
+    // newly forked test chain replaces the running one, peer is told that we do not follow the previous test chain anymore
+    node.fork_test_chain(next_test_chain.clone());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| {
+        peer.received_messages().iter().any(|message| match message {
+            PeerMessage::Deactivate(message) => message.deactivate() == &test_chain.chain_id,
+            _ => false,
+        })
+    }));
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch(peer, &next_test_chain.chain_id)));
+    assert_eq!(0, simulated_peer.disconnections());
+
+    Ok(())
+}
+
+#[test]
+fn test_messages_for_unfollowed_chain_are_not_processed() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_unfollowed_chain"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+    let unfollowed_chain_id: ChainId = vec![9, 9, 9, 9];
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::Silent);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_messages_for_unfollowed_chain_are_not_processed", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch(peer, chain.chain_id())));
+
+    // peer is told that we do not follow the chain
+    simulated_peer.send(GetCurrentHeadMessage::new(unfollowed_chain_id.clone()).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_deactivate_count(peer, &unfollowed_chain_id) == 1));
+
+    // branch of the unfollowed chain is not downloaded and peer is not told again
+    let unfollowed_branch = CurrentBranchMessage::new(unfollowed_chain_id.clone(), CurrentBranch::new(chain.head().unwrap().1.clone(), vec![]));
+    simulated_peer.send(unfollowed_branch.into());
+    simulated_peer.send(DeactivateMessage::new(unfollowed_chain_id.clone()).into());
+    assert!(wait_for_chain_manager(&simulated_peer, &chain));
+    assert_eq!(1, received_deactivate_count(&simulated_peer, &unfollowed_chain_id));
+    assert!(!simulated_peer.received_messages().iter().any(|message| if let PeerMessage::GetBlockHeaders(_) = message { true } else { false }));
+    assert_eq!(0, simulated_peer.disconnections());
+
+    Ok(())
+}
+
+#[test]
+fn test_peer_which_deactivated_chain_is_not_asked_for_branch() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_deactivated_chain"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::Silent);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_peer_which_deactivated_chain_is_not_asked_for_branch", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch(peer, chain.chain_id())));
+
+    // peer which does not follow our chain is neither asked for its branch nor disconnected as stalled
+    simulated_peer.send(DeactivateMessage::new(chain.chain_id().clone()).into());
+    assert!(wait_for_chain_manager(&simulated_peer, &chain));
+    let asked_before_deactivation = received_get_current_branch_count(&simulated_peer, chain.chain_id());
+    assert!(!simulated_peer.wait_for(QUIET_PERIOD, |peer| received_get_current_branch_count(peer, chain.chain_id()) > asked_before_deactivation));
+
+    // any message for our chain activates the chain again
+    simulated_peer.send(GetCurrentHeadMessage::new(chain.chain_id().clone()).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_current_branch_count(peer, chain.chain_id()) > asked_before_deactivation));
+    assert_eq!(0, simulated_peer.disconnections());
+
+    Ok(())
+}
+
+#[test]
+fn test_mempool_is_not_advertised_to_peer_which_deactivated_chain() -> Result<(), failure::Error> {
+    let log = create_logger();
+    let tmp_storage = TmpStorage::create(common::prepare_empty_dir("__shell_simulated_peer_mempool_deactivated_chain"))?;
+    let chain = prepare_chain(&tmp_storage, &log)?;
+    let genesis = prepare_applied_genesis(&tmp_storage, &chain)?;
+
+    let config = SimulatedPeerConfig::new(&tezos_env().version, chain.clone()).behaviour(Behaviour::Silent);
+    let simulated_peer = SimulatedPeer::listen(config, Some(log.clone()))?;
+    let _node = TestNode::start("test_mempool_is_not_advertised_to_peer_which_deactivated_chain", &tmp_storage, &chain, simulated_peer.listener_address().unwrap(), false, &log)?;
+    assert!(simulated_peer.wait_for_connection(WAIT_TIMEOUT));
+
+    // operation received after the peer deactivated our chain is not advertised to the peer
+    let operation = Operation::from_bytes([genesis.hash.clone(), vec![1, 2, 3]].concat())?;
+    let operation_hash = operation.message_hash()?;
+    simulated_peer.send(CurrentHeadMessage::with_mempool(chain.chain_id().clone(), (*genesis.header).clone(), Mempool::new(vec![operation_hash.clone()], vec![])).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_operations(peer, &operation_hash)));
+    simulated_peer.send(DeactivateMessage::new(chain.chain_id().clone()).into());
+    simulated_peer.send(OperationMessage::new(operation).into());
+    assert!(wait_for_mempool_operation(&simulated_peer, &operation_hash));
+    assert!(!simulated_peer.wait_for(QUIET_PERIOD, |peer| received_advertised_operation(peer, &operation_hash)));
+
+    // peer's current head activates the chain again, so next operation is advertised
+    let next_operation = Operation::from_bytes([genesis.hash.clone(), vec![4, 5, 6]].concat())?;
+    let next_operation_hash = next_operation.message_hash()?;
+    simulated_peer.send(CurrentHeadMessage::with_mempool(chain.chain_id().clone(), (*genesis.header).clone(), Mempool::new(vec![next_operation_hash.clone()], vec![])).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_get_operations(peer, &next_operation_hash)));
+    simulated_peer.send(OperationMessage::new(next_operation).into());
+    assert!(simulated_peer.wait_for(WAIT_TIMEOUT, |peer| received_advertised_operation(peer, &next_operation_hash)));
+
+    Ok(())
+}
+
+/// Shell actors of the tested node, which is connected only to the simulated peer
+struct TestNode {
+    actor_system: ActorSystem,
+    shell_channel: ShellChannelRef,
+    _tokio_runtime: Runtime,
+}
+
+impl TestNode {
+    fn start(name: &str, tmp_storage: &TmpStorage, chain: &SyntheticChain, simulated_peer: SocketAddr, enable_testchain: bool, log: &Logger) -> Result<Self, failure::Error> {
+        let tokio_runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()?;
+        let identity = Identity::generate(0.0, 1, |_| ())?;
+        let local_peer_id = HashType::CryptoboxPublicKeyHash.string_to_bytes(&identity.peer_id)?;
+
+        let actor_system = SystemBuilder::new().name(name).log(log.clone()).create().expect("Failed to create actor system");
+        let network_channel = NetworkChannel::actor(&actor_system).expect("Failed to create network channel");
+        let shell_channel = ShellChannel::actor(&actor_system).expect("Failed to create shell channel");
+        let _ = ChainManager::actor(&actor_system, network_channel.clone(), shell_channel.clone(), tmp_storage.storage(), chain.chain_id(), &local_peer_id, enable_testchain, peer_timeouts())
+            .expect("Failed to create chain manager");
+        let _ = MempoolManager::actor(&actor_system, network_channel.clone(), shell_channel.clone(), tmp_storage.storage(), chain.chain_id())
+            .expect("Failed to create mempool manager");
+        let _ = PeerManager::actor(
+            &actor_system,
+            network_channel,
+            shell_channel.clone(),
+            tokio_runtime.handle().clone(),
+            &[],
+            &[simulated_peer],
+            Threshold::new(1, 1),
+            0,
+            &bind_listeners(0, &[IpAddr::V4(Ipv4Addr::UNSPECIFIED)], log)?,
+            identity,
+            tezos_env().version.clone(),
+            0.0,
+            false,
+            BandwidthLimits::default(),
+            tmp_storage.storage().clone(),
+            BanPolicy::default(),
+        ).expect("Failed to create peer manager");
+
+        Ok(TestNode { actor_system, shell_channel, _tokio_runtime: tokio_runtime })
+    }
+
+    /// Simulate the chain feeder, which applied block forking the test chain
+    fn fork_test_chain(&self, test_chain: TestChain) {
+        self.shell_channel.tell(
+            Publish {
+                msg: TestChainForked {
+                    forking_block: test_chain.genesis.clone(),
+                    test_chain,
+                }.into(),
+                topic: ShellChannelTopic::ShellEvents.into(),
+            }, None);
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = futures::executor::block_on(self.actor_system.shutdown());
+    }
+}
+
+/// Peers are checked often and silent peer is detected quickly, so the tests do not have to wait long
+fn peer_timeouts() -> PeerTimeouts {
+    PeerTimeouts {
+        check_chain_completeness_interval: Duration::from_secs(2),
+        ask_current_branch_interval: Duration::from_secs(2),
+        silent_peer_timeout: Duration::from_secs(3),
+        ..PeerTimeouts::default()
+    }
+}
+
+/// Store genesis block and create synthetic chain on top of it
+fn prepare_chain(tmp_storage: &TmpStorage, log: &Logger) -> Result<SyntheticChain, failure::Error> {
+    let mut block_storage = BlockStorage::new(tmp_storage.storage());
+    let init_data = resolve_storage_init_chain_data(tezos_env(), log.clone())?;
+    let genesis = initialize_storage_with_genesis_block(&mut block_storage, &init_data, tezos_env(), &vec![0; 32], log.clone())?;
+    Ok(SyntheticChain::new(init_data.chain_id, &genesis.hash, &genesis.header, CHAIN_LENGTH, VALIDATION_PASSES))
+}
+
+/// Store genesis of the test chain, like the chain feeder does, when the test chain is forked by the main chain genesis
+fn prepare_test_chain(tmp_storage: &TmpStorage, chain: &SyntheticChain, test_chain_id: ChainId, genesis: BlockHash) -> Result<TestChain, failure::Error> {
+    let mut block_storage = BlockStorage::new(tmp_storage.storage());
+    let mut block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+    let mut operations_meta_storage = OperationsMetaStorage::new(tmp_storage.storage());
+
+    let (_, first_block) = chain.blocks().first().expect("Synthetic chain is empty");
+    let forking_block: BlockHeaderWithHash = block_storage.get(first_block.predecessor())?.expect("Genesis is not stored");
+    let json_data = BlockJsonDataBuilder::default()
+        .block_header_proto_json("{}".to_string())
+        .block_header_proto_metadata_json("{}".to_string())
+        .operations_proto_metadata_json("[]".to_string())
+        .build().unwrap();
+    let additional_data = BlockAdditionalDataBuilder::default()
+        .max_operations_ttl(60)
+        .last_allowed_fork_level(0)
+        .build().unwrap();
+    store_test_chain_genesis(&mut block_storage, &mut block_meta_storage, &mut operations_meta_storage, &forking_block, &json_data, &additional_data, &genesis, &test_chain_id)?;
+
+    Ok(TestChain {
+        chain_id: test_chain_id,
+        genesis,
+        protocol: vec![7; 32],
+        expiration: unix_timestamp_now() + 3600,
+    })
+}
+
+/// Mark stored genesis as applied, like the chain feeder does after the genesis is committed
+fn prepare_applied_genesis(tmp_storage: &TmpStorage, chain: &SyntheticChain) -> Result<BlockHeaderWithHash, failure::Error> {
+    let block_storage = BlockStorage::new(tmp_storage.storage());
+    let mut block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+
+    let (_, first_block) = chain.blocks().first().expect("Synthetic chain is empty");
+    let genesis: BlockHeaderWithHash = block_storage.get(first_block.predecessor())?.expect("Genesis is not stored");
+    block_meta_storage.put(&genesis.hash, &Meta::genesis_meta(&genesis.hash, chain.chain_id(), true))?;
+    Ok(genesis)
+}
+
+fn received_get_current_branch(peer: &SimulatedPeer, chain_id: &ChainId) -> bool {
+    received_get_current_branch_count(peer, chain_id) > 0
+}
+
+fn received_get_current_branch_count(peer: &SimulatedPeer, chain_id: &ChainId) -> usize {
+    peer.received_messages().iter().filter(|message| match message {
+        PeerMessage::GetCurrentBranch(message) => &message.chain_id == chain_id,
+        _ => false,
+    }).count()
+}
+
+fn received_deactivate_count(peer: &SimulatedPeer, chain_id: &ChainId) -> usize {
+    peer.received_messages().iter().filter(|message| match message {
+        PeerMessage::Deactivate(message) => message.deactivate() == chain_id,
+        _ => false,
+    }).count()
+}
+
+fn received_get_operations(peer: &SimulatedPeer, operation_hash: &OperationHash) -> bool {
+    peer.received_messages().iter().any(|message| match message {
+        PeerMessage::GetOperations(message) => message.get_operations().contains(operation_hash),
+        _ => false,
+    })
+}
+
+fn received_advertised_operation(peer: &SimulatedPeer, operation_hash: &OperationHash) -> bool {
+    peer.received_messages().iter().any(|message| match message {
+        PeerMessage::CurrentHead(message) => message.current_mempool().pending().contains(operation_hash),
+        _ => false,
+    })
+}
+
+fn received_block_header_count(peer: &SimulatedPeer) -> usize {
+    peer.received_messages().iter().filter(|message| if let PeerMessage::BlockHeader(_) = message { true } else { false }).count()
+}
+
+fn received_operation(peer: &SimulatedPeer, operation_hash: &OperationHash) -> bool {
+    peer.received_messages().iter().any(|message| match message {
+        PeerMessage::Operation(message) => message.operation().message_hash().map(|hash| &hash == operation_hash).unwrap_or(false),
+        _ => false,
+    })
+}
+
+/// Wait until the chain manager processed all messages, which the peer sent so far.
+///
+/// Chain manager processes the messages of the peer in order, so the answer to our request means, that the preceding messages
+/// were processed. Request is sent twice, because the chain manager could send a message to itself while processing them.
+fn wait_for_chain_manager(peer: &SimulatedPeer, chain: &SyntheticChain) -> bool {
+    let (_, first_block) = chain.blocks().first().expect("Synthetic chain is empty");
+    (0..2).all(|_| {
+        let answered = received_block_header_count(peer);
+        peer.send(GetBlockHeadersMessage::new(vec![first_block.predecessor().clone()]).into());
+        peer.wait_for(WAIT_TIMEOUT, |peer| received_block_header_count(peer) > answered)
+    })
+}
+
+/// Wait until the mempool manager added the operation to the mempool, only operations from the mempool are provided to peers
+fn wait_for_mempool_operation(peer: &SimulatedPeer, operation_hash: &OperationHash) -> bool {
+    peer.send(GetOperationsMessage::new(vec![operation_hash.clone()]).into());
+    peer.wait_for(WAIT_TIMEOUT, |peer| received_operation(peer, operation_hash))
+}
+
+fn tezos_env() -> &'static TezosEnvironmentConfiguration {
+    TEZOS_ENV.get(&TEZOS_NETWORK).expect("no environment configuration")
+}
+
+fn wait_until<F: Fn() -> bool>(timeout: Duration, condition: F) -> bool {
+    let started = Instant::now();
+    while !condition() {
+        if started.elapsed() > timeout {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+fn create_logger() -> Logger {
+    let drain = slog_async::Async::new(slog_term::FullFormat::new(slog_term::TermDecorator::new().build()).build().fuse()).build().filter_level(Level::Info).fuse();
+
+    Logger::root(drain, slog::o!())
+}
@@ -1,6 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use getset::{CopyGetters, Getters, Setters};
@@ -8,7 +9,7 @@ use rocksdb::{ColumnFamilyDescriptor, MergeOperands, Options};
 
 use crypto::hash::{BlockHash, ChainId, HashType};
 
-use crate::{BlockHeaderWithHash, StorageError};
+use crate::{BlockHeaderWithHash, StorageError, SystemStorage};
 use crate::num_from_slice;
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage, SchemaError};
 use crate::persistent::database::{IteratorMode, IteratorWithSchema};
@@ -16,18 +17,33 @@ use crate::persistent::database::{IteratorMode, IteratorWithSchema};
 pub type BlockMetaStorageKV = dyn KeyValueStoreWithSchema<BlockMetaStorage> + Sync + Send;
 
 pub trait BlockMetaStorageReader: Sync + Send {
-    /// Load local head (block with highest level) from dedicated storage
+    /// Load local head from dedicated storage.
+    ///
+    /// Current head is the block selected by fork choice. If no head was stored yet
+    /// (e.g. database created by older version), block with the highest level is returned.
     fn load_current_head(&self) -> Result<Option<BlockHash>, StorageError>;
+
+    /// Find applied block with the highest level, which descends from the `genesis` (e.g. head of the test chain).
+    ///
+    /// Returns `None` if the `genesis` is not applied.
+    fn load_chain_head(&self, genesis: &BlockHash) -> Result<Option<BlockHash>, StorageError>;
+
+    /// Get block metadata, e.g. to find successors of the block
+    fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError>;
 }
 
 #[derive(Clone)]
 pub struct BlockMetaStorage {
-    kv: Arc<BlockMetaStorageKV>
+    kv: Arc<BlockMetaStorageKV>,
+    system: SystemStorage,
 }
 
 impl BlockMetaStorage {
     pub fn new(persistent_storage: &PersistentStorage) -> Self {
-        BlockMetaStorage { kv: persistent_storage.kv() }
+        BlockMetaStorage {
+            kv: persistent_storage.kv(),
+            system: SystemStorage::new(persistent_storage.kv()),
+        }
     }
 
     /// Create new metadata record in storage from given block header
@@ -42,7 +58,7 @@ impl BlockMetaStorage {
                 let meta = Meta {
                     is_applied: false,
                     predecessor: Some(block_header.header.predecessor().clone()),
-                    successor: None,
+                    successors: vec![],
                     level: block_header.header.level(),
                     chain_id: chain_id.clone(),
                 };
@@ -50,17 +66,18 @@ impl BlockMetaStorage {
             }
         }
 
-        // create/update record for block predecessor
+        // create/update record for block predecessor, block can have more successors (forks),
+        // so new successor is just appended by merge operator
         match self.get(&block_header.header.predecessor())?.as_mut() {
             Some(meta) => {
-                meta.successor = Some(block_header.hash.clone());
+                meta.successors = vec![block_header.hash.clone()];
                 self.put(block_header.header.predecessor(), &meta)?;
             },
             None => {
                 let meta = Meta {
                     is_applied: false,
                     predecessor: None,
-                    successor: Some(block_header.hash.clone()),
+                    successors: vec![block_header.hash.clone()],
                     level: block_header.header.level() - 1,
                     chain_id: chain_id.clone(),
                 };
@@ -77,6 +94,13 @@ impl BlockMetaStorage {
             .map_err(StorageError::from)
     }
 
+    /// Overwrite the metadata record, flags and successors are not merged with the stored record
+    #[inline]
+    pub fn replace(&mut self, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
+        self.kv.put(block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
         self.kv.get(block_hash)
@@ -88,10 +112,15 @@ impl BlockMetaStorage {
         self.kv.iterator(mode)
             .map_err(StorageError::from)
     }
-}
 
-impl BlockMetaStorageReader for BlockMetaStorage {
-    fn load_current_head(&self) -> Result<Option<BlockHash>, StorageError> {
+    /// Store block selected by fork choice as a new current head
+    #[inline]
+    pub fn store_current_head(&mut self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.system.set_current_head(block_hash)
+    }
+
+    /// Find block with the highest level from all applied blocks
+    fn find_highest_applied_block(&self) -> Result<Option<BlockHash>, StorageError> {
         self.iter(IteratorMode::End)
             .and_then(|meta_iterator|
                 Ok(
@@ -109,22 +138,65 @@ impl BlockMetaStorageReader for BlockMetaStorage {
     }
 }
 
+impl BlockMetaStorageReader for BlockMetaStorage {
+    #[inline]
+    fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
+        BlockMetaStorage::get(self, block_hash)
+    }
+
+    fn load_current_head(&self) -> Result<Option<BlockHash>, StorageError> {
+        match self.system.get_current_head()? {
+            Some(block_hash) => match self.get(&block_hash)? {
+                Some(meta) if meta.is_applied() => Ok(Some(block_hash)),
+                _ => self.find_highest_applied_block(),
+            },
+            None => self.find_highest_applied_block(),
+        }
+    }
+
+    fn load_chain_head(&self, genesis: &BlockHash) -> Result<Option<BlockHash>, StorageError> {
+        let mut head = match self.get(genesis)? {
+            Some(meta) if meta.is_applied() => (genesis.clone(), meta.level()),
+            _ => return Ok(None),
+        };
+
+        // walk all applied descendants of the genesis
+        let mut blocks_to_check = vec![genesis.clone()];
+        let mut checked_blocks = HashSet::new();
+        while let Some(block_hash) = blocks_to_check.pop() {
+            if !checked_blocks.insert(block_hash.clone()) {
+                continue;
+            }
+            if let Some(meta) = self.get(&block_hash)?.filter(|meta| meta.is_applied()) {
+                if meta.level() > head.1 {
+                    head = (block_hash, meta.level());
+                }
+                blocks_to_check.extend(meta.successors().iter().cloned());
+            }
+        }
+
+        Ok(Some(head.0))
+    }
+}
+
 const LEN_BLOCK_HASH: usize = HashType::BlockHash.size();
 const LEN_CHAIN_ID: usize = HashType::ChainId.size();
 
 const MASK_IS_APPLIED: u8 = 0b0000_0001;
-const MASK_HAS_SUCCESSOR: u8 = 0b0000_0010;
 const MASK_HAS_PREDECESSOR: u8 = 0b0000_0100;
+/// Marks the layout with the list of successors. Layout of the database version 11 with a single successor
+/// has the same length as the current layout with one successor, so the records are told apart by this flag.
+const MASK_SUCCESSORS_LIST: u8 = 0b1000_0000;
 
 const IDX_MASK: usize = 0;
 const IDX_PREDECESSOR: usize = IDX_MASK + 1;
-const IDX_SUCCESSOR: usize = IDX_PREDECESSOR + LEN_BLOCK_HASH;
-const IDX_LEVEL: usize = IDX_SUCCESSOR + LEN_BLOCK_HASH;
+const IDX_LEVEL: usize = IDX_PREDECESSOR + LEN_BLOCK_HASH;
 const IDX_CHAIN_ID: usize = IDX_LEVEL + std::mem::size_of::<i32>();
-const IDX_END: usize = IDX_CHAIN_ID + LEN_CHAIN_ID;
+const IDX_SUCCESSORS: usize = IDX_CHAIN_ID + LEN_CHAIN_ID;
 
 const BLANK_BLOCK_HASH: [u8; LEN_BLOCK_HASH] = [0; LEN_BLOCK_HASH];
-const LEN_META: usize = std::mem::size_of::<u8>() + LEN_BLOCK_HASH + LEN_BLOCK_HASH + std::mem::size_of::<i32>() + LEN_CHAIN_ID;
+/// Length of the fixed part of the encoded `Meta`, successors are appended after it
+const LEN_META: usize = std::mem::size_of::<u8>() + LEN_BLOCK_HASH + std::mem::size_of::<i32>() + LEN_CHAIN_ID;
 
 macro_rules! is_applied {
     ($mask:expr) => {{ ($mask & MASK_IS_APPLIED) != 0 }}
@@ -132,8 +204,8 @@ macro_rules! is_applied {
 macro_rules! has_predecessor {
     ($mask:expr) => {{ ($mask & MASK_HAS_PREDECESSOR) != 0 }}
 }
-macro_rules! has_successor {
-    ($mask:expr) => {{ ($mask & MASK_HAS_SUCCESSOR) != 0 }}
+macro_rules! has_successors_list {
+    ($mask:expr) => {{ ($mask & MASK_SUCCESSORS_LIST) != 0 }}
 }
 
 /// Meta information for the block
@@ -141,8 +213,9 @@ macro_rules! has_successor {
 pub struct Meta {
     #[get = "pub"]
     predecessor: Option<BlockHash>,
+    /// All known successors of the block, there can be more than one in case of fork
     #[get = "pub"]
-    successor: Option<BlockHash>,
+    successors: Vec<BlockHash>,
     #[get_copy = "pub"]
     #[set = "pub"]
     is_applied: bool,
@@ -153,12 +226,16 @@ pub struct Meta {
 }
 
 impl Meta {
+    pub fn new(is_applied: bool, predecessor: Option<BlockHash>, successors: Vec<BlockHash>, level: i32, chain_id: ChainId) -> Self {
+        Meta { is_applied, predecessor, successors, level, chain_id }
+    }
+
     /// Create Metadata for specific genesis block
     pub fn genesis_meta(genesis_hash: &BlockHash, genesis_chain_id: &ChainId, is_applied: bool) -> Self {
         Meta {
             is_applied,
             predecessor: Some(genesis_hash.clone()), // this is what we want
-            successor: None, // we do not know (yet) successor of the genesis
+            successors: vec![], // we do not know (yet) successor of the genesis
             level: 0,
             chain_id: genesis_chain_id.clone(),
         }
@@ -167,35 +244,32 @@ impl Meta {
 
 /// Codec for `Meta`
 ///
-/// * bytes layout: `[mask(1)][predecessor(32)][successor(32)][level(4)][chain_id(4)]`
+/// * bytes layout: `[mask(1)][predecessor(32)][level(4)][chain_id(4)][successors(n * 32)]`
+/// * records without the successors list flag in the mask are rejected
 impl Decoder for Meta {
     fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
-        if LEN_META == bytes.len() {
+        if bytes.len() >= LEN_META && (bytes.len() - LEN_META) % LEN_BLOCK_HASH == 0 && has_successors_list!(bytes[IDX_MASK]) {
             // mask
             let mask = bytes[IDX_MASK];
             let is_processed = is_applied!(mask);
             // predecessor
             let predecessor = if has_predecessor!(mask) {
-                let block_hash = bytes[IDX_PREDECESSOR..IDX_SUCCESSOR].to_vec();
+                let block_hash = bytes[IDX_PREDECESSOR..IDX_LEVEL].to_vec();
                 assert_eq!(LEN_BLOCK_HASH, block_hash.len(), "Predecessor expected length is {} but found {}", LEN_BLOCK_HASH, block_hash.len());
                 Some(block_hash)
             } else {
                 None
             };
-            // successor
-            let successor = if has_successor!(mask) {
-                let block_hash = bytes[IDX_SUCCESSOR..IDX_LEVEL].to_vec();
-                assert_eq!(LEN_BLOCK_HASH, block_hash.len(), "Successor expected length is {} but found {}", LEN_BLOCK_HASH, block_hash.len());
-                Some(block_hash)
-            } else {
-                None
-            };
             // level
             let level = num_from_slice!(bytes, IDX_LEVEL, i32);
             // chain_id
-            let chain_id = bytes[IDX_CHAIN_ID..IDX_END].to_vec();
+            let chain_id = bytes[IDX_CHAIN_ID..IDX_SUCCESSORS].to_vec();
             assert_eq!(LEN_CHAIN_ID, chain_id.len(), "Chain ID expected length is {} but found {}", LEN_CHAIN_ID, chain_id.len());
-            Ok(Meta { predecessor, successor, is_applied: is_processed, level, chain_id })
+            // successors
+            let successors = bytes[IDX_SUCCESSORS..].chunks_exact(LEN_BLOCK_HASH)
+                .map(|block_hash| block_hash.to_vec())
+                .collect();
+            Ok(Meta { predecessor, successors, is_applied: is_processed, level, chain_id })
         } else {
             Err(SchemaError::DecodeError)
         }
@@ -204,35 +278,62 @@ impl Decoder for Meta {
 
 impl Encoder for Meta {
     fn encode(&self) -> Result<Vec<u8>, SchemaError> {
-        let mut mask = 0u8;
+        let mut mask = MASK_SUCCESSORS_LIST;
         if self.is_applied {
             mask |= MASK_IS_APPLIED;
         }
         if self.predecessor.is_some() {
             mask |= MASK_HAS_PREDECESSOR;
         }
-        if self.successor.is_some() {
-            mask |= MASK_HAS_SUCCESSOR;
-        }
 
-        let mut value = Vec::with_capacity(LEN_META);
+        let mut value = Vec::with_capacity(LEN_META + self.successors.len() * LEN_BLOCK_HASH);
         value.push(mask);
         match &self.predecessor {
             Some(predecessor) => value.extend(predecessor),
             None => value.extend(&BLANK_BLOCK_HASH)
         }
-        match &self.successor {
-            Some(successor) => value.extend(successor),
-            None => value.extend(&BLANK_BLOCK_HASH)
-        }
         value.extend(&self.level.to_be_bytes());
         value.extend(&self.chain_id);
-        assert_eq!(LEN_META, value.len(), "Invalid size. predecessor={:?}, successor={:?}, level={:?}, data={:?}", &self.predecessor, &self.successor, self.level, &value);
+        assert_eq!(LEN_META, value.len(), "Invalid size. predecessor={:?}, level={:?}, data={:?}", &self.predecessor, self.level, &value);
+        for successor in &self.successors {
+            assert_eq!(LEN_BLOCK_HASH, successor.len(), "Successor expected length is {} but found {}", LEN_BLOCK_HASH, successor.len());
+            value.extend(successor);
+        }
 
         Ok(value)
     }
 }
 
+/// Length of the record of the database version 11: `[mask(1)][predecessor(32)][successor(32)][level(4)][chain_id(4)]`
+const LEN_META_V11: usize = LEN_META + LEN_BLOCK_HASH;
+/// Flag of the single successor of the database version 11
+const MASK_V11_HAS_SUCCESSOR: u8 = 0b0000_0010;
+
+/// Convert the record of the database version 11 to the current layout.
+///
+/// Returns `None` for the record, which is already in the current layout, so the conversion can be repeated.
+pub(crate) fn upgrade_v11_meta(bytes: &[u8]) -> Result<Option<Vec<u8>>, SchemaError> {
+    if bytes.is_empty() || has_successors_list!(bytes[IDX_MASK]) {
+        return Ok(None);
+    }
+    if bytes.len() != LEN_META_V11 {
+        return Err(SchemaError::DecodeError);
+    }
+
+    let mask = bytes[IDX_MASK];
+    let mut value = Vec::with_capacity(LEN_META_V11);
+    value.push((mask & (MASK_IS_APPLIED | MASK_HAS_PREDECESSOR)) | MASK_SUCCESSORS_LIST);
+    // predecessor
+    value.extend_from_slice(&bytes[IDX_PREDECESSOR..IDX_PREDECESSOR + LEN_BLOCK_HASH]);
+    // level and chain_id follow the successor
+    value.extend_from_slice(&bytes[IDX_PREDECESSOR + 2 * LEN_BLOCK_HASH..]);
+    // successor
+    if (mask & MASK_V11_HAS_SUCCESSOR) != 0 {
+        value.extend_from_slice(&bytes[IDX_PREDECESSOR + LEN_BLOCK_HASH..IDX_PREDECESSOR + 2 * LEN_BLOCK_HASH]);
+    }
+    Ok(Some(value))
+}
+
 impl KeyValueSchema for BlockMetaStorage {
     type Key = BlockHash;
     type Value = Meta;
@@ -255,7 +356,7 @@ fn merge_meta_value(_new_key: &[u8], existing_val: Option<&[u8]>, operands: &mut
     for op in operands {
         match result {
             Some(ref mut val) => {
-                assert_eq!(LEN_META, val.len(), "Value length is incorrect. Was expecting {} but instead found {}", LEN_META, val.len());
+                assert!(val.len() >= LEN_META, "Value length is incorrect. Was expecting at least {} but instead found {}", LEN_META, val.len());
 
                 let mask_val = val[IDX_MASK];
                 let mask_op = op[IDX_MASK];
@@ -265,13 +366,15 @@ fn merge_meta_value(_new_key: &[u8], existing_val: Option<&[u8]>, operands: &mut
 
                 // if op has predecessor and val has not, copy it from op to val
                 if has_predecessor!(mask_op) && !has_predecessor!(mask_val) {
-                    val.splice(IDX_PREDECESSOR..IDX_SUCCESSOR, op[IDX_PREDECESSOR..IDX_SUCCESSOR].iter().cloned());
+                    val.splice(IDX_PREDECESSOR..IDX_LEVEL, op[IDX_PREDECESSOR..IDX_LEVEL].iter().cloned());
                 }
-                // if op has successor and val has not, copy it from op to val
-                if has_successor!(mask_op) && !has_successor!(mask_val) {
-                    val.splice(IDX_SUCCESSOR..IDX_LEVEL, op[IDX_SUCCESSOR..IDX_LEVEL].iter().cloned());
+                // append all successors from op which are not yet present in val
+                for successor in op[IDX_SUCCESSORS..].chunks_exact(LEN_BLOCK_HASH) {
+                    if !val[IDX_SUCCESSORS..].chunks_exact(LEN_BLOCK_HASH).any(|s| s == successor) {
+                        val.extend_from_slice(successor);
+                    }
                 }
-                assert_eq!(LEN_META, val.len(), "Invalid length after merge operator was applied. Was expecting {} but found {}.", LEN_META, val.len());
+                assert_eq!(0, (val.len() - LEN_META) % LEN_BLOCK_HASH, "Invalid length after merge operator was applied. Found {}.", val.len());
             },
             None => result = Some(op.to_vec())
         }
@@ -298,7 +401,7 @@ mod tests {
         let expected = Meta {
             is_applied: false,
             predecessor: Some(vec![98; 32]),
-            successor: Some(vec![21; 32]),
+            successors: vec![vec![21; 32]],
             level: 34,
             chain_id: vec![44; 4],
         };
@@ -307,6 +410,17 @@ mod tests {
         Ok(assert_eq!(expected, decoded))
     }
 
+    #[test]
+    fn block_meta_previous_layout_is_rejected() {
+        // database version 11: [mask][predecessor][successor][level][chain_id]
+        let mut bytes = vec![0b0000_0111];
+        bytes.extend_from_slice(&[98; 32]);
+        bytes.extend_from_slice(&[21; 32]);
+        bytes.extend_from_slice(&34i32.to_be_bytes());
+        bytes.extend_from_slice(&[44; 4]);
+        assert!(Meta::decode(&bytes).is_err());
+    }
+
     #[test]
     fn genesis_block_initialized_success() -> Result<(), Error> {
         let tmp_storage = TmpStorage::create("__blockmeta_genesistest")?;
@@ -321,7 +435,7 @@ mod tests {
                 let expected = Meta {
                     is_applied: true,
                     predecessor: Some(k.clone()),
-                    successor: None,
+                    successors: vec![],
                     level: 0,
                     chain_id: chain_id.clone(),
                 };
@@ -341,7 +455,7 @@ mod tests {
         let mut v = Meta {
             is_applied: false,
             predecessor: None,
-            successor: None,
+            successors: vec![],
             level: 1_245_762,
             chain_id: vec![44; 4],
         };
@@ -350,11 +464,11 @@ mod tests {
         let p = storage.get(&k)?;
         assert!(p.is_some());
         v.is_applied = true;
-        v.successor = Some(vec![21; 32]);
+        v.successors = vec![vec![21; 32]];
         storage.put(&k, &v)?;
         v.is_applied = false;
         v.predecessor = Some(vec![98; 32]);
-        v.successor = None;
+        v.successors = vec![];
         storage.put(&k, &v)?;
         v.predecessor = None;
         storage.put(&k, &v)?;
@@ -363,7 +477,7 @@ mod tests {
                 let expected = Meta {
                     is_applied: true,
                     predecessor: Some(vec![98; 32]),
-                    successor: Some(vec![21; 32]),
+                    successors: vec![vec![21; 32]],
                     level: 1_245_762,
                     chain_id: vec![44; 4],
                 };
@@ -375,6 +489,87 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn block_meta_storage_multiple_successors_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__blockmeta_successorstest")?;
+
+        let k = vec![44; 32];
+        let mut v = Meta {
+            is_applied: true,
+            predecessor: Some(vec![98; 32]),
+            successors: vec![vec![21; 32]],
+            level: 10,
+            chain_id: vec![44; 4],
+        };
+        let mut storage = BlockMetaStorage::new(tmp_storage.storage());
+        storage.put(&k, &v)?;
+        v.successors = vec![vec![22; 32]];
+        storage.put(&k, &v)?;
+        v.successors = vec![vec![21; 32], vec![23; 32]];
+        storage.put(&k, &v)?;
+        match storage.get(&k)? {
+            Some(value) => {
+                assert_eq!(vec![vec![21; 32], vec![22; 32], vec![23; 32]], value.successors);
+                assert_eq!(Some(vec![98; 32]), value.predecessor);
+                assert!(value.is_applied);
+            },
+            _ => panic!("value not present"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_current_head_prefers_stored_head_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__blockmeta_currentheadtest")?;
+        let mut storage = BlockMetaStorage::new(tmp_storage.storage());
+
+        let meta = |level| Meta {
+            is_applied: true,
+            predecessor: Some(vec![1; 32]),
+            successors: vec![],
+            level,
+            chain_id: vec![44; 4],
+        };
+        storage.put(&vec![2; 32], &meta(2))?;
+        storage.put(&vec![3; 32], &meta(3))?;
+
+        // without stored head, the highest block is used
+        assert_eq!(Some(vec![3; 32]), storage.load_current_head()?);
+
+        // stored head wins even when it has lower level
+        storage.store_current_head(&vec![2; 32])?;
+        assert_eq!(Some(vec![2; 32]), storage.load_current_head()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_chain_head_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__blockmeta_chainheadtest")?;
+        let mut storage = BlockMetaStorage::new(tmp_storage.storage());
+
+        let meta = |is_applied, predecessor, successors, level| Meta {
+            is_applied,
+            predecessor: Some(predecessor),
+            successors,
+            level,
+            chain_id: vec![44; 4],
+        };
+        // genesis is its own predecessor, block 3 is not applied yet
+        storage.put(&vec![1; 32], &meta(true, vec![1; 32], vec![vec![2; 32]], 1))?;
+        storage.put(&vec![2; 32], &meta(true, vec![1; 32], vec![vec![3; 32]], 2))?;
+        storage.put(&vec![3; 32], &meta(false, vec![2; 32], vec![], 3))?;
+        // applied block, which does not descend from the genesis
+        storage.put(&vec![9; 32], &meta(true, vec![8; 32], vec![], 9))?;
+
+        assert_eq!(Some(vec![2; 32]), storage.load_chain_head(&vec![1; 32])?);
+        assert_eq!(None, storage.load_chain_head(&vec![3; 32])?);
+        assert_eq!(None, storage.load_chain_head(&vec![7; 32])?);
+
+        Ok(())
+    }
+
     #[test]
     fn merge_meta_value_test() -> Result<(), Error> {
         use rocksdb::{Options, DB};
@@ -390,18 +585,18 @@ mod tests {
             let mut v = Meta {
                 is_applied: false,
                 predecessor: None,
-                successor: None,
+                successors: vec![],
                 level: 2,
                 chain_id: vec![44; 4],
             };
             let p = BlockMetaStorageKV::merge(&db, &k, &v);
             assert!(p.is_ok(), "p: {:?}", p.unwrap_err());
             v.is_applied = true;
-            v.successor = Some(vec![21; 32]);
+            v.successors = vec![vec![21; 32]];
             let _ = BlockMetaStorageKV::merge(&db, &k, &v);
             v.is_applied = false;
             v.predecessor = Some(vec![98; 32]);
-            v.successor = None;
+            v.successors = vec![];
             let _ = BlockMetaStorageKV::merge(&db, &k, &v);
             v.predecessor = None;
             let m = BlockMetaStorageKV::merge(&db, &k, &v);
@@ -411,7 +606,7 @@ mod tests {
                     let expected = Meta {
                         is_applied: true,
                         predecessor: Some(vec![98; 32]),
-                        successor: Some(vec![21; 32]),
+                        successors: vec![vec![21; 32]],
                         level: 2,
                         chain_id: vec![44; 4],
                     };
@@ -11,6 +11,7 @@ use crypto::hash::{BlockHash, ContextHash};
 
 use crate::{BlockHeaderWithHash, Direction, IteratorMode, StorageError};
 use crate::persistent::{BincodeEncoded, CommitLogSchema, CommitLogWithSchema, KeyValueSchema, KeyValueStoreWithSchema, Location, PersistentStorage};
+use crate::persistent::database::IteratorWithSchema;
 
 /// Store block header data in a key-value store and into commit log.
 /// The value is first inserted into commit log, which returns a location of the newly inserted value.
@@ -122,7 +123,7 @@ impl BlockStorage {
     }
 
     #[inline]
-    fn get_block_header_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<BlockHeaderWithHash, StorageError> {
+    pub(crate) fn get_block_header_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<BlockHeaderWithHash, StorageError> {
         match self.clog.get(&location.block_header).map_err(StorageError::from)? {
             BlockStorageColumn::BlockHeader(block_header) => Ok(block_header),
             _ => Err(StorageError::InvalidColumn)
@@ -130,7 +131,7 @@ impl BlockStorage {
     }
 
     #[inline]
-    fn get_block_json_data_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<Option<BlockJsonData>, StorageError> {
+    pub(crate) fn get_block_json_data_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<Option<BlockJsonData>, StorageError> {
         match &location.block_json_data {
             Some(block_json_data_location) => match self.clog.get(block_json_data_location).map_err(StorageError::from)? {
                 BlockStorageColumn::BlockJsonData(json_data) => Ok(Some(json_data)),
@@ -141,7 +142,7 @@ impl BlockStorage {
     }
 
     #[inline]
-    fn get_block_additional_data_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<Option<BlockAdditionalData>, StorageError> {
+    pub(crate) fn get_block_additional_data_by_location(&self, location: &BlockStorageColumnsLocation) -> Result<Option<BlockAdditionalData>, StorageError> {
         match &location.block_additional_data {
             Some(block_additional_data_location) => match self.clog.get(block_additional_data_location).map_err(StorageError::from)? {
                 BlockStorageColumn::BlockAdditionalData(data) => Ok(Some(data)),
@@ -151,6 +152,49 @@ impl BlockStorage {
         }
     }
 
+    /// Iterate locations of all stored blocks
+    #[inline]
+    pub(crate) fn iter_locations(&self) -> Result<IteratorWithSchema<BlockPrimaryIndex>, StorageError> {
+        self.primary_index.kv.iterator(IteratorMode::Start)
+            .map_err(StorageError::from)
+    }
+
+    /// Iterate locations stored in the by-level index
+    #[inline]
+    pub(crate) fn iter_level_locations(&self) -> Result<IteratorWithSchema<BlockByLevelIndex>, StorageError> {
+        self.by_level_index.kv.iterator(IteratorMode::Start)
+            .map_err(StorageError::from)
+    }
+
+    /// Iterate locations stored in the by-context-hash index
+    #[inline]
+    pub(crate) fn iter_context_hash_locations(&self) -> Result<IteratorWithSchema<BlockByContextHashIndex>, StorageError> {
+        self.by_context_hash_index.kv.iterator(IteratorMode::Start)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub(crate) fn get_level_location(&self, level: BlockLevel) -> Result<Option<BlockStorageColumnsLocation>, StorageError> {
+        self.by_level_index.get(&level)
+    }
+
+    #[inline]
+    pub(crate) fn put_level_location(&mut self, level: BlockLevel, location: &BlockStorageColumnsLocation) -> Result<(), StorageError> {
+        self.by_level_index.put(level, location)
+    }
+
+    #[inline]
+    pub(crate) fn delete_level_location(&mut self, level: BlockLevel) -> Result<(), StorageError> {
+        self.by_level_index.kv.delete(&level)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub(crate) fn delete_context_hash_location(&mut self, context_hash: &ContextHash) -> Result<(), StorageError> {
+        self.by_context_hash_index.kv.delete(context_hash)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     fn get_blocks_with_json_data_by_location<I>(&self, locations: I) -> Result<Vec<(BlockHeaderWithHash, BlockJsonData)>, StorageError>
         where
@@ -124,6 +124,11 @@ impl ContextDiff {
         }
     }
 
+    /// Level of the checkouted context
+    pub fn predecessor_level(&self) -> Option<usize> {
+        self.predecessor_index.level
+    }
+
     pub fn set(&mut self, context_hash: &Option<ContextHash>, key: &Vec<String>, value: &Vec<u8>) -> Result<(), ContextError> {
         ensure_eq_context_hash!(context_hash, &self);
 
@@ -14,7 +14,7 @@ use crypto::hash::{BlockHash, HashType};
 use tezos_context::channel::ContextAction;
 use tezos_messages::base::signature_public_key_hash::{ConversionError, SignaturePublicKeyHash};
 
-use crate::num_from_slice;
+use crate::{IteratorMode, num_from_slice};
 use crate::persistent::{CommitLogSchema, CommitLogWithSchema, Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, Location, PersistentStorage, SchemaError};
 use crate::persistent::codec::{range_from_idx_len, vec_from_slice};
 use crate::persistent::commit_log::fold_consecutive_locations;
@@ -69,6 +69,40 @@ impl ContextActionStorage {
             .and_then(|locations| self.get_records_by_locations(&locations))
     }
 
+    /// Remove all actions of the block from the indexes.
+    ///
+    /// Records are kept in the commit log, because it is append only, but they are no longer reachable.
+    pub fn delete_by_block_hash(&mut self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        for (key, location) in self.context_primary_index.get_entries_by_block_hash(block_hash)? {
+            let value = self.get_record_by_location(&location)?;
+            for contract_address in extract_contract_addresses(&value) {
+                self.context_by_contract_index.delete(&ContextActionByContractIndexKey::new(&contract_address, value.id()))?;
+            }
+            self.context_primary_index.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Find actions with the location, which can not be read from the commit log
+    pub(crate) fn find_invalid_locations(&self) -> Result<Vec<(BlockHash, SequenceNumber)>, StorageError> {
+        let mut invalid = vec![];
+        for (key, location) in self.context_primary_index.kv.iterator(IteratorMode::Start)? {
+            let key = key?;
+            let is_valid = location.map_err(StorageError::from)
+                .and_then(|location| self.get_record_by_location(&location))
+                .is_ok();
+            if !is_valid {
+                invalid.push((key.block_hash, key.id));
+            }
+        }
+        Ok(invalid)
+    }
+
+    /// Remove action from the primary index
+    pub(crate) fn delete_from_primary_index(&mut self, block_hash: &BlockHash, id: SequenceNumber) -> Result<(), StorageError> {
+        self.context_primary_index.delete(&ContextActionPrimaryIndexKey::new(block_hash, id))
+    }
+
     /// Retrieve record value from commit log or return error if value is not present.
     #[inline]
     fn get_record_by_location(&self, location: &Location) -> Result<ContextActionRecordValue, StorageError> {
@@ -207,6 +241,20 @@ impl ContextActionPrimaryIndex {
             .map(|(_, value)| value.map_err(StorageError::from))
             .collect()
     }
+
+    #[inline]
+    fn get_entries_by_block_hash(&self, block_hash: &BlockHash) -> Result<Vec<(ContextActionPrimaryIndexKey, Location)>, StorageError> {
+        let key = ContextActionPrimaryIndexKey::from_block_hash_prefix(block_hash);
+        self.kv.prefix_iterator(&key)?
+            .map(|(key, value)| Ok((key?, value?)))
+            .collect()
+    }
+
+    #[inline]
+    fn delete(&mut self, key: &ContextActionPrimaryIndexKey) -> Result<(), StorageError> {
+        self.kv.delete(key)
+            .map_err(StorageError::from)
+    }
 }
 
 impl KeyValueSchema for ContextActionPrimaryIndex {
@@ -310,6 +358,11 @@ impl ContextActionByContractIndex {
         self.kv.put(key, value).map_err(StorageError::from)
     }
 
+    #[inline]
+    fn delete(&mut self, key: &ContextActionByContractIndexKey) -> Result<(), StorageError> {
+        self.kv.delete(key).map_err(StorageError::from)
+    }
+
     #[inline]
     fn get_by_contract_address(&self, contract_address: &ContractAddress, from_id: Option<SequenceNumber>, limit: usize) -> Result<Vec<Location>, StorageError> {
         let iterate_from_key = from_id
@@ -0,0 +1,599 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Offline verification of the storage consistency.
+//!
+//! Checker walks all stored blocks and verifies, that:
+//! * block header can be read from the commit log and its hash matches the content,
+//! * block metadata agree with the header and predecessor/successor links are consistent,
+//! * applied blocks have the context assigned,
+//! * operations metadata agree with the stored operations,
+//! * all locations stored in the indexes point to valid commit log records.
+//!
+//! Problems, which can be recomputed from the other stored data, are optionally repaired.
+//! Data, which can not be recomputed (e.g. unreadable block header), are only reported.
+//!
+//! Operations and context of the blocks below the [pruned level](SystemStorage::get_pruned_level) were removed
+//! by the history pruning, so they are not verified.
+
+use std::fmt;
+
+use slog::{info, Logger, warn};
+
+use crypto::hash::{BlockHash, ChainId, HashType};
+use tezos_messages::p2p::binary_message::MessageHash;
+
+use crate::{BlockHeaderWithHash, BlockMetaStorage, BlockStorage, BlockStorageReader, ContextActionStorage, IteratorMode, OperationsMetaStorage, OperationsStorage, OperationsStorageReader, StorageError, SystemStorage};
+use crate::block_meta_storage::Meta;
+use crate::block_storage::{BlockLevel, BlockStorageColumnsLocation};
+use crate::operations_meta_storage;
+use crate::persistent::PersistentStorage;
+use crate::persistent::sequence::SequenceNumber;
+
+/// Inconsistency found in the storage
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityProblem {
+    /// Block header can not be read from the commit log
+    InvalidBlockLocation { block_hash: String },
+    /// Hash of the stored block header does not match the key
+    BlockHashMismatch { block_hash: String, computed_hash: String },
+    /// Json or additional data of the block can not be read from the commit log
+    InvalidBlockDataLocation { block_hash: String },
+    /// By-level index is missing or points to an invalid block
+    InvalidLevelIndex { level: BlockLevel },
+    /// By-context-hash index points to an invalid block
+    InvalidContextHashIndex { context_hash: String },
+    MissingBlockMeta { block_hash: String },
+    /// Predecessor or level in the metadata do not match the header
+    InvalidBlockMeta { block_hash: String },
+    /// Predecessor metadata do not contain the block as a successor
+    MissingSuccessor { block_hash: String, successor: String },
+    /// Metadata contain successor, which is not stored or has a different predecessor
+    InvalidSuccessor { block_hash: String, successor: String },
+    AppliedWithoutContext { block_hash: String },
+    /// Block has context and apply results stored, but it is not marked as applied
+    NotAppliedWithContext { block_hash: String },
+    /// Stored operations can not be read or do not belong to the block
+    InvalidOperations { block_hash: String },
+    MissingOperationsMeta { block_hash: String },
+    /// Validation passes in the metadata do not match the stored operations
+    InvalidOperationsMeta { block_hash: String },
+    /// Operations are stored for unknown block
+    OrphanOperations { block_hash: String },
+    /// Context action can not be read from the commit log
+    InvalidContextActionLocation { block_hash: String, id: SequenceNumber },
+}
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityProblem::InvalidBlockLocation { block_hash } => write!(f, "Block {} can not be read from the commit log", block_hash),
+            IntegrityProblem::BlockHashMismatch { block_hash, computed_hash } => write!(f, "Block {} has content with hash {}", block_hash, computed_hash),
+            IntegrityProblem::InvalidBlockDataLocation { block_hash } => write!(f, "Json or additional data of the block {} can not be read from the commit log", block_hash),
+            IntegrityProblem::InvalidLevelIndex { level } => write!(f, "By-level index for level {} is missing or invalid", level),
+            IntegrityProblem::InvalidContextHashIndex { context_hash } => write!(f, "By-context-hash index for context {} is invalid", context_hash),
+            IntegrityProblem::MissingBlockMeta { block_hash } => write!(f, "Metadata of the block {} are missing", block_hash),
+            IntegrityProblem::InvalidBlockMeta { block_hash } => write!(f, "Metadata of the block {} do not match its header", block_hash),
+            IntegrityProblem::MissingSuccessor { block_hash, successor } => write!(f, "Block {} does not have successor {}", block_hash, successor),
+            IntegrityProblem::InvalidSuccessor { block_hash, successor } => write!(f, "Block {} has invalid successor {}", block_hash, successor),
+            IntegrityProblem::AppliedWithoutContext { block_hash } => write!(f, "Block {} is applied, but its context is missing", block_hash),
+            IntegrityProblem::NotAppliedWithContext { block_hash } => write!(f, "Block {} has context, but it is not marked as applied", block_hash),
+            IntegrityProblem::InvalidOperations { block_hash } => write!(f, "Operations of the block {} are invalid", block_hash),
+            IntegrityProblem::MissingOperationsMeta { block_hash } => write!(f, "Operations metadata of the block {} are missing", block_hash),
+            IntegrityProblem::InvalidOperationsMeta { block_hash } => write!(f, "Operations metadata of the block {} do not match stored operations", block_hash),
+            IntegrityProblem::OrphanOperations { block_hash } => write!(f, "Operations are stored for unknown block {}", block_hash),
+            IntegrityProblem::InvalidContextActionLocation { block_hash, id } => write!(f, "Context action {} of the block {} can not be read from the commit log", id, block_hash),
+        }
+    }
+}
+
+impl slog::Value for IntegrityProblem {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Result of the storage verification
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    /// Count of verified blocks
+    pub checked_blocks: usize,
+    /// All found problems
+    pub problems: Vec<IntegrityProblem>,
+    /// Count of repaired problems
+    pub repaired: usize,
+}
+
+impl IntegrityReport {
+    pub fn is_consistent(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Verifies consistency of the storage and optionally repairs the problems, which can be recomputed.
+///
+/// Checker is expected to run, while the node is not running, because concurrent writes would be reported as problems.
+pub struct IntegrityChecker {
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    operations_storage: OperationsStorage,
+    operations_meta_storage: OperationsMetaStorage,
+    context_action_storage: ContextActionStorage,
+    system_storage: SystemStorage,
+    repair: bool,
+    log: Logger,
+}
+
+impl IntegrityChecker {
+    pub fn new(persistent_storage: &PersistentStorage, repair: bool, log: Logger) -> Self {
+        IntegrityChecker {
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
+            context_action_storage: ContextActionStorage::new(persistent_storage),
+            system_storage: SystemStorage::new(persistent_storage.kv()),
+            repair,
+            log,
+        }
+    }
+
+    /// Verify the whole storage
+    pub fn check(&mut self) -> Result<IntegrityReport, StorageError> {
+        let mut report = IntegrityReport::default();
+        let chain_id = match self.system_storage.get_chain_id()? {
+            Some(chain_id) => chain_id,
+            None => {
+                info!(self.log, "Storage is not initialized, nothing to verify");
+                return Ok(report);
+            }
+        };
+
+        let pruned_level = self.system_storage.get_pruned_level()?.unwrap_or(0);
+
+        let blocks = self.block_storage.iter_locations()?
+            .filter_map(|(block_hash, location)| match (block_hash, location) {
+                (Ok(block_hash), Ok(location)) => Some(Ok((block_hash, location))),
+                (Ok(block_hash), Err(_)) => Some(Err(block_hash)),
+                // key can not be decoded, so there is nothing to report
+                (Err(_), _) => None,
+            })
+            .collect::<Vec<_>>();
+        for block in blocks {
+            match block {
+                Ok((block_hash, location)) => self.check_block(&block_hash, &location, &chain_id, pruned_level, &mut report)?,
+                Err(block_hash) => self.report(&mut report, IntegrityProblem::InvalidBlockLocation { block_hash: block_hash_to_string(&block_hash) }, false),
+            }
+            report.checked_blocks += 1;
+            if report.checked_blocks % 100_000 == 0 {
+                info!(self.log, "Storage verification progress"; "checked_blocks" => report.checked_blocks, "problems" => report.problems.len());
+            }
+        }
+
+        self.check_level_index(&mut report)?;
+        self.check_context_hash_index(&mut report)?;
+        self.check_orphan_operations(&mut report)?;
+        self.check_context_actions(&mut report)?;
+
+        Ok(report)
+    }
+
+    fn check_block(&mut self, block_hash: &BlockHash, location: &BlockStorageColumnsLocation, chain_id: &ChainId, pruned_level: BlockLevel, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let block = match self.block_storage.get_block_header_by_location(location) {
+            Ok(block) => block,
+            Err(_) => {
+                self.report(report, IntegrityProblem::InvalidBlockLocation { block_hash: block_hash_to_string(block_hash) }, false);
+                return Ok(());
+            }
+        };
+        // hash of the genesis is given by the environment configuration, genesis is its own predecessor
+        let is_genesis = block.header.predecessor() == block_hash;
+        let computed_hash = block.header.message_hash().unwrap_or_default();
+        if (!is_genesis && &computed_hash != block_hash) || &block.hash != block_hash {
+            self.report(report, IntegrityProblem::BlockHashMismatch { block_hash: block_hash_to_string(block_hash), computed_hash: block_hash_to_string(&computed_hash) }, false);
+            return Ok(());
+        }
+
+        let json_data = self.block_storage.get_block_json_data_by_location(location);
+        let additional_data = self.block_storage.get_block_additional_data_by_location(location);
+        let has_apply_result = match (&json_data, &additional_data) {
+            (Ok(json_data), Ok(additional_data)) => json_data.is_some() && additional_data.is_some(),
+            _ => {
+                self.report(report, IntegrityProblem::InvalidBlockDataLocation { block_hash: block_hash_to_string(block_hash) }, false);
+                false
+            }
+        };
+
+        // operations and context of the pruned block could be removed
+        let is_pruned = block.header.level() < pruned_level;
+
+        self.check_level_location(&block, location, report)?;
+        self.check_block_meta(&block, has_apply_result, is_pruned, chain_id, report)?;
+        if is_pruned {
+            Ok(())
+        } else {
+            self.check_operations(&block, chain_id, report)
+        }
+    }
+
+    fn check_level_location(&mut self, block: &BlockHeaderWithHash, location: &BlockStorageColumnsLocation, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let level = block.header.level();
+        let is_valid = match self.block_storage.get_level_location(level)? {
+            Some(level_location) => self.block_storage.get_block_header_by_location(&level_location)
+                .map(|level_block| level_block.header.level() == level)
+                .unwrap_or(false),
+            None => false,
+        };
+        if !is_valid {
+            let repaired = self.repair && self.block_storage.put_level_location(level, location).is_ok();
+            self.report(report, IntegrityProblem::InvalidLevelIndex { level }, repaired);
+        }
+        Ok(())
+    }
+
+    fn check_block_meta(&mut self, block: &BlockHeaderWithHash, has_apply_result: bool, is_pruned: bool, chain_id: &ChainId, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let block_hash = &block.hash;
+        let predecessor = block.header.predecessor();
+
+        let meta = match self.block_meta_storage.get(block_hash)? {
+            Some(meta) => meta,
+            None => {
+                let repaired = self.repair && self.block_meta_storage.put_block_header(block, chain_id).is_ok();
+                self.report(report, IntegrityProblem::MissingBlockMeta { block_hash: block_hash_to_string(block_hash) }, repaired);
+                return Ok(());
+            }
+        };
+
+        let mut meta = if meta.predecessor().as_ref() != Some(predecessor) || meta.level() != block.header.level() {
+            let fixed_meta = Meta::new(meta.is_applied(), Some(predecessor.clone()), meta.successors().clone(), block.header.level(), meta.chain_id().clone());
+            let repaired = self.repair && self.block_meta_storage.replace(block_hash, &fixed_meta).is_ok();
+            self.report(report, IntegrityProblem::InvalidBlockMeta { block_hash: block_hash_to_string(block_hash) }, repaired);
+            fixed_meta
+        } else {
+            meta
+        };
+
+        // genesis is its own predecessor, predecessor of the oldest stored block is not known
+        if predecessor != block_hash {
+            if let Some(predecessor_meta) = self.block_meta_storage.get(predecessor)? {
+                if !predecessor_meta.successors().contains(block_hash) {
+                    let repaired = self.repair && self.block_meta_storage.put_block_header(block, meta.chain_id()).is_ok();
+                    self.report(report, IntegrityProblem::MissingSuccessor { block_hash: block_hash_to_string(predecessor), successor: block_hash_to_string(block_hash) }, repaired);
+                }
+            }
+        }
+
+        let mut invalid_successors = vec![];
+        for successor in meta.successors() {
+            let is_valid = match self.block_storage.get(successor) {
+                Ok(Some(successor_block)) => successor_block.header.predecessor() == block_hash,
+                _ => false,
+            };
+            if !is_valid {
+                invalid_successors.push(successor.clone());
+            }
+        }
+        if !invalid_successors.is_empty() {
+            let successors = meta.successors().iter().filter(|successor| !invalid_successors.contains(successor)).cloned().collect();
+            meta = Meta::new(meta.is_applied(), meta.predecessor().clone(), successors, meta.level(), meta.chain_id().clone());
+            let repaired = self.repair && self.block_meta_storage.replace(block_hash, &meta).is_ok();
+            for successor in invalid_successors {
+                self.report(report, IntegrityProblem::InvalidSuccessor { block_hash: block_hash_to_string(block_hash), successor: block_hash_to_string(&successor) }, repaired);
+            }
+        }
+
+        if is_pruned {
+            return Ok(());
+        }
+        let has_context = match self.block_storage.get_by_context_hash(block.header.context()) {
+            Ok(context_block) => context_block.is_some(),
+            Err(_) => false,
+        };
+        if meta.is_applied() && !has_context {
+            meta.set_is_applied(false);
+            let repaired = self.repair && self.block_meta_storage.replace(block_hash, &meta).is_ok();
+            self.report(report, IntegrityProblem::AppliedWithoutContext { block_hash: block_hash_to_string(block_hash) }, repaired);
+        } else if !meta.is_applied() && has_context && has_apply_result {
+            meta.set_is_applied(true);
+            let repaired = self.repair && self.block_meta_storage.replace(block_hash, &meta).is_ok();
+            self.report(report, IntegrityProblem::NotAppliedWithContext { block_hash: block_hash_to_string(block_hash) }, repaired);
+        }
+
+        Ok(())
+    }
+
+    fn check_operations(&mut self, block: &BlockHeaderWithHash, chain_id: &ChainId, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let block_hash = &block.hash;
+        let operations = match self.operations_storage.get_operations(block_hash) {
+            Ok(operations) => operations,
+            Err(_) => {
+                self.report(report, IntegrityProblem::InvalidOperations { block_hash: block_hash_to_string(block_hash) }, false);
+                return Ok(());
+            }
+        };
+        let is_valid = operations.iter()
+            .all(|operations| operations.operations_for_block().hash() == block_hash && (operations.operations_for_block().validation_pass() as u8) < block.header.validation_pass());
+        if !is_valid {
+            self.report(report, IntegrityProblem::InvalidOperations { block_hash: block_hash_to_string(block_hash) }, false);
+        }
+
+        match self.operations_meta_storage.get(block_hash)? {
+            Some(meta) => {
+                let expected_meta = operations_meta_storage::Meta::with_operations(block, meta.chain_id(), &operations);
+                if meta != expected_meta {
+                    let repaired = self.repair && self.operations_meta_storage.replace(block_hash, &expected_meta).is_ok();
+                    self.report(report, IntegrityProblem::InvalidOperationsMeta { block_hash: block_hash_to_string(block_hash) }, repaired);
+                }
+            }
+            None => {
+                let expected_meta = operations_meta_storage::Meta::with_operations(block, chain_id, &operations);
+                let repaired = self.repair && self.operations_meta_storage.replace(block_hash, &expected_meta).is_ok();
+                self.report(report, IntegrityProblem::MissingOperationsMeta { block_hash: block_hash_to_string(block_hash) }, repaired);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Index entries are checked after all blocks, so entries of the existing blocks are already repaired
+    fn check_level_index(&mut self, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let mut invalid_levels = vec![];
+        for (level, location) in self.block_storage.iter_level_locations()? {
+            let level = match level {
+                Ok(level) => level,
+                Err(_) => continue,
+            };
+            let is_valid = location.map_err(StorageError::from)
+                .and_then(|location| self.block_storage.get_block_header_by_location(&location))
+                .map(|block| block.header.level() == level)
+                .unwrap_or(false);
+            if !is_valid {
+                invalid_levels.push(level);
+            }
+        }
+
+        for level in invalid_levels {
+            let repaired = self.repair && self.block_storage.delete_level_location(level).is_ok();
+            self.report(report, IntegrityProblem::InvalidLevelIndex { level }, repaired);
+        }
+        Ok(())
+    }
+
+    fn check_context_hash_index(&mut self, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let mut invalid_context_hashes = vec![];
+        for (context_hash, location) in self.block_storage.iter_context_hash_locations()? {
+            let context_hash = match context_hash {
+                Ok(context_hash) => context_hash,
+                Err(_) => continue,
+            };
+            let is_valid = location.map_err(StorageError::from)
+                .and_then(|location| self.block_storage.get_block_header_by_location(&location))
+                .map(|block| block.header.context() == &context_hash)
+                .unwrap_or(false);
+            if !is_valid {
+                invalid_context_hashes.push(context_hash);
+            }
+        }
+
+        for context_hash in invalid_context_hashes {
+            let repaired = self.repair && self.block_storage.delete_context_hash_location(&context_hash).is_ok();
+            self.report(report, IntegrityProblem::InvalidContextHashIndex { context_hash: HashType::ContextHash.bytes_to_string(&context_hash) }, repaired);
+        }
+        Ok(())
+    }
+
+    fn check_orphan_operations(&mut self, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        let mut orphans: Vec<BlockHash> = vec![];
+        for (key, _) in self.operations_storage.iter(IteratorMode::Start)? {
+            let block_hash = match key {
+                Ok(key) => key.block_hash().clone(),
+                Err(_) => continue,
+            };
+            // operations of the same block are stored next to each other
+            if orphans.last() == Some(&block_hash) {
+                continue;
+            }
+            if !self.block_storage.contains(&block_hash)? {
+                orphans.push(block_hash);
+            }
+        }
+
+        for block_hash in orphans {
+            let repaired = self.repair && self.operations_storage.delete_operations(&block_hash).is_ok();
+            self.report(report, IntegrityProblem::OrphanOperations { block_hash: block_hash_to_string(&block_hash) }, repaired);
+        }
+        Ok(())
+    }
+
+    fn check_context_actions(&mut self, report: &mut IntegrityReport) -> Result<(), StorageError> {
+        for (block_hash, id) in self.context_action_storage.find_invalid_locations()? {
+            let repaired = self.repair && self.context_action_storage.delete_from_primary_index(&block_hash, id).is_ok();
+            self.report(report, IntegrityProblem::InvalidContextActionLocation { block_hash: block_hash_to_string(&block_hash), id }, repaired);
+        }
+        Ok(())
+    }
+
+    fn report(&self, report: &mut IntegrityReport, problem: IntegrityProblem, repaired: bool) {
+        warn!(self.log, "Storage inconsistency found"; "problem" => &problem, "repaired" => repaired);
+        if repaired {
+            report.repaired += 1;
+        }
+        report.problems.push(problem);
+    }
+}
+
+#[inline]
+fn block_hash_to_string(block_hash: &BlockHash) -> String {
+    HashType::BlockHash.bytes_to_string(block_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use failure::Error;
+
+    use tezos_messages::p2p::encoding::prelude::*;
+
+    use crate::{BlockAdditionalDataBuilder, BlockJsonDataBuilder};
+    use crate::tests_common::{self, log, TmpStorage};
+
+    use super::*;
+
+    fn block(predecessor: &BlockHash, level: i32, context: u8) -> BlockHeaderWithHash {
+        tests_common::block(predecessor, level, context, 2)
+    }
+
+    fn store_block(storage: &PersistentStorage, block: &BlockHeaderWithHash, chain_id: &Vec<u8>) -> Result<(), Error> {
+        BlockStorage::new(storage).put_block_header(block)?;
+        BlockMetaStorage::new(storage).put_block_header(block, chain_id)?;
+        OperationsMetaStorage::new(storage).put_block_header(block, chain_id)?;
+        Ok(())
+    }
+
+    #[test]
+    fn consistent_storage_has_no_problems() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__integrity_consistent")?;
+        let chain_id = vec![1, 2, 3, 4];
+        SystemStorage::new(tmp_storage.storage().kv()).set_chain_id(&chain_id)?;
+
+        let block_1 = block(&vec![0; 32], 1, 1);
+        let block_2 = block(&block_1.hash, 2, 2);
+        store_block(tmp_storage.storage(), &block_1, &chain_id)?;
+        store_block(tmp_storage.storage(), &block_2, &chain_id)?;
+
+        let report = IntegrityChecker::new(tmp_storage.storage(), false, log()).check()?;
+        assert_eq!(2, report.checked_blocks);
+        assert!(report.is_consistent(), "Unexpected problems: {:?}", report.problems);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pruned_blocks_are_not_reported() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__integrity_pruned")?;
+        let chain_id = vec![1, 2, 3, 4];
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+        system_storage.set_chain_id(&chain_id)?;
+
+        let block_1 = block(&vec![0; 32], 1, 1);
+        let block_2 = block(&block_1.hash, 2, 2);
+        let block_3 = block(&block_2.hash, 3, 3);
+        for block in &[&block_1, &block_2, &block_3] {
+            BlockStorage::new(tmp_storage.storage()).put_block_header(block)?;
+            BlockMetaStorage::new(tmp_storage.storage()).put_block_header(block, &chain_id)?;
+        }
+        // operations metadata of the pruned blocks were removed in the rolling mode and their context was pruned
+        let mut block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+        block_meta_storage.put(&block_1.hash, &Meta::new(true, Some(vec![0; 32]), vec![block_2.hash.clone()], 1, chain_id.clone()))?;
+        block_meta_storage.put(&block_2.hash, &Meta::new(true, Some(block_1.hash.clone()), vec![block_3.hash.clone()], 2, chain_id.clone()))?;
+        system_storage.set_pruned_level(3)?;
+
+        let report = IntegrityChecker::new(tmp_storage.storage(), true, log()).check()?;
+        assert_eq!(3, report.checked_blocks);
+        // only block above the pruned level is verified
+        assert_eq!(vec![IntegrityProblem::MissingOperationsMeta { block_hash: block_hash_to_string(&block_3.hash) }], report.problems);
+        assert!(OperationsMetaStorage::new(tmp_storage.storage()).get(&block_1.hash)?.is_none());
+        assert!(block_meta_storage.get(&block_2.hash)?.unwrap().is_applied());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repair_recomputable_problems() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__integrity_repair")?;
+        let chain_id = vec![1, 2, 3, 4];
+        SystemStorage::new(tmp_storage.storage().kv()).set_chain_id(&chain_id)?;
+
+        let block_1 = block(&vec![0; 32], 1, 1);
+        let block_2 = block(&block_1.hash, 2, 2);
+        store_block(tmp_storage.storage(), &block_1, &chain_id)?;
+        BlockStorage::new(tmp_storage.storage()).put_block_header(&block_2)?;
+        // block is marked as applied, but there is no context for it
+        let mut block_meta_storage = BlockMetaStorage::new(tmp_storage.storage());
+        block_meta_storage.put(&block_1.hash, &Meta::new(true, Some(vec![0; 32]), vec![], 1, chain_id.clone()))?;
+        // operations are stored for unknown block
+        let orphan_block = block(&block_2.hash, 3, 3);
+        OperationsStorage::new(tmp_storage.storage()).put_operations(&OperationsForBlocksMessage::new(OperationsForBlock::new(orphan_block.hash.clone(), 0), Path::Op, vec![]))?;
+
+        let report = IntegrityChecker::new(tmp_storage.storage(), true, log()).check()?;
+        assert!(report.problems.contains(&IntegrityProblem::AppliedWithoutContext { block_hash: block_hash_to_string(&block_1.hash) }));
+        assert!(report.problems.contains(&IntegrityProblem::MissingBlockMeta { block_hash: block_hash_to_string(&block_2.hash) }));
+        assert!(report.problems.contains(&IntegrityProblem::MissingOperationsMeta { block_hash: block_hash_to_string(&block_2.hash) }));
+        assert!(report.problems.contains(&IntegrityProblem::OrphanOperations { block_hash: block_hash_to_string(&orphan_block.hash) }));
+        assert_eq!(report.problems.len(), report.repaired);
+
+        // everything was repaired
+        let report = IntegrityChecker::new(tmp_storage.storage(), false, log()).check()?;
+        assert!(report.is_consistent(), "Unexpected problems: {:?}", report.problems);
+        assert!(!block_meta_storage.get(&block_1.hash)?.unwrap().is_applied());
+        assert!(block_meta_storage.get(&block_1.hash)?.unwrap().successors().contains(&block_2.hash));
+
+        Ok(())
+    }
+
+    #[test]
+    fn genesis_hash_is_not_computed_from_header() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__integrity_genesis")?;
+        let chain_id = vec![1, 2, 3, 4];
+        SystemStorage::new(tmp_storage.storage().kv()).set_chain_id(&chain_id)?;
+
+        // genesis is its own predecessor, so its hash is given by the environment configuration
+        let genesis_hash = vec![9; 32];
+        let genesis_header = BlockHeaderBuilder::default()
+            .level(0)
+            .proto(0)
+            .predecessor(genesis_hash.clone())
+            .timestamp(0)
+            .validation_pass(0)
+            .operations_hash(vec![0; 32])
+            .fitness(vec![])
+            .context(vec![0; 32])
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        let genesis = BlockHeaderWithHash { hash: genesis_hash, header: Arc::new(genesis_header) };
+        let mut block_storage = BlockStorage::new(tmp_storage.storage());
+        block_storage.put_block_header(&genesis)?;
+        block_storage.put_block_json_data(&genesis.hash, BlockJsonDataBuilder::default()
+            .block_header_proto_json("{}".to_string())
+            .block_header_proto_metadata_json("{}".to_string())
+            .operations_proto_metadata_json("[]".to_string())
+            .build()
+            .unwrap())?;
+        block_storage.put_block_additional_data(&genesis.hash, BlockAdditionalDataBuilder::default()
+            .max_operations_ttl(0)
+            .last_allowed_fork_level(0)
+            .build()
+            .unwrap())?;
+        block_storage.assign_to_context(&genesis.hash, genesis.header.context())?;
+        BlockMetaStorage::new(tmp_storage.storage()).put(&genesis.hash, &Meta::genesis_meta(&genesis.hash, &chain_id, true))?;
+        OperationsMetaStorage::new(tmp_storage.storage()).put(&genesis.hash, &operations_meta_storage::Meta::genesis_meta(&chain_id))?;
+
+        let block_1 = block(&genesis.hash, 1, 1);
+        store_block(tmp_storage.storage(), &block_1, &chain_id)?;
+
+        let report = IntegrityChecker::new(tmp_storage.storage(), false, log()).check()?;
+        assert_eq!(2, report.checked_blocks);
+        assert!(report.is_consistent(), "Unexpected problems: {:?}", report.problems);
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_block_with_invalid_hash() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__integrity_invalid_hash")?;
+        let chain_id = vec![1, 2, 3, 4];
+        SystemStorage::new(tmp_storage.storage().kv()).set_chain_id(&chain_id)?;
+
+        let block_1 = block(&vec![0; 32], 1, 1);
+        let forged_block = BlockHeaderWithHash { hash: vec![7; 32], header: Arc::new((*block_1.header).clone()) };
+        store_block(tmp_storage.storage(), &forged_block, &chain_id)?;
+
+        let report = IntegrityChecker::new(tmp_storage.storage(), true, log()).check()?;
+        assert!(report.problems.contains(&IntegrityProblem::BlockHashMismatch { block_hash: block_hash_to_string(&forged_block.hash), computed_hash: block_hash_to_string(&block_1.hash) }));
+        assert_eq!(0, report.repaired);
+
+        Ok(())
+    }
+}
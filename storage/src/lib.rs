@@ -14,13 +14,16 @@ use crypto::hash::{BlockHash, ChainId, ContextHash, HashType};
 use tezos_api::environment::{OPERATION_LIST_LIST_HASH_EMPTY, TezosEnvironmentConfiguration, TezosEnvironmentError};
 use tezos_api::ffi::{ApplyBlockResult, CommitGenesisResult};
 use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash, MessageHashError};
-use tezos_messages::p2p::encoding::prelude::BlockHeader;
+use tezos_messages::p2p::encoding::prelude::{BlockHeader, BlockHeaderBuilder};
 
 pub use crate::block_meta_storage::{BlockMetaStorage, BlockMetaStorageKV, BlockMetaStorageReader};
 pub use crate::block_storage::{BlockAdditionalData, BlockAdditionalDataBuilder, BlockJsonData, BlockJsonDataBuilder, BlockStorage, BlockStorageReader};
 pub use crate::context_action_storage::{ContextActionPrimaryIndexKey, ContextActionRecordValue, ContextActionStorage};
 pub use crate::operations_meta_storage::{OperationsMetaStorage, OperationsMetaStorageKV};
 pub use crate::operations_storage::{OperationKey, OperationsStorage, OperationsStorageKV, OperationsStorageReader};
+pub use crate::peer_ban_storage::{BanKey, PeerBan, PeerBanStorage};
+pub use crate::point_storage::{PointInfo, PointStorage, PointTrust};
+pub use crate::protocol_storage::{ProtocolStorage, ProtocolStorageKV, ProtocolStorageReader};
 use crate::persistent::{CommitLogError, DBError, Decoder, Encoder, SchemaError};
 pub use crate::persistent::database::{Direction, IteratorMode};
 use crate::persistent::sequence::SequenceError;
@@ -32,7 +35,15 @@ pub mod operations_meta_storage;
 pub mod block_storage;
 pub mod block_meta_storage;
 pub mod context_action_storage;
+pub mod integrity;
+pub mod merkle_tree;
+pub mod migration;
 pub mod p2p_message_storage;
+pub mod peer_ban_storage;
+pub mod point_storage;
+pub mod protocol_storage;
+pub mod pruning;
+pub mod snapshot;
 pub mod system_storage;
 pub mod skip_list;
 pub mod context;
@@ -234,6 +245,46 @@ pub fn store_commit_genesis_result(
     Ok(block_json_data)
 }
 
+/// Stores genesis of the test chain forked by the `forking_block` and marks it as applied, so blocks of the test chain can be applied on top of it.
+///
+/// Like the genesis of the main chain, test chain genesis is its own predecessor. It is stored with the context and the data of the forking block.
+pub fn store_test_chain_genesis(
+    block_storage: &mut BlockStorage,
+    block_meta_storage: &mut BlockMetaStorage,
+    operations_meta_storage: &mut OperationsMetaStorage,
+    forking_block: &BlockHeaderWithHash,
+    forking_block_json_data: &BlockJsonData,
+    forking_block_additional_data: &BlockAdditionalData,
+    genesis_hash: &BlockHash,
+    test_chain_id: &ChainId) -> Result<BlockHeaderWithHash, StorageError> {
+
+    let genesis_with_hash = BlockHeaderWithHash {
+        hash: genesis_hash.clone(),
+        header: Arc::new(
+            BlockHeaderBuilder::default()
+                .level(forking_block.header.level())
+                .proto(forking_block.header.proto())
+                .predecessor(genesis_hash.clone())
+                .timestamp(forking_block.header.timestamp())
+                .validation_pass(0)
+                .operations_hash(OPERATION_LIST_LIST_HASH_EMPTY.clone())
+                .fitness(forking_block.header.fitness().clone())
+                .context(forking_block.header.context().clone())
+                .protocol_data(vec![])
+                .build().unwrap()
+        ),
+    };
+    block_storage.put_block_header(&genesis_with_hash)?;
+    block_storage.put_block_json_data(genesis_hash, forking_block_json_data.clone())?;
+    block_storage.put_block_additional_data(genesis_hash, forking_block_additional_data.clone())?;
+    block_storage.assign_to_context(genesis_hash, forking_block.header.context())?;
+
+    block_meta_storage.put(genesis_hash, &block_meta_storage::Meta::new(true, Some(genesis_hash.clone()), vec![], forking_block.header.level(), test_chain_id.clone()))?;
+    operations_meta_storage.put(genesis_hash, &operations_meta_storage::Meta::genesis_meta(test_chain_id))?;
+
+    Ok(genesis_with_hash)
+}
+
 pub fn initialize_storage_with_genesis_block(
     block_storage: &mut BlockStorage,
     init_storage_data: &StorageInitInfo,
@@ -275,8 +326,10 @@ pub mod tests_common {
     use std::sync::Arc;
 
     use failure::Error;
+    use slog::Discard;
 
     use crate::block_storage;
+    use crate::p2p_message_storage::{P2PMessageSecondaryIndex, P2PMessageStorage};
     use crate::persistent::*;
     use crate::persistent::sequence::Sequences;
     use crate::skip_list::{DatabaseBackedSkipList, Lane, ListValue};
@@ -306,6 +359,11 @@ pub mod tests_common {
                 context_action_storage::ContextActionPrimaryIndex::descriptor(),
                 context_action_storage::ContextActionByContractIndex::descriptor(),
                 SystemStorage::descriptor(),
+                PeerBanStorage::descriptor(),
+                PointStorage::descriptor(),
+                ProtocolStorage::descriptor(),
+                P2PMessageStorage::descriptor(),
+                P2PMessageSecondaryIndex::descriptor(),
                 Sequences::descriptor(),
                 DatabaseBackedSkipList::descriptor(),
                 Lane::descriptor(),
@@ -325,6 +383,10 @@ pub mod tests_common {
         pub fn storage(&self) -> &PersistentStorage {
             &self.persistent_storage
         }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
     }
 
     impl Drop for TmpStorage {
@@ -333,4 +395,26 @@ pub mod tests_common {
             let _ = fs::remove_dir_all(&self.path);
         }
     }
+
+    /// Block of the test chain, all bytes of its context hash are set to the `context`
+    pub fn block(predecessor: &BlockHash, level: i32, context: u8, validation_pass: u8) -> BlockHeaderWithHash {
+        let header = BlockHeaderBuilder::default()
+            .level(level)
+            .proto(1)
+            .predecessor(predecessor.clone())
+            .timestamp(level as i64)
+            .validation_pass(validation_pass)
+            .operations_hash(vec![0; 32])
+            .fitness(vec![])
+            .context(vec![context; 32])
+            .protocol_data(vec![])
+            .build()
+            .unwrap();
+        BlockHeaderWithHash::new(header).unwrap()
+    }
+
+    /// Logger, which discards all records
+    pub fn log() -> Logger {
+        Logger::root(Discard, slog::o!())
+    }
 }
@@ -0,0 +1,616 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Native implementation of the Irmin tree used by the Tezos context.
+//!
+//! Context is a tree of nodes, where leaves (blobs) hold the values. Every context commit points to the root node
+//! of the tree, so the context hash returned by the protocol can be computed from the [`ContextAction`]s.
+//! Hashes are compatible with the Irmin store used by the OCaml node:
+//! * blob - `blake2b(len(value) ++ value)`
+//! * node - `blake2b(count ++ [kind ++ len(name) ++ name ++ len(hash) ++ hash]*)`, entries sorted by name
+//! * commit - `blake2b(len(root) ++ root ++ count(parents) ++ [len(parent) ++ parent]* ++ date ++ len(author) ++ author ++ len(message) ++ message)`
+//!
+//! Lengths and counts are encoded as big endian `u64`, except the node entry name, which is LEB128 encoded.
+//!
+//! Nodes with more than 256 entries are hashed as irmin-pack inodes. Entries are split into 32 buckets
+//! by the OCaml `Hashtbl.seeded_hash` of the name (seeded by the depth) until a bucket holds at most 32 entries:
+//! * values - `blake2b(0 ++ count ++ [len(name) ++ name ++ kind ++ hash]*)`, entries sorted by name
+//! * tree - `blake2b(1 ++ depth ++ count(entries) ++ count(pointers) ++ [index ++ hash]*)`, hash of the bucket inode
+//!
+//! All lengths, counts and the depth of the inode are LEB128 encoded.
+//!
+//! Tree is persistent, unchanged subtrees are shared between the committed trees and hashes of the unchanged
+//! subtrees are computed only once.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+use failure::Fail;
+
+use crypto::blake2b;
+use crypto::hash::{ContextHash, HashType};
+use tezos_context::channel::ContextAction;
+
+use crate::persistent::ContextMap;
+use crate::skip_list::Bucket;
+
+/// Hash of the blob, node or commit
+pub type EntryHash = Vec<u8>;
+
+const HASH_LEN: usize = 32;
+const NODE_KIND_TREE: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
+const NODE_KIND_BLOB: [u8; 8] = [255, 0, 0, 0, 0, 0, 0, 0];
+/// Nodes with more entries are hashed as inodes
+const INODE_STABLE_HASH: usize = 256;
+/// Max count of the values of the inode and count of the buckets of the inode tree
+const INODE_ENTRIES: usize = 32;
+const INODE_KIND_NODE: u8 = 0;
+const INODE_KIND_CONTENTS: u8 = 1;
+
+/// Metadata of the context commit, which are part of the commit hash
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommitInfo {
+    pub author: String,
+    pub message: String,
+    /// Unix timestamp in seconds
+    pub date: i64,
+}
+
+/// Possible errors for merkle tree
+#[derive(Debug, Fail)]
+pub enum MerkleTreeError {
+    #[fail(display = "Unknown context_hash: {}", context_hash)]
+    UnknownContextHash {
+        context_hash: String,
+    },
+}
+
+#[derive(Clone)]
+enum Entry {
+    Blob {
+        value: Arc<Vec<u8>>,
+        hash: EntryHash,
+    },
+    Tree(Arc<Tree>),
+}
+
+#[derive(Clone, Default)]
+struct Tree {
+    entries: BTreeMap<String, Entry>,
+    /// Cached hash of the tree, it is reset when the tree is modified
+    hash: Option<EntryHash>,
+}
+
+/// Working tree modified by the context actions, together with the recently committed trees.
+pub struct MerkleTree {
+    root: Tree,
+    /// Recently committed trees, which can be checked out
+    commits: VecDeque<(ContextHash, Arc<Tree>)>,
+    /// Max count of retained committed trees
+    retained_commits: usize,
+}
+
+impl MerkleTree {
+    /// Create empty tree, at most `retained_commits` recent commits can be checked out
+    pub fn new(retained_commits: usize) -> Self {
+        MerkleTree {
+            root: Tree::default(),
+            commits: VecDeque::new(),
+            retained_commits,
+        }
+    }
+
+    /// Apply context modification, `Checkout` and `Commit` actions switch the working tree.
+    pub fn apply_action(&mut self, action: &ContextAction) -> Result<(), MerkleTreeError> {
+        match action {
+            ContextAction::Set { key, value, .. } => self.set(key, value),
+            ContextAction::Delete { key, .. }
+            | ContextAction::RemoveRecursively { key, .. } => self.delete(key),
+            ContextAction::Copy { from_key, to_key, .. } => self.copy(from_key, to_key),
+            ContextAction::Checkout { context_hash, .. } => self.checkout(context_hash)?,
+            ContextAction::Commit { new_context_hash, .. } => self.commit(new_context_hash),
+            _ => (),
+        };
+        Ok(())
+    }
+
+    /// Set value of the blob at the `key`, missing nodes are created
+    pub fn set(&mut self, key: &[String], value: &[u8]) {
+        let blob = Entry::Blob {
+            hash: hash_blob(value),
+            value: Arc::new(value.to_vec()),
+        };
+        set_entry(&mut self.root, key, blob);
+    }
+
+    /// Remove blob or the whole subtree at the `key`, nodes left without entries are removed too
+    pub fn delete(&mut self, key: &[String]) {
+        remove_entry(&mut self.root, key);
+    }
+
+    /// Copy blob or the whole subtree from the `from_key` to the `to_key`
+    pub fn copy(&mut self, from_key: &[String], to_key: &[String]) {
+        if let Some(entry) = find_entry(&self.root, from_key) {
+            set_entry(&mut self.root, to_key, entry);
+        }
+    }
+
+    /// Get value of the blob at the `key`
+    pub fn get(&self, key: &[String]) -> Option<Vec<u8>> {
+        match find_entry(&self.root, key) {
+            Some(Entry::Blob { value, .. }) => Some(value.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Hash of the root node of the working tree
+    pub fn tree_hash(&mut self) -> EntryHash {
+        hash_tree(&mut self.root)
+    }
+
+    /// Store the working tree, so it can be checked out by the `context_hash` later
+    pub fn commit(&mut self, context_hash: &ContextHash) {
+        // hashes are computed before the tree is shared
+        hash_tree(&mut self.root);
+        self.commits.retain(|(hash, _)| hash != context_hash);
+        self.commits.push_back((context_hash.clone(), Arc::new(self.root.clone())));
+        while self.commits.len() > self.retained_commits {
+            self.commits.pop_front();
+        }
+    }
+
+    /// Use the committed tree as the working tree
+    pub fn checkout(&mut self, context_hash: &ContextHash) -> Result<(), MerkleTreeError> {
+        match self.commits.iter().find(|(hash, _)| hash == context_hash) {
+            Some((_, tree)) => {
+                self.root = (**tree).clone();
+                Ok(())
+            }
+            None => Err(MerkleTreeError::UnknownContextHash { context_hash: HashType::ContextHash.bytes_to_string(context_hash) })
+        }
+    }
+
+    /// Rebuild the working tree from the full context, e.g. when the context was not committed by this tree.
+    ///
+    /// Keys of the context are joined by `/`.
+    pub fn checkout_context(&mut self, context_hash: &ContextHash, context: &ContextMap) {
+        self.root = Tree::default();
+        for (key, value) in context {
+            if let Bucket::Exists(value) = value {
+                let key: Vec<String> = key.split('/').map(|step| step.to_string()).collect();
+                self.set(&key, value);
+            }
+        }
+        self.commit(context_hash);
+    }
+}
+
+/// Hash of the context commit, which points to the tree with the `root_hash`
+pub fn hash_commit(root_hash: &EntryHash, parent_hash: Option<&ContextHash>, info: &CommitInfo) -> ContextHash {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&(HASH_LEN as u64).to_be_bytes());
+    bytes.extend_from_slice(root_hash);
+    match parent_hash {
+        Some(parent_hash) => {
+            bytes.extend_from_slice(&1u64.to_be_bytes());
+            bytes.extend_from_slice(&(parent_hash.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(parent_hash);
+        }
+        None => bytes.extend_from_slice(&0u64.to_be_bytes()),
+    }
+    bytes.extend_from_slice(&(info.date as u64).to_be_bytes());
+    bytes.extend_from_slice(&(info.author.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(info.author.as_bytes());
+    bytes.extend_from_slice(&(info.message.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(info.message.as_bytes());
+    blake2b::digest_256(&bytes)
+}
+
+fn hash_blob(value: &[u8]) -> EntryHash {
+    let mut bytes = Vec::with_capacity(8 + value.len());
+    bytes.extend_from_slice(&(value.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(value);
+    blake2b::digest_256(&bytes)
+}
+
+fn hash_tree(tree: &mut Tree) -> EntryHash {
+    if let Some(hash) = &tree.hash {
+        return hash.clone();
+    }
+
+    let mut entries = Vec::with_capacity(tree.entries.len());
+    for (name, entry) in tree.entries.iter_mut() {
+        let name = name.as_str();
+        let node_entry = match entry {
+            Entry::Blob { hash, .. } => NodeEntry { name, is_blob: true, hash: hash.clone() },
+            Entry::Tree(subtree) => match subtree.hash.clone() {
+                Some(hash) => NodeEntry { name, is_blob: false, hash },
+                None => NodeEntry { name, is_blob: false, hash: hash_tree(Arc::make_mut(subtree)) },
+            },
+        };
+        entries.push(node_entry);
+    }
+
+    let hash = if entries.len() > INODE_STABLE_HASH {
+        let entries: Vec<&NodeEntry> = entries.iter().collect();
+        hash_inode(&entries, 0)
+    } else {
+        hash_node(&entries)
+    };
+    tree.hash = Some(hash.clone());
+    hash
+}
+
+/// Entry of the node, which is part of the node hash
+struct NodeEntry<'a> {
+    name: &'a str,
+    is_blob: bool,
+    hash: EntryHash,
+}
+
+fn hash_node(entries: &[NodeEntry]) -> EntryHash {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+    for entry in entries {
+        let kind = if entry.is_blob { NODE_KIND_BLOB } else { NODE_KIND_TREE };
+        bytes.extend_from_slice(&kind);
+        write_leb128(&mut bytes, entry.name.len() as u64);
+        bytes.extend_from_slice(entry.name.as_bytes());
+        bytes.extend_from_slice(&(entry.hash.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&entry.hash);
+    }
+    blake2b::digest_256(&bytes)
+}
+
+/// Hash of the inode at the `depth`, `entries` are sorted by name
+fn hash_inode(entries: &[&NodeEntry], depth: u32) -> EntryHash {
+    let mut bytes = vec![];
+    if entries.len() <= INODE_ENTRIES {
+        bytes.push(0);
+        write_leb128(&mut bytes, entries.len() as u64);
+        for entry in entries {
+            write_leb128(&mut bytes, entry.name.len() as u64);
+            bytes.extend_from_slice(entry.name.as_bytes());
+            bytes.push(if entry.is_blob { INODE_KIND_CONTENTS } else { INODE_KIND_NODE });
+            bytes.extend_from_slice(&entry.hash);
+        }
+    } else {
+        let mut buckets: Vec<Vec<&NodeEntry>> = vec![vec![]; INODE_ENTRIES];
+        for entry in entries {
+            buckets[inode_index(depth, entry.name)].push(*entry);
+        }
+
+        bytes.push(1);
+        write_leb128(&mut bytes, u64::from(depth));
+        write_leb128(&mut bytes, entries.len() as u64);
+        write_leb128(&mut bytes, buckets.iter().filter(|bucket| !bucket.is_empty()).count() as u64);
+        for (index, bucket) in buckets.iter().enumerate().filter(|(_, bucket)| !bucket.is_empty()) {
+            bytes.push(index as u8);
+            bytes.extend_from_slice(&hash_inode(bucket, depth + 1));
+        }
+    }
+    blake2b::digest_256(&bytes)
+}
+
+/// Index of the bucket of the inode tree at the `depth`, where the entry `name` belongs
+fn inode_index(depth: u32, name: &str) -> usize {
+    ocaml_hash_string(depth, name.as_bytes()) as usize % INODE_ENTRIES
+}
+
+/// OCaml `Hashtbl.seeded_hash` of the string
+fn ocaml_hash_string(seed: u32, s: &[u8]) -> u32 {
+    fn mix(h: u32, d: u32) -> u32 {
+        let d = d.wrapping_mul(0xcc9e_2d51).rotate_left(15).wrapping_mul(0x1b87_3593);
+        (h ^ d).rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64)
+    }
+
+    let mut h = seed;
+    let mut blocks = s.chunks_exact(4);
+    for block in &mut blocks {
+        h = mix(h, u32::from_le_bytes([block[0], block[1], block[2], block[3]]));
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let w = tail.iter().rev().fold(0u32, |w, byte| (w << 8) | u32::from(*byte));
+        h = mix(h, w);
+    }
+    h ^= s.len() as u32;
+
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h & 0x3fff_ffff
+}
+
+fn write_leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn find_entry(tree: &Tree, key: &[String]) -> Option<Entry> {
+    let (name, rest) = key.split_first()?;
+    match (tree.entries.get(name), rest.is_empty()) {
+        (Some(entry), true) => Some(entry.clone()),
+        (Some(Entry::Tree(subtree)), false) => find_entry(subtree, rest),
+        _ => None,
+    }
+}
+
+fn set_entry(tree: &mut Tree, key: &[String], entry: Entry) {
+    let (name, rest) = match key.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    tree.hash = None;
+    if rest.is_empty() {
+        tree.entries.insert(name.clone(), entry);
+        return;
+    }
+
+    let child = tree.entries.entry(name.clone())
+        .or_insert_with(|| Entry::Tree(Arc::new(Tree::default())));
+    if let Entry::Blob { .. } = child {
+        *child = Entry::Tree(Arc::new(Tree::default()));
+    }
+    if let Entry::Tree(subtree) = child {
+        set_entry(Arc::make_mut(subtree), rest, entry);
+    }
+}
+
+/// Returns `true` if the tree was modified
+fn remove_entry(tree: &mut Tree, key: &[String]) -> bool {
+    let (name, rest) = match key.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    let removed = if rest.is_empty() {
+        tree.entries.remove(name).is_some()
+    } else {
+        let (removed, is_empty) = match tree.entries.get_mut(name) {
+            Some(Entry::Tree(subtree)) if find_entry(subtree.as_ref(), rest).is_some() => {
+                let subtree = Arc::make_mut(subtree);
+                (remove_entry(subtree, rest), subtree.entries.is_empty())
+            }
+            _ => (false, false),
+        };
+        if is_empty {
+            tree.entries.remove(name);
+        }
+        removed
+    };
+
+    if removed {
+        tree.hash = None;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::hashmap;
+
+    use super::*;
+
+    fn key(key: &str) -> Vec<String> {
+        key.split('/').map(|step| step.to_string()).collect()
+    }
+
+    #[test]
+    fn test_hash_empty_tree() {
+        let mut tree = MerkleTree::new(1);
+        assert_eq!(blake2b::digest_256(&0u64.to_be_bytes()), tree.tree_hash());
+    }
+
+    #[test]
+    fn test_hash_does_not_depend_on_order() {
+        let mut tree_1 = MerkleTree::new(1);
+        tree_1.set(&key("data/contracts/a"), &[1]);
+        tree_1.set(&key("data/contracts/b"), &[2]);
+        tree_1.set(&key("protocol"), &[3]);
+
+        let mut tree_2 = MerkleTree::new(1);
+        tree_2.set(&key("protocol"), &[3]);
+        tree_2.set(&key("data/contracts/b"), &[2]);
+        tree_2.set(&key("data/contracts/a"), &[1]);
+
+        assert_eq!(tree_1.tree_hash(), tree_2.tree_hash());
+        assert_eq!(Some(vec![2]), tree_1.get(&key("data/contracts/b")));
+    }
+
+    #[test]
+    fn test_delete_removes_empty_nodes() {
+        let mut tree = MerkleTree::new(1);
+        tree.set(&key("protocol"), &[3]);
+        let expected_hash = tree.tree_hash();
+
+        tree.set(&key("data/contracts/a"), &[1]);
+        assert_ne!(expected_hash, tree.tree_hash());
+        tree.delete(&key("data/contracts/a"));
+        assert_eq!(expected_hash, tree.tree_hash());
+
+        tree.set(&key("data/contracts/a"), &[1]);
+        tree.set(&key("data/contracts/b"), &[2]);
+        tree.delete(&key("data"));
+        assert_eq!(expected_hash, tree.tree_hash());
+        assert_eq!(None, tree.get(&key("data/contracts/a")));
+    }
+
+    #[test]
+    fn test_copy_subtree() {
+        let mut tree = MerkleTree::new(1);
+        tree.set(&key("data/contracts/a"), &[1]);
+        tree.set(&key("data/contracts/b"), &[2]);
+        tree.copy(&key("data/contracts"), &key("data/backup"));
+        assert_eq!(Some(vec![1]), tree.get(&key("data/backup/a")));
+
+        // modification of the copy does not change the source
+        tree.set(&key("data/backup/a"), &[5]);
+        assert_eq!(Some(vec![1]), tree.get(&key("data/contracts/a")));
+
+        let mut expected = MerkleTree::new(1);
+        expected.set(&key("data/contracts/a"), &[1]);
+        expected.set(&key("data/contracts/b"), &[2]);
+        expected.set(&key("data/backup/a"), &[5]);
+        expected.set(&key("data/backup/b"), &[2]);
+        assert_eq!(expected.tree_hash(), tree.tree_hash());
+    }
+
+    #[test]
+    fn test_checkout_committed_tree() {
+        let mut tree = MerkleTree::new(2);
+        tree.set(&key("a"), &[1]);
+        tree.commit(&vec![1; 32]);
+        let hash_1 = tree.tree_hash();
+        tree.set(&key("b"), &[2]);
+        tree.commit(&vec![2; 32]);
+        tree.set(&key("c"), &[3]);
+        tree.commit(&vec![3; 32]);
+
+        // oldest commit is not retained
+        assert!(tree.checkout(&vec![1; 32]).is_err());
+        tree.checkout(&vec![2; 32]).unwrap();
+        assert_eq!(None, tree.get(&key("c")));
+        assert_eq!(Some(vec![2]), tree.get(&key("b")));
+
+        tree.checkout_context(&vec![1; 32], &hashmap! { "a".to_string() => Bucket::Exists(vec![1]), "b".to_string() => Bucket::Deleted });
+        assert_eq!(hash_1, tree.tree_hash());
+        assert!(tree.checkout(&vec![1; 32]).is_ok());
+    }
+
+    #[test]
+    fn test_hash_tree() {
+        let mut tree = MerkleTree::new(1);
+        tree.set(&key("a"), &[1]);
+        assert_eq!("d49a53323107f2ae40b01eaa4e9bec4d02801daf60bab82dc2529e40d40fa917", hex::encode(tree.tree_hash()));
+
+        let mut tree = MerkleTree::new(1);
+        tree.set(&key("a/foo"), b"abc");
+        tree.set(&key("a/aaa"), b"abcd");
+        tree.set(&key("b/boo"), b"ab");
+        tree.set(&key("x"), b"a");
+        tree.set(&key("one/two/three"), b"a");
+        assert_eq!("dbaed7b6dbd850744b8bdf89da80ee7eb3b8e43d63490446878c2ee49a1991b2", hex::encode(tree.tree_hash()));
+    }
+
+    #[test]
+    fn test_hash_commit() {
+        // hashes computed by irmin
+        let info = CommitInfo { author: "Tezos".to_string(), message: "Genesis".to_string(), date: 0 };
+        let mut tree = MerkleTree::new(1);
+        tree.set(&key("a"), b"abc");
+        let genesis_hash = hash_commit(&tree.tree_hash(), None, &info);
+        assert_eq!("cf9518334e1d18b342e5815c7770db0c1a1ede0b49dea915caa966090df6acef", hex::encode(&genesis_hash));
+
+        tree.set(&key("data/x"), b"a");
+        let info = CommitInfo { message: "".to_string(), ..info };
+        let hash = hash_commit(&tree.tree_hash(), Some(&genesis_hash), &info);
+        assert_eq!("ca7bc7022ffbd35acc97f7defb00c486bb7f4d19a2d62790d5949775eb74f3c8", hex::encode(&hash));
+    }
+
+    #[test]
+    fn test_hash_large_node() {
+        let names: Vec<String> = (0..300).map(|i| format!("contract_{}", i)).collect();
+
+        let mut tree_1 = MerkleTree::new(1);
+        let mut tree_2 = MerkleTree::new(1);
+        for name in &names {
+            tree_1.set(&key(&format!("data/{}", name)), name.as_bytes());
+        }
+        for name in names.iter().rev() {
+            tree_2.set(&key(&format!("data/{}", name)), name.as_bytes());
+        }
+        assert_eq!(tree_1.tree_hash(), tree_2.tree_hash());
+
+        // node with more than 256 entries is not hashed as the plain node
+        let mut data = tree_1.root.entries.get("data").map(|entry| match entry {
+            Entry::Tree(tree) => (**tree).clone(),
+            Entry::Blob { .. } => panic!("data is not a tree"),
+        }).unwrap();
+        let entries: Vec<NodeEntry> = data.entries.iter()
+            .map(|(name, entry)| match entry {
+                Entry::Blob { hash, .. } => NodeEntry { name: name.as_str(), is_blob: true, hash: hash.clone() },
+                Entry::Tree(_) => panic!("entry is not a blob"),
+            })
+            .collect();
+        assert_ne!(hash_node(&entries), hash_tree(&mut data));
+
+        // node with 256 entries is hashed as the plain node
+        for name in &names[INODE_STABLE_HASH..] {
+            tree_1.delete(&key(&format!("data/{}", name)));
+        }
+        let mut expected = MerkleTree::new(1);
+        for name in &names[..INODE_STABLE_HASH] {
+            expected.set(&key(&format!("data/{}", name)), name.as_bytes());
+        }
+        assert_eq!(expected.tree_hash(), tree_1.tree_hash());
+    }
+
+    #[test]
+    fn test_hash_inode_with_full_bucket() {
+        fn values_inode(entries: &[&NodeEntry]) -> EntryHash {
+            let mut bytes = vec![0];
+            write_leb128(&mut bytes, entries.len() as u64);
+            for entry in entries {
+                write_leb128(&mut bytes, entry.name.len() as u64);
+                bytes.extend_from_slice(entry.name.as_bytes());
+                bytes.push(INODE_KIND_CONTENTS);
+                bytes.extend_from_slice(&entry.hash);
+            }
+            blake2b::digest_256(&bytes)
+        }
+
+        fn tree_inode(depth: u64, count: usize, buckets: &[(u8, EntryHash)]) -> EntryHash {
+            let mut bytes = vec![1];
+            write_leb128(&mut bytes, depth);
+            write_leb128(&mut bytes, count as u64);
+            write_leb128(&mut bytes, buckets.len() as u64);
+            for (index, hash) in buckets {
+                bytes.push(*index);
+                bytes.extend_from_slice(hash);
+            }
+            blake2b::digest_256(&bytes)
+        }
+
+        // all entries fall into the first bucket of the root inode, so the bucket is split again at the next depth
+        let mut names: Vec<String> = (0..)
+            .map(|i| format!("contract_{}", i))
+            .filter(|name| inode_index(0, name) == 0)
+            .take(INODE_ENTRIES + 8)
+            .collect();
+        names.sort();
+        let entries: Vec<NodeEntry> = names.iter()
+            .map(|name| NodeEntry { name: name.as_str(), is_blob: true, hash: hash_blob(name.as_bytes()) })
+            .collect();
+        let entries: Vec<&NodeEntry> = entries.iter().collect();
+
+        let mut buckets: BTreeMap<usize, Vec<&NodeEntry>> = BTreeMap::new();
+        for entry in &entries {
+            buckets.entry(inode_index(1, entry.name)).or_default().push(*entry);
+        }
+        assert!(buckets.values().all(|bucket| bucket.len() <= INODE_ENTRIES));
+        let bucket_hashes: Vec<(u8, EntryHash)> = buckets.iter()
+            .map(|(index, bucket)| (*index as u8, values_inode(bucket)))
+            .collect();
+
+        let expected = tree_inode(0, entries.len(), &[(0, tree_inode(1, entries.len(), &bucket_hashes))]);
+        assert_eq!(expected, hash_inode(&entries, 0));
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut bytes = vec![];
+        write_leb128(&mut bytes, 5);
+        write_leb128(&mut bytes, 300);
+        assert_eq!(vec![5, 0xac, 0x02], bytes);
+    }
+}
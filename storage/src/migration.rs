@@ -0,0 +1,378 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Upgrades the database created by the previous versions of the node to the current [`DATABASE_VERSION`].
+//!
+//! Every schema change increases the database version and appends a [`Migration`] step to the [`migrations`].
+//! Steps are run in order at startup, each one upgrades the database by a single version. Step migrates the data in batches
+//! and the position of the last migrated batch is stored in the [`SystemStorage`], so an interrupted migration is resumed
+//! from that position on the next start.
+
+use failure::Fail;
+use slog::{info, Logger};
+
+use crate::{BlockMetaStorage, StorageError};
+use crate::block_meta_storage::upgrade_v11_meta;
+use crate::persistent::{DBError, KeyValueSchema, PersistentStorage};
+use crate::system_storage::{DbVersion, SystemStorage};
+
+/// Current version of the database schema
+pub const DATABASE_VERSION: DbVersion = 12;
+
+/// Count of the records migrated in a single batch
+const BATCH_SIZE: usize = 10_000;
+
+/// Ordered list of all migration steps.
+///
+/// When [`DATABASE_VERSION`] is increased, the step upgrading the previous version must be appended here.
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(BlockMetaSuccessorsMigration),
+    ]
+}
+
+/// Position of the last migrated batch, its meaning is defined by the migration step
+pub type MigrationCursor = Vec<u8>;
+
+/// Single step, which upgrades the database from the [`version`](Migration::version) to the next version.
+pub trait Migration: Send + Sync {
+    /// Version of the database upgraded by this step
+    fn version(&self) -> DbVersion;
+
+    /// Human readable description of the schema change
+    fn description(&self) -> &str;
+
+    /// Migrate the next batch of the data after the `cursor`, `None` cursor means the beginning of the data.
+    ///
+    /// Returns cursor of the migrated batch or `None` when all data are migrated. Batch can be migrated again
+    /// if the node was stopped before the returned cursor was stored, so the migration of a batch must be idempotent.
+    /// Column families and commit logs are accessed through the `storage`.
+    fn migrate_batch(&self, storage: &PersistentStorage, cursor: Option<&[u8]>) -> Result<Option<MigrationCursor>, StorageError>;
+}
+
+/// Upgrades block metadata from the single successor to the list of all known successors (version 11 to 12).
+///
+/// Cursor is the key of the last migrated block.
+struct BlockMetaSuccessorsMigration;
+
+impl Migration for BlockMetaSuccessorsMigration {
+    fn version(&self) -> DbVersion {
+        11
+    }
+
+    fn description(&self) -> &str {
+        "store all known successors of the block metadata"
+    }
+
+    fn migrate_batch(&self, storage: &PersistentStorage, cursor: Option<&[u8]>) -> Result<Option<MigrationCursor>, StorageError> {
+        let db = storage.kv();
+        let cf = db.cf_handle(BlockMetaStorage::name())
+            .ok_or(DBError::MissingColumnFamily { name: BlockMetaStorage::name() })?;
+        let mode = match cursor {
+            Some(cursor) => rocksdb::IteratorMode::From(cursor, rocksdb::Direction::Forward),
+            None => rocksdb::IteratorMode::Start,
+        };
+
+        let mut last_key = None;
+        let batch = db.iterator_cf(cf, mode).map_err(DBError::from)?
+            .filter(|(key, _)| Some(&key[..]) != cursor)
+            .take(BATCH_SIZE);
+        for (key, value) in batch {
+            if let Some(value) = upgrade_v11_meta(&value)? {
+                db.put_cf(cf, &key, &value).map_err(DBError::from)?;
+            }
+            last_key = Some(key.to_vec());
+        }
+        Ok(last_key)
+    }
+}
+
+/// Possible errors for database migration
+#[derive(Debug, Fail)]
+pub enum MigrationError {
+    #[fail(display = "Database version {} is newer than supported version {}", db_version, supported_version)]
+    UnsupportedVersion {
+        db_version: DbVersion,
+        supported_version: DbVersion,
+    },
+    #[fail(display = "Migration from database version {} is missing", db_version)]
+    MissingMigration {
+        db_version: DbVersion
+    },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError {
+        error: StorageError
+    },
+}
+
+impl From<StorageError> for MigrationError {
+    fn from(error: StorageError) -> Self {
+        MigrationError::StorageError { error }
+    }
+}
+
+impl slog::Value for MigrationError {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Runs the migration steps required to upgrade the database to the target version
+pub struct Migrator {
+    target_version: DbVersion,
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl Migrator {
+    pub fn new(target_version: DbVersion, mut migrations: Vec<Box<dyn Migration>>) -> Self {
+        migrations.sort_by_key(|migration| migration.version());
+        Migrator { target_version, migrations }
+    }
+
+    /// Migrator upgrading the database to the [`DATABASE_VERSION`]
+    pub fn current() -> Self {
+        Self::new(DATABASE_VERSION, migrations())
+    }
+
+    /// Upgrade the database to the target version and return count of the applied steps.
+    ///
+    /// Empty database is marked with the target version.
+    pub fn run(&self, storage: &PersistentStorage, log: &Logger) -> Result<usize, MigrationError> {
+        let mut system_storage = SystemStorage::new(storage.kv());
+        let db_version = match system_storage.get_db_version()? {
+            Some(db_version) => db_version,
+            None => {
+                system_storage.set_db_version(self.target_version)?;
+                return Ok(0);
+            }
+        };
+
+        let steps = self.steps(db_version)?;
+        for step in &steps {
+            self.apply(*step, storage, &mut system_storage, log)?;
+        }
+        Ok(steps.len())
+    }
+
+    /// Report steps, which would upgrade the database to the target version, and return their count.
+    ///
+    /// Database is only read, so the `system_storage` can be backed by the database [opened read only](crate::persistent::open_kv_read_only).
+    pub fn dry_run(&self, system_storage: &SystemStorage, log: &Logger) -> Result<usize, MigrationError> {
+        let steps = match system_storage.get_db_version()? {
+            Some(db_version) => self.steps(db_version)?,
+            None => vec![],
+        };
+        for step in &steps {
+            info!(log, "Pending database migration"; "version" => step.version(), "description" => step.description());
+        }
+        Ok(steps.len())
+    }
+
+    /// Resolve ordered steps from the `db_version` to the target version
+    fn steps(&self, db_version: DbVersion) -> Result<Vec<&dyn Migration>, MigrationError> {
+        if db_version > self.target_version {
+            return Err(MigrationError::UnsupportedVersion { db_version, supported_version: self.target_version });
+        }
+        (db_version..self.target_version)
+            .map(|version| self.migrations.iter()
+                .find(|migration| migration.version() == version)
+                .map(|migration| migration.as_ref())
+                .ok_or(MigrationError::MissingMigration { db_version: version }))
+            .collect()
+    }
+
+    fn apply(&self, step: &dyn Migration, storage: &PersistentStorage, system_storage: &mut SystemStorage, log: &Logger) -> Result<(), MigrationError> {
+        let mut cursor = system_storage.get_migration_cursor()?;
+        if cursor.is_some() {
+            info!(log, "Resuming interrupted database migration"; "version" => step.version(), "description" => step.description());
+        } else {
+            info!(log, "Migrating database"; "version" => step.version(), "description" => step.description());
+        }
+
+        while let Some(next_cursor) = step.migrate_batch(storage, cursor.as_deref())? {
+            system_storage.set_migration_cursor(&next_cursor)?;
+            cursor = Some(next_cursor);
+        }
+        storage.clog().flush().map_err(StorageError::from)?;
+
+        // cursor is removed first, so it can never be used by the following step
+        system_storage.delete_migration_cursor()?;
+        system_storage.set_db_version(step.version() + 1)?;
+        info!(log, "Database migrated"; "version" => step.version() + 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::sync::{Arc, Mutex};
+
+    use failure::Error;
+
+    use crate::block_meta_storage::Meta;
+    use crate::persistent::open_kv_read_only;
+    use crate::tests_common::{log, TmpStorage};
+
+    use super::*;
+
+    /// Migrates `batches` batches, the cursor is the number of the migrated batch
+    struct TestMigration {
+        version: DbVersion,
+        batches: u64,
+        fail_at: Option<u64>,
+        migrated: Arc<Mutex<Vec<(DbVersion, u64)>>>,
+    }
+
+    impl Migration for TestMigration {
+        fn version(&self) -> DbVersion {
+            self.version
+        }
+
+        fn description(&self) -> &str {
+            "test migration"
+        }
+
+        fn migrate_batch(&self, _storage: &PersistentStorage, cursor: Option<&[u8]>) -> Result<Option<MigrationCursor>, StorageError> {
+            let batch = cursor.map(|cursor| u64::from_be_bytes(cursor.try_into().unwrap()) + 1).unwrap_or(0);
+            if batch == self.batches {
+                return Ok(None);
+            }
+            if self.fail_at == Some(batch) {
+                return Err(StorageError::MissingKey);
+            }
+            self.migrated.lock().unwrap().push((self.version, batch));
+            Ok(Some(batch.to_be_bytes().to_vec()))
+        }
+    }
+
+    fn test_migrations(versions: &[DbVersion], fail_at: Option<u64>, migrated: &Arc<Mutex<Vec<(DbVersion, u64)>>>) -> Vec<Box<dyn Migration>> {
+        versions.iter()
+            .map(|version| Box::new(TestMigration { version: *version, batches: 2, fail_at, migrated: migrated.clone() }) as Box<dyn Migration>)
+            .collect()
+    }
+
+    #[test]
+    fn migrate_empty_database() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_empty_database")?;
+        let migrated = Arc::new(Mutex::new(vec![]));
+
+        assert_eq!(0, Migrator::new(3, test_migrations(&[1, 2], None, &migrated)).run(tmp_storage.storage(), &log())?);
+        assert_eq!(Some(3), SystemStorage::new(tmp_storage.storage().kv()).get_db_version()?);
+        assert!(migrated.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_in_order() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_in_order")?;
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+        system_storage.set_db_version(1)?;
+        let migrated = Arc::new(Mutex::new(vec![]));
+
+        assert_eq!(2, Migrator::new(3, test_migrations(&[2, 1], None, &migrated)).run(tmp_storage.storage(), &log())?);
+        assert_eq!(vec![(1, 0), (1, 1), (2, 0), (2, 1)], *migrated.lock().unwrap());
+        assert_eq!(Some(3), system_storage.get_db_version()?);
+        assert_eq!(None, system_storage.get_migration_cursor()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_interrupted_migration() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_resume_interrupted")?;
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+        system_storage.set_db_version(1)?;
+        let migrated = Arc::new(Mutex::new(vec![]));
+
+        assert!(Migrator::new(2, test_migrations(&[1], Some(1), &migrated)).run(tmp_storage.storage(), &log()).is_err());
+        assert_eq!(Some(1), system_storage.get_db_version()?);
+        assert_eq!(Some(0u64.to_be_bytes().to_vec()), system_storage.get_migration_cursor()?);
+
+        assert_eq!(1, Migrator::new(2, test_migrations(&[1], None, &migrated)).run(tmp_storage.storage(), &log())?);
+        assert_eq!(vec![(1, 0), (1, 1)], *migrated.lock().unwrap());
+        assert_eq!(Some(2), system_storage.get_db_version()?);
+        assert_eq!(None, system_storage.get_migration_cursor()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_does_not_modify_database() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_dry_run")?;
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+        system_storage.set_db_version(1)?;
+        let migrated = Arc::new(Mutex::new(vec![]));
+
+        let mut read_only_storage = SystemStorage::new(Arc::new(open_kv_read_only(tmp_storage.path())?));
+        assert_eq!(2, Migrator::new(3, test_migrations(&[1, 2], None, &migrated)).dry_run(&read_only_storage, &log())?);
+        assert!(migrated.lock().unwrap().is_empty());
+        assert_eq!(Some(1), system_storage.get_db_version()?);
+        assert!(read_only_storage.set_db_version(2).is_err());
+
+        Ok(())
+    }
+
+    /// Record of the database version 11: `[mask][predecessor][successor][level][chain_id]`
+    fn block_meta_v11(mask: u8, successor: u8, level: i32) -> Vec<u8> {
+        let mut bytes = vec![mask];
+        bytes.extend_from_slice(&[1; 32]);
+        bytes.extend_from_slice(&[successor; 32]);
+        bytes.extend_from_slice(&level.to_be_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        bytes
+    }
+
+    #[test]
+    fn migrate_block_meta_successors() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_block_meta_successors")?;
+        let storage = tmp_storage.storage();
+        let db = storage.kv();
+        let cf = db.cf_handle(BlockMetaStorage::name()).unwrap();
+        // applied block with predecessor and successor, block without successor
+        db.put_cf(cf, &[2; 32], &block_meta_v11(0b0000_0111, 3, 2))?;
+        db.put_cf(cf, &[3; 32], &block_meta_v11(0b0000_0100, 0, 3))?;
+        SystemStorage::new(db.clone()).set_db_version(11)?;
+
+        // migration resumed after the first block
+        let migration = BlockMetaSuccessorsMigration;
+        let block_meta_storage = BlockMetaStorage::new(storage);
+        assert_eq!(Some(vec![3; 32]), migration.migrate_batch(storage, Some(&[2; 32][..]))?);
+        assert!(block_meta_storage.get(&vec![2; 32]).is_err());
+        assert_eq!(None, migration.migrate_batch(storage, Some(&[3; 32][..]))?);
+
+        assert_eq!(1, Migrator::current().run(storage, &log())?);
+        assert_eq!(Some(DATABASE_VERSION), SystemStorage::new(db.clone()).get_db_version()?);
+        assert_eq!(Some(Meta::new(true, Some(vec![1; 32]), vec![vec![3; 32]], 2, vec![1, 2, 3, 4])), block_meta_storage.get(&vec![2; 32])?);
+        assert_eq!(Some(Meta::new(false, Some(vec![1; 32]), vec![], 3, vec![1, 2, 3, 4])), block_meta_storage.get(&vec![3; 32])?);
+
+        // migrated records are not changed again
+        assert_eq!(Some(vec![3; 32]), migration.migrate_batch(storage, None)?);
+        assert_eq!(Some(Meta::new(true, Some(vec![1; 32]), vec![vec![3; 32]], 2, vec![1, 2, 3, 4])), block_meta_storage.get(&vec![2; 32])?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_database_version() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__migration_unsupported_version")?;
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+        let migrated = Arc::new(Mutex::new(vec![]));
+
+        system_storage.set_db_version(1)?;
+        match Migrator::new(3, test_migrations(&[2], None, &migrated)).run(tmp_storage.storage(), &log()) {
+            Err(MigrationError::MissingMigration { db_version }) => assert_eq!(1, db_version),
+            _ => panic!("Missing migration was expected"),
+        }
+
+        system_storage.set_db_version(4)?;
+        match Migrator::new(3, test_migrations(&[1, 2], None, &migrated)).run(tmp_storage.storage(), &log()) {
+            Err(MigrationError::UnsupportedVersion { db_version, .. }) => assert_eq!(4, db_version),
+            _ => panic!("Unsupported version was expected"),
+        }
+        assert!(migrated.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+}
@@ -73,6 +73,19 @@ impl OperationsMetaStorage {
             .map_err(StorageError::from)
     }
 
+    /// Overwrite the metadata record, flags are not merged with the stored record
+    #[inline]
+    pub fn replace(&mut self, block_hash: &BlockHash, meta: &Meta) -> Result<(), StorageError> {
+        self.kv.put(block_hash, meta)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn delete(&mut self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.delete(block_hash)
+            .map_err(StorageError::from)
+    }
+
     #[inline]
     pub fn get(&self, block_hash: &BlockHash) -> Result<Option<Meta>, StorageError> {
         self.kv.get(block_hash)
@@ -159,6 +172,24 @@ impl Meta {
         }
     }
 
+    /// Metadata of the block, for which the `operations` are stored
+    pub fn with_operations(block_header: &BlockHeaderWithHash, chain_id: &ChainId, operations: &[OperationsForBlocksMessage]) -> Self {
+        let mut is_validation_pass_present = vec![false as u8; block_header.header.validation_pass() as usize];
+        for operation in operations {
+            if let Some(is_present) = is_validation_pass_present.get_mut(operation.operations_for_block().validation_pass() as usize) {
+                *is_present = true as u8;
+            }
+        }
+
+        Meta {
+            validation_passes: block_header.header.validation_pass(),
+            is_complete: is_validation_pass_present.iter().all(|v| *v == (true as u8)),
+            is_validation_pass_present,
+            level: block_header.header.level(),
+            chain_id: chain_id.clone(),
+        }
+    }
+
     pub fn get_missing_validation_passes(&self) -> HashSet<i8> {
         if self.is_complete {
             HashSet::new()
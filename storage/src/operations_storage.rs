@@ -9,8 +9,9 @@ use crypto::hash::{BlockHash, HashType};
 use tezos_messages::p2p::binary_message::BinaryMessage;
 use tezos_messages::p2p::encoding::prelude::*;
 
+use crate::{IteratorMode, StorageError};
 use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage, SchemaError};
-use crate::StorageError;
+use crate::persistent::database::IteratorWithSchema;
 
 pub type OperationsStorageKV = dyn KeyValueStoreWithSchema<OperationsStorage> + Sync + Send;
 
@@ -44,6 +45,26 @@ impl OperationsStorage {
         self.kv.put(key, value)
             .map_err(StorageError::from)
     }
+
+    #[inline]
+    pub fn iter(&self, mode: IteratorMode<Self>) -> Result<IteratorWithSchema<Self>, StorageError> {
+        self.kv.iterator(mode)
+            .map_err(StorageError::from)
+    }
+
+    /// Remove operations of all validation passes of the block
+    pub fn delete_operations(&mut self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        let key = OperationKey {
+            block_hash: block_hash.clone(),
+            validation_pass: 0
+        };
+
+        for (key, _) in self.kv.prefix_iterator(&key)? {
+            self.kv.delete(&key?)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl OperationsStorageReader for OperationsStorage {
@@ -102,6 +123,11 @@ impl OperationKey {
             validation_pass
         }
     }
+
+    #[inline]
+    pub fn block_hash(&self) -> &BlockHash {
+        &self.block_hash
+    }
 }
 
 impl<'a> From<&'a OperationsForBlock> for OperationKey {
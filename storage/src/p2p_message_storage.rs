@@ -1,5 +1,7 @@
 use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
 use crate::persistent::{KeyValueStoreWithSchema, PersistentStorage, KeyValueSchema, Decoder, SchemaError, Encoder};
+use crate::persistent::sequence::Sequences;
+use crate::persistent::database::{Direction, IteratorMode};
 use tezos_messages::p2p::encoding::connection::ConnectionMessage;
 use tezos_messages::p2p::encoding::peer::PeerMessage;
 use serde::{Serialize, Deserialize};
@@ -10,7 +12,7 @@ use crate::p2p_message_storage::rpc_message::P2PRpcMessage;
 use std::net::{SocketAddr, Ipv4Addr, IpAddr, Ipv6Addr};
 use std::time::{SystemTime, UNIX_EPOCH};
 use lazy_static::lazy_static;
-use rocksdb::{ColumnFamilyDescriptor, Options, SliceTransform};
+use rocksdb::{ColumnFamilyDescriptor, DB, Options, SliceTransform};
 
 pub type P2PMessageStorageKV = dyn KeyValueStoreWithSchema<P2PMessageStorage> + Sync + Send;
 
@@ -38,6 +40,15 @@ impl P2PMessageStorage {
         }
     }
 
+    /// Storage of the messages recorded by another node, the database of the node is opened [read only](crate::persistent::open_kv_read_only)
+    pub fn recorded(db: Arc<DB>) -> Self {
+        Self {
+            kv: db.clone(),
+            host_index: P2PMessageSecondaryIndex { kv: db.clone() },
+            seq: Sequences::new(db, 1000).generator("p2p_exp_msg_index_gen"),
+        }
+    }
+
     fn count(&self) -> u64 {
         COUNT.load(Ordering::SeqCst)
     }
@@ -104,6 +115,14 @@ impl P2PMessageStorage {
         Ok(ret)
     }
 
+    /// Return at most `limit` messages starting with the message `from_id`, messages are ordered by their id
+    pub fn get_from(&self, from_id: u64, limit: usize) -> Result<Vec<P2PMessage>, StorageError> {
+        self.kv.iterator(IteratorMode::From(&from_id, Direction::Forward))?
+            .take(limit)
+            .map(|(_, value)| Ok(value?))
+            .collect()
+    }
+
     pub fn get_range_for_host(&self, host: SocketAddr, offset: u64, count: u64) -> Result<Vec<P2PRpcMessage>, StorageError> {
         let idx = self.host_index.get_for_host(host, offset, count)?;
         let mut ret = Vec::with_capacity(idx.len());
@@ -307,6 +326,37 @@ pub enum P2PMessage {
     },
 }
 
+impl P2PMessage {
+    pub fn id(&self) -> u64 {
+        match self {
+            P2PMessage::ConnectionMessage { id, .. } | P2PMessage::P2PMessage { id, .. } | P2PMessage::Metadata { id, .. } => *id,
+        }
+    }
+
+    /// Unix timestamp (nanoseconds) when the message was stored
+    pub fn timestamp(&self) -> u128 {
+        match self {
+            P2PMessage::ConnectionMessage { timestamp, .. } | P2PMessage::P2PMessage { timestamp, .. } | P2PMessage::Metadata { timestamp, .. } => *timestamp,
+        }
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        match self {
+            P2PMessage::ConnectionMessage { remote_addr, .. } | P2PMessage::P2PMessage { remote_addr, .. } | P2PMessage::Metadata { remote_addr, .. } => *remote_addr,
+        }
+    }
+
+    /// Returns true if the message was received from the remote peer.
+    ///
+    /// Peer messages are stored with the `incoming` flag inverted, i.e. received peer messages have the flag unset.
+    pub fn is_received(&self) -> bool {
+        match self {
+            P2PMessage::ConnectionMessage { incoming, .. } | P2PMessage::Metadata { incoming, .. } => *incoming,
+            P2PMessage::P2PMessage { incoming, .. } => !*incoming,
+        }
+    }
+}
+
 impl Decoder for P2PMessage {
     fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
         bincode::deserialize(bytes)
@@ -420,8 +470,8 @@ pub mod rpc_message {
         Disconnect,
         Bootstrap,
         Advertise(AdvertiseMessage),
-        SwapRequest(SwapMessage),
-        SwapAck(SwapMessage),
+        SwapRequest(MappedSwapMessage),
+        SwapAck(MappedSwapMessage),
         GetCurrentBranch(MappedGetCurrentBranchMessage),
         CurrentBranch(MappedCurrentBranchMessage),
         Deactivate(MappedDeactivateMessage),
@@ -446,8 +496,8 @@ pub mod rpc_message {
                 PeerMessage::Disconnect => MappedPeerMessage::Disconnect,
                 PeerMessage::Bootstrap => MappedPeerMessage::Bootstrap,
                 PeerMessage::Advertise(msg) => MappedPeerMessage::Advertise(msg),
-                PeerMessage::SwapRequest(msg) => MappedPeerMessage::SwapRequest(msg),
-                PeerMessage::SwapAck(msg) => MappedPeerMessage::SwapAck(msg),
+                PeerMessage::SwapRequest(msg) => MappedPeerMessage::SwapRequest(msg.into()),
+                PeerMessage::SwapAck(msg) => MappedPeerMessage::SwapAck(msg.into()),
                 PeerMessage::GetCurrentBranch(msg) => MappedPeerMessage::GetCurrentBranch(msg.into()),
                 PeerMessage::CurrentBranch(msg) => MappedPeerMessage::CurrentBranch(msg.into()),
                 PeerMessage::Deactivate(msg) => MappedPeerMessage::Deactivate(msg.into()),
@@ -692,6 +742,21 @@ pub mod rpc_message {
         }
     }
 
+    #[derive(Debug, Serialize)]
+    pub struct MappedSwapMessage {
+        point: String,
+        peer_id: String,
+    }
+
+    impl From<SwapMessage> for MappedSwapMessage {
+        fn from(value: SwapMessage) -> Self {
+            Self {
+                point: value.point().clone(),
+                peer_id: HashType::CryptoboxPublicKeyHash.hash_to_string(value.peer_id()),
+            }
+        }
+    }
+
     #[derive(Debug, Serialize)]
     pub struct MappedDeactivateMessage {
         deactivate: String,
@@ -776,4 +841,41 @@ pub mod rpc_message {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use crate::tests_common::TmpStorage;
+
+    use super::*;
+
+    #[test]
+    fn p2p_message_storage_get_from_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__p2p_message_storage_get_from_test")?;
+        let mut storage = P2PMessageStorage::new(tmp_storage.storage());
+
+        let addr: SocketAddr = "10.0.0.1:9732".parse()?;
+        storage.store_metadata_message(&MetadataMessage::new(false, false), true, addr)?;
+        storage.store_peer_message(&vec![PeerMessage::Bootstrap], false, addr)?;
+        storage.store_peer_message(&vec![PeerMessage::Disconnect], true, addr)?;
+
+        let messages = storage.get_from(0, 10)?;
+        assert_eq!(3, messages.len());
+        assert!(messages.iter().all(|message| message.remote_addr() == addr));
+        assert!(messages[0].is_received());
+        assert!(messages[1].is_received());
+        assert!(!messages[2].is_received());
+        assert!(messages[0].id() < messages[1].id() && messages[1].id() < messages[2].id());
+
+        let messages = storage.get_from(messages[1].id(), 1)?;
+        assert_eq!(1, messages.len());
+        match &messages[0] {
+            P2PMessage::P2PMessage { message, .. } => assert_eq!(1, message.len()),
+            _ => panic!("Expected peer message"),
+        }
+
+        Ok(())
+    }
+}
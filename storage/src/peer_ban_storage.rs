@@ -0,0 +1,143 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage};
+use crate::persistent::database::IteratorMode;
+use crate::StorageError;
+
+pub type PeerBanStorageKV = dyn KeyValueStoreWithSchema<PeerBanStorage> + Sync + Send;
+
+/// Peer can be banned by its IP address or by its peer id (public key hash)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BanKey {
+    Ip(IpAddr),
+    PeerId(String),
+}
+
+impl BincodeEncoded for BanKey {}
+
+/// Ban record, which is kept in the storage even after the ban expires,
+/// so the ban duration can grow for repeated offenders
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerBan {
+    /// Unix timestamp (seconds) until which the peer is banned
+    pub banned_until: i64,
+    /// How many times the peer was banned
+    pub ban_count: u32,
+    /// Reason of the last ban
+    pub reason: String,
+}
+
+impl PeerBan {
+    #[inline]
+    pub fn is_active(&self, now: i64) -> bool {
+        self.banned_until > now
+    }
+}
+
+impl BincodeEncoded for PeerBan {}
+
+/// Storage of the banned peers, so bans survive node restarts
+#[derive(Clone)]
+pub struct PeerBanStorage {
+    kv: Arc<PeerBanStorageKV>
+}
+
+impl PeerBanStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self { kv: persistent_storage.kv() }
+    }
+
+    #[inline]
+    pub fn put(&mut self, key: &BanKey, ban: &PeerBan) -> Result<(), StorageError> {
+        self.kv.put(key, ban)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get(&self, key: &BanKey) -> Result<Option<PeerBan>, StorageError> {
+        self.kv.get(key)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn delete(&mut self, key: &BanKey) -> Result<(), StorageError> {
+        self.kv.delete(key)
+            .map_err(StorageError::from)
+    }
+
+    /// Check if there is an active ban for the key
+    #[inline]
+    pub fn is_banned(&self, key: &BanKey, now: i64) -> Result<bool, StorageError> {
+        Ok(self.get(key)?.filter(|ban| ban.is_active(now)).is_some())
+    }
+
+    /// Lift the ban, but keep the ban count, so repeated offences are still punished harder
+    pub fn unban(&mut self, key: &BanKey, now: i64) -> Result<(), StorageError> {
+        match self.get(key)? {
+            Some(mut ban) => {
+                ban.banned_until = now;
+                self.put(key, &ban)
+            }
+            None => Ok(())
+        }
+    }
+
+    /// Return all ban records
+    pub fn get_all(&self) -> Result<Vec<(BanKey, PeerBan)>, StorageError> {
+        self.kv.iterator(IteratorMode::Start)?
+            .map(|(key, value)| Ok((key?, value?)))
+            .collect()
+    }
+}
+
+impl KeyValueSchema for PeerBanStorage {
+    type Key = BanKey;
+    type Value = PeerBan;
+
+    #[inline]
+    fn name() -> &'static str {
+        "peer_ban_storage"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use crate::tests_common::TmpStorage;
+
+    use super::*;
+
+    #[test]
+    fn peer_ban_storage_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__peer_ban_storage_test")?;
+        let mut storage = PeerBanStorage::new(tmp_storage.storage());
+
+        let ip_key = BanKey::Ip("10.0.0.1".parse()?);
+        let peer_id_key = BanKey::PeerId("idtqxHUjbjbCfaDn4jczoPGsnhacKX".to_string());
+        let ban = PeerBan { banned_until: 1_000, ban_count: 1, reason: "test".to_string() };
+        storage.put(&ip_key, &ban)?;
+        storage.put(&peer_id_key, &ban)?;
+
+        assert!(storage.is_banned(&ip_key, 999)?);
+        assert!(!storage.is_banned(&ip_key, 1_000)?);
+        assert!(!storage.is_banned(&BanKey::Ip("10.0.0.2".parse()?), 999)?);
+        assert_eq!(2, storage.get_all()?.len());
+
+        storage.unban(&peer_id_key, 500)?;
+        assert!(!storage.is_banned(&peer_id_key, 500)?);
+        assert_eq!(Some(1), storage.get(&peer_id_key)?.map(|ban| ban.ban_count));
+
+        storage.delete(&ip_key)?;
+        assert_eq!(None, storage.get(&ip_key)?);
+        assert_eq!(1, storage.get_all()?.len());
+
+        Ok(())
+    }
+}
@@ -78,6 +78,12 @@ pub trait KeyValueStoreWithSchema<S: KeyValueSchema> {
     /// # Arguments
     /// * `key` - Key (specified by schema), to be checked for existence
     fn contains(&self, key: &S::Key) -> Result<bool, DBError>;
+
+    /// Delete existing value associated with given key from the database.
+    ///
+    /// # Arguments
+    /// * `key` - Value of key specified by schema
+    fn delete(&self, key: &S::Key) -> Result<(), DBError>;
 }
 
 impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
@@ -152,6 +158,15 @@ impl<S: KeyValueSchema> KeyValueStoreWithSchema<S> for DB {
 
         Ok(contains)
     }
+
+    fn delete(&self, key: &S::Key) -> Result<(), DBError> {
+        let key = key.encode()?;
+        let cf = self.cf_handle(S::name())
+            .ok_or(DBError::MissingColumnFamily { name: S::name() })?;
+
+        self.delete_cf_opt(cf, &key, &default_write_options())
+            .map_err(DBError::from)
+    }
 }
 
 fn default_write_options() -> WriteOptions {
@@ -35,6 +35,19 @@ pub fn open_kv<P, I>(path: P, cfs: I) -> Result<DB, DBError>
         .map_err(DBError::from)
 }
 
+/// Open existing RocksDB database at given path for reading only
+///
+/// All column families of the database are opened, any write to the database fails.
+pub fn open_kv_read_only<P>(path: P) -> Result<DB, DBError>
+    where
+        P: AsRef<Path>,
+{
+    let db_opts = Options::default();
+    let cfs = DB::list_cf(&db_opts, &path)?;
+    DB::open_cf_for_read_only(&db_opts, path, cfs, false)
+        .map_err(DBError::from)
+}
+
 /// Create default database configuration options
 fn default_kv_options() -> Options {
     let mut db_opts = Options::default();
@@ -0,0 +1,191 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage};
+use crate::persistent::database::IteratorMode;
+use crate::StorageError;
+
+pub type PointStorageKV = dyn KeyValueStoreWithSchema<PointStorage> + Sync + Send;
+
+impl BincodeEncoded for SocketAddr {}
+
+/// Trust level of the point
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PointTrust {
+    /// Point learned from DNS lookup or from other peers
+    Default,
+    /// Point configured by the node operator, it is never greylisted
+    Trusted,
+}
+
+/// Everything we know about the point (address of a remote node, where it accepts p2p connections)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PointInfo {
+    /// Peer id of the node, which was connected at this point the last time
+    pub peer_id: Option<String>,
+    /// Unix timestamp (seconds), when the point was last advertised or connected
+    pub last_seen: i64,
+    /// Unix timestamp (seconds) of the last successful connection
+    pub last_established: Option<i64>,
+    /// Unix timestamp (seconds) of the last failed connection
+    pub last_failed: Option<i64>,
+    /// Count of failed connections since the last successful one
+    pub failed_connections: u32,
+    pub trust: PointTrust,
+    /// Unix timestamp (seconds) until which we should not connect to the point
+    pub greylisted_until: i64,
+    /// Node at the point told us, that it is private, so the point must not be advertised to other peers
+    pub private_node: bool,
+}
+
+impl PointInfo {
+    pub fn new(now: i64) -> Self {
+        PointInfo {
+            peer_id: None,
+            last_seen: now,
+            last_established: None,
+            last_failed: None,
+            failed_connections: 0,
+            trust: PointTrust::Default,
+            greylisted_until: 0,
+            private_node: false,
+        }
+    }
+
+    #[inline]
+    pub fn is_trusted(&self) -> bool {
+        self.trust == PointTrust::Trusted
+    }
+
+    #[inline]
+    pub fn is_greylisted(&self, now: i64) -> bool {
+        !self.is_trusted() && self.greylisted_until > now
+    }
+
+    pub fn connection_established(&mut self, peer_id: String, private_node: bool, now: i64) {
+        self.peer_id = Some(peer_id);
+        self.private_node = private_node;
+        self.last_seen = now;
+        self.last_established = Some(now);
+        self.failed_connections = 0;
+        self.greylisted_until = 0;
+    }
+
+    /// Record failed connection, point will not be connected for `greylist_duration` seconds
+    pub fn connection_failed(&mut self, greylist_duration: i64, now: i64) {
+        self.last_failed = Some(now);
+        self.failed_connections = self.failed_connections.saturating_add(1);
+        self.greylisted_until = now + greylist_duration;
+    }
+}
+
+impl BincodeEncoded for PointInfo {}
+
+/// Address book of the known points, so the node can reconnect after restart without the DNS lookup
+#[derive(Clone)]
+pub struct PointStorage {
+    kv: Arc<PointStorageKV>
+}
+
+impl PointStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self { kv: persistent_storage.kv() }
+    }
+
+    #[inline]
+    pub fn put(&mut self, point: &SocketAddr, info: &PointInfo) -> Result<(), StorageError> {
+        self.kv.put(point, info)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get(&self, point: &SocketAddr) -> Result<Option<PointInfo>, StorageError> {
+        self.kv.get(point)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn delete(&mut self, point: &SocketAddr) -> Result<(), StorageError> {
+        self.kv.delete(point)
+            .map_err(StorageError::from)
+    }
+
+    /// Apply `update` to the stored point info. Point, which is not known yet, is created.
+    pub fn update<F>(&mut self, point: &SocketAddr, now: i64, update: F) -> Result<PointInfo, StorageError>
+        where
+            F: FnOnce(&mut PointInfo)
+    {
+        let mut info = self.get(point)?.unwrap_or_else(|| PointInfo::new(now));
+        update(&mut info);
+        self.put(point, &info)?;
+        Ok(info)
+    }
+
+    /// Return all known points
+    pub fn get_all(&self) -> Result<Vec<(SocketAddr, PointInfo)>, StorageError> {
+        self.kv.iterator(IteratorMode::Start)?
+            .map(|(key, value)| Ok((key?, value?)))
+            .collect()
+    }
+}
+
+impl KeyValueSchema for PointStorage {
+    type Key = SocketAddr;
+    type Value = PointInfo;
+
+    #[inline]
+    fn name() -> &'static str {
+        "point_storage"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use crate::tests_common::TmpStorage;
+
+    use super::*;
+
+    #[test]
+    fn point_storage_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__point_storage_test")?;
+        let mut storage = PointStorage::new(tmp_storage.storage());
+
+        let point: SocketAddr = "10.0.0.1:9732".parse()?;
+        let ipv6_point: SocketAddr = "[fe80::e828:209d:20e:c0ae]:9732".parse()?;
+        assert_eq!(None, storage.get(&point)?);
+
+        storage.update(&point, 100, |_| ())?;
+        assert_eq!(Some(PointInfo::new(100)), storage.get(&point)?);
+
+        let info = storage.update(&point, 200, |info| info.connection_failed(60, 200))?;
+        assert_eq!(1, info.failed_connections);
+        assert!(info.is_greylisted(259));
+        assert!(!info.is_greylisted(260));
+
+        let info = storage.update(&point, 300, |info| info.connection_established("idtqxHUjbjbCfaDn4jczoPGsnhacKX".to_string(), true, 300))?;
+        assert_eq!(0, info.failed_connections);
+        assert!(info.private_node);
+        assert_eq!(Some(300), info.last_established);
+        assert_eq!(Some(200), info.last_failed);
+
+        storage.update(&ipv6_point, 400, |info| {
+            info.trust = PointTrust::Trusted;
+            info.connection_failed(60, 400);
+        })?;
+        assert!(!storage.get(&ipv6_point)?.unwrap().is_greylisted(400));
+        assert_eq!(2, storage.get_all()?.len());
+
+        storage.delete(&point)?;
+        assert_eq!(None, storage.get(&point)?);
+        assert_eq!(1, storage.get_all()?.len());
+
+        Ok(())
+    }
+}
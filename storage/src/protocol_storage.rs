@@ -0,0 +1,118 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::sync::Arc;
+
+use crypto::hash::ProtocolHash;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::persistent::{Decoder, Encoder, KeyValueSchema, KeyValueStoreWithSchema, PersistentStorage, SchemaError};
+use crate::persistent::database::IteratorMode;
+use crate::StorageError;
+
+pub type ProtocolStorageKV = dyn KeyValueStoreWithSchema<ProtocolStorage> + Sync + Send;
+
+pub trait ProtocolStorageReader: Sync + Send {
+    fn get(&self, protocol_hash: &ProtocolHash) -> Result<Option<Protocol>, StorageError>;
+
+    fn contains(&self, protocol_hash: &ProtocolHash) -> Result<bool, StorageError>;
+
+    fn get_protocol_hashes(&self) -> Result<Vec<ProtocolHash>, StorageError>;
+}
+
+/// Storage of the protocol sources, protocols are indexed by protocol hash
+#[derive(Clone)]
+pub struct ProtocolStorage {
+    kv: Arc<ProtocolStorageKV>
+}
+
+impl ProtocolStorage {
+    pub fn new(persistent_storage: &PersistentStorage) -> Self {
+        Self { kv: persistent_storage.kv() }
+    }
+
+    #[inline]
+    pub fn put(&mut self, protocol_hash: &ProtocolHash, protocol: &Protocol) -> Result<(), StorageError> {
+        self.kv.put(protocol_hash, protocol)
+            .map_err(StorageError::from)
+    }
+}
+
+impl ProtocolStorageReader for ProtocolStorage {
+    #[inline]
+    fn get(&self, protocol_hash: &ProtocolHash) -> Result<Option<Protocol>, StorageError> {
+        self.kv.get(protocol_hash)
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    fn contains(&self, protocol_hash: &ProtocolHash) -> Result<bool, StorageError> {
+        self.kv.contains(protocol_hash)
+            .map_err(StorageError::from)
+    }
+
+    fn get_protocol_hashes(&self) -> Result<Vec<ProtocolHash>, StorageError> {
+        self.kv.iterator(IteratorMode::Start)?
+            .map(|(key, _)| key.map_err(StorageError::from))
+            .collect()
+    }
+}
+
+impl KeyValueSchema for ProtocolStorage {
+    type Key = ProtocolHash;
+    type Value = Protocol;
+
+    #[inline]
+    fn name() -> &'static str {
+        "protocol_storage"
+    }
+}
+
+impl Decoder for Protocol {
+    #[inline]
+    fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        Protocol::from_bytes(bytes.to_vec())
+            .map_err(|_| SchemaError::DecodeError)
+    }
+}
+
+impl Encoder for Protocol {
+    #[inline]
+    fn encode(&self) -> Result<Vec<u8>, SchemaError> {
+        self.as_bytes()
+            .map_err(|_| SchemaError::EncodeError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+
+    use tezos_messages::p2p::binary_message::MessageHash;
+
+    use crate::tests_common::TmpStorage;
+
+    use super::*;
+
+    #[test]
+    fn protocol_storage_test() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__protocol_storage_test")?;
+        let mut storage = ProtocolStorage::new(tmp_storage.storage());
+
+        let protocol = Protocol::new(0, vec![Component::new("Main".to_string(), None, "let x = 1".to_string())]);
+        let protocol_hash = protocol.message_hash()?;
+        assert!(!storage.contains(&protocol_hash)?);
+
+        storage.put(&protocol_hash, &protocol)?;
+        assert!(storage.contains(&protocol_hash)?);
+        assert_eq!(vec![protocol_hash.clone()], storage.get_protocol_hashes()?);
+
+        let stored = storage.get(&protocol_hash)?.expect("Protocol should be stored");
+        assert_eq!(1, stored.components().len());
+        assert_eq!("Main", stored.components()[0].name());
+        assert_eq!(protocol_hash, stored.message_hash()?);
+
+        Ok(())
+    }
+}
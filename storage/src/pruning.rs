@@ -0,0 +1,393 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Removes the history of old blocks according to the [`HistoryMode`].
+//!
+//! * [`HistoryMode::Archive`] keeps everything.
+//! * [`HistoryMode::Full`] removes context of the blocks older than the retained cycles, so context based RPCs
+//!   are available only for the recent blocks. Blocks and operations are kept, so the chain can still be validated and served to peers.
+//! * [`HistoryMode::Rolling`] removes also operations of the old blocks together with their operations metadata,
+//!   so the pruned blocks are not reported as blocks with the complete operations.
+//!
+//! All blocks at the pruned levels are pruned, including the blocks of the forks.
+//!
+//! Block headers and context action records are stored in the append only commit logs. Commit logs are never compacted
+//! or rotated, so the records are kept in every mode and only their indexes are removed, pruning does not reclaim
+//! the disk space used by the commit logs.
+//!
+//! History mode of the node is stored in the [`SystemStorage`], removed history can not be restored, so the database
+//! can not be used by a node in the mode, which keeps more history, see [`check_history_mode`].
+
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+
+use failure::Fail;
+
+use crypto::hash::BlockHash;
+
+use crate::{BlockMetaStorage, ContextActionStorage, OperationsMetaStorage, OperationsStorage, StorageError, SystemStorage};
+use crate::block_storage::BlockLevel;
+use crate::persistent::{ContextList, PersistentStorage};
+use crate::persistent::database::IteratorMode;
+use crate::skip_list::SkipListError;
+
+/// How much of the history is kept by the node
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HistoryMode {
+    Archive,
+    Full,
+    Rolling,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseHistoryModeError(String);
+
+impl FromStr for HistoryMode {
+    type Err = ParseHistoryModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "archive" => Ok(HistoryMode::Archive),
+            "full" => Ok(HistoryMode::Full),
+            "rolling" => Ok(HistoryMode::Rolling),
+            _ => Err(ParseHistoryModeError(format!("Invalid variant name: {}", s)))
+        }
+    }
+}
+
+impl HistoryMode {
+    /// Higher value means that more history is kept
+    fn retained_history(&self) -> u8 {
+        match self {
+            HistoryMode::Archive => 2,
+            HistoryMode::Full => 1,
+            HistoryMode::Rolling => 0,
+        }
+    }
+}
+
+impl fmt::Display for HistoryMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryMode::Archive => write!(f, "archive"),
+            HistoryMode::Full => write!(f, "full"),
+            HistoryMode::Rolling => write!(f, "rolling"),
+        }
+    }
+}
+
+/// Level of the first block of the oldest retained cycle, blocks below this level can be pruned.
+///
+/// The current cycle and `retained_cycles` preceding cycles are retained.
+pub fn cutoff_level(head_level: BlockLevel, retained_cycles: i32, blocks_per_cycle: i32) -> BlockLevel {
+    let current_cycle = head_level / blocks_per_cycle;
+    cmp::max(current_cycle - retained_cycles, 0) * blocks_per_cycle
+}
+
+/// Check that the database can be used by the node in the history `mode` and store the mode.
+///
+/// Database, which was already pruned, can not be used in the mode keeping more history than the mode, which pruned it.
+pub fn check_history_mode(system_storage: &mut SystemStorage, mode: HistoryMode) -> Result<(), PruningError> {
+    if let Some(db_mode) = system_storage.get_history_mode()? {
+        if db_mode == mode {
+            return Ok(());
+        }
+        let pruned = system_storage.get_pruned_level()?.unwrap_or(0) > 0;
+        if pruned && mode.retained_history() > db_mode.retained_history() {
+            return Err(PruningError::IncompatibleHistoryMode { db_mode, mode });
+        }
+    }
+    Ok(system_storage.set_history_mode(mode)?)
+}
+
+/// Possible errors for history pruning
+#[derive(Debug, Fail)]
+pub enum PruningError {
+    #[fail(display = "Database was pruned in the {} history mode, it can not be used in the {} history mode", db_mode, mode)]
+    IncompatibleHistoryMode {
+        db_mode: HistoryMode,
+        mode: HistoryMode,
+    },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError {
+        error: StorageError
+    },
+    #[fail(display = "Failed to prune context: {}", error)]
+    ContextError {
+        error: SkipListError
+    },
+}
+
+impl From<StorageError> for PruningError {
+    fn from(error: StorageError) -> Self {
+        PruningError::StorageError { error }
+    }
+}
+
+impl From<SkipListError> for PruningError {
+    fn from(error: SkipListError) -> Self {
+        PruningError::ContextError { error }
+    }
+}
+
+impl slog::Value for PruningError {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Prunes blocks level by level, progress is stored in the [`SystemStorage`], so pruning continues after restart.
+///
+/// All blocks at the level are pruned, including the blocks of the forks, which are found by the successors in the block metadata.
+pub struct HistoryPruner {
+    mode: HistoryMode,
+    block_meta_storage: BlockMetaStorage,
+    operations_storage: OperationsStorage,
+    operations_meta_storage: OperationsMetaStorage,
+    context_action_storage: ContextActionStorage,
+    context_list: ContextList,
+    system_storage: SystemStorage,
+    /// Blocks at the pruned level, which are pruned by the next batch
+    pruned_level_blocks: Option<(BlockLevel, Vec<BlockHash>)>,
+}
+
+impl HistoryPruner {
+    pub fn new(persistent_storage: &PersistentStorage, mode: HistoryMode) -> Self {
+        Self {
+            mode,
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
+            context_action_storage: ContextActionStorage::new(persistent_storage),
+            context_list: persistent_storage.context_storage(),
+            system_storage: SystemStorage::new(persistent_storage.kv()),
+            pruned_level_blocks: None,
+        }
+    }
+
+    /// All blocks below this level are already pruned
+    pub fn pruned_level(&self) -> Result<BlockLevel, StorageError> {
+        self.system_storage.get_pruned_level()
+            .map(|level| level.unwrap_or(0))
+    }
+
+    /// Prune at most `limit` levels below the `cutoff_level` and return the new pruned level.
+    ///
+    /// Only blocks with the stored context are pruned, so the context can still be rebuilt for the `cutoff_level`.
+    pub fn prune(&mut self, cutoff_level: BlockLevel, limit: i32) -> Result<BlockLevel, PruningError> {
+        let pruned_level = self.pruned_level()?;
+        if self.mode == HistoryMode::Archive {
+            return Ok(pruned_level);
+        }
+
+        let context_levels = self.context_list.read().expect("lock poisoning").len() as BlockLevel;
+        let target_level = cmp::min(cmp::min(cutoff_level, pruned_level.saturating_add(limit)), context_levels - 1);
+        if target_level <= pruned_level {
+            return Ok(pruned_level);
+        }
+
+        let mut blocks = self.blocks_at_level(pruned_level)?;
+        for level in pruned_level..target_level {
+            for block_hash in &blocks {
+                self.context_action_storage.delete_by_block_hash(block_hash)?;
+                if self.mode == HistoryMode::Rolling {
+                    // operations are not complete anymore, so the metadata is removed with them
+                    self.operations_storage.delete_operations(block_hash)?;
+                    self.operations_meta_storage.delete(block_hash)?;
+                }
+            }
+            blocks = self.successors_at_level(&blocks, level + 1)?;
+        }
+        self.pruned_level_blocks = Some((target_level, blocks));
+
+        // context list is shared with the context listener, so it is locked only for its own pruning
+        self.context_list.write().expect("lock poisoning").prune(pruned_level as usize, target_level as usize)?;
+        self.system_storage.set_pruned_level(target_level)?;
+
+        Ok(target_level)
+    }
+
+    /// All blocks at the `level`. Blocks are known from the previous batch, otherwise (e.g. after restart) they are searched in the block metadata.
+    fn blocks_at_level(&mut self, level: BlockLevel) -> Result<Vec<BlockHash>, StorageError> {
+        match self.pruned_level_blocks.take() {
+            Some((pruned_level, blocks)) if pruned_level == level => Ok(blocks),
+            _ => Ok(
+                self.block_meta_storage.iter(IteratorMode::Start)?
+                    .filter_map(|(block_hash, meta)| block_hash.and_then(|block_hash| meta.map(|meta| (block_hash, meta))).ok())
+                    .filter(|(_, meta)| meta.level() == level)
+                    .map(|(block_hash, _)| block_hash)
+                    .collect()
+            ),
+        }
+    }
+
+    /// Successors of all the `blocks` at the `level`, so the forks are followed too
+    fn successors_at_level(&self, blocks: &[BlockHash], level: BlockLevel) -> Result<Vec<BlockHash>, StorageError> {
+        let mut successors: Vec<BlockHash> = vec![];
+        for block_hash in blocks {
+            if let Some(meta) = self.block_meta_storage.get(block_hash)? {
+                for successor in meta.successors() {
+                    // genesis is its own successor
+                    if successors.contains(successor) || blocks.contains(successor) {
+                        continue;
+                    }
+                    if self.block_meta_storage.get(successor)?.filter(|meta| meta.level() == level).is_some() {
+                        successors.push(successor.clone());
+                    }
+                }
+            }
+        }
+        Ok(successors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+    use maplit::hashmap;
+
+    use tezos_context::channel::ContextAction;
+    use tezos_messages::p2p::encoding::prelude::*;
+
+    use crate::{BlockHeaderWithHash, BlockStorage, BlockStorageReader, OperationsStorageReader};
+    use crate::skip_list::Bucket;
+    use crate::tests_common::{block, TmpStorage};
+
+    use super::*;
+
+    #[test]
+    fn test_cutoff_level() {
+        assert_eq!(0, cutoff_level(0, 8, 2048));
+        assert_eq!(0, cutoff_level(8 * 2048 + 100, 8, 2048));
+        assert_eq!(2048, cutoff_level(9 * 2048, 8, 2048));
+        assert_eq!(2 * 2048, cutoff_level(10 * 2048 + 2047, 8, 2048));
+    }
+
+    #[test]
+    fn test_parse_history_mode() {
+        assert_eq!(HistoryMode::Archive, "archive".parse::<HistoryMode>().unwrap());
+        assert_eq!(HistoryMode::Full, "Full".parse::<HistoryMode>().unwrap());
+        assert_eq!(HistoryMode::Rolling, "rolling".parse::<HistoryMode>().unwrap());
+        assert!("experimental".parse::<HistoryMode>().is_err());
+    }
+
+    #[test]
+    fn test_prune_in_batches() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__pruning_in_batches")?;
+        let context_list = tmp_storage.storage().context_storage();
+        for level in 0..100u8 {
+            context_list.write().expect("lock poisoning").push(&hashmap! { level.to_string() => Bucket::Exists(vec![level]) })?;
+        }
+
+        let mut pruner = HistoryPruner::new(tmp_storage.storage(), HistoryMode::Full);
+        assert_eq!(30, pruner.prune(80, 30)?);
+        assert_eq!(60, pruner.prune(80, 30)?);
+        assert_eq!(80, pruner.prune(80, 30)?);
+        assert_eq!(80, pruner.prune(80, 30)?);
+        assert_eq!(80, pruner.pruned_level()?);
+
+        let context_list = context_list.read().expect("lock poisoning");
+        assert_eq!(Some(Bucket::Exists(vec![50])), context_list.get_key(99, &"50".to_string())?);
+        assert_eq!(Some(100), context_list.get(99)?.map(|context| context.len()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_rolling() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__pruning_rolling")?;
+        let storage = tmp_storage.storage();
+        let chain_id = vec![1, 2, 3, 4];
+        let mut block_storage = BlockStorage::new(storage);
+        let mut block_meta_storage = BlockMetaStorage::new(storage);
+        let mut operations_storage = OperationsStorage::new(storage);
+        let mut operations_meta_storage = OperationsMetaStorage::new(storage);
+        let mut context_action_storage = ContextActionStorage::new(storage);
+        let context_list = storage.context_storage();
+
+        let mut blocks: Vec<BlockHeaderWithHash> = vec![];
+        for level in 0..12 {
+            let predecessor = match level {
+                // fork of the block at level 2
+                10 => blocks[2].hash.clone(),
+                11 => blocks[10].hash.clone(),
+                _ => blocks.last().map(|block| block.hash.clone()).unwrap_or_else(|| vec![0; 32]),
+            };
+            let block = match level {
+                10 | 11 => block(&predecessor, level - 7, level as u8, 1),
+                _ => block(&predecessor, level, level as u8, 1),
+            };
+            let operations = OperationsForBlocksMessage::new(OperationsForBlock::new(block.hash.clone(), 0), Path::Op, vec![]);
+            block_storage.put_block_header(&block)?;
+            block_meta_storage.put_block_header(&block, &chain_id)?;
+            operations_meta_storage.put_block_header(&block, &chain_id)?;
+            operations_meta_storage.put_operations(&operations)?;
+            operations_storage.put_operations(&operations)?;
+            context_action_storage.put_action(&block.hash, ContextAction::Commit {
+                parent_context_hash: None,
+                block_hash: Some(block.hash.clone()),
+                new_context_hash: block.header.context().clone(),
+                start_time: 0.0,
+                end_time: 0.0,
+            })?;
+            if level < 10 {
+                context_list.write().expect("lock poisoning").push(&hashmap! { level.to_string() => Bucket::Exists(vec![level as u8]) })?;
+            }
+            blocks.push(block);
+        }
+
+        let mut pruner = HistoryPruner::new(storage, HistoryMode::Rolling);
+        assert_eq!(4, pruner.prune(5, 4)?);
+        // blocks at the pruned level are found again after restart
+        let mut pruner = HistoryPruner::new(storage, HistoryMode::Rolling);
+        assert_eq!(5, pruner.prune(5, 4)?);
+
+        // blocks of the fork are pruned too
+        let pruned_blocks = blocks[..5].iter().chain(&blocks[10..]);
+        for block in pruned_blocks {
+            assert!(block_storage.get(&block.hash)?.is_some());
+            assert!(operations_storage.get_operations(&block.hash)?.is_empty());
+            assert!(operations_meta_storage.get(&block.hash)?.is_none());
+            assert!(!operations_meta_storage.is_complete(&block.hash)?);
+            assert!(context_action_storage.get_by_block_hash(&block.hash)?.is_empty());
+        }
+        for block in &blocks[5..10] {
+            assert_eq!(1, operations_storage.get_operations(&block.hash)?.len());
+            assert!(operations_meta_storage.is_complete(&block.hash)?);
+            assert_eq!(1, context_action_storage.get_by_block_hash(&block.hash)?.len());
+        }
+        let context_list = context_list.read().expect("lock poisoning");
+        assert_eq!(Some(10), context_list.get(9)?.map(|context| context.len()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_history_mode() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__pruning_check_history_mode")?;
+        let mut system_storage = SystemStorage::new(tmp_storage.storage().kv());
+
+        // mode can be changed until the database is pruned
+        check_history_mode(&mut system_storage, HistoryMode::Rolling)?;
+        check_history_mode(&mut system_storage, HistoryMode::Archive)?;
+        check_history_mode(&mut system_storage, HistoryMode::Rolling)?;
+        assert_eq!(Some(HistoryMode::Rolling), system_storage.get_history_mode()?);
+
+        system_storage.set_pruned_level(10)?;
+        check_history_mode(&mut system_storage, HistoryMode::Rolling)?;
+        assert!(check_history_mode(&mut system_storage, HistoryMode::Full).is_err());
+        assert!(check_history_mode(&mut system_storage, HistoryMode::Archive).is_err());
+        assert_eq!(Some(HistoryMode::Rolling), system_storage.get_history_mode()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_is_not_pruned() -> Result<(), Error> {
+        let tmp_storage = TmpStorage::create("__pruning_archive")?;
+        let mut pruner = HistoryPruner::new(tmp_storage.storage(), HistoryMode::Archive);
+        assert_eq!(0, pruner.prune(80, 30)?);
+        Ok(())
+    }
+}
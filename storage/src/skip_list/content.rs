@@ -141,6 +141,15 @@ impl ListValue {
 
         Ok(())
     }
+
+    /// Remove all values stored in this container
+    pub fn delete(&self) -> Result<(), SkipListError> {
+        for (key, _) in self.db.prefix_iterator(&ListValueKey::from_id(self.id))? {
+            self.db.delete(&ListValueKey::new(self.id, &key?.key))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl KeyValueSchema for ListValue {
@@ -78,6 +78,16 @@ impl Lane {
 
         Ok(ListValue::new(value_id, self.value_db.clone()))
     }
+
+    /// Remove node at the index together with its values, missing node is ignored
+    pub fn delete_list_value(&mut self, index: usize) -> Result<(), SkipListError> {
+        if let Some(list_value) = self.get_list_value(index)? {
+            list_value.delete()?;
+            self.lane_db.delete(&self.node_header(index))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl KeyValueSchema for Lane {
@@ -132,6 +132,16 @@ pub trait SkipList {
     fn levels(&self) -> usize;
 
     fn contains(&self, index: usize) -> bool;
+
+    /// Remove nodes, which are not required to rebuild state at the `cutoff` index or at any later index.
+    ///
+    /// Nodes, which were not required for the `previous_cutoff`, are expected to be removed already.
+    /// The `cutoff` must be lower than the length of the list, because the push of the next index requires preceding nodes.
+    fn prune(&mut self, previous_cutoff: usize, cutoff: usize) -> Result<(), SkipListError>;
+
+    /// Start the empty list at the `index`, as if it contained empty values at all preceding indexes, which were already pruned
+    /// (e.g. when the context of a single block is imported). Next pushed value is stored at the `index`.
+    fn start_at(&mut self, index: usize) -> Result<(), SkipListError>;
 }
 
 impl SkipList for DatabaseBackedSkipList {
@@ -151,6 +161,66 @@ impl SkipList for DatabaseBackedSkipList {
     fn contains(&self, index: usize) -> bool {
         self.state.len > index
     }
+
+    /// Rebuilding of the state at index `i` visits all nodes of the highest lane, but on every lower lane only the nodes
+    /// after the last complete node of the higher lane before `i` (and the last node of that complete node).
+    /// Preceding nodes are never visited for any index >= `cutoff`, so they are removed. Lanes, which can be
+    /// the highest lane for such index, are kept whole.
+    fn prune(&mut self, previous_cutoff: usize, cutoff: usize) -> Result<(), SkipListError> {
+        if cutoff >= self.state.len {
+            return Err(SkipListError::OutOfBoundsError);
+        }
+
+        for level in 0..Self::index_level(cutoff) {
+            // count of the lower lane nodes covered by complete higher nodes before the index
+            let covered = |index: usize| (index + 1) / LEVEL_BASE.pow(level as u32 + 1) * LEVEL_BASE;
+
+            let mut lane = self.lane(level);
+            for node_index in covered(previous_cutoff).saturating_sub(1)..covered(cutoff).saturating_sub(1) {
+                lane.delete_list_value(node_index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only nodes, which are kept by the [prune](SkipList::prune) to the `index - 1`, are stored, so the push of the `index`
+    /// finds all preceding nodes it merges into the higher lanes.
+    fn start_at(&mut self, index: usize) -> Result<(), SkipListError> {
+        if self.state.len > 0 {
+            return Err(SkipListError::InternalError { description: format!("List is not empty, length: {}", self.state.len) });
+        }
+        if index == 0 {
+            return Ok(());
+        }
+
+        let cutoff = index - 1;
+        let mut level = 0;
+        loop {
+            // node of the lane is stored, when all its nodes of the lower lane are stored
+            let nodes = index / LEVEL_BASE.pow(level as u32);
+            if nodes == 0 {
+                break;
+            }
+            let first_node = if level < Self::index_level(cutoff) {
+                ((cutoff + 1) / LEVEL_BASE.pow(level as u32 + 1) * LEVEL_BASE).saturating_sub(1)
+            } else {
+                0
+            };
+
+            let mut lane = self.lane(level);
+            for node_index in first_node..nodes {
+                lane.put_list_value(node_index)?;
+            }
+            level += 1;
+        }
+
+        self.state.levels = max(level, 1);
+        self.state.len = index;
+
+        self.list_db.put(&self.list_id, &self.state)
+            .map_err(SkipListError::from)
+    }
 }
 
 pub trait TypedSkipList<K: Codec, V: Codec>: SkipList {
@@ -0,0 +1,956 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! Export and import of the storage snapshot of a single applied block.
+//!
+//! Snapshot contains all block headers from the snapshot block down to the genesis, operations of the blocks
+//! within the `max_operations_ttl` of the snapshot block, json and additional data of the snapshot and genesis blocks,
+//! the whole context at the snapshot block taken from the [`ContextList`] and the Irmin context of the protocol runner.
+//!
+//! Snapshot uses the TezEdge format, it is not compatible with the `tezos-node snapshot` files. The Irmin context
+//! is copied file by file from the [`IRMIN_CONTEXT_DIR`] of the Tezos data dir, so it contains all contexts stored
+//! by the protocol runner of the exporting node, not only the context of the snapshot block.
+//!
+//! # Format
+//!
+//! * header: `[magic(16)][version(4)]`
+//! * entries: `[payload length(4)][hash(32)][payload]`, where the payload is a bincode encoded [`SnapshotEntry`]
+//!   and the hash is `blake2b_256(previous hash ++ payload)`, previous hash of the first entry is 32 zero bytes
+//! * the first entry is [`SnapshotEntry::Metadata`] and the last one is [`SnapshotEntry::End`] with the count
+//!   of the preceding entries
+//!
+//! Numbers are big endian. Block headers are stored as `[block hash(32)][block header]` and operations as the list
+//! of messages, both in the binary p2p encoding. Block headers follow each other from the snapshot block
+//! to the genesis, each followed by its operations. Irmin context files are sorted by path and split into chunks,
+//! which follow each other by offset.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use slog::{info, Logger};
+
+use crypto::blake2b;
+use crypto::hash::{BlockHash, ChainId, HashType};
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
+use tezos_messages::p2p::encoding::prelude::*;
+
+use crate::{BlockAdditionalData, BlockHeaderWithHash, BlockJsonData, BlockMetaStorage, BlockMetaStorageReader, BlockStorage, BlockStorageReader, OperationsMetaStorage, OperationsStorage, OperationsStorageReader, StorageError, SystemStorage};
+use crate::block_meta_storage;
+use crate::block_storage::BlockLevel;
+use crate::operations_meta_storage;
+use crate::persistent::{ContextList, ContextMap, Decoder, Encoder, PersistentStorage};
+use crate::skip_list::{Bucket, SkipListError};
+
+/// Directory of the Irmin context of the protocol runner within the Tezos data dir
+pub const IRMIN_CONTEXT_DIR: &str = "context";
+
+/// Identifies the TezEdge snapshot file
+const SNAPSHOT_MAGIC: &[u8; 16] = b"TEZEDGE-SNAPSHOT";
+/// Version of the snapshot format
+const SNAPSHOT_VERSION: u32 = 2;
+/// Length of the entry hash
+const ENTRY_HASH_LEN: usize = 32;
+/// Max length of a single entry payload, protects against allocation of the corrupted length
+const MAX_ENTRY_LEN: usize = 256 * 1024 * 1024;
+/// Max length of the Irmin context file chunk
+const IRMIN_CONTEXT_CHUNK_LEN: u64 = 16 * 1024 * 1024;
+
+/// Possible errors for snapshots
+#[derive(Debug, Fail)]
+pub enum SnapshotError {
+    #[fail(display = "Snapshot file error: {}", error)]
+    IoError {
+        error: io::Error
+    },
+    #[fail(display = "Storage error: {}", error)]
+    StorageError {
+        error: StorageError
+    },
+    #[fail(display = "Context error: {}", error)]
+    ContextError {
+        error: SkipListError
+    },
+    #[fail(display = "Block {} is unknown", block_hash)]
+    UnknownBlock {
+        block_hash: String
+    },
+    #[fail(display = "Block {} is not applied", block_hash)]
+    BlockNotApplied {
+        block_hash: String
+    },
+    #[fail(display = "Context of the block {} is not available", block_hash)]
+    ContextNotAvailable {
+        block_hash: String
+    },
+    #[fail(display = "Block {} is missing in the storage, history down to the genesis is required", block_hash)]
+    MissingBlock {
+        block_hash: String
+    },
+    #[fail(display = "Failed to encode snapshot entry: {}", reason)]
+    EncodingError {
+        reason: String
+    },
+    #[fail(display = "Invalid snapshot: {}", reason)]
+    InvalidFormat {
+        reason: String
+    },
+    #[fail(display = "Hash of the snapshot entry {} does not match its content", entry)]
+    IntegrityError {
+        entry: u64
+    },
+    #[fail(display = "Snapshot was created for chain {}, but the storage belongs to chain {}", snapshot_chain_id, chain_id)]
+    ChainIdMismatch {
+        snapshot_chain_id: String,
+        chain_id: String,
+    },
+    #[fail(display = "Snapshot can be imported only into an empty storage")]
+    StorageNotEmpty,
+    #[fail(display = "Irmin context is not available at {}", path)]
+    IrminContextNotAvailable {
+        path: String
+    },
+    #[fail(display = "Snapshot can be imported only into an empty Irmin context dir, but {} is not empty", path)]
+    IrminContextNotEmpty {
+        path: String
+    },
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::IoError { error }
+    }
+}
+
+impl From<StorageError> for SnapshotError {
+    fn from(error: StorageError) -> Self {
+        SnapshotError::StorageError { error }
+    }
+}
+
+impl From<SkipListError> for SnapshotError {
+    fn from(error: SkipListError) -> Self {
+        SnapshotError::ContextError { error }
+    }
+}
+
+impl slog::Value for SnapshotError {
+    fn serialize(&self, _record: &slog::Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Summary of the exported, verified or imported snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotInfo {
+    pub chain_id: ChainId,
+    pub block_hash: BlockHash,
+    pub level: BlockLevel,
+    /// Count of block headers
+    pub blocks: usize,
+    /// Count of the operations messages (one per validation pass)
+    pub operations: usize,
+    /// Count of the context key/value pairs
+    pub context_values: usize,
+    /// Count of the Irmin context files
+    pub irmin_context_files: usize,
+}
+
+impl SnapshotInfo {
+    fn new(chain_id: ChainId, block_hash: BlockHash, level: BlockLevel) -> Self {
+        SnapshotInfo { chain_id, block_hash, level, blocks: 0, operations: 0, context_values: 0, irmin_context_files: 0 }
+    }
+}
+
+/// Single record of the snapshot file
+#[derive(Serialize, Deserialize, Debug)]
+enum SnapshotEntry {
+    /// Snapshot block, always the first entry
+    Metadata {
+        chain_id: ChainId,
+        block_hash: BlockHash,
+        level: BlockLevel,
+    },
+    /// Block hash followed by the block header in the binary p2p encoding
+    BlockHeader(Vec<u8>),
+    /// Operations of all stored validation passes of the preceding block in the binary p2p encoding
+    Operations(Vec<Vec<u8>>),
+    /// Apply result data of the snapshot and genesis blocks
+    BlockData {
+        block_hash: BlockHash,
+        json_data: Option<BlockJsonData>,
+        additional_data: Option<BlockAdditionalData>,
+    },
+    /// Single value of the context at the snapshot block, keys are joined by `/`
+    Context {
+        key: String,
+        value: Vec<u8>,
+    },
+    /// Chunk of the Irmin context file, path is relative to the Irmin context dir and its steps are joined by `/`
+    IrminContext {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Always the last entry
+    End {
+        entries: u64
+    },
+}
+
+/// Validated content of the snapshot, passed to the importer
+enum SnapshotItem {
+    BlockHeader(BlockHeaderWithHash),
+    Operations(BlockHeaderWithHash, Vec<OperationsForBlocksMessage>),
+    BlockData {
+        block_hash: BlockHash,
+        json_data: Option<BlockJsonData>,
+        additional_data: Option<BlockAdditionalData>,
+    },
+    Context(String, Vec<u8>),
+    IrminContext {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+}
+
+/// Exports and imports snapshots of the storage.
+///
+/// Snapshots are expected to be exported and imported, while the node is not running.
+pub struct SnapshotManager {
+    block_storage: BlockStorage,
+    block_meta_storage: BlockMetaStorage,
+    operations_storage: OperationsStorage,
+    operations_meta_storage: OperationsMetaStorage,
+    system_storage: SystemStorage,
+    context_list: ContextList,
+    irmin_context_dir: PathBuf,
+    log: Logger,
+}
+
+impl SnapshotManager {
+    /// Irmin context of the protocol runner is exported from and imported into the `tezos_data_dir`
+    pub fn new(persistent_storage: &PersistentStorage, tezos_data_dir: &Path, log: Logger) -> Self {
+        SnapshotManager {
+            block_storage: BlockStorage::new(persistent_storage),
+            block_meta_storage: BlockMetaStorage::new(persistent_storage),
+            operations_storage: OperationsStorage::new(persistent_storage),
+            operations_meta_storage: OperationsMetaStorage::new(persistent_storage),
+            system_storage: SystemStorage::new(persistent_storage.kv()),
+            context_list: persistent_storage.context_storage(),
+            irmin_context_dir: tezos_data_dir.join(IRMIN_CONTEXT_DIR),
+            log,
+        }
+    }
+
+    /// Export snapshot of the applied block into the new file at `path`, existing file is never overwritten
+    pub fn export(&self, block_hash: &BlockHash, path: &Path) -> Result<SnapshotInfo, SnapshotError> {
+        let meta = self.block_meta_storage.get(block_hash)?
+            .ok_or_else(|| SnapshotError::UnknownBlock { block_hash: block_hash_to_string(block_hash) })?;
+        if !meta.is_applied() {
+            return Err(SnapshotError::BlockNotApplied { block_hash: block_hash_to_string(block_hash) });
+        }
+        let level = meta.level();
+        let pruned_level = self.system_storage.get_pruned_level()?.unwrap_or(0);
+        let context = if level < pruned_level {
+            None
+        } else {
+            self.context_list.read().expect("lock poisoning").get(level as usize)?
+        };
+        let context = context.ok_or_else(|| SnapshotError::ContextNotAvailable { block_hash: block_hash_to_string(block_hash) })?;
+        if !self.irmin_context_dir.is_dir() {
+            return Err(SnapshotError::IrminContextNotAvailable { path: self.irmin_context_dir.display().to_string() });
+        }
+        let max_operations_ttl = self.block_storage.get_with_additional_data(block_hash)?
+            .map(|(_, additional_data)| additional_data.max_operations_ttl())
+            .unwrap_or(0) as usize;
+
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let mut writer = SnapshotWriter::new(BufWriter::new(file))?;
+        let mut info = SnapshotInfo::new(meta.chain_id().clone(), block_hash.clone(), level);
+        writer.write(&SnapshotEntry::Metadata { chain_id: info.chain_id.clone(), block_hash: block_hash.clone(), level })?;
+
+        // headers from the snapshot block to the genesis, operations only for the recent blocks
+        let mut current_hash = block_hash.clone();
+        let genesis_hash = loop {
+            let block = self.block_storage.get(&current_hash)?
+                .ok_or_else(|| SnapshotError::MissingBlock { block_hash: block_hash_to_string(&current_hash) })?;
+            writer.write(&SnapshotEntry::BlockHeader(encoded(block.encode())?))?;
+            info.blocks += 1;
+
+            if info.blocks <= max_operations_ttl + 1 {
+                let operations = self.operations_storage.get_operations(&block.hash)?;
+                if !operations.is_empty() {
+                    info.operations += operations.len();
+                    let operations = operations.iter()
+                        .map(|operations| encoded(operations.as_bytes()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    writer.write(&SnapshotEntry::Operations(operations))?;
+                }
+            }
+            if info.blocks % 100_000 == 0 {
+                info!(self.log, "Snapshot export progress"; "blocks" => info.blocks);
+            }
+
+            // genesis is its own predecessor
+            if block.header.predecessor() == &block.hash {
+                break block.hash;
+            }
+            current_hash = block.header.predecessor().clone();
+        };
+
+        let mut data_blocks = vec![block_hash];
+        if &genesis_hash != block_hash {
+            data_blocks.push(&genesis_hash);
+        }
+        for data_block_hash in data_blocks {
+            writer.write(&SnapshotEntry::BlockData {
+                block_hash: data_block_hash.clone(),
+                json_data: self.block_storage.get_with_json_data(data_block_hash)?.map(|(_, json_data)| json_data),
+                additional_data: self.block_storage.get_with_additional_data(data_block_hash)?.map(|(_, additional_data)| additional_data),
+            })?;
+        }
+
+        // sorted, so the same block always produces the same snapshot
+        let mut context = context.into_iter()
+            .filter_map(|(key, value)| match value {
+                Bucket::Exists(value) => Some((key, value)),
+                Bucket::Deleted => None,
+            })
+            .collect::<Vec<_>>();
+        context.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        for (key, value) in context {
+            writer.write(&SnapshotEntry::Context { key, value })?;
+            info.context_values += 1;
+        }
+
+        let mut irmin_context_files = vec![];
+        list_files(&self.irmin_context_dir, "", &mut irmin_context_files)?;
+        irmin_context_files.sort_unstable();
+        for path in irmin_context_files {
+            let mut file = File::open(self.irmin_context_file_path(&path))?;
+            let mut offset = 0;
+            loop {
+                let mut data = vec![];
+                (&mut file).take(IRMIN_CONTEXT_CHUNK_LEN).read_to_end(&mut data)?;
+                let len = data.len() as u64;
+                writer.write(&SnapshotEntry::IrminContext { path: path.clone(), offset, data })?;
+                offset += len;
+                if len < IRMIN_CONTEXT_CHUNK_LEN {
+                    break;
+                }
+            }
+            info.irmin_context_files += 1;
+        }
+
+        writer.finish()?;
+        Ok(info)
+    }
+
+    /// Verify integrity of the snapshot file without modifying the storage
+    pub fn verify(&self, path: &Path) -> Result<SnapshotInfo, SnapshotError> {
+        read_snapshot(path, |_| Ok(()))
+    }
+
+    /// Import snapshot into the empty storage and set the snapshot block as the current head.
+    ///
+    /// The whole file is verified before the storage is modified.
+    pub fn import(&mut self, path: &Path) -> Result<SnapshotInfo, SnapshotError> {
+        if self.block_meta_storage.load_current_head()?.is_some() || self.context_list.read().expect("lock poisoning").len() > 0 {
+            return Err(SnapshotError::StorageNotEmpty);
+        }
+        if fs::read_dir(&self.irmin_context_dir).map(|mut entries| entries.next().is_some()).unwrap_or(false) {
+            return Err(SnapshotError::IrminContextNotEmpty { path: self.irmin_context_dir.display().to_string() });
+        }
+
+        let info = self.verify(path)?;
+        match self.system_storage.get_chain_id()? {
+            Some(chain_id) if chain_id != info.chain_id => return Err(SnapshotError::ChainIdMismatch {
+                snapshot_chain_id: HashType::ChainId.bytes_to_string(&info.chain_id),
+                chain_id: HashType::ChainId.bytes_to_string(&chain_id),
+            }),
+            Some(_) => (),
+            None => self.system_storage.set_chain_id(&info.chain_id)?,
+        }
+        info!(self.log, "Snapshot verified, importing";
+            "block_hash" => block_hash_to_string(&info.block_hash),
+            "level" => info.level,
+            "blocks" => info.blocks);
+
+        let mut context = ContextMap::new();
+        read_snapshot(path, |item| self.import_item(item, &info, &mut context))?;
+
+        // context is stored only for the snapshot block, preceding levels are pruned
+        {
+            let mut context_list = self.context_list.write().expect("lock poisoning");
+            context_list.start_at(info.level as usize)?;
+            context_list.push(&context)?;
+        }
+        self.system_storage.set_pruned_level(info.level)?;
+        self.block_meta_storage.store_current_head(&info.block_hash)?;
+
+        Ok(info)
+    }
+
+    fn import_item(&mut self, item: SnapshotItem, info: &SnapshotInfo, context: &mut ContextMap) -> Result<(), SnapshotError> {
+        match item {
+            SnapshotItem::BlockHeader(block) => {
+                self.block_storage.put_block_header(&block)?;
+                let is_genesis = block.header.predecessor() == &block.hash;
+                if is_genesis {
+                    self.block_meta_storage.put(&block.hash, &block_meta_storage::Meta::genesis_meta(&block.hash, &info.chain_id, true))?;
+                    self.operations_meta_storage.put(&block.hash, &operations_meta_storage::Meta::genesis_meta(&info.chain_id))?;
+                } else {
+                    self.block_meta_storage.put_block_header(&block, &info.chain_id)?;
+                    self.operations_meta_storage.put_block_header(&block, &info.chain_id)?;
+                }
+                if block.hash == info.block_hash {
+                    let meta = block_meta_storage::Meta::new(true, Some(block.header.predecessor().clone()), vec![], block.header.level(), info.chain_id.clone());
+                    self.block_meta_storage.put(&block.hash, &meta)?;
+                }
+                if is_genesis || block.hash == info.block_hash {
+                    self.block_storage.assign_to_context(&block.hash, block.header.context())?;
+                }
+            }
+            SnapshotItem::Operations(block, operations) => {
+                for message in &operations {
+                    self.operations_storage.put_operations(message)?;
+                }
+                self.operations_meta_storage.replace(&block.hash, &operations_meta_storage::Meta::with_operations(&block, &info.chain_id, &operations))?;
+            }
+            SnapshotItem::BlockData { block_hash, json_data, additional_data } => {
+                if let Some(json_data) = json_data {
+                    self.block_storage.put_block_json_data(&block_hash, json_data)?;
+                }
+                if let Some(additional_data) = additional_data {
+                    self.block_storage.put_block_additional_data(&block_hash, additional_data)?;
+                }
+            }
+            SnapshotItem::Context(key, value) => {
+                context.insert(key, Bucket::Exists(value));
+            }
+            SnapshotItem::IrminContext { path, offset, data } => {
+                let file_path = self.irmin_context_file_path(&path);
+                if let Some(dir) = file_path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                // chunks of the file follow each other, so the first one creates the file
+                let mut file = if offset == 0 {
+                    OpenOptions::new().write(true).create_new(true).open(&file_path)?
+                } else {
+                    OpenOptions::new().append(true).open(&file_path)?
+                };
+                file.write_all(&data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn irmin_context_file_path(&self, path: &str) -> PathBuf {
+        path.split('/').fold(self.irmin_context_dir.clone(), |file_path, step| file_path.join(step))
+    }
+}
+
+/// Read and validate the whole snapshot, every validated item is passed to the `handle`
+fn read_snapshot<F>(path: &Path, mut handle: F) -> Result<SnapshotInfo, SnapshotError>
+    where
+        F: FnMut(SnapshotItem) -> Result<(), SnapshotError>
+{
+    let mut reader = SnapshotReader::new(BufReader::new(File::open(path)?))?;
+    let mut info = match reader.next_entry()? {
+        Some(SnapshotEntry::Metadata { chain_id, block_hash, level }) => SnapshotInfo::new(chain_id, block_hash, level),
+        _ => return Err(invalid_format("metadata must be the first entry")),
+    };
+
+    // hash of the next expected block header, `None` after the genesis
+    let mut expected_block_hash = Some(info.block_hash.clone());
+    let mut last_block: Option<BlockHeaderWithHash> = None;
+    let mut genesis_hash = None;
+    // path and expected offset of the next chunk of the last Irmin context file
+    let mut irmin_context_file: Option<(String, u64)> = None;
+
+    while let Some(entry) = reader.next_entry()? {
+        match entry {
+            SnapshotEntry::Metadata { .. } => return Err(invalid_format("duplicate metadata")),
+            SnapshotEntry::BlockHeader(bytes) => {
+                if bytes.len() <= HashType::BlockHash.size() {
+                    return Err(invalid_format("invalid block header"));
+                }
+                let block = BlockHeaderWithHash::decode(&bytes).map_err(|_| invalid_format("invalid block header"))?;
+                // hash of the genesis is given by the environment configuration, genesis is its own predecessor
+                let is_genesis = block.header.predecessor() == &block.hash;
+                if !is_genesis && block.header.message_hash().ok().as_ref() != Some(&block.hash) {
+                    return Err(invalid_format(&format!("hash of the block {} does not match its header", block_hash_to_string(&block.hash))));
+                }
+                if expected_block_hash.as_ref() != Some(&block.hash) {
+                    return Err(invalid_format(&format!("unexpected block {}", block_hash_to_string(&block.hash))));
+                }
+                if block.hash == info.block_hash && block.header.level() != info.level {
+                    return Err(invalid_format("snapshot block level does not match the metadata"));
+                }
+                if is_genesis {
+                    genesis_hash = Some(block.hash.clone());
+                    expected_block_hash = None;
+                } else {
+                    expected_block_hash = Some(block.header.predecessor().clone());
+                }
+                info.blocks += 1;
+                last_block = Some(block.clone());
+                handle(SnapshotItem::BlockHeader(block))?;
+            }
+            SnapshotEntry::Operations(operations) => {
+                let block = last_block.clone().ok_or_else(|| invalid_format("operations must follow the block header"))?;
+                let operations = operations.into_iter()
+                    .map(|bytes| OperationsForBlocksMessage::from_bytes(bytes).map_err(|e| invalid_format(&format!("invalid operations: {}", e))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if operations.iter().any(|operations| operations.operations_for_block().hash() != &block.hash) {
+                    return Err(invalid_format(&format!("operations do not belong to the block {}", block_hash_to_string(&block.hash))));
+                }
+                info.operations += operations.len();
+                handle(SnapshotItem::Operations(block, operations))?;
+            }
+            SnapshotEntry::BlockData { block_hash, json_data, additional_data } => {
+                if block_hash != info.block_hash && genesis_hash.as_ref() != Some(&block_hash) {
+                    return Err(invalid_format(&format!("unexpected block data of the block {}", block_hash_to_string(&block_hash))));
+                }
+                handle(SnapshotItem::BlockData { block_hash, json_data, additional_data })?;
+            }
+            SnapshotEntry::Context { key, value } => {
+                info.context_values += 1;
+                handle(SnapshotItem::Context(key, value))?;
+            }
+            SnapshotEntry::IrminContext { path, offset, data } => {
+                if path.split('/').any(|step| step.is_empty() || step == "." || step == ".." || step.contains('\\')) {
+                    return Err(invalid_format(&format!("invalid Irmin context file path {}", path)));
+                }
+                let (is_new_file, expected_offset) = match &irmin_context_file {
+                    Some((last_path, next_offset)) if last_path == &path => (false, *next_offset),
+                    Some((last_path, _)) if last_path > &path => return Err(invalid_format("Irmin context files are not sorted")),
+                    _ => (true, 0),
+                };
+                if offset != expected_offset {
+                    return Err(invalid_format(&format!("unexpected chunk of the Irmin context file {}", path)));
+                }
+                if is_new_file {
+                    info.irmin_context_files += 1;
+                }
+                irmin_context_file = Some((path.clone(), offset + data.len() as u64));
+                handle(SnapshotItem::IrminContext { path, offset, data })?;
+            }
+            SnapshotEntry::End { .. } => return Err(invalid_format("unexpected end entry")),
+        }
+    }
+
+    if genesis_hash.is_none() {
+        return Err(invalid_format("block headers do not reach the genesis"));
+    }
+    Ok(info)
+}
+
+/// Writes hash chained entries
+struct SnapshotWriter<W: Write> {
+    writer: W,
+    hash: Vec<u8>,
+    entries: u64,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    fn new(mut writer: W) -> Result<Self, SnapshotError> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_be_bytes())?;
+        Ok(SnapshotWriter { writer, hash: vec![0; ENTRY_HASH_LEN], entries: 0 })
+    }
+
+    fn write(&mut self, entry: &SnapshotEntry) -> Result<(), SnapshotError> {
+        let payload = bincode::serialize(entry).map_err(|e| SnapshotError::EncodingError { reason: format!("{}", e) })?;
+        self.hash = entry_hash(&self.hash, &payload);
+        self.writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&self.hash)?;
+        self.writer.write_all(&payload)?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), SnapshotError> {
+        let entries = self.entries;
+        self.write(&SnapshotEntry::End { entries })?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads hash chained entries and verifies their integrity
+struct SnapshotReader<R: Read> {
+    reader: R,
+    hash: Vec<u8>,
+    entries: u64,
+    finished: bool,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    fn new(mut reader: R) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 16];
+        read_exact(&mut reader, &mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_format("not a TezEdge snapshot file"));
+        }
+        let mut version = [0; 4];
+        read_exact(&mut reader, &mut version)?;
+        let version = u32::from_be_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_format(&format!("unsupported version {}", version)));
+        }
+        Ok(SnapshotReader { reader, hash: vec![0; ENTRY_HASH_LEN], entries: 0, finished: false })
+    }
+
+    /// Returns `None` after the end entry
+    fn next_entry(&mut self) -> Result<Option<SnapshotEntry>, SnapshotError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut len = [0; 4];
+        read_exact(&mut self.reader, &mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_ENTRY_LEN {
+            return Err(SnapshotError::IntegrityError { entry: self.entries });
+        }
+        let mut hash = vec![0; ENTRY_HASH_LEN];
+        read_exact(&mut self.reader, &mut hash)?;
+        let mut payload = vec![0; len];
+        read_exact(&mut self.reader, &mut payload)?;
+
+        if entry_hash(&self.hash, &payload) != hash {
+            return Err(SnapshotError::IntegrityError { entry: self.entries });
+        }
+        self.hash = hash;
+
+        match bincode::deserialize::<SnapshotEntry>(&payload).map_err(|e| invalid_format(&format!("invalid entry {}: {}", self.entries, e)))? {
+            SnapshotEntry::End { entries } if entries == self.entries => {
+                self.finished = true;
+                Ok(None)
+            }
+            SnapshotEntry::End { .. } => Err(invalid_format("count of the entries does not match")),
+            entry => {
+                self.entries += 1;
+                Ok(Some(entry))
+            }
+        }
+    }
+}
+
+/// Collect paths of all files in the `dir` recursively, steps of the paths are joined by `/`
+fn list_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<(), SnapshotError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string()
+            .map_err(|name| SnapshotError::EncodingError { reason: format!("file name {:?} is not valid unicode", name) })?;
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Truncated file is reported as invalid snapshot
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), SnapshotError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_format("unexpected end of file"),
+        _ => e.into(),
+    })
+}
+
+#[inline]
+fn entry_hash(previous_hash: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(previous_hash.len() + payload.len());
+    data.extend_from_slice(previous_hash);
+    data.extend_from_slice(payload);
+    blake2b::digest_256(&data)
+}
+
+#[inline]
+fn encoded<E: std::fmt::Display>(result: Result<Vec<u8>, E>) -> Result<Vec<u8>, SnapshotError> {
+    result.map_err(|e| SnapshotError::EncodingError { reason: format!("{}", e) })
+}
+
+#[inline]
+fn invalid_format(reason: &str) -> SnapshotError {
+    SnapshotError::InvalidFormat { reason: reason.to_string() }
+}
+
+#[inline]
+fn block_hash_to_string(block_hash: &BlockHash) -> String {
+    HashType::BlockHash.bytes_to_string(block_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use failure::Error;
+
+    use tezos_api::ffi::ApplyBlockResult;
+    use tezos_messages::p2p::encoding::prelude::Path as OperationHashesPath;
+
+    use crate::{BlockAdditionalDataBuilder, BlockJsonDataBuilder, store_applied_block_result};
+    use crate::context::{ContextApi, ContextIndex, TezedgeContext};
+    use crate::tests_common::{block, log, TmpStorage};
+
+    use super::*;
+
+    /// Genesis is its own predecessor, so its hash is not computed from the header
+    fn genesis() -> BlockHeaderWithHash {
+        let genesis_hash = vec![9; 32];
+        let header = block(&genesis_hash, 0, 0, 1).header;
+        BlockHeaderWithHash { hash: genesis_hash, header: Arc::new((*header).clone()) }
+    }
+
+    fn json_data() -> BlockJsonData {
+        BlockJsonDataBuilder::default()
+            .block_header_proto_json("{}".to_string())
+            .block_header_proto_metadata_json("{}".to_string())
+            .operations_proto_metadata_json("[]".to_string())
+            .build()
+            .unwrap()
+    }
+
+    fn additional_data() -> BlockAdditionalData {
+        BlockAdditionalDataBuilder::default()
+            .max_operations_ttl(60)
+            .last_allowed_fork_level(0)
+            .build()
+            .unwrap()
+    }
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Empty Tezos data dir
+    fn tezos_data_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Tezos data dir with the Irmin context files
+    fn tezos_data_dir_with_context(name: &str) -> Result<PathBuf, Error> {
+        let path = tezos_data_dir(name);
+        let context_dir = path.join(IRMIN_CONTEXT_DIR);
+        fs::create_dir_all(context_dir.join("index"))?;
+        fs::write(context_dir.join("store.pack"), vec![7; 1000])?;
+        fs::write(context_dir.join("store.branches"), vec![])?;
+        fs::write(context_dir.join("index").join("data"), b"index data")?;
+        Ok(path)
+    }
+
+    /// Store genesis and two blocks, the last one is applied with context
+    fn prepare_storage(storage: &PersistentStorage, chain_id: &ChainId) -> Result<(BlockHeaderWithHash, BlockHeaderWithHash), Error> {
+        SystemStorage::new(storage.kv()).set_chain_id(chain_id)?;
+        let mut block_storage = BlockStorage::new(storage);
+        let mut block_meta_storage = BlockMetaStorage::new(storage);
+        let mut operations_storage = OperationsStorage::new(storage);
+        let context_list = storage.context_storage();
+
+        let genesis = genesis();
+        let block_1 = block(&genesis.hash, 1, 1, 1);
+        let block_2 = block(&block_1.hash, 2, 2, 1);
+        block_storage.put_block_header(&genesis)?;
+        block_storage.put_block_json_data(&genesis.hash, json_data())?;
+        block_storage.put_block_additional_data(&genesis.hash, additional_data())?;
+        block_storage.assign_to_context(&genesis.hash, genesis.header.context())?;
+        block_meta_storage.put(&genesis.hash, &block_meta_storage::Meta::genesis_meta(&genesis.hash, chain_id, true))?;
+        for block in &[&block_1, &block_2] {
+            block_storage.put_block_header(block)?;
+            block_meta_storage.put_block_header(block, chain_id)?;
+            operations_storage.put_operations(&OperationsForBlocksMessage::new(OperationsForBlock::new(block.hash.clone(), 0), OperationHashesPath::Op, vec![]))?;
+        }
+        block_storage.put_block_json_data(&block_2.hash, json_data())?;
+        block_storage.put_block_additional_data(&block_2.hash, additional_data())?;
+        block_storage.assign_to_context(&block_2.hash, block_2.header.context())?;
+        block_meta_storage.put(&block_2.hash, &block_meta_storage::Meta::new(true, Some(block_1.hash.clone()), vec![], 2, chain_id.clone()))?;
+
+        let mut context_list = context_list.write().expect("lock poisoning");
+        context_list.push(&ContextMap::new())?;
+        let mut context = ContextMap::new();
+        context.insert("data/a".to_string(), Bucket::Exists(vec![1]));
+        context_list.push(&context)?;
+        context.insert("data/b".to_string(), Bucket::Exists(vec![2]));
+        context.insert("data/a".to_string(), Bucket::Deleted);
+        context_list.push(&context)?;
+
+        Ok((genesis, block_2))
+    }
+
+    #[test]
+    fn export_and_import_snapshot() -> Result<(), Error> {
+        let chain_id = vec![1, 2, 3, 4];
+        let source_storage = TmpStorage::create("__snapshot_export_source")?;
+        let source_data_dir = tezos_data_dir_with_context("__snapshot_export_source_tezos")?;
+        let (genesis, block_2) = prepare_storage(source_storage.storage(), &chain_id)?;
+
+        let path = snapshot_path("__snapshot_export_and_import");
+        let exported = SnapshotManager::new(source_storage.storage(), &source_data_dir, log()).export(&block_2.hash, &path)?;
+        assert_eq!(3, exported.blocks);
+        assert_eq!(2, exported.operations);
+        assert_eq!(1, exported.context_values);
+        assert_eq!(3, exported.irmin_context_files);
+        // existing snapshot is not overwritten
+        assert!(SnapshotManager::new(source_storage.storage(), &source_data_dir, log()).export(&block_2.hash, &path).is_err());
+
+        let target_storage = TmpStorage::create("__snapshot_export_target")?;
+        let target_data_dir = tezos_data_dir("__snapshot_export_target_tezos");
+        let imported = SnapshotManager::new(target_storage.storage(), &target_data_dir, log()).import(&path)?;
+        assert_eq!(exported, imported);
+
+        let source_context_dir = source_data_dir.join(IRMIN_CONTEXT_DIR);
+        let target_context_dir = target_data_dir.join(IRMIN_CONTEXT_DIR);
+        for file in &["store.pack", "store.branches", "index/data"] {
+            assert_eq!(fs::read(source_context_dir.join(file))?, fs::read(target_context_dir.join(file))?);
+        }
+
+        let storage = target_storage.storage();
+        let block_meta_storage = BlockMetaStorage::new(storage);
+        assert_eq!(Some(block_2.hash.clone()), block_meta_storage.load_current_head()?);
+        assert!(block_meta_storage.get(&genesis.hash)?.unwrap().is_applied());
+        assert_eq!(Some(chain_id), SystemStorage::new(storage.kv()).get_chain_id()?);
+        assert_eq!(Some(2), SystemStorage::new(storage.kv()).get_pruned_level()?);
+        assert!(OperationsMetaStorage::new(storage).is_complete(&block_2.hash)?);
+        assert_eq!(1, OperationsStorage::new(storage).get_operations(&block_2.hash)?.len());
+        let block_storage = BlockStorage::new(storage);
+        assert!(block_storage.get_with_json_data(&block_2.hash)?.is_some());
+        assert!(block_storage.get_with_additional_data(&genesis.hash)?.is_some());
+        assert_eq!(Some(block_2.hash.clone()), block_storage.get_by_context_hash(block_2.header.context())?.map(|block| block.hash));
+
+        let context_list = storage.context_storage();
+        let context_list = context_list.read().expect("lock poisoning");
+        assert_eq!(3, context_list.len());
+        assert_eq!(Some(Bucket::Exists(vec![2])), context_list.get_key(2, &"data/b".to_string())?);
+        assert_eq!(None, context_list.get_key(2, &"data/a".to_string())?);
+
+        // storage is not empty anymore
+        assert!(SnapshotManager::new(storage, &tezos_data_dir("__snapshot_export_empty_tezos"), log()).import(&path).is_err());
+
+        // Irmin context is not empty
+        let other_storage = TmpStorage::create("__snapshot_export_other")?;
+        match SnapshotManager::new(other_storage.storage(), &target_data_dir, log()).import(&path) {
+            Err(SnapshotError::IrminContextNotEmpty { .. }) => (),
+            result => panic!("Unexpected import result: {:?}", result),
+        }
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&source_data_dir);
+        let _ = fs::remove_dir_all(&target_data_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_snapshot_is_not_imported() -> Result<(), Error> {
+        let chain_id = vec![1, 2, 3, 4];
+        let source_storage = TmpStorage::create("__snapshot_corrupted_source")?;
+        let source_data_dir = tezos_data_dir_with_context("__snapshot_corrupted_source_tezos")?;
+        let (_, block_2) = prepare_storage(source_storage.storage(), &chain_id)?;
+
+        let path = snapshot_path("__snapshot_corrupted");
+        SnapshotManager::new(source_storage.storage(), &source_data_dir, log()).export(&block_2.hash, &path)?;
+        let mut content = fs::read(&path)?;
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&path, content)?;
+
+        let target_storage = TmpStorage::create("__snapshot_corrupted_target")?;
+        let target_data_dir = tezos_data_dir("__snapshot_corrupted_target_tezos");
+        let mut snapshot_manager = SnapshotManager::new(target_storage.storage(), &target_data_dir, log());
+        match snapshot_manager.import(&path) {
+            Err(SnapshotError::IntegrityError { .. }) => (),
+            result => panic!("Unexpected import result: {:?}", result),
+        }
+        assert!(BlockStorage::new(target_storage.storage()).get(&block_2.hash)?.is_none());
+        assert_eq!(0, target_storage.storage().context_storage().read().expect("lock poisoning").len());
+        assert!(!target_data_dir.join(IRMIN_CONTEXT_DIR).exists());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&source_data_dir);
+        let _ = fs::remove_dir_all(&target_data_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn apply_block_on_imported_snapshot() -> Result<(), Error> {
+        let chain_id = vec![1, 2, 3, 4];
+        let source_storage = TmpStorage::create("__snapshot_apply_source")?;
+        let source_data_dir = tezos_data_dir_with_context("__snapshot_apply_source_tezos")?;
+        let (_, block_2) = prepare_storage(source_storage.storage(), &chain_id)?;
+        let path = snapshot_path("__snapshot_apply");
+        SnapshotManager::new(source_storage.storage(), &source_data_dir, log()).export(&block_2.hash, &path)?;
+
+        let target_storage = TmpStorage::create("__snapshot_apply_target")?;
+        let target_data_dir = tezos_data_dir("__snapshot_apply_target_tezos");
+        SnapshotManager::new(target_storage.storage(), &target_data_dir, log()).import(&path)?;
+        let storage = target_storage.storage();
+        let mut block_storage = BlockStorage::new(storage);
+        let mut block_meta_storage = BlockMetaStorage::new(storage);
+        let mut operations_meta_storage = OperationsMetaStorage::new(storage);
+
+        // successor of the snapshot block is received
+        let block_3 = block(&block_2.hash, 3, 3, 1);
+        let operations = OperationsForBlocksMessage::new(OperationsForBlock::new(block_3.hash.clone(), 0), OperationHashesPath::Op, vec![]);
+        block_storage.put_block_header(&block_3)?;
+        block_meta_storage.put_block_header(&block_3, &chain_id)?;
+        OperationsStorage::new(storage).put_operations(&operations)?;
+        operations_meta_storage.put_block_header(&block_3, &chain_id)?;
+        operations_meta_storage.put_operations(&operations)?;
+
+        // block can be applied the same way as by the chain feeder
+        assert!(operations_meta_storage.is_complete(&block_3.hash)?);
+        assert!(block_meta_storage.get(&block_2.hash)?.unwrap().is_applied());
+        assert!(block_storage.get_with_additional_data(&block_2.hash)?.is_some());
+
+        // context of the block is committed on top of the imported context
+        let mut context = TezedgeContext::new(BlockStorage::new(storage), storage.context_storage());
+        let predecessor_context_hash = Some(block_2.header.context().clone());
+        let mut context_diff = context.checkout(block_2.header.context())?;
+        context_diff.set(&predecessor_context_hash, &vec!["data".to_string(), "c".to_string()], &vec![3])?;
+        context.copy_to_diff(&predecessor_context_hash, &vec!["data".to_string(), "b".to_string()], &vec!["data".to_string(), "d".to_string()], &mut context_diff)?;
+        context.commit(&block_3.hash, &predecessor_context_hash, block_3.header.context(), &context_diff)?;
+
+        let apply_result = ApplyBlockResult {
+            validation_result_message: "applied".to_string(),
+            context_hash: block_3.header.context().clone(),
+            block_header_proto_json: "{}".to_string(),
+            block_header_proto_metadata_json: "{}".to_string(),
+            operations_proto_metadata_json: "[]".to_string(),
+            max_operations_ttl: 60,
+            last_allowed_fork_level: 0,
+            forking_testchain: false,
+            forking_testchain_data: None,
+        };
+        let mut block_3_meta = block_meta_storage.get(&block_3.hash)?.unwrap();
+        store_applied_block_result(&mut block_storage, &mut block_meta_storage, &block_3.hash, apply_result, &mut block_3_meta)?;
+        block_meta_storage.store_current_head(&block_3.hash)?;
+
+        assert!(block_meta_storage.get(&block_3.hash)?.unwrap().is_applied());
+        assert_eq!(Some(block_3.hash.clone()), block_meta_storage.load_current_head()?);
+        assert_eq!(Some(block_3.hash.clone()), block_storage.get_by_context_hash(block_3.header.context())?.map(|block| block.hash));
+        let context_index = ContextIndex::new(None, Some(block_3.header.context().clone()));
+        assert_eq!(Some(Bucket::Exists(vec![2])), context.get_key(&context_index, &vec!["data".to_string(), "b".to_string()])?);
+        assert_eq!(Some(Bucket::Exists(vec![2])), context.get_key(&context_index, &vec!["data".to_string(), "d".to_string()])?);
+        assert_eq!(Some(Bucket::Exists(vec![3])), context.get_key(&context_index, &vec!["data".to_string(), "c".to_string()])?);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(&source_data_dir);
+        let _ = fs::remove_dir_all(&target_data_dir);
+        Ok(())
+    }
+}
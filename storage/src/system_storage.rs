@@ -5,9 +5,11 @@ use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
-use crypto::hash::ChainId;
+use crypto::hash::{BlockHash, ChainId};
 
+use crate::block_storage::BlockLevel;
 use crate::persistent::{BincodeEncoded, KeyValueSchema, KeyValueStoreWithSchema};
+use crate::pruning::HistoryMode;
 use crate::StorageError;
 
 pub type SystemStorageKv = dyn KeyValueStoreWithSchema<SystemStorage> + Sync + Send;
@@ -25,6 +27,10 @@ pub struct SystemStorage {
 impl SystemStorage {
     const CHAIN_ID: &'static str = "chain_id";
     const DB_VERSION: &'static str = "db_version";
+    const CURRENT_HEAD: &'static str = "current_head";
+    const MIGRATION_CURSOR: &'static str = "migration_cursor";
+    const PRUNED_LEVEL: &'static str = "pruned_level";
+    const HISTORY_MODE: &'static str = "history_mode";
 
     pub fn new(kv: Arc<SystemStorageKv>) -> Self {
         SystemStorage { kv }
@@ -61,6 +67,79 @@ impl SystemStorage {
         self.kv.put(&Self::DB_VERSION.to_string(), &SystemValue::Integer(db_version))
             .map_err(StorageError::from)
     }
+
+    /// Position of the interrupted migration from the current database version
+    #[inline]
+    pub fn get_migration_cursor(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        self.kv.get(&Self::MIGRATION_CURSOR.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Bytes(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_migration_cursor(&mut self, cursor: &[u8]) -> Result<(), StorageError> {
+        self.kv.put(&Self::MIGRATION_CURSOR.to_string(), &SystemValue::Bytes(cursor.to_vec()))
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn delete_migration_cursor(&mut self) -> Result<(), StorageError> {
+        self.kv.delete(&Self::MIGRATION_CURSOR.to_string())
+            .map_err(StorageError::from)
+    }
+
+    /// Blocks below this level were already pruned
+    #[inline]
+    pub fn get_pruned_level(&self) -> Result<Option<BlockLevel>, StorageError> {
+        self.kv.get(&Self::PRUNED_LEVEL.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Integer(value)) => Some(value as BlockLevel),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_pruned_level(&mut self, level: BlockLevel) -> Result<(), StorageError> {
+        self.kv.put(&Self::PRUNED_LEVEL.to_string(), &SystemValue::Integer(level as i64))
+            .map_err(StorageError::from)
+    }
+
+    /// History mode of the node, which pruned the database
+    #[inline]
+    pub fn get_history_mode(&self) -> Result<Option<HistoryMode>, StorageError> {
+        self.kv.get(&Self::HISTORY_MODE.to_string())
+            .map(|result| match result {
+                Some(SystemValue::String(value)) => value.parse().ok(),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_history_mode(&mut self, mode: HistoryMode) -> Result<(), StorageError> {
+        self.kv.put(&Self::HISTORY_MODE.to_string(), &SystemValue::String(mode.to_string()))
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn get_current_head(&self) -> Result<Option<BlockHash>, StorageError> {
+        self.kv.get(&Self::CURRENT_HEAD.to_string())
+            .map(|result| match result {
+                Some(SystemValue::Hash(value)) => Some(value),
+                _ => None
+            })
+            .map_err(StorageError::from)
+    }
+
+    #[inline]
+    pub fn set_current_head(&mut self, block_hash: &BlockHash) -> Result<(), StorageError> {
+        self.kv.put(&Self::CURRENT_HEAD.to_string(), &SystemValue::Hash(block_hash.clone()))
+            .map_err(StorageError::from)
+    }
 }
 
 
@@ -78,7 +157,8 @@ impl KeyValueSchema for SystemStorage {
 pub enum SystemValue {
     String(String),
     Integer(i64),
-    Hash(Vec<u8>)
+    Hash(Vec<u8>),
+    Bytes(Vec<u8>),
 }
 
 impl BincodeEncoded for SystemValue {}
\ No newline at end of file
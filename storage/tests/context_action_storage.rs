@@ -100,4 +100,50 @@ fn context_get_values_by_contract_address() -> Result<(), Error> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn context_delete_values_by_block_hash() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__ctx_storage_delete_by_block_hash")?;
+
+    let str_block_hash_1 = "BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET";
+    let block_hash_1 = HashType::BlockHash.string_to_bytes(str_block_hash_1)?;
+    let str_block_hash_2 = "BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ";
+    let block_hash_2 = HashType::BlockHash.string_to_bytes(str_block_hash_2)?;
+    let contract_key = |block_hash: &str| ContextAction::Set {
+        key: vec![
+            "data".to_string(),
+            "contracts".to_string(),
+            "index".to_string(),
+            "ad".to_string(),
+            "af".to_string(),
+            "43".to_string(),
+            "23".to_string(),
+            "f9".to_string(),
+            "3e".to_string(),
+            "000003cb7d7842406496fc07288635562bfd17e176c4".to_string(),
+            "balance".to_string()
+        ],
+        value: vec![10, 200],
+        operation_hash: None,
+        block_hash: Some(block_hash.into()),
+        context_hash: None,
+        value_as_json: None,
+        start_time: 0.0,
+        end_time: 0.0,
+    };
+
+    let mut storage = ContextActionStorage::new(tmp_storage.storage());
+    storage.put_action(&block_hash_1, contract_key(str_block_hash_1))?;
+    storage.put_action(&block_hash_2, contract_key(str_block_hash_2))?;
+
+    storage.delete_by_block_hash(&block_hash_1)?;
+
+    assert!(storage.get_by_block_hash(&block_hash_1)?.is_empty());
+    assert_eq!(1, storage.get_by_block_hash(&block_hash_2)?.len());
+    let values = storage.get_by_contract_address(&hex::decode("000003cb7d7842406496fc07288635562bfd17e176c4")?, None, 10)?;
+    assert_eq!(1, values.len(), "Was expecting vector of {} elements but instead found {}", 1, values.len());
+    assert_eq!(1, values[0].id());
+
+    Ok(())
+}
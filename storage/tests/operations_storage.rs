@@ -42,4 +42,26 @@ fn test_get_operations() -> Result<(), Error> {
     assert_eq!(1, operations.len(), "Was expecting vector of {} elements but instead found {}", 1, operations.len());
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_delete_operations() -> Result<(), Error> {
+    let tmp_storage = TmpStorage::create("__op_storage_delete_operations")?;
+
+    let block_hash_1 = HashType::BlockHash.string_to_bytes("BKyQ9EofHrgaZKENioHyP4FZNsTmiSEcVmcghgzCC9cGhE7oCET")?;
+    let block_hash_2 = HashType::BlockHash.string_to_bytes("BLaf78njreWdt2WigJjM9e3ecEdVKm5ehahUfYBKvcWvZ8vfTcJ")?;
+
+    let mut storage = OperationsStorage::new(tmp_storage.storage());
+    for validation_pass in 0..4 {
+        let message = OperationsForBlocksMessage::new(OperationsForBlock::new(block_hash_1.clone(), validation_pass), Path::Op, vec![]);
+        storage.put_operations(&message)?;
+    }
+    let message = OperationsForBlocksMessage::new(OperationsForBlock::new(block_hash_2.clone(), 0), Path::Op, vec![]);
+    storage.put_operations(&message)?;
+
+    storage.delete_operations(&block_hash_1)?;
+    assert!(storage.get_operations(&block_hash_1)?.is_empty());
+    assert_eq!(1, storage.get_operations(&block_hash_2)?.len());
+
+    Ok(())
+}
@@ -12,7 +12,7 @@ use rand::{
 use serde::{Deserialize, Serialize};
 
 use storage::persistent::BincodeEncoded;
-use storage::skip_list::{DatabaseBackedSkipList, TypedSkipList};
+use storage::skip_list::{DatabaseBackedSkipList, Lane, TypedSkipList};
 use storage::tests_common::TmpStorage;
 
 #[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
@@ -219,6 +219,59 @@ pub fn list_check_get_key() {
     assert_eq!(val.unwrap(), None);
 }
 
+#[test]
+pub fn list_prune() {
+    let tmp_storage = TmpStorage::create("__skip_list:list_prune").expect("Storage error");
+    let mut list: Box<dyn TypedSkipList<i32, i32>> = Box::new(DatabaseBackedSkipList::new(10, tmp_storage.storage().kv(), tmp_storage.storage().seq().generator("__skip_list:list_prune")).expect("failed to create skip list"));
+    let mut aggregate = HashMap::new();
+    let mut snapshots = Vec::new();
+    let mut push = |list: &mut Box<dyn TypedSkipList<i32, i32>>, index: i32| {
+        let map = hashmap! { index % 50 => index };
+        list.push(&map).expect("failed to push value to skip list");
+        aggregate.extend(map);
+        snapshots.push(aggregate.clone());
+    };
+    for index in 0..600 {
+        push(&mut list, index);
+    }
+
+    list.prune(0, 300).expect("failed to prune skip list");
+    list.prune(300, 520).expect("failed to prune skip list");
+    let lane = Lane::new(10, 0, tmp_storage.storage().kv(), tmp_storage.storage().kv(), tmp_storage.storage().seq().generator("__skip_list:list_prune"));
+    assert!(lane.get_list_value(0).expect("failed to get lane node").is_none(), "Node was not pruned");
+
+    for index in 600..700 {
+        push(&mut list, index);
+    }
+    for index in 520..700 {
+        let val = list.get(index).expect("failed to get value from skip list");
+        assert_eq!(val.as_ref(), snapshots.get(index), "Failed at index {}", index);
+        let val = list.get_key(index, &7).expect("failed to get value from skip list");
+        assert_eq!(val.as_ref(), snapshots[index].get(&7), "Failed at index {}", index);
+    }
+}
+
+#[test]
+pub fn list_start_at() {
+    let tmp_storage = TmpStorage::create("__skip_list:list_start_at").expect("Storage error");
+    for (list_id, start) in [(20, 1), (21, 15), (22, 63), (23, 64), (24, 520)].iter() {
+        let mut list: Box<dyn TypedSkipList<i32, i32>> = Box::new(DatabaseBackedSkipList::new(*list_id, tmp_storage.storage().kv(), tmp_storage.storage().seq().generator("__skip_list:list_start_at")).expect("failed to create skip list"));
+        list.start_at(*start).expect("failed to start skip list");
+        assert_eq!(*start, list.len());
+        assert!(!list.contains(*start));
+
+        let mut aggregate = HashMap::new();
+        for index in *start..*start + 200 {
+            let map = hashmap! { index as i32 % 50 => index as i32 };
+            list.push(&map).expect("failed to push value to skip list");
+            aggregate.extend(map);
+            assert_eq!(Some(&aggregate), list.get(index).expect("failed to get value from skip list").as_ref(), "Failed at index {}", index);
+            assert_eq!(aggregate.get(&7), list.get_key(index, &7).expect("failed to get value from skip list").as_ref(), "Failed at index {}", index);
+        }
+        list.prune(*start, *start + 100).expect("failed to prune skip list");
+    }
+}
+
 #[test]
 pub fn skip_list_simulate_ledger() {
     let tmp_storage = TmpStorage::create("__skip_list:skip_list_simulate_ledger").expect("Storage error");
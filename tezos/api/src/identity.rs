@@ -3,6 +3,10 @@
 
 use serde::{Deserialize, Serialize};
 
+use crypto::crypto_box::random_keypair;
+use crypto::hash::HashType;
+use crypto::proof_of_work::{generate_proof_of_work, ProofOfWorkError};
+
 /// This node identity information
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Identity {
@@ -10,4 +14,42 @@ pub struct Identity {
     pub public_key: String,
     pub secret_key: String,
     pub proof_of_work_stamp: String,
-}
\ No newline at end of file
+}
+
+impl Identity {
+    /// Generate new identity with random key pair and proof of work stamp of the `expected_pow` difficulty.
+    ///
+    /// Proof of work is searched in `threads` parallel threads, `progress` is periodically called with count of tried stamps.
+    pub fn generate<F>(expected_pow: f64, threads: usize, progress: F) -> Result<Self, ProofOfWorkError>
+        where
+            F: Fn(u64)
+    {
+        let (public_key, secret_key) = random_keypair();
+        let proof_of_work_stamp = generate_proof_of_work(public_key.as_bytes(), expected_pow, threads, progress)?;
+
+        Ok(Identity {
+            peer_id: HashType::CryptoboxPublicKeyHash.bytes_to_string(public_key.as_bytes()),
+            public_key: hex::encode(public_key.as_bytes()),
+            secret_key: hex::encode(secret_key.as_bytes()),
+            proof_of_work_stamp: hex::encode(proof_of_work_stamp),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto::proof_of_work::check_proof_of_work;
+
+    use super::*;
+
+    #[test]
+    fn test_generate_identity() -> Result<(), failure::Error> {
+        let identity = Identity::generate(8.0, 2, |_| ())?;
+
+        let public_key = hex::decode(&identity.public_key)?;
+        assert_eq!(HashType::CryptoboxPublicKeyHash.bytes_to_string(&public_key), identity.peer_id);
+        assert!(identity.peer_id.starts_with("id"));
+        check_proof_of_work(&public_key, &hex::decode(&identity.proof_of_work_stamp)?, 8.0)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module provides comparison of block fitness, which is used by fork choice.
+//!
+//! Fitness is compared in the same way as in tezos shell:
+//! at first the number of fitness elements is compared, then elements are compared one by one,
+//! where shorter element is lower and elements of the same length are compared lexicographically.
+
+use std::cmp::Ordering;
+
+pub type Fitness = Vec<Vec<u8>>;
+
+/// Compare two fitness values, returns `Ordering::Greater` if `fitness_a` is better than `fitness_b`
+pub fn fitness_compare(fitness_a: &[Vec<u8>], fitness_b: &[Vec<u8>]) -> Ordering {
+    fitness_a.len().cmp(&fitness_b.len())
+        .then_with(|| {
+            fitness_a.iter().zip(fitness_b.iter())
+                .map(|(a, b)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::base::fitness::fitness_compare;
+
+    #[test]
+    fn test_fitness_compare() {
+        let fitness = vec![vec![0], vec![0, 0, 0, 0, 0, 0, 0, 2]];
+
+        assert_eq!(Ordering::Equal, fitness_compare(&fitness, &fitness.clone()));
+        // higher value
+        assert_eq!(Ordering::Greater, fitness_compare(&vec![vec![0], vec![0, 0, 0, 0, 0, 0, 0, 3]], &fitness));
+        assert_eq!(Ordering::Less, fitness_compare(&vec![vec![0], vec![0, 0, 0, 0, 0, 0, 0, 1]], &fitness));
+        // longer element wins
+        assert_eq!(Ordering::Greater, fitness_compare(&vec![vec![0, 0], vec![0, 0, 0, 0, 0, 0, 0, 0]], &fitness));
+        // more elements wins
+        assert_eq!(Ordering::Greater, fitness_compare(&vec![vec![0], vec![0], vec![0]], &fitness));
+        assert_eq!(Ordering::Less, fitness_compare(&vec![], &fitness));
+    }
+}
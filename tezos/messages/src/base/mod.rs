@@ -1,4 +1,5 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-pub mod signature_public_key_hash;
\ No newline at end of file
+pub mod fitness;
+pub mod signature_public_key_hash;
@@ -19,6 +19,17 @@ pub enum AckMessage {
     Nack(NackInfo),
 }
 
+/// Reason, why the connection was refused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NackMotive {
+    NoMotive = 0,
+    TooManyConnections = 1,
+    UnknownChainName = 2,
+    DeprecatedP2pVersion = 3,
+    DeprecatedDistributedDbVersion = 4,
+    AlreadyConnected = 5,
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct NackInfo {
     pub motive: i16,
@@ -42,6 +53,13 @@ impl fmt::Debug for NackInfo {
 }
 
 impl NackInfo {
+    pub fn new(motive: NackMotive, potential_peers_to_connect: Vec<String>) -> Self {
+        NackInfo {
+            motive: motive as i16,
+            potential_peers_to_connect,
+        }
+    }
+
     fn encoding() -> Encoding {
         Encoding::Obj(
             vec![
@@ -1,6 +1,8 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::net::{IpAddr, SocketAddr};
+
 use getset::Getters;
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +19,36 @@ pub struct AdvertiseMessage {
     body: BinaryDataCache,
 }
 
+impl AdvertiseMessage {
+    pub fn new(addresses: &[SocketAddr]) -> Self {
+        AdvertiseMessage {
+            id: addresses.iter().map(SocketAddr::to_string).collect(),
+            body: Default::default(),
+        }
+    }
+
+    /// Advertised points, which are not valid socket addresses are skipped.
+    pub fn points(&self) -> Vec<SocketAddr> {
+        self.id.iter().filter_map(|point| parse_point(point)).collect()
+    }
+}
+
+/// Parse p2p point in the `ip:port` or `[ipv6]:port` format.
+///
+/// IPv4-mapped IPv6 addresses (`[::ffff:a.b.c.d]:port`) are converted to IPv4 addresses,
+/// so the same point is always represented by the same socket address.
+pub fn parse_point(point: &str) -> Option<SocketAddr> {
+    let mut address = point.parse::<SocketAddr>().ok()?;
+    if let IpAddr::V6(ip) = address.ip() {
+        if let [0, 0, 0, 0, 0, 0xffff, _, _] = ip.segments() {
+            if let Some(ip) = ip.to_ipv4() {
+                address.set_ip(IpAddr::V4(ip));
+            }
+        }
+    }
+    Some(address)
+}
+
 impl HasEncoding for AdvertiseMessage {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
@@ -26,10 +26,14 @@ pub struct CurrentHeadMessage {
 
 impl CurrentHeadMessage {
     pub fn new(chain_id: ChainId, current_block_header: BlockHeader) -> Self {
+        Self::with_mempool(chain_id, current_block_header, Default::default())
+    }
+
+    pub fn with_mempool(chain_id: ChainId, current_block_header: BlockHeader, current_mempool: Mempool) -> Self {
         CurrentHeadMessage {
             chain_id,
             current_block_header,
-            current_mempool: Default::default(),
+            current_mempool,
             body: Default::default()
         }
     }
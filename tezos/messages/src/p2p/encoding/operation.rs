@@ -3,6 +3,7 @@
 
 use std::sync::Arc;
 
+use getset::Getters;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{BlockHash, HashType, OperationHash};
@@ -10,14 +11,30 @@ use tezos_encoding::encoding::{Encoding, Field, HasEncoding, SchemaType};
 
 use crate::p2p::binary_message::cache::{BinaryDataCache, CachedData, CacheReader, CacheWriter};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct OperationMessage {
+    #[get = "pub"]
     operation: Operation,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl OperationMessage {
+    pub fn new(operation: Operation) -> Self {
+        OperationMessage {
+            operation,
+            body: Default::default()
+        }
+    }
+}
+
+impl From<Operation> for OperationMessage {
+    fn from(operation: Operation) -> Self {
+        OperationMessage::new(operation)
+    }
+}
+
 impl HasEncoding for OperationMessage {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
@@ -85,14 +102,24 @@ impl CachedData for Operation {
 }
 
 // -----------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct GetOperationsMessage {
+    #[get = "pub"]
     get_operations: Vec<OperationHash>,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl GetOperationsMessage {
+    pub fn new(get_operations: Vec<OperationHash>) -> Self {
+        GetOperationsMessage {
+            get_operations,
+            body: Default::default()
+        }
+    }
+}
+
 impl HasEncoding for GetOperationsMessage {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
@@ -118,11 +118,16 @@ macro_rules! into_peer_message {
 }
 
 into_peer_message!(AdvertiseMessage, Advertise);
+into_peer_message!(DeactivateMessage, Deactivate);
 into_peer_message!(GetCurrentBranchMessage, GetCurrentBranch);
 into_peer_message!(CurrentBranchMessage, CurrentBranch);
 into_peer_message!(GetBlockHeadersMessage, GetBlockHeaders);
 into_peer_message!(BlockHeaderMessage, BlockHeader);
 into_peer_message!(GetCurrentHeadMessage, GetCurrentHead);
 into_peer_message!(CurrentHeadMessage, CurrentHead);
+into_peer_message!(GetOperationsMessage, GetOperations);
+into_peer_message!(OperationMessage, Operation);
 into_peer_message!(GetOperationsForBlocksMessage, GetOperationsForBlocks);
 into_peer_message!(OperationsForBlocksMessage, OperationsForBlocks);
+into_peer_message!(GetProtocolsMessage, GetProtocols);
+into_peer_message!(ProtocolMessage, Protocol);
@@ -1,6 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use getset::Getters;
 use serde::{Deserialize, Serialize};
 
 use crypto::hash::{HashType, ProtocolHash};
@@ -8,8 +9,9 @@ use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
 
 use crate::p2p::binary_message::cache::{BinaryDataCache, CachedData, CacheReader, CacheWriter};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct ProtocolMessage {
+    #[get = "pub"]
     protocol: Protocol,
 
     #[serde(skip_serializing)]
@@ -36,17 +38,37 @@ impl CachedData for ProtocolMessage {
     }
 }
 
+impl From<Protocol> for ProtocolMessage {
+    fn from(protocol: Protocol) -> Self {
+        ProtocolMessage { protocol, body: Default::default() }
+    }
+}
+
 // -----------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct Component {
+    #[get = "pub"]
     name: String,
+    #[get = "pub"]
     interface: Option<String>,
+    #[get = "pub"]
     implementation: String,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl Component {
+    pub fn new(name: String, interface: Option<String>, implementation: String) -> Self {
+        Component {
+            name,
+            interface,
+            implementation,
+            body: Default::default()
+        }
+    }
+}
+
 impl HasEncoding for Component {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
@@ -80,6 +102,14 @@ pub struct Protocol {
 }
 
 impl Protocol {
+    pub fn new(expected_env_version: i16, components: Vec<Component>) -> Self {
+        Protocol {
+            expected_env_version,
+            components,
+            body: Default::default()
+        }
+    }
+
     pub fn expected_env_version(&self) -> i16 {
         self.expected_env_version
     }
@@ -111,14 +141,24 @@ impl CachedData for Protocol {
 }
 
 // -----------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Getters, Clone)]
 pub struct GetProtocolsMessage {
+    #[get = "pub"]
     get_protocols: Vec<ProtocolHash>,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl GetProtocolsMessage {
+    pub fn new(get_protocols: Vec<ProtocolHash>) -> Self {
+        GetProtocolsMessage {
+            get_protocols,
+            body: Default::default()
+        }
+    }
+}
+
 impl HasEncoding for GetProtocolsMessage {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
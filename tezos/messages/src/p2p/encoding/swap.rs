@@ -4,6 +4,7 @@
 use getset::Getters;
 use serde::{Serialize, Deserialize};
 
+use crypto::hash::{CryptoboxPublicKeyHash, HashType};
 use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
 
 use crate::p2p::binary_message::cache::{BinaryDataCache, CachedData, CacheReader, CacheWriter};
@@ -13,17 +14,27 @@ pub struct SwapMessage {
     #[get = "pub"]
     point: String,
     #[get = "pub"]
-    peer_id: String,
+    peer_id: CryptoboxPublicKeyHash,
 
     #[serde(skip_serializing)]
     body: BinaryDataCache,
 }
 
+impl SwapMessage {
+    pub fn new(point: String, peer_id: CryptoboxPublicKeyHash) -> Self {
+        SwapMessage {
+            point,
+            peer_id,
+            body: Default::default(),
+        }
+    }
+}
+
 impl HasEncoding for SwapMessage {
     fn encoding() -> Encoding {
         Encoding::Obj(vec![
             Field::new("point", Encoding::String),
-            Field::new("peer_id", Encoding::String),
+            Field::new("peer_id", Encoding::Hash(HashType::CryptoboxPublicKeyHash)),
         ])
     }
 }
@@ -1,6 +1,7 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
 
 use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
@@ -8,10 +9,13 @@ use tezos_encoding::encoding::{Encoding, Field, HasEncoding};
 use crate::p2p::binary_message::cache::{BinaryDataCache, CachedData, CacheReader, CacheWriter};
 use std::fmt;
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Getters, CopyGetters)]
 pub struct Version {
+    #[get = "pub"]
     chain_name: String,
+    #[get_copy = "pub"]
     distributed_db_version: u16,
+    #[get_copy = "pub"]
     p2p_version: u16,
     #[serde(skip_serializing)]
     body: BinaryDataCache,
@@ -39,6 +43,19 @@ impl Version {
     pub fn supports(&self, other: &Version) -> bool {
         self.chain_name == other.chain_name && self.distributed_db_version == other.distributed_db_version
     }
+
+    /// Pick the best version supported by both sides of the connection.
+    ///
+    /// Versions are compatible in case they [support](Version::supports) each other. The highest common `distributed_db_version` is preferred
+    /// and the negotiated `p2p_version` is the lower one of the two, because newer p2p versions are backward compatible.
+    /// Returns `None` if there is no compatible version.
+    pub fn negotiate(local_versions: &[Version], remote_versions: &[Version]) -> Option<Version> {
+        local_versions.iter()
+            .flat_map(|local| remote_versions.iter()
+                .filter(move |remote| local.supports(remote))
+                .map(move |remote| Version::new(local.chain_name.clone(), local.distributed_db_version, std::cmp::min(local.p2p_version, remote.p2p_version))))
+            .max_by_key(|version| (version.distributed_db_version, version.p2p_version))
+    }
 }
 
 impl HasEncoding for Version {
@@ -1,8 +1,11 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
+use std::net::SocketAddr;
+
 use failure::Error;
 use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::advertise::parse_point;
 use tezos_messages::p2p::encoding::prelude::*;
 
 #[test]
@@ -13,4 +16,29 @@ fn can_deserialize_advertise() -> Result<(), Error> {
     assert_eq!("[fe80::e828:209d:20e:c0ae]:375", &message.id()[0]);
     assert_eq!("234.123.124.91:9876", &message.id()[1]);
     Ok(assert_eq!("123.123.124.21:9876", &message.id()[2]))
-}
\ No newline at end of file
+}
+
+#[test]
+fn can_serialize_advertise() -> Result<(), Error> {
+    let addresses = vec!["[fe80::e828:209d:20e:c0ae]:375".parse()?, "234.123.124.91:9876".parse()?, "123.123.124.21:9876".parse()?];
+    let message = AdvertiseMessage::new(&addresses);
+    let expected = hex::decode("0000001e5b666538303a3a653832383a323039643a3230653a633061655d3a333735000000133233342e3132332e3132342e39313a39383736000000133132332e3132332e3132342e32313a39383736")?;
+    Ok(assert_eq!(expected, message.as_bytes()?))
+}
+
+#[test]
+fn can_parse_advertised_points() -> Result<(), Error> {
+    let message_bytes = hex::decode("0000001e5b666538303a3a653832383a323039643a3230653a633061655d3a333735000000133233342e3132332e3132342e39313a39383736000000133132332e3132332e3132342e32313a39383736")?;
+    let message = AdvertiseMessage::from_bytes(message_bytes)?;
+    let expected: Vec<SocketAddr> = vec!["[fe80::e828:209d:20e:c0ae]:375".parse()?, "234.123.124.91:9876".parse()?, "123.123.124.21:9876".parse()?];
+    Ok(assert_eq!(expected, message.points()))
+}
+
+#[test]
+fn can_parse_point() -> Result<(), Error> {
+    assert_eq!(Some("[2001:db8::1]:9732".parse::<SocketAddr>()?), parse_point("[2001:db8::1]:9732"));
+    assert_eq!(Some("10.0.0.1:9732".parse::<SocketAddr>()?), parse_point("10.0.0.1:9732"));
+    assert_eq!(Some("10.0.0.1:9732".parse::<SocketAddr>()?), parse_point("[::ffff:10.0.0.1]:9732"));
+    assert_eq!(None, parse_point("2001:db8::1:9732"));
+    Ok(assert_eq!(None, parse_point("localhost:9732")))
+}
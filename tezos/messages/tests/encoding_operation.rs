@@ -12,4 +12,26 @@ fn can_deserialize() -> Result<(), Error> {
     let operation = Operation::from_bytes(message_bytes)?;
     assert_eq!("BKqTKfGwK3zHnVXX33X5PPHy1FDTnbkajj3eFtCXGFyfimQhT1H", HashType::BlockHash.bytes_to_string(&operation.branch()));
     Ok(assert_eq!("000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08", &hex::encode(&operation.data())))
-}
\ No newline at end of file
+}
+
+#[test]
+fn can_serialize_get_operations_message() -> Result<(), Error> {
+    let response: PeerMessageResponse = GetOperationsMessage::new(vec![vec![7; 32]]).into();
+    let message_bytes = response.as_bytes()?;
+    let expected_writer_result = hex::decode(format!("00000026003000000020{}", "07".repeat(32)))?;
+    Ok(assert_eq!(expected_writer_result, message_bytes))
+}
+
+#[test]
+fn can_serialize_and_deserialize_operation_message() -> Result<(), Error> {
+    let message_bytes = hex::decode("10490b79070cf19175cd7e3b9c1ee66f6e85799980404b119132ea7e58a4a97e000008c387fa065a181d45d47a9b78ddc77e92a881779ff2cbabbf9646eade4bf1405a08e00b725ed849eea46953b10b5cdebc518e6fd47e69b82d2ca18c4cf6d2f312dd08")?;
+    let operation = Operation::from_bytes(message_bytes)?;
+    let response: PeerMessageResponse = OperationMessage::new(operation.clone()).into();
+
+    let messages = PeerMessageResponse::from_bytes(response.as_bytes()?)?;
+    assert_eq!(1, messages.messages().len());
+    match messages.messages().get(0).unwrap() {
+        PeerMessage::Operation(message) => Ok(assert_eq!(&operation, message.operation())),
+        _ => panic!("Unsupported encoding: {:?}", messages)
+    }
+}
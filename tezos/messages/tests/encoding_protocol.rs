@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: MIT
 
 use failure::Error;
-use tezos_messages::p2p::binary_message::BinaryMessage;
+use crypto::hash::HashType;
+use tezos_messages::p2p::binary_message::{BinaryMessage, MessageHash};
 use tezos_messages::p2p::encoding::prelude::*;
 
 #[test]
@@ -11,4 +12,31 @@ fn can_deserialize_protocol() -> Result<(), Error> {
     let message = Protocol::from_bytes(message_bytes)?;
     assert_eq!(68, message.components().len());
     Ok(assert_eq!(0, message.expected_env_version()))
-}
\ No newline at end of file
+}
+
+#[test]
+fn can_serialize_and_deserialize_protocol_message() -> Result<(), Error> {
+    let protocol = Protocol::new(0, vec![
+        Component::new("Main".to_string(), Some("module M = struct end".to_string()), "let x = 1".to_string()),
+        Component::new("Misc".to_string(), None, "let y = 2".to_string()),
+    ]);
+    let protocol_hash = protocol.message_hash()?;
+
+    let message_bytes = ProtocolMessage::from(protocol).as_bytes()?;
+    let message = ProtocolMessage::from_bytes(message_bytes)?;
+    let protocol = message.protocol();
+    assert_eq!(2, protocol.components().len());
+    assert_eq!("Main", protocol.components()[0].name());
+    assert_eq!(&Some("module M = struct end".to_string()), protocol.components()[0].interface());
+    assert_eq!(&None, protocol.components()[1].interface());
+    assert_eq!("let y = 2", protocol.components()[1].implementation());
+    Ok(assert_eq!(protocol_hash, protocol.message_hash()?))
+}
+
+#[test]
+fn can_serialize_and_deserialize_get_protocols() -> Result<(), Error> {
+    let protocol_hash = HashType::ProtocolHash.string_to_bytes("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb")?;
+    let message_bytes = GetProtocolsMessage::new(vec![protocol_hash.clone()]).as_bytes()?;
+    let message = GetProtocolsMessage::from_bytes(message_bytes)?;
+    Ok(assert_eq!(&vec![protocol_hash], message.get_protocols()))
+}
@@ -0,0 +1,31 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use failure::Error;
+use tezos_messages::p2p::binary_message::BinaryMessage;
+use tezos_messages::p2p::encoding::prelude::*;
+
+#[test]
+fn can_serialize_and_deserialize_swap() -> Result<(), Error> {
+    let message = SwapMessage::new("123.123.124.21:9876".to_string(), hex::decode("47f5c302e9ed3c9a73279fe9f4ad99b6")?);
+    let message_bytes = message.as_bytes()?;
+    let deserialized = SwapMessage::from_bytes(message_bytes)?;
+    assert_eq!("123.123.124.21:9876", deserialized.point());
+    Ok(assert_eq!(&hex::decode("47f5c302e9ed3c9a73279fe9f4ad99b6")?, deserialized.peer_id()))
+}
+
+#[test]
+fn can_deserialize_swap() -> Result<(), Error> {
+    // peer id is the fixed size hash of the public key of the ocaml node identity (idrRoknJh9zwEePNswF3MPGFzmKaVp)
+    let message_bytes = hex::decode("000000133132332e3132332e3132342e32313a3938373647f5c302e9ed3c9a73279fe9f4ad99b6")?;
+    let message = SwapMessage::from_bytes(message_bytes)?;
+    assert_eq!("123.123.124.21:9876", message.point());
+    Ok(assert_eq!(&hex::decode("47f5c302e9ed3c9a73279fe9f4ad99b6")?, message.peer_id()))
+}
+
+#[test]
+fn can_serialize_swap() -> Result<(), Error> {
+    let message = SwapMessage::new("123.123.124.21:9876".to_string(), hex::decode("47f5c302e9ed3c9a73279fe9f4ad99b6")?);
+    let expected = hex::decode("000000133132332e3132332e3132342e32313a3938373647f5c302e9ed3c9a73279fe9f4ad99b6")?;
+    Ok(assert_eq!(expected, message.as_bytes()?))
+}
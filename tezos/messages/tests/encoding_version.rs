@@ -0,0 +1,34 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use tezos_messages::p2p::encoding::prelude::*;
+
+const CHAIN_NAME: &str = "TEZOS_ALPHANET_CARTHAGE_2019-11-28T13:02:13Z";
+
+#[test]
+fn can_negotiate_version() {
+    let local = vec![
+        Version::new(CHAIN_NAME.to_string(), 1, 1),
+        Version::new(CHAIN_NAME.to_string(), 0, 1),
+    ];
+
+    // lower p2p version of the remote peer is used
+    let negotiated = Version::negotiate(&local, &[Version::new(CHAIN_NAME.to_string(), 0, 0)]);
+    assert_eq!(Some(Version::new(CHAIN_NAME.to_string(), 0, 0)), negotiated);
+
+    // highest common distributed db version is preferred
+    let negotiated = Version::negotiate(&local, &[Version::new(CHAIN_NAME.to_string(), 0, 2), Version::new(CHAIN_NAME.to_string(), 1, 2)]);
+    let negotiated = negotiated.expect("Expected negotiated version");
+    assert_eq!(CHAIN_NAME, negotiated.chain_name());
+    assert_eq!(1, negotiated.distributed_db_version());
+    assert_eq!(1, negotiated.p2p_version());
+}
+
+#[test]
+fn cannot_negotiate_incompatible_version() {
+    let local = vec![Version::new(CHAIN_NAME.to_string(), 0, 1)];
+
+    assert_eq!(None, Version::negotiate(&local, &[Version::new(CHAIN_NAME.to_string(), 2, 1)]));
+    assert_eq!(None, Version::negotiate(&local, &[Version::new("TEZOS_MAINNET".to_string(), 0, 1)]));
+    assert_eq!(None, Version::negotiate(&local, &[]));
+}